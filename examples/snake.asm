@@ -0,0 +1,224 @@
+; the snake game from easy6502, https://skilldrick.github.io/easy6502/, assembled for 0x0600.
+; the assembler has no `define`, so the addresses and values it gives names to are written
+; out, with the names alongside:
+;
+;   $00 appleL, $01 appleH           screen location of the apple
+;   $10 snakeHeadL, $11 snakeHeadH   screen location of the snake's head
+;   $12 snakeBodyStart               start of the snake's body, in byte pairs
+;   $02 snakeDirection               1 up, 2 right, 4 down, 8 left
+;   $03 snakeLength                  in bytes, two a segment
+;   $fe sysRandom                    a new random byte every instruction
+;   $ff sysLastKey                   ascii for the last key pressed, W A S D steer
+
+    JSR init
+    JSR loop
+
+init:
+    JSR initSnake
+    JSR generateApplePosition
+    RTS
+
+initSnake:
+    LDA #2              ; start moving right
+    STA $02
+    LDA #4              ; two segments long
+    STA $03
+    LDA #$11
+    STA $10
+    LDA #$10
+    STA $12
+    LDA #$0f
+    STA $14             ; body segment 1
+    LDA #$04
+    STA $11
+    STA $13             ; body segment 1
+    STA $15             ; body segment 2
+    RTS
+
+generateApplePosition:
+    LDA $fe             ; a random low byte
+    STA $00
+    LDA $fe             ; and a random page from 2 to 5
+    AND #$03
+    CLC
+    ADC #2
+    STA $01
+    RTS
+
+loop:
+    JSR readKeys
+    JSR checkCollision
+    JSR updateSnake
+    JSR drawApple
+    JSR drawSnake
+    JSR spinWheels
+    JMP loop
+
+readKeys:
+    LDA $ff
+    CMP #$77            ; w
+    BEQ upKey
+    CMP #$64            ; d
+    BEQ rightKey
+    CMP #$73            ; s
+    BEQ downKey
+    CMP #$61            ; a
+    BEQ leftKey
+    RTS
+upKey:
+    LDA #4              ; can't turn round from moving down
+    BIT $02
+    BNE illegalMove
+    LDA #1
+    STA $02
+    RTS
+rightKey:
+    LDA #8
+    BIT $02
+    BNE illegalMove
+    LDA #2
+    STA $02
+    RTS
+downKey:
+    LDA #1
+    BIT $02
+    BNE illegalMove
+    LDA #4
+    STA $02
+    RTS
+leftKey:
+    LDA #2
+    BIT $02
+    BNE illegalMove
+    LDA #8
+    STA $02
+    RTS
+illegalMove:
+    RTS
+
+checkCollision:
+    JSR checkAppleCollision
+    JSR checkSnakeCollision
+    RTS
+
+checkAppleCollision:
+    LDA $00
+    CMP $10
+    BNE doneCheckingAppleCollision
+    LDA $01
+    CMP $11
+    BNE doneCheckingAppleCollision
+    INC $03             ; eat the apple, a segment longer
+    INC $03
+    JSR generateApplePosition
+doneCheckingAppleCollision:
+    RTS
+
+checkSnakeCollision:
+    LDX #2              ; from the second segment
+snakeCollisionLoop:
+    LDA $10,X
+    CMP $10
+    BNE continueCollisionLoop
+maybeCollided:
+    LDA $11,X
+    CMP $11
+    BEQ didCollide
+continueCollisionLoop:
+    INX
+    INX
+    CPX $03             ; the last segment, and no collision
+    BEQ didntCollide
+    JMP snakeCollisionLoop
+didCollide:
+    JMP gameOver
+didntCollide:
+    RTS
+
+updateSnake:
+    LDX $03
+    DEX
+    TXA
+updateloop:
+    LDA $10,X
+    STA $12,X
+    DEX
+    BPL updateloop
+
+    LDA $02
+    LSR
+    BCS up
+    LSR
+    BCS right
+    LSR
+    BCS down
+    LSR
+    BCS left
+up:
+    LDA $10
+    SEC
+    SBC #$20
+    STA $10
+    BCC upup
+    RTS
+upup:
+    DEC $11
+    LDA #$1
+    CMP $11
+    BEQ collision
+    RTS
+right:
+    INC $10
+    LDA #$1f
+    BIT $10
+    BEQ collision
+    RTS
+down:
+    LDA $10
+    CLC
+    ADC #$20
+    STA $10
+    BCS downdown
+    RTS
+downdown:
+    INC $11
+    LDA #$6
+    CMP $11
+    BEQ collision
+    RTS
+left:
+    DEC $10
+    LDA $10
+    AND #$1f
+    CMP #$1f
+    BEQ collision
+    RTS
+collision:
+    JMP gameOver
+
+drawApple:
+    LDY #0
+    LDA $fe
+    STA ($00),Y
+    RTS
+
+drawSnake:
+    LDX $03
+    LDA #0
+    STA ($10,X)         ; rub out the end of the tail
+    LDX #0
+    LDA #1
+    STA ($10,X)         ; paint the head
+    RTS
+
+spinWheels:
+    LDX #0
+spinloop:
+    NOP
+    NOP
+    DEX
+    BNE spinloop
+    RTS
+
+; the byte after the program is a BRK
+gameOver:
@@ -0,0 +1,119 @@
+// the easy6502 snake game in a window, steered with W A S D or the arrow keys
+//
+//   cargo run --example snake --features sdl2
+use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nes_emulator::asm::assemble_at;
+use nes_emulator::bus::Bus;
+use nes_emulator::cpu::CPU;
+use nes_emulator::easy6502::{read_screen, HEIGHT, LAST_KEY, ORIGIN, RANDOM, WIDTH};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::EventPump;
+
+const SCALE: u32 = 10;
+
+// the ascii the game wants for a key, if it's one of them
+fn key(key: Keycode) -> Option<u8> {
+    match key {
+        Keycode::W | Keycode::Up => Some(b'w'),
+        Keycode::A | Keycode::Left => Some(b'a'),
+        Keycode::S | Keycode::Down => Some(b's'),
+        Keycode::D | Keycode::Right => Some(b'd'),
+        _ => None,
+    }
+}
+
+// false once the window's closed
+fn handle_events(cpu: &mut CPU, events: &mut EventPump) -> bool {
+    for event in events.poll_iter() {
+        match event {
+            Event::Quit { .. }
+            | Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => return false,
+            Event::KeyDown {
+                keycode: Some(code),
+                ..
+            } => {
+                if let Some(ascii) = key(code) {
+                    cpu.poke(LAST_KEY, ascii);
+                }
+            }
+            _ => {}
+        }
+    }
+    true
+}
+
+fn run() -> Result<(), String> {
+    let program = assemble_at(include_str!("snake.asm"), ORIGIN).map_err(|e| e.to_string())?;
+    let mut cpu = CPU::new(Bus::new());
+    cpu.load_at(&program, ORIGIN).map_err(|e| e.to_string())?;
+    cpu.reset();
+
+    let sdl = sdl2::init()?;
+    let window = sdl
+        .video()?
+        .window("Snake", WIDTH as u32 * SCALE, HEIGHT as u32 * SCALE)
+        .position_centered()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, WIDTH as u32, HEIGHT as u32)
+        .map_err(|e| e.to_string())?;
+    let mut events = sdl.event_pump()?;
+
+    // xorshift, seeded from the clock so the apples aren't the same every game
+    let mut random = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.subsec_nanos())
+        .unwrap_or(0)
+        | 1;
+    let mut frame = [0; WIDTH * HEIGHT * 3];
+    let mut result = Ok(());
+
+    cpu.run_with_callback(|cpu| {
+        if !handle_events(cpu, &mut events) {
+            cpu.halted = true;
+            return;
+        }
+        random ^= random << 13;
+        random ^= random >> 17;
+        random ^= random << 5;
+        cpu.poke(RANDOM, random as u8);
+
+        if read_screen(cpu, &mut frame) {
+            let drawn = texture
+                .update(None, &frame, WIDTH * 3)
+                .map_err(|e| e.to_string())
+                .and_then(|()| canvas.copy(&texture, None, None));
+            if let Err(e) = drawn {
+                result = Err(e);
+                cpu.halted = true;
+                return;
+            }
+            canvas.present();
+        }
+        // the game's only clock is how fast the cpu goes
+        thread::sleep(Duration::new(0, 70_000));
+    })
+    .map_err(|e| e.to_string())?;
+    result?;
+    // the window's closed, or the game ran into something and hit the BRK after its end
+    println!("score: {}", cpu.peek(0x03) / 2 - 2);
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::rom::TvSystem;
+
+// in cpu cycles
+const RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+// the same pitches with pal's slower cpu
+const PAL_RATE_TABLE: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+fn rate_table(tv_system: TvSystem) -> &'static [u16; 16] {
+    match tv_system {
+        TvSystem::Ntsc => &RATE_TABLE,
+        TvSystem::Pal => &PAL_RATE_TABLE,
+    }
+}
+
+// 0x4010 - 0x4013, plays 1 bit deltas fetched from cpu memory, see
+// https://www.nesdev.org/wiki/APU_DMC
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    tv_system: TvSystem,
+    rate: u16,
+    timer: u16,
+    // 7 bits
+    level: u8,
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    // the memory reader fills this, the output unit empties it
+    sample_buffer: Option<u8>,
+    dma_request: Option<u16>,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    pub irq: bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        Dmc {
+            irq_enabled: false,
+            looping: false,
+            tv_system: TvSystem::Ntsc,
+            rate: RATE_TABLE[0],
+            timer: 0,
+            level: 0,
+            sample_address: 0xc000,
+            sample_length: 1,
+            current_address: 0xc000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            dma_request: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq: false,
+        }
+    }
+
+    // `register` is 0 - 3
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            // IL-- RRRR
+            0 => {
+                self.irq_enabled = value & 0b1000_0000 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looping = value & 0b0100_0000 != 0;
+                self.rate = rate_table(self.tv_system)[(value & 0b1111) as usize];
+            }
+            // -DDD DDDD
+            1 => self.level = value & 0b0111_1111,
+            // 0xc000 + A * 64
+            2 => self.sample_address = 0xc000 | ((value as u16) << 6),
+            // L * 16 + 1 bytes
+            _ => self.sample_length = ((value as u16) << 4) + 1,
+        }
+    }
+
+    // see Noise::set_tv_system
+    pub fn set_tv_system(&mut self, tv_system: TvSystem) {
+        let old = rate_table(self.tv_system);
+        if let Some(index) = old.iter().position(|&rate| rate == self.rate) {
+            self.rate = rate_table(tv_system)[index];
+        }
+        self.tv_system = tv_system;
+    }
+
+    // bit 4 of 0x4015: off stops the sample, on starts it over unless it's still playing
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // every cpu cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.rate - 1;
+            self.clock_output();
+        } else {
+            self.timer -= 1;
+        }
+
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            self.dma_request = Some(self.current_address);
+        }
+    }
+
+    fn clock_output(&mut self) {
+        if !self.silence {
+            // moves by 2 but never wraps
+            if self.shift_register & 1 == 1 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    // the address the bus should read the next sample byte from, once. a sample stopped since
+    // the request was made doesn't want it anymore
+    pub fn take_dma_request(&mut self) -> Option<u16> {
+        self.dma_request.take().filter(|_| self.bytes_remaining > 0)
+    }
+
+    // the byte read for take_dma_request
+    pub fn fill(&mut self, sample: u8) {
+        self.sample_buffer = Some(sample);
+        // wraps around to 0x8000, not 0x0000
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // 0 - 127
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // plays `samples` from a fake memory at the fastest rate, returning every level it moves to
+    fn play(dmc: &mut Dmc, samples: &[u8], cycles: usize) -> Vec<u8> {
+        let mut levels = vec![];
+        for _ in 0..cycles {
+            dmc.clock_timer();
+            if let Some(addr) = dmc.take_dma_request() {
+                dmc.fill(samples[(addr - 0xc000) as usize % samples.len()]);
+            }
+            if levels.last() != Some(&dmc.output()) {
+                levels.push(dmc.output());
+            }
+        }
+        levels
+    }
+
+    #[test]
+    fn test_level_clamps_at_both_ends() {
+        let mut dmc = Dmc::new();
+        dmc.write(0, 0x0f);
+        dmc.write(1, 124);
+        dmc.write(3, 0);
+        dmc.set_enabled(true);
+
+        let levels = play(&mut dmc, &[0xff], 54 * 17);
+        assert_eq!(levels, [124, 126]);
+
+        let mut dmc = Dmc::new();
+        dmc.write(0, 0x0f);
+        dmc.write(1, 3);
+        dmc.write(3, 0);
+        dmc.set_enabled(true);
+
+        let levels = play(&mut dmc, &[0x00], 54 * 17);
+        assert_eq!(levels, [3, 1]);
+    }
+
+    #[test]
+    fn test_address_wraps_to_0x8000() {
+        let mut dmc = Dmc::new();
+        dmc.write(2, 0xff);
+        dmc.write(3, 0xff);
+        dmc.set_enabled(true);
+        // 0xffc0 + 64 bytes runs off the end of memory
+        for _ in 0..64 {
+            dmc.fill(0);
+            dmc.sample_buffer = None;
+        }
+
+        dmc.clock_timer();
+        assert_eq!(dmc.take_dma_request(), Some(0x8000));
+    }
+}
@@ -0,0 +1,86 @@
+// the volume for pulse and noise, either constant or a sawtooth that decays from 15 to 0 once
+// per period quarter frames, see https://www.nesdev.org/wiki/APU_Envelope
+#[derive(Default)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Envelope {
+    start: bool,
+    looping: bool,
+    constant_volume: bool,
+    // the constant volume, and the divider period otherwise
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    // --LC VVVV, the L bit doubles as the length counter halt
+    pub fn write(&mut self, value: u8) {
+        self.looping = value & 0b0010_0000 != 0;
+        self.constant_volume = value & 0b0001_0000 != 0;
+        self.volume = value & 0b1111;
+    }
+
+    // the channel's length write restarts the decay on the next quarter frame
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decays_once_per_period() {
+        let mut envelope = Envelope::default();
+        envelope.write(0b0000_0001);
+        envelope.restart();
+
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        // a period of 1 is two clocks per step
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        envelope.clock();
+        assert_eq!(envelope.output(), 14);
+    }
+
+    #[test]
+    fn test_loops_back_to_15() {
+        let mut envelope = Envelope::default();
+        envelope.write(0b0010_0000);
+        envelope.restart();
+
+        for _ in 0..16 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::rom::TvSystem;
+
+// the cpu cycle each step lands on after the sequencer starts, rounded up from the usual
+// 3728.5, 7456.5, ... apu cycles: steps 1 to 3, then the end of 4 and 5 step mode
+const STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+// from 4156.5, 8313.5, ... apu cycles
+const PAL_STEPS: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+// which of the channels' slower units to clock this cycle
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FrameClocks {
+    // envelopes and the triangle's linear counter
+    pub quarter: bool,
+    // length counters and sweeps
+    pub half: bool,
+}
+
+// 0x4017 writes, see https://www.nesdev.org/wiki/APU_Frame_Counter
+//   76543210
+//   |+------- irq inhibit
+//   +-------- mode (0: 4 step, 1: 5 step)
+#[derive(Default)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameCounter {
+    five_step: bool,
+    irq_inhibit: bool,
+    pub irq: bool,
+    cycle: u32,
+    tv_system: TvSystem,
+}
+
+impl FrameCounter {
+    // starts the sequence over. 5 step mode clocks everything straight away
+    pub fn write(&mut self, value: u8) -> FrameClocks {
+        self.five_step = value & 0b1000_0000 != 0;
+        self.irq_inhibit = value & 0b0100_0000 != 0;
+        if self.irq_inhibit {
+            self.irq = false;
+        }
+        self.cycle = 0;
+
+        FrameClocks {
+            quarter: self.five_step,
+            half: self.five_step,
+        }
+    }
+
+    pub fn set_tv_system(&mut self, tv_system: TvSystem) {
+        self.tv_system = tv_system;
+    }
+
+    // every cpu cycle
+    pub fn clock(&mut self) -> FrameClocks {
+        self.cycle += 1;
+        let steps = match self.tv_system {
+            TvSystem::Ntsc => &STEPS,
+            TvSystem::Pal => &PAL_STEPS,
+        };
+        let end = if self.five_step { steps[4] } else { steps[3] };
+
+        match self.cycle {
+            cycle if cycle == steps[0] || cycle == steps[2] => FrameClocks {
+                quarter: true,
+                half: false,
+            },
+            cycle if cycle == steps[1] => FrameClocks {
+                quarter: true,
+                half: true,
+            },
+            cycle if cycle == end => {
+                // only 4 step mode asks for an irq
+                if !self.five_step && !self.irq_inhibit {
+                    self.irq = true;
+                }
+                self.cycle = 0;
+                FrameClocks {
+                    quarter: true,
+                    half: true,
+                }
+            }
+            _ => FrameClocks::default(),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+// what the top 5 bits of a length write load the counter with, in half frames
+#[rustfmt::skip]
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// silences a channel after a while, counting down each half frame unless halted, see
+// https://www.nesdev.org/wiki/APU_Length_Counter
+#[derive(Default)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct LengthCounter {
+    enabled: bool,
+    halt: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    // the channel's enable bit in 0x4015, turning it off also clears the counter
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    pub fn set_halt(&mut self, halt: bool) {
+        self.halt = halt;
+    }
+
+    // disabled channels ignore loads
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index & 0b1_1111) as usize];
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.counter > 0
+    }
+
+    pub fn counter(&self) -> u8 {
+        self.counter
+    }
+}
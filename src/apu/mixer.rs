@@ -0,0 +1,19 @@
+// the dac is non-linear, the usual approximation from https://www.nesdev.org/wiki/APU_Mixer
+// gives 0.0 - ~1.0
+pub fn mix(pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+    let pulse = (pulse1 + pulse2) as f32;
+    let pulse_out = if pulse == 0.0 {
+        0.0
+    } else {
+        95.88 / (8128.0 / pulse + 100.0)
+    };
+
+    let tnd = triangle as f32 / 8227.0 + noise as f32 / 12241.0 + dmc as f32 / 22638.0;
+    let tnd_out = if tnd == 0.0 {
+        0.0
+    } else {
+        159.79 / (1.0 / tnd + 100.0)
+    };
+
+    pulse_out + tnd_out
+}
@@ -0,0 +1,464 @@
+pub mod dmc;
+pub mod envelope;
+pub mod frame_counter;
+pub mod length_counter;
+pub mod mixer;
+pub mod noise;
+pub mod output;
+pub mod pulse;
+pub mod triangle;
+
+use dmc::Dmc;
+use frame_counter::{FrameClocks, FrameCounter};
+use noise::Noise;
+use output::ApuOutput;
+use pulse::Pulse;
+use triangle::Triangle;
+
+use crate::rom::TvSystem;
+
+// 0x4000 - 0x4003  pulse 1
+// 0x4004 - 0x4007  pulse 2
+// 0x4008 - 0x400b  triangle
+// 0x400c - 0x400f  noise
+// 0x4010 - 0x4013  dmc
+// 0x4015           channel enables when written, length counter and irq status when read
+// 0x4017           frame counter, writes only
+// see https://www.nesdev.org/wiki/APU
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Apu {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+    pub frame_counter: FrameCounter,
+    samples: ApuOutput,
+    // the pulse timers only run every other cpu cycle
+    odd_cycle: bool,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_counter: FrameCounter::default(),
+            samples: ApuOutput::new(),
+            odd_cycle: false,
+        }
+    }
+
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write(addr - 0x4000, value),
+            0x4004..=0x4007 => self.pulse2.write(addr - 0x4004, value),
+            0x4008..=0x400b => self.triangle.write(addr - 0x4008, value),
+            0x400c..=0x400f => self.noise.write(addr - 0x400c, value),
+            0x4010..=0x4013 => self.dmc.write(addr - 0x4010, value),
+            0x4015 => {
+                self.pulse1.length.set_enabled(value & 0b0001 != 0);
+                self.pulse2.length.set_enabled(value & 0b0010 != 0);
+                self.triangle.length.set_enabled(value & 0b0100 != 0);
+                self.noise.length.set_enabled(value & 0b1000 != 0);
+                self.dmc.set_enabled(value & 0b1_0000 != 0);
+                self.dmc.irq = false;
+            }
+            0x4017 => {
+                let clocks = self.frame_counter.write(value);
+                self.clock_frame(clocks);
+            }
+            _ => {}
+        }
+    }
+
+    // which channels still have length left, and both irqs, which reading acknowledges
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.dmc.irq = false;
+        self.frame_counter.irq = false;
+        status
+    }
+
+    // read_status without acknowledging the irqs
+    pub fn peek_status(&self) -> u8 {
+        (self.pulse1.length.active() as u8)
+            | ((self.pulse2.length.active() as u8) << 1)
+            | ((self.triangle.length.active() as u8) << 2)
+            | ((self.noise.length.active() as u8) << 3)
+            | ((self.dmc.active() as u8) << 4)
+            | ((self.frame_counter.irq as u8) << 6)
+            | ((self.dmc.irq as u8) << 7)
+    }
+
+    pub fn tick(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            self.triangle.clock_timer();
+            self.noise.clock_timer();
+            self.dmc.clock_timer();
+            let clocks = self.frame_counter.clock();
+            self.clock_frame(clocks);
+            if self.odd_cycle {
+                self.pulse1.clock_timer();
+                self.pulse2.clock_timer();
+            }
+            self.odd_cycle = !self.odd_cycle;
+
+            let level = self.output();
+            self.samples.push(level);
+        }
+    }
+
+    // the channels' periods, the frame counter's steps and the rate samples are made at all
+    // depend on the cpu clock
+    pub fn set_tv_system(&mut self, tv_system: TvSystem) {
+        self.noise.set_tv_system(tv_system);
+        self.dmc.set_tv_system(tv_system);
+        self.frame_counter.set_tv_system(tv_system);
+        self.samples.set_cpu_frequency(tv_system.cpu_frequency());
+    }
+
+    // 44100 to start with
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.samples.set_sample_rate(sample_rate);
+    }
+
+    // the high and low pass the console's output goes through, on by default
+    pub fn set_filters_enabled(&mut self, enabled: bool) {
+        self.samples.set_filters_enabled(enabled);
+    }
+
+    pub fn samples_available(&self) -> usize {
+        self.samples.samples_available()
+    }
+
+    pub fn drain_samples(&mut self, out: &mut [f32]) -> usize {
+        self.samples.drain_samples(out)
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.dmc.irq || self.frame_counter.irq
+    }
+
+    // the reset button silences every channel like writing 0 to 0x4015, and drops the irqs.
+    // the frame counter keeps its mode
+    pub fn reset(&mut self) {
+        self.write_register(0x4015, 0);
+        self.frame_counter.irq = false;
+    }
+
+    // off and on again. the samples that haven't been drained yet, the sample rate and the
+    // filters are the frontend's, so they stay
+    pub fn power_cycle(&mut self, tv_system: TvSystem) {
+        let samples = std::mem::take(&mut self.samples);
+        *self = Apu {
+            samples,
+            ..Apu::new()
+        };
+        self.set_tv_system(tv_system);
+    }
+
+    fn clock_frame(&mut self, clocks: FrameClocks) {
+        if clocks.quarter {
+            self.clock_quarter_frame();
+        }
+        if clocks.half {
+            self.clock_half_frame();
+        }
+    }
+
+    // envelopes and the triangle's linear counter
+    pub fn clock_quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear_counter();
+    }
+
+    // length counters and sweeps
+    pub fn clock_half_frame(&mut self) {
+        self.pulse1.length.clock();
+        self.pulse2.length.clock();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+        self.pulse1.clock_sweep();
+        self.pulse2.clock_sweep();
+    }
+
+    // the mixed level of every channel right now, 0.0 - ~1.0
+    pub fn output(&self) -> f32 {
+        mixer::mix(
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // pulse 1 on at constant volume 15 with `duty` and an 11 bit timer `period`
+    fn pulse1(duty: u8, period: u16) -> Apu {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4000, (duty << 6) | 0b0001_1111);
+        apu.write_register(0x4002, period as u8);
+        apu.write_register(0x4003, (period >> 8) as u8);
+        apu
+    }
+
+    // the first channel's level after every cpu cycle
+    fn run(apu: &mut Apu, cycles: usize) -> Vec<u8> {
+        (0..cycles)
+            .map(|_| {
+                apu.tick(1);
+                apu.pulse1.output()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_duty_cycles() {
+        // a step is 2 * (period + 1) cpu cycles, a whole waveform 8 steps
+        let step = 2 * (8 + 1);
+        for (duty, high_steps) in [(0, 1), (1, 2), (2, 4), (3, 6)] {
+            let mut apu = pulse1(duty, 8);
+            let levels = run(&mut apu, 8 * step);
+
+            let high = levels.iter().filter(|&&level| level == 15).count();
+            assert_eq!(high, high_steps * step, "duty {}", duty);
+        }
+    }
+
+    #[test]
+    fn test_waveform_order() {
+        let step = 2 * (8 + 1);
+        let mut apu = pulse1(1, 8);
+        let levels = run(&mut apu, 16 * step);
+
+        // one sample from the middle of every step. the timer starts out expired, so the first
+        // apu cycle already moves off the low step 0 of the 25% wave
+        let samples: Vec<u8> = levels
+            .iter()
+            .skip(step / 2)
+            .step_by(step)
+            .copied()
+            .collect();
+        assert_eq!(
+            samples,
+            [15, 15, 0, 0, 0, 0, 0, 0, 15, 15, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_short_period_is_muted() {
+        let mut apu = pulse1(2, 7);
+        assert!(run(&mut apu, 200).iter().all(|&level| level == 0));
+    }
+
+    #[test]
+    fn test_sweep_overflow_mutes_even_when_disabled() {
+        let mut apu = pulse1(2, 0x600);
+        // shift 1 up: 0x600 + 0x300 is past 11 bits
+        apu.write_register(0x4001, 0b0000_0001);
+
+        assert!(run(&mut apu, 8000).iter().all(|&level| level == 0));
+    }
+
+    #[test]
+    fn test_sweep_moves_the_period() {
+        let mut apu = pulse1(2, 0x100);
+        apu.write_register(0x4005, 0);
+        apu.write_register(0x4006, 0x00);
+        apu.write_register(0x4007, 0x01);
+        // both negate by 1 >> a shift of 1, pulse 1 one further
+        apu.write_register(0x4001, 0b1000_1001);
+        apu.write_register(0x4005, 0b1000_1001);
+
+        apu.clock_half_frame();
+        assert_eq!(apu.pulse1.period(), 0x100 - 0x80 - 1);
+        assert_eq!(apu.pulse2.period(), 0x100 - 0x80);
+    }
+
+    #[test]
+    fn test_disabling_clears_the_length_counter() {
+        let mut apu = pulse1(2, 8);
+        assert_eq!(apu.read_status() & 0b01, 0b01);
+
+        apu.write_register(0x4015, 0);
+        assert_eq!(apu.read_status() & 0b01, 0);
+        assert_eq!(apu.pulse1.length.counter(), 0);
+
+        // and loads are ignored while it's off
+        apu.write_register(0x4003, 0);
+        assert_eq!(apu.read_status() & 0b01, 0);
+    }
+
+    #[test]
+    fn test_length_counter_runs_out() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4000, 0b0001_1111);
+        // index 3 loads 2
+        apu.write_register(0x4003, 3 << 3);
+
+        apu.clock_half_frame();
+        assert_eq!(apu.read_status() & 0b01, 0b01);
+        apu.clock_half_frame();
+        assert_eq!(apu.read_status() & 0b01, 0);
+    }
+
+    #[test]
+    fn test_mixer_output() {
+        let apu = pulse1(3, 8);
+        // duty 3 starts high. the silent triangle still sits at 15, which is in there too
+        let pulse = 95.88 / (8128.0 / 15.0 + 100.0);
+        let triangle = 159.79 / (8227.0 / 15.0 + 100.0);
+        assert!((apu.output() - pulse - triangle).abs() < 1e-6);
+        assert_eq!(mixer::mix(0, 0, 0, 0, 0), 0.0);
+    }
+
+    #[test]
+    fn test_mixer_triangle_and_noise() {
+        let tnd = 15.0 / 8227.0 + 15.0 / 12241.0 + 127.0 / 22638.0;
+        assert!((mixer::mix(0, 0, 15, 15, 127) - 159.79 / (1.0 / tnd + 100.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_status_reflects_every_length_counter() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b1111);
+        apu.write_register(0x400b, 0);
+        assert_eq!(apu.read_status(), 0b0100);
+        apu.write_register(0x400f, 0);
+        assert_eq!(apu.read_status(), 0b1100);
+        apu.write_register(0x4003, 0);
+        apu.write_register(0x4007, 0);
+        assert_eq!(apu.read_status(), 0b1111);
+
+        apu.write_register(0x4015, 0b0011);
+        assert_eq!(apu.read_status(), 0b0011);
+    }
+
+    #[test]
+    fn test_four_step_sequence_clocks_length_twice() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4000, 0b0001_1111);
+        // index 1 loads 254
+        apu.write_register(0x4003, 1 << 3);
+
+        apu.tick(29829);
+
+        assert_eq!(apu.pulse1.length.counter(), 252);
+    }
+
+    #[test]
+    fn test_frame_irq_at_the_end_of_the_sequence() {
+        let mut apu = Apu::new();
+        apu.tick(29828);
+        assert!(!apu.irq_pending());
+
+        apu.tick(1);
+        assert!(apu.irq_pending());
+        assert_eq!(apu.read_status() & 0b0100_0000, 0b0100_0000);
+        assert!(!apu.irq_pending());
+    }
+
+    #[test]
+    fn test_pal_frame_irq_is_later() {
+        let mut apu = Apu::new();
+        apu.set_tv_system(TvSystem::Pal);
+        apu.tick(33252);
+        assert!(!apu.irq_pending());
+
+        apu.tick(1);
+        assert!(apu.irq_pending());
+    }
+
+    #[test]
+    fn test_irq_inhibit_clears_and_suppresses() {
+        let mut apu = Apu::new();
+        apu.tick(29829);
+        assert!(apu.irq_pending());
+
+        apu.write_register(0x4017, 0b0100_0000);
+        assert!(!apu.irq_pending());
+        apu.tick(29829 * 2);
+        assert!(!apu.irq_pending());
+    }
+
+    #[test]
+    fn test_reset_silences_and_drops_irqs() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4003, 1 << 3);
+        apu.tick(29829);
+        assert!(apu.irq_pending());
+
+        apu.reset();
+
+        assert!(!apu.irq_pending());
+        assert_eq!(apu.peek_status(), 0);
+    }
+
+    #[test]
+    fn test_five_step_mode() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4000, 0b0001_1111);
+        apu.write_register(0x4003, 1 << 3);
+
+        // clocks a half frame on the write, and never raises an irq
+        apu.write_register(0x4017, 0b1000_0000);
+        assert_eq!(apu.pulse1.length.counter(), 253);
+        apu.tick(37281);
+        assert_eq!(apu.pulse1.length.counter(), 251);
+        assert!(!apu.irq_pending());
+    }
+
+    #[test]
+    fn test_sample_count() {
+        let mut apu = Apu::new();
+        apu.set_sample_rate(48_000);
+
+        apu.tick(60_000);
+
+        assert_eq!(
+            apu.samples_available(),
+            60_000 * 48_000 / output::CPU_FREQUENCY as usize
+        );
+    }
+
+    #[test]
+    fn test_constant_level_high_passes_to_zero() {
+        let mut apu = Apu::new();
+        // nothing but the dmc's level and the resting triangle, both constant
+        apu.write_register(0x4011, 64);
+        let mut out = vec![0.0; 8192];
+
+        for _ in 0..20 {
+            apu.tick(20_000);
+        }
+        let count = apu.drain_samples(&mut out);
+        assert!(out[count - 1].abs() < 0.001);
+
+        apu.set_filters_enabled(false);
+        apu.tick(20_000);
+        let count = apu.drain_samples(&mut out);
+        assert!(out[count - 1] > 0.1);
+    }
+}
@@ -0,0 +1,186 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use crate::rom::TvSystem;
+
+// in cpu cycles
+const PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+// the same pitches with pal's slower cpu
+const PAL_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+fn period_table(tv_system: TvSystem) -> &'static [u16; 16] {
+    match tv_system {
+        TvSystem::Ntsc => &PERIOD_TABLE,
+        TvSystem::Pal => &PAL_PERIOD_TABLE,
+    }
+}
+
+// 0x400c - 0x400f, pseudo random bits out of a 15 bit lfsr, see
+// https://www.nesdev.org/wiki/APU_Noise
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Noise {
+    // the short mode taps bit 6 instead of bit 1, which repeats every 93 (or 31) clocks
+    short_mode: bool,
+    tv_system: TvSystem,
+    period: u16,
+    timer: u16,
+    shift_register: u16,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            short_mode: false,
+            tv_system: TvSystem::Ntsc,
+            period: PERIOD_TABLE[0],
+            timer: 0,
+            // loaded with 1 at power on
+            shift_register: 1,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    // `register` is 0 - 3, 1 (0x400d) is unused
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            // --LC VVVV
+            0 => {
+                self.length.set_halt(value & 0b0010_0000 != 0);
+                self.envelope.write(value);
+            }
+            // M--- PPPP
+            2 => {
+                self.short_mode = value & 0b1000_0000 != 0;
+                self.period = period_table(self.tv_system)[(value & 0b1111) as usize];
+            }
+            // LLLL L---
+            3 => {
+                self.length.load(value >> 3);
+                self.envelope.restart();
+            }
+            _ => {}
+        }
+    }
+
+    // a period already written moves to the same entry in the other table
+    pub fn set_tv_system(&mut self, tv_system: TvSystem) {
+        let old = period_table(self.tv_system);
+        if let Some(index) = old.iter().position(|&period| period == self.period) {
+            self.period = period_table(tv_system)[index];
+        }
+        self.tv_system = tv_system;
+    }
+
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            self.clock_shift_register();
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_shift_register(&mut self) {
+        let tap = if self.short_mode { 6 } else { 1 };
+        let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+        self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+    }
+
+    // 0 - 15, silent whenever bit 0 of the shift register is set
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.shift_register & 1 == 1 {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // clocks until the shift register comes back around
+    fn sequence_length(noise: &mut Noise) -> usize {
+        let start = noise.shift_register;
+        (1..=0x8000)
+            .find(|_| {
+                noise.clock_shift_register();
+                noise.shift_register == start
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_short_mode_repeats_after_93() {
+        let mut noise = Noise::new();
+        noise.write(2, 0b1000_0000);
+
+        assert_eq!(sequence_length(&mut noise), 93);
+    }
+
+    #[test]
+    fn test_long_mode_repeats_after_32767() {
+        let mut noise = Noise::new();
+
+        assert_eq!(sequence_length(&mut noise), 32767);
+    }
+
+    #[test]
+    fn test_period_table() {
+        let mut noise = Noise::new();
+        noise.write(2, 0x03);
+        let start = noise.shift_register;
+
+        // the timer starts out expired, after that every 32 cycles
+        noise.clock_timer();
+        let first = noise.shift_register;
+        assert_ne!(first, start);
+        for _ in 0..31 {
+            noise.clock_timer();
+        }
+        assert_eq!(noise.shift_register, first);
+        noise.clock_timer();
+        assert_ne!(noise.shift_register, first);
+    }
+
+    // clocks from the next shift to the one after
+    fn period(noise: &mut Noise) -> usize {
+        let mut shift = || {
+            let start = noise.shift_register;
+            1 + (0..5000)
+                .position(|_| {
+                    noise.clock_timer();
+                    noise.shift_register != start
+                })
+                .unwrap()
+        };
+        shift();
+        shift()
+    }
+
+    #[test]
+    fn test_pal_period_table() {
+        let mut noise = Noise::new();
+        noise.set_tv_system(TvSystem::Pal);
+        noise.write(2, 0x03);
+        assert_eq!(period(&mut noise), 30);
+        noise.write(2, 0x0f);
+        assert_eq!(period(&mut noise), 3778);
+
+        // what was written carries over
+        noise.set_tv_system(TvSystem::Ntsc);
+        assert_eq!(period(&mut noise), 4068);
+    }
+}
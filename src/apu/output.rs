@@ -0,0 +1,183 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+// the ntsc cpu clock, the apu produces a level every cycle of it
+pub const CPU_FREQUENCY: u32 = 1_789_773;
+pub const PAL_CPU_FREQUENCY: u32 = 1_662_607;
+const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+// the console's own output filtering, roughly
+const HIGH_PASS_HZ: f32 = 90.0;
+const LOW_PASS_HZ: f32 = 14_000.0;
+
+// averages the per cycle levels down to `sample_rate` and keeps up to a second of them for
+// the frontend to drain
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct ApuOutput {
+    sample_rate: u32,
+    // CPU_FREQUENCY, or PAL_CPU_FREQUENCY
+    cpu_frequency: u32,
+    // goes up by sample_rate every cycle, a sample is due every cpu_frequency. all integers,
+    // so the sample count never drifts from the cycle count
+    phase: u32,
+    sum: f32,
+    summed: u32,
+    filters_enabled: bool,
+    high_pass_alpha: f32,
+    high_pass_previous_in: f32,
+    high_pass_previous_out: f32,
+    low_pass_alpha: f32,
+    low_pass_previous_out: f32,
+    // samples already made belong to the frontend, a restored state starts with none queued
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    buffer: VecDeque<f32>,
+}
+
+impl Default for ApuOutput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ApuOutput {
+    pub fn new() -> Self {
+        let mut output = ApuOutput {
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            cpu_frequency: CPU_FREQUENCY,
+            phase: 0,
+            sum: 0.0,
+            summed: 0,
+            filters_enabled: true,
+            high_pass_alpha: 0.0,
+            high_pass_previous_in: 0.0,
+            high_pass_previous_out: 0.0,
+            low_pass_alpha: 0.0,
+            low_pass_previous_out: 0.0,
+            buffer: VecDeque::new(),
+        };
+        output.set_sample_rate(DEFAULT_SAMPLE_RATE);
+        output
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.phase = 0;
+
+        let dt = 1.0 / sample_rate as f32;
+        let high_pass_rc = 1.0 / (2.0 * PI * HIGH_PASS_HZ);
+        let low_pass_rc = 1.0 / (2.0 * PI * LOW_PASS_HZ);
+        self.high_pass_alpha = high_pass_rc / (high_pass_rc + dt);
+        self.low_pass_alpha = dt / (low_pass_rc + dt);
+
+        self.buffer = VecDeque::with_capacity(sample_rate as usize);
+    }
+
+    pub fn set_cpu_frequency(&mut self, cpu_frequency: u32) {
+        self.cpu_frequency = cpu_frequency;
+        self.phase = 0;
+    }
+
+    pub fn set_filters_enabled(&mut self, enabled: bool) {
+        self.filters_enabled = enabled;
+    }
+
+    // one cpu cycle's worth of mixed output
+    pub fn push(&mut self, level: f32) {
+        self.sum += level;
+        self.summed += 1;
+
+        self.phase += self.sample_rate;
+        if self.phase >= self.cpu_frequency {
+            self.phase -= self.cpu_frequency;
+            let sample = self.sum / self.summed as f32;
+            self.sum = 0.0;
+            self.summed = 0;
+            self.emit(sample);
+        }
+    }
+
+    fn emit(&mut self, sample: f32) {
+        let sample = if self.filters_enabled {
+            self.filter(sample)
+        } else {
+            sample
+        };
+
+        // nobody's draining, drop the oldest
+        if self.buffer.len() == self.sample_rate as usize {
+            self.buffer.pop_front();
+        }
+        self.buffer.push_back(sample);
+    }
+
+    // first order high pass to take out the dc, then low pass
+    fn filter(&mut self, sample: f32) -> f32 {
+        let high_passed = self.high_pass_alpha
+            * (self.high_pass_previous_out + sample - self.high_pass_previous_in);
+        self.high_pass_previous_in = sample;
+        self.high_pass_previous_out = high_passed;
+
+        self.low_pass_previous_out +=
+            self.low_pass_alpha * (high_passed - self.low_pass_previous_out);
+        self.low_pass_previous_out
+    }
+
+    pub fn samples_available(&self) -> usize {
+        self.buffer.len()
+    }
+
+    // copies out as many of the oldest samples as fit, returning how many
+    pub fn drain_samples(&mut self, out: &mut [f32]) -> usize {
+        let count = out.len().min(self.buffer.len());
+        for (slot, sample) in out.iter_mut().zip(self.buffer.drain(..count)) {
+            *slot = sample;
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_drift_over_a_minute() {
+        let mut output = ApuOutput::new();
+        output.set_filters_enabled(false);
+        let mut out = vec![0.0; 4096];
+        let mut samples = 0;
+
+        for second in 0..60 {
+            for _ in 0..CPU_FREQUENCY {
+                output.push(0.5);
+            }
+            while output.samples_available() > 0 {
+                samples += output.drain_samples(&mut out);
+            }
+            assert_eq!(samples, (second + 1) * 44_100);
+        }
+    }
+
+    #[test]
+    fn test_pal_second_makes_a_second_of_samples() {
+        let mut output = ApuOutput::new();
+        output.set_cpu_frequency(PAL_CPU_FREQUENCY);
+        output.set_sample_rate(1000);
+        for _ in 0..PAL_CPU_FREQUENCY {
+            output.push(0.5);
+        }
+
+        assert_eq!(output.samples_available(), 1000);
+    }
+
+    #[test]
+    fn test_buffer_keeps_the_newest_second() {
+        let mut output = ApuOutput::new();
+        output.set_sample_rate(1000);
+        for _ in 0..CPU_FREQUENCY * 2 {
+            output.push(0.5);
+        }
+
+        assert_eq!(output.samples_available(), 1000);
+    }
+}
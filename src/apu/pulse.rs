@@ -0,0 +1,132 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+
+// each row is the waveform of one duty setting, stepped through left to right
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+// a square wave channel, 0x4000 - 0x4003 for the first and 0x4004 - 0x4007 for the second,
+// see https://www.nesdev.org/wiki/APU_Pulse
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pulse {
+    // the first channel's sweep negates with one's complement, so it goes one lower
+    ones_complement: bool,
+    duty: u8,
+    step: u8,
+    // 11 bits, the timer is clocked every other cpu cycle
+    period: u16,
+    timer: u16,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    pub fn new(ones_complement: bool) -> Self {
+        Pulse {
+            ones_complement,
+            duty: 0,
+            step: 0,
+            period: 0,
+            timer: 0,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    // `register` is 0 - 3, which of the channel's four it is
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            // DDLC VVVV
+            0 => {
+                self.duty = value >> 6;
+                self.length.set_halt(value & 0b0010_0000 != 0);
+                self.envelope.write(value);
+            }
+            // EPPP NSSS
+            1 => {
+                self.sweep_enabled = value & 0b1000_0000 != 0;
+                self.sweep_period = (value >> 4) & 0b111;
+                self.sweep_negate = value & 0b1000 != 0;
+                self.sweep_shift = value & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            // LLLL LTTT
+            _ => {
+                self.period = (self.period & 0x00ff) | ((value as u16 & 0b111) << 8);
+                self.length.load(value >> 3);
+                self.envelope.restart();
+                self.step = 0;
+            }
+        }
+    }
+
+    // one apu cycle, every other cpu cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // the period the sweep would move to. the channel is muted when that's out of range, even
+    // with the sweep off
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if self.sweep_negate {
+            self.period
+                .saturating_sub(change + self.ones_complement as u16)
+        } else {
+            self.period + change
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.period < 8 || self.sweep_target() > 0x7ff
+    }
+
+    pub fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift != 0 && !self.muted() {
+            self.period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    // 0 - 15
+    pub fn output(&self) -> u8 {
+        if !self.length.active()
+            || self.muted()
+            || DUTY_TABLE[self.duty as usize][self.step as usize] == 0
+        {
+            return 0;
+        }
+        self.envelope.output()
+    }
+
+    pub fn period(&self) -> u16 {
+        self.period
+    }
+}
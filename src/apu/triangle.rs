@@ -0,0 +1,161 @@
+use super::length_counter::LengthCounter;
+
+#[rustfmt::skip]
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+// 0x4008 - 0x400b, no volume control, see https://www.nesdev.org/wiki/APU_Triangle
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Triangle {
+    step: u8,
+    // 11 bits, clocked every cpu cycle
+    period: u16,
+    timer: u16,
+    pub length: LengthCounter,
+    // the linear counter is a second, finer length counter clocked every quarter frame
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    // periods of 0 and 1 step the wave far above hearing, which only comes out as pops. off
+    // means the wave holds still there instead
+    pub allow_ultrasonic: bool,
+}
+
+impl Default for Triangle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Triangle {
+            step: 0,
+            period: 0,
+            timer: 0,
+            length: LengthCounter::default(),
+            control: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false,
+            allow_ultrasonic: false,
+        }
+    }
+
+    // `register` is 0 - 3, 1 (0x4009) is unused
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            // CRRR RRRR, the control bit doubles as the length counter halt
+            0 => {
+                self.control = value & 0b1000_0000 != 0;
+                self.length.set_halt(self.control);
+                self.linear_reload_value = value & 0b0111_1111;
+            }
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            // LLLL LTTT
+            3 => {
+                self.period = (self.period & 0x00ff) | ((value as u16 & 0b111) << 8);
+                self.length.load(value >> 3);
+                self.linear_reload = true;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            let ultrasonic = self.period < 2 && !self.allow_ultrasonic;
+            if self.length.active() && self.linear_counter > 0 && !ultrasonic {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_linear_counter(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    // 0 - 15. a silenced triangle stops where it is rather than dropping to 0
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.step as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // on, with the linear counter loaded to `linear` and a period of 2
+    fn triangle(linear: u8) -> Triangle {
+        let mut triangle = Triangle::new();
+        triangle.length.set_enabled(true);
+        triangle.write(0, linear);
+        triangle.write(2, 2);
+        triangle.write(3, 0);
+        triangle.clock_linear_counter();
+        triangle
+    }
+
+    // the level after each step, a step being period + 1 cycles
+    fn steps(triangle: &mut Triangle, count: usize) -> Vec<u8> {
+        (0..count)
+            .map(|_| {
+                for _ in 0..3 {
+                    triangle.clock_timer();
+                }
+                triangle.output()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sequence_order() {
+        let mut triangle = triangle(0x7f);
+        let levels = steps(&mut triangle, 32);
+
+        let mut expected: Vec<u8> = SEQUENCE[1..].to_vec();
+        expected.push(SEQUENCE[0]);
+        assert_eq!(levels, expected);
+    }
+
+    #[test]
+    fn test_linear_counter_gates_the_sequencer() {
+        let mut triangle = triangle(2);
+        // control is clear, so the reload flag drops after the first clock
+        triangle.clock_linear_counter();
+        assert_eq!(steps(&mut triangle, 1), [14]);
+
+        triangle.clock_linear_counter();
+        let held = steps(&mut triangle, 4);
+        assert_eq!(held, [14, 14, 14, 14]);
+    }
+
+    #[test]
+    fn test_ultrasonic_periods() {
+        let mut triangle = triangle(0x7f);
+        triangle.write(2, 1);
+        for _ in 0..20 {
+            triangle.clock_timer();
+        }
+        assert_eq!(triangle.output(), 15);
+
+        triangle.allow_ultrasonic = true;
+        for _ in 0..20 {
+            triangle.clock_timer();
+        }
+        assert_ne!(triangle.output(), 15);
+    }
+}
@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::bus::DEFAULT_ORIGIN;
+use crate::cpu::AddressingMode;
+use crate::opcode::{OpCode, CPU_OP_CODES};
+
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    // 1 based, like an editor
+    pub line: usize,
+    pub kind: AsmErrorKind,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AsmErrorKind {
+    UnknownMnemonic(String),
+    // the mnemonic exists but not with this operand
+    InvalidAddressingMode(String),
+    InvalidOperand(String),
+    UnknownLabel(String),
+    DuplicateLabel(String),
+    // how far the target is from the end of the branch, which has to fit in an i8
+    BranchOutOfRange(i32),
+    // a value too big for the byte it has to go in
+    ValueOutOfRange(u16),
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            AsmErrorKind::UnknownMnemonic(m) => write!(f, "Unknown mnemonic {}", m),
+            AsmErrorKind::InvalidAddressingMode(m) => {
+                write!(f, "{} doesn't take that operand", m)
+            }
+            AsmErrorKind::InvalidOperand(o) => write!(f, "Can't parse operand {}", o),
+            AsmErrorKind::UnknownLabel(l) => write!(f, "Unknown label {}", l),
+            AsmErrorKind::DuplicateLabel(l) => write!(f, "Label {} is already defined", l),
+            AsmErrorKind::BranchOutOfRange(d) => {
+                write!(
+                    f,
+                    "Branch target is {} bytes away, the limit is -128 to 127",
+                    d
+                )
+            }
+            AsmErrorKind::ValueOutOfRange(v) => write!(f, "${:x} doesn't fit in a byte", v),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(u16),
+    Label(String),
+}
+
+// an operand after the first pass, in the form the instruction was written
+enum Operand {
+    None,
+    Accumulator,
+    Immediate(Value),
+    // plain, ,X and ,Y. labels are always absolute
+    Direct(Value, Index),
+    IndirectX(Value),
+    IndirectY(Value),
+    Indirect(Value),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Index {
+    None,
+    X,
+    Y,
+}
+
+enum Item {
+    Instruction {
+        address: u16,
+        opcode: &'static OpCode,
+        value: Option<Value>,
+    },
+    Bytes(Vec<Value>),
+    Words(Vec<Value>),
+}
+
+// `source` assembled to run from 0x8000, which is where CPU::load puts it:
+//
+// loop:  INX          ; labels end in a colon
+//        CPX #$10
+//        BNE loop
+//        .byte $01, $02
+//        .word loop
+//
+// operands are written like the disassembler shows them, $ for hex, % for binary and plain
+// numbers are decimal
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    assemble_at(source, DEFAULT_ORIGIN)
+}
+
+pub fn assemble_at(source: &str, origin: u16) -> Result<Vec<u8>, AsmError> {
+    // first pass: work out the size of everything so labels get their addresses
+    let mut labels = HashMap::new();
+    let mut items = vec![];
+    let mut address = origin;
+
+    for (number, line) in source.lines().enumerate() {
+        let number = number + 1;
+        let error = |kind| AsmError { line: number, kind };
+
+        let mut line = line.split(';').next().unwrap().trim();
+        if let Some((label, rest)) = line.split_once(':') {
+            let label = label.trim();
+            if !is_label(label) {
+                return Err(error(AsmErrorKind::InvalidOperand(label.to_string())));
+            }
+            if labels.insert(label.to_string(), address).is_some() {
+                return Err(error(AsmErrorKind::DuplicateLabel(label.to_string())));
+            }
+            line = rest.trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operand) = match line.split_once(char::is_whitespace) {
+            Some((mnemonic, operand)) => (mnemonic, operand.trim()),
+            None => (line, ""),
+        };
+        let mnemonic = mnemonic.to_ascii_uppercase();
+
+        let item = match mnemonic.as_str() {
+            ".BYTE" => Item::Bytes(parse_list(operand).map_err(error)?),
+            ".WORD" => Item::Words(parse_list(operand).map_err(error)?),
+            _ => {
+                let operand = parse_operand(operand).map_err(error)?;
+                let (opcode, value) = select_opcode(&mnemonic, operand).map_err(error)?;
+                Item::Instruction {
+                    address,
+                    opcode,
+                    value,
+                }
+            }
+        };
+        address = address.wrapping_add(match &item {
+            Item::Instruction { opcode, .. } => opcode.bytes as u16,
+            Item::Bytes(values) => values.len() as u16,
+            Item::Words(values) => values.len() as u16 * 2,
+        });
+        items.push((number, item));
+    }
+
+    // second pass: everything has an address now
+    let mut result = vec![];
+    for (number, item) in items {
+        let error = |kind| AsmError { line: number, kind };
+        let resolve = |value: &Value| match value {
+            Value::Number(n) => Ok(*n),
+            Value::Label(label) => labels
+                .get(label)
+                .copied()
+                .ok_or_else(|| error(AsmErrorKind::UnknownLabel(label.clone()))),
+        };
+        let byte = |value: u16| {
+            u8::try_from(value).map_err(|_| error(AsmErrorKind::ValueOutOfRange(value)))
+        };
+
+        match item {
+            Item::Instruction {
+                address,
+                opcode,
+                value,
+            } => {
+                result.push(opcode.hex);
+                let Some(value) = value else { continue };
+                let value = resolve(&value)?;
+                if opcode.mode == AddressingMode::Relative {
+                    let distance = value as i32 - (address as i32 + 2);
+                    let offset = i8::try_from(distance)
+                        .map_err(|_| error(AsmErrorKind::BranchOutOfRange(distance)))?;
+                    result.push(offset as u8);
+                } else if opcode.bytes == 2 {
+                    result.push(byte(value)?);
+                } else {
+                    result.extend(value.to_le_bytes());
+                }
+            }
+            Item::Bytes(values) => {
+                for value in values {
+                    result.push(byte(resolve(&value)?)?);
+                }
+            }
+            Item::Words(values) => {
+                for value in values {
+                    result.extend(resolve(&value)?.to_le_bytes());
+                }
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+fn is_label(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_value(s: &str) -> Result<Value, AsmErrorKind> {
+    let s = s.trim();
+    let invalid = || AsmErrorKind::InvalidOperand(s.to_string());
+    let number = if let Some(hex) = s.strip_prefix('$') {
+        u16::from_str_radix(hex, 16)
+    } else if let Some(binary) = s.strip_prefix('%') {
+        u16::from_str_radix(binary, 2)
+    } else if s.starts_with(|c: char| c.is_ascii_digit()) {
+        s.parse()
+    } else if is_label(s) {
+        return Ok(Value::Label(s.to_string()));
+    } else {
+        return Err(invalid());
+    };
+    number.map(Value::Number).map_err(|_| invalid())
+}
+
+fn parse_list(s: &str) -> Result<Vec<Value>, AsmErrorKind> {
+    s.split(',').map(parse_value).collect()
+}
+
+fn parse_operand(s: &str) -> Result<Operand, AsmErrorKind> {
+    let upper = s.to_ascii_uppercase();
+    let indexed = |suffix: &str| {
+        upper
+            .strip_suffix(suffix)
+            .map(|_| &s[..s.len() - suffix.len()])
+    };
+
+    if s.is_empty() {
+        return Ok(Operand::None);
+    }
+    if upper == "A" {
+        return Ok(Operand::Accumulator);
+    }
+    if let Some(value) = s.strip_prefix('#') {
+        return Ok(Operand::Immediate(parse_value(value)?));
+    }
+    if s.starts_with('(') {
+        if let Some(inner) = indexed(",X)") {
+            return Ok(Operand::IndirectX(parse_value(&inner[1..])?));
+        }
+        if let Some(inner) = indexed("),Y") {
+            return Ok(Operand::IndirectY(parse_value(&inner[1..])?));
+        }
+        if let Some(inner) = s.strip_suffix(')') {
+            return Ok(Operand::Indirect(parse_value(&inner[1..])?));
+        }
+        return Err(AsmErrorKind::InvalidOperand(s.to_string()));
+    }
+    if let Some(value) = indexed(",X") {
+        return Ok(Operand::Direct(parse_value(value)?, Index::X));
+    }
+    if let Some(value) = indexed(",Y") {
+        return Ok(Operand::Direct(parse_value(value)?, Index::Y));
+    }
+    Ok(Operand::Direct(parse_value(s)?, Index::None))
+}
+
+fn find(mnemonic: &str, mode: AddressingMode) -> Option<&'static OpCode> {
+    CPU_OP_CODES
+        .iter()
+        .find(|op| op.mnemonic == mnemonic && op.mode == mode)
+}
+
+// the opcode for `mnemonic` written with `operand`, preferring zero page when the value fits
+fn select_opcode(
+    mnemonic: &str,
+    operand: Operand,
+) -> Result<(&'static OpCode, Option<Value>), AsmErrorKind> {
+    if !CPU_OP_CODES.iter().any(|op| op.mnemonic == mnemonic) {
+        return Err(AsmErrorKind::UnknownMnemonic(mnemonic.to_string()));
+    }
+    let invalid = || AsmErrorKind::InvalidAddressingMode(mnemonic.to_string());
+    let with = |mode, value| {
+        find(mnemonic, mode)
+            .map(|op| (op, value))
+            .ok_or_else(invalid)
+    };
+
+    match operand {
+        // ASL and friends can be written without the A
+        Operand::None => with(AddressingMode::NoneAddressing, None)
+            .or_else(|_| with(AddressingMode::Accumulator, None)),
+        Operand::Accumulator => with(AddressingMode::Accumulator, None),
+        Operand::Immediate(value) => with(AddressingMode::Immediate, Some(value)),
+        Operand::IndirectX(value) => with(AddressingMode::IndirectX, Some(value)),
+        Operand::IndirectY(value) => with(AddressingMode::IndirectY, Some(value)),
+        Operand::Indirect(value) => with(AddressingMode::Indirect, Some(value)),
+        Operand::Direct(value, index) => {
+            if index == Index::None {
+                if let Some(op) = find(mnemonic, AddressingMode::Relative) {
+                    return Ok((op, Some(value)));
+                }
+            }
+            let (zero_page, absolute) = match index {
+                Index::None => (AddressingMode::ZeroPage, AddressingMode::Absolute),
+                Index::X => (AddressingMode::ZeroPageX, AddressingMode::AbsoluteX),
+                Index::Y => (AddressingMode::ZeroPageY, AddressingMode::AbsoluteY),
+            };
+            match value {
+                Value::Number(n) if n <= 0xff => {
+                    with(zero_page, Some(value.clone())).or_else(|_| with(absolute, Some(value)))
+                }
+                _ => with(absolute, Some(value)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> AsmError {
+        assemble(source).unwrap_err()
+    }
+
+    #[test]
+    fn test_addressing_modes() {
+        let source = "
+            LDA #$10
+            LDA $10
+            LDA $10,X
+            LDX $10,Y
+            LDA $1000
+            LDA $1000,X
+            LDA $1000,Y
+            LDA ($10,X)
+            LDA ($10),Y
+            JMP ($1000)
+            ASL A
+            LSR
+            INX
+        ";
+
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![
+                0xa9, 0x10, 0xa5, 0x10, 0xb5, 0x10, 0xb6, 0x10, 0xad, 0x00, 0x10, 0xbd, 0x00, 0x10,
+                0xb9, 0x00, 0x10, 0xa1, 0x10, 0xb1, 0x10, 0x6c, 0x00, 0x10, 0x0a, 0x4a, 0xe8,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_zero_page_value_without_a_zero_page_mode() {
+        // there's no STA zp,Y, so it has to be absolute
+        assert_eq!(assemble("STA $10,Y").unwrap(), vec![0x99, 0x10, 0x00]);
+    }
+
+    #[test]
+    fn test_number_formats_and_case() {
+        assert_eq!(
+            assemble("lda #%1010\nldx #16 ; sixteen").unwrap(),
+            vec![0xa9, 0x0a, 0xa2, 0x10]
+        );
+    }
+
+    #[test]
+    fn test_forward_and_backward_labels() {
+        let source = "
+                  JSR sub
+                  BEQ done
+            loop: DEX
+                  BNE loop
+            done: BRK
+            sub:  RTS
+        ";
+
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x20, 0x09, 0x80, 0xf0, 0x03, 0xca, 0xd0, 0xfd, 0x00, 0x60]
+        );
+    }
+
+    #[test]
+    fn test_data_directives() {
+        let source = "
+            start: .byte $01, 2, %11
+                   .word $1234, start
+        ";
+
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x01, 0x02, 0x03, 0x34, 0x12, 0x00, 0x80]
+        );
+    }
+
+    #[test]
+    fn test_branch_out_of_range() {
+        let source = format!("BNE far\n.byte {}\nfar: BRK", vec!["0"; 128].join(","));
+
+        assert_eq!(
+            error(&source),
+            AsmError {
+                line: 1,
+                kind: AsmErrorKind::BranchOutOfRange(128),
+            }
+        );
+        // 127 is fine
+        let source = format!("BNE far\n.byte {}\nfar: BRK", vec!["0"; 127].join(","));
+        assert_eq!(assemble(&source).unwrap()[1], 127);
+    }
+
+    #[test]
+    fn test_errors_carry_the_line() {
+        assert_eq!(
+            error("INX\nFOO #$01"),
+            AsmError {
+                line: 2,
+                kind: AsmErrorKind::UnknownMnemonic(String::from("FOO")),
+            }
+        );
+        assert_eq!(
+            error("a: INX\na: INX").kind,
+            AsmErrorKind::DuplicateLabel(String::from("a"))
+        );
+        assert_eq!(
+            error("JMP nowhere").kind,
+            AsmErrorKind::UnknownLabel(String::from("nowhere"))
+        );
+        assert_eq!(
+            error("INX #$01").kind,
+            AsmErrorKind::InvalidAddressingMode(String::from("INX"))
+        );
+        assert_eq!(
+            error("LDA #$100").kind,
+            AsmErrorKind::ValueOutOfRange(0x100)
+        );
+        assert_eq!(
+            error("LDA ($10").to_string(),
+            "line 1: Can't parse operand ($10"
+        );
+    }
+}
@@ -0,0 +1,517 @@
+// steps through a .nes file at a prompt:
+//
+//   cargo run --bin debugger -- game.nes
+//
+// everything it does is a library call, this is just the commands and how they're printed
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::ops::RangeInclusive;
+use std::process;
+
+use nes_emulator::cpu::{StatusFlags, StepResult, StopReason, CPU};
+use nes_emulator::emulator::Emulator;
+use nes_emulator::hexdump::hexdump;
+use nes_emulator::rom::Rom;
+use nes_emulator::trace::trace;
+use nes_emulator::watchpoints::WatchKind;
+
+const HELP: &str = "\
+s [n]              step n instructions, printing each
+n                  step over a JSR, or step
+c                  continue to a breakpoint, watchpoint or BRK
+b [addr]           toggle a breakpoint at addr, or list them
+w [addr[-addr]] [r|w|rw]
+                   toggle a watchpoint, or list them
+x <addr> [len]     hexdump len bytes
+d [addr] [count]   disassemble count instructions
+r                  registers
+bt                 the JSRs that led here, innermost first, as best the stack can tell
+q                  quit
+addresses are hex, with or without $ or 0x. counts are decimal, or hex with $ or 0x
+an empty line does the last command again";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Step(usize),
+    StepOver,
+    Continue,
+    // None lists them
+    Breakpoint(Option<u16>),
+    Watchpoint(Option<(RangeInclusive<u16>, WatchKind)>),
+    Hexdump(u16, usize),
+    // None is the program counter
+    Disassemble(Option<u16>, usize),
+    Registers,
+    Backtrace,
+    Help,
+    Quit,
+}
+
+fn parse_address(s: &str) -> Result<u16, String> {
+    let hex = s
+        .strip_prefix('$')
+        .or_else(|| s.strip_prefix("0x"))
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    u16::from_str_radix(hex, 16).map_err(|_| format!("not an address: {}", s))
+}
+
+fn parse_count(s: &str) -> Result<usize, String> {
+    let parsed = match s.strip_prefix('$').or_else(|| s.strip_prefix("0x")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("not a count: {}", s))
+}
+
+fn parse_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    match s.split_once('-') {
+        Some((start, end)) => {
+            let (start, end) = (parse_address(start)?, parse_address(end)?);
+            if start > end {
+                return Err(format!("backwards range: {}", s));
+            }
+            Ok(start..=end)
+        }
+        None => {
+            let addr = parse_address(s)?;
+            Ok(addr..=addr)
+        }
+    }
+}
+
+fn parse_watch_kind(s: &str) -> Result<WatchKind, String> {
+    match s {
+        "r" => Ok(WatchKind::Read),
+        "w" => Ok(WatchKind::Write),
+        "rw" => Ok(WatchKind::ReadWrite),
+        _ => Err(format!("not r, w or rw: {}", s)),
+    }
+}
+
+fn parse_command(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (name, args) = match words.split_first() {
+        Some((name, args)) => (*name, args),
+        None => return Err("no command".to_string()),
+    };
+    let arg = |i: usize| args.get(i).copied();
+
+    let command = match name {
+        "s" => Command::Step(arg(0).map(parse_count).transpose()?.unwrap_or(1)),
+        "n" => Command::StepOver,
+        "c" => Command::Continue,
+        "b" => Command::Breakpoint(arg(0).map(parse_address).transpose()?),
+        "w" => match arg(0) {
+            Some(range) => {
+                let kind = arg(1)
+                    .map(parse_watch_kind)
+                    .transpose()?
+                    .unwrap_or(WatchKind::ReadWrite);
+                Command::Watchpoint(Some((parse_range(range)?, kind)))
+            }
+            None => Command::Watchpoint(None),
+        },
+        "x" => {
+            let addr = arg(0).ok_or("x needs an address")?;
+            let len = arg(1).map(parse_count).transpose()?.unwrap_or(64);
+            Command::Hexdump(parse_address(addr)?, len)
+        }
+        "d" => Command::Disassemble(
+            arg(0).map(parse_address).transpose()?,
+            arg(1).map(parse_count).transpose()?.unwrap_or(10),
+        ),
+        "r" => Command::Registers,
+        "bt" => Command::Backtrace,
+        "h" | "?" | "help" => Command::Help,
+        "q" => Command::Quit,
+        _ => return Err(format!("unknown command {}, h for help", name)),
+    };
+    let max_args = match command {
+        Command::Step(_) | Command::Breakpoint(_) => 1,
+        Command::Watchpoint(_) | Command::Hexdump(..) | Command::Disassemble(..) => 2,
+        _ => 0,
+    };
+    if args.len() > max_args {
+        return Err(format!("too many arguments to {}", name));
+    }
+    Ok(command)
+}
+
+// PC:C000 A:00 X:00 Y:00 SP:FD P:24 nv-bdIzc CYC:7
+fn format_registers(cpu: &CPU) -> String {
+    let flags: String = [
+        (StatusFlags::NEGATIVE, 'n'),
+        (StatusFlags::OVERFLOW, 'v'),
+        (StatusFlags::UNUSED, '-'),
+        (StatusFlags::BREAK, 'b'),
+        (StatusFlags::DECIMAL, 'd'),
+        (StatusFlags::INTERRUPT_DISABLE, 'i'),
+        (StatusFlags::ZERO, 'z'),
+        (StatusFlags::CARRY, 'c'),
+    ]
+    .iter()
+    .map(|&(flag, c)| {
+        if c != '-' && cpu.status.contains(flag) {
+            c.to_ascii_uppercase()
+        } else {
+            c
+        }
+    })
+    .collect();
+
+    format!(
+        "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X} {} CYC:{}",
+        cpu.program_counter,
+        cpu.a,
+        cpu.x,
+        cpu.y,
+        cpu.sp,
+        cpu.status.bits(),
+        flags,
+        cpu.cycles
+    )
+}
+
+fn format_stop(reason: &StopReason) -> String {
+    match reason {
+        StopReason::Halted => "halted".to_string(),
+        StopReason::Breakpoint(addr) => format!("breakpoint at ${:04X}", addr),
+        StopReason::Reached(addr) => format!("stopped at ${:04X}", addr),
+        StopReason::Watchpoint {
+            addr,
+            kind,
+            value,
+            pc,
+        } => {
+            let access = match kind {
+                WatchKind::Write => "wrote",
+                _ => "read",
+            };
+            format!(
+                "watchpoint: ${:04X} {} ${:02X} at ${:04X}",
+                pc, access, value, addr
+            )
+        }
+    }
+}
+
+fn format_watch_kind(kind: WatchKind) -> &'static str {
+    match kind {
+        WatchKind::Read => "r",
+        WatchKind::Write => "w",
+        WatchKind::ReadWrite => "rw",
+    }
+}
+
+// Ok(false) once it's time to quit
+fn execute(cpu: &mut CPU, command: &Command, out: &mut impl Write) -> io::Result<bool> {
+    match command {
+        Command::Step(count) => {
+            for _ in 0..*count {
+                writeln!(out, "{}", trace(cpu))?;
+                match cpu.step() {
+                    Ok(StepResult::Executed { .. }) => {}
+                    Ok(StepResult::Stopped(reason)) => {
+                        writeln!(out, "{}", format_stop(&reason))?;
+                        break;
+                    }
+                    Err(e) => {
+                        writeln!(out, "error: {}", e)?;
+                        break;
+                    }
+                }
+                if let Some(reason) = cpu.take_watchpoint_hit() {
+                    writeln!(out, "{}", format_stop(&reason))?;
+                    break;
+                }
+            }
+        }
+        Command::StepOver | Command::Continue => {
+            let result = match command {
+                Command::StepOver => cpu.step_over(),
+                _ => cpu.run(),
+            };
+            match result {
+                Ok(reason) => writeln!(out, "{}", format_stop(&reason))?,
+                Err(e) => writeln!(out, "error: {}", e)?,
+            }
+            writeln!(out, "{}", trace(cpu))?;
+        }
+        Command::Breakpoint(None) => {
+            for addr in cpu.breakpoints() {
+                writeln!(out, "${:04X}", addr)?;
+            }
+        }
+        Command::Breakpoint(Some(addr)) => {
+            if cpu.remove_breakpoint(*addr) {
+                writeln!(out, "removed breakpoint at ${:04X}", addr)?;
+            } else {
+                cpu.add_breakpoint(*addr);
+                writeln!(out, "breakpoint at ${:04X}", addr)?;
+            }
+        }
+        Command::Watchpoint(None) => {
+            for (addrs, kind) in cpu.watchpoints() {
+                writeln!(
+                    out,
+                    "${:04X}-${:04X} {}",
+                    addrs.start(),
+                    addrs.end(),
+                    format_watch_kind(*kind)
+                )?;
+            }
+        }
+        Command::Watchpoint(Some((addrs, kind))) => {
+            let verb = if cpu.remove_watchpoint(addrs, *kind) {
+                "removed watchpoint"
+            } else {
+                cpu.add_watchpoint(addrs.clone(), *kind);
+                "watchpoint"
+            };
+            writeln!(
+                out,
+                "{} ${:04X}-${:04X} {}",
+                verb,
+                addrs.start(),
+                addrs.end(),
+                format_watch_kind(*kind)
+            )?;
+        }
+        Command::Hexdump(addr, len) => {
+            write!(out, "{}", hexdump(&cpu.peek_range(*addr, *len), *addr))?;
+        }
+        Command::Disassemble(addr, count) => {
+            let addr = addr.unwrap_or(cpu.program_counter);
+            for instruction in cpu.disassemble_at(addr, *count) {
+                writeln!(out, "{}", instruction)?;
+            }
+        }
+        Command::Registers => writeln!(out, "{}", format_registers(cpu))?,
+        Command::Backtrace => {
+            for call in cpu.call_stack() {
+                writeln!(out, "${:04X}", call)?;
+            }
+        }
+        Command::Help => writeln!(out, "{}", HELP)?,
+        Command::Quit => return Ok(false),
+    }
+    Ok(true)
+}
+
+// reads commands until `q` or the end of `input`
+fn repl(cpu: &mut CPU, input: impl BufRead, out: &mut impl Write) -> io::Result<()> {
+    let mut last = None;
+    let mut lines = input.lines();
+    loop {
+        write!(out, "> ")?;
+        out.flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(()),
+        };
+
+        let command = if line.trim().is_empty() {
+            match last.take() {
+                Some(command) => command,
+                None => continue,
+            }
+        } else {
+            match parse_command(&line) {
+                Ok(command) => command,
+                Err(e) => {
+                    writeln!(out, "{}", e)?;
+                    continue;
+                }
+            }
+        };
+        if !execute(cpu, &command, out)? {
+            return Ok(());
+        }
+        last = Some(command);
+    }
+}
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: debugger <rom.nes>");
+            process::exit(2);
+        }
+    };
+    let emulator = fs::read(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|raw| Rom::new(&raw))
+        .and_then(Emulator::new);
+    let mut emulator = match emulator {
+        Ok(emulator) => emulator,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let cpu = emulator.cpu_mut();
+    // so `c` has somewhere to stop in a program with no breakpoints set
+    cpu.halt_on_brk = true;
+    println!("{}", trace(cpu));
+    let stdin = io::stdin();
+    if let Err(e) = repl(cpu, stdin.lock(), &mut io::stdout()) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes_emulator::asm::assemble;
+    use nes_emulator::bus::Bus;
+
+    fn cpu(source: &str) -> CPU {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(assemble(source).unwrap()).unwrap();
+        cpu.reset();
+        cpu
+    }
+
+    fn run_script(cpu: &mut CPU, script: &str) -> String {
+        let mut out = vec![];
+        repl(cpu, script.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_addresses() {
+        assert_eq!(parse_address("$c000"), Ok(0xc000));
+        assert_eq!(parse_address("0xC000"), Ok(0xc000));
+        assert_eq!(parse_address("c000"), Ok(0xc000));
+        assert_eq!(parse_address("fa"), Ok(0x00fa));
+        assert!(parse_address("10000").is_err());
+        assert!(parse_address("$").is_err());
+        assert!(parse_address("zz").is_err());
+    }
+
+    #[test]
+    fn test_counts() {
+        assert_eq!(parse_count("10"), Ok(10));
+        assert_eq!(parse_count("$10"), Ok(16));
+        assert_eq!(parse_count("0x10"), Ok(16));
+        assert!(parse_count("ten").is_err());
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(parse_command("s"), Ok(Command::Step(1)));
+        assert_eq!(parse_command("  s   5 "), Ok(Command::Step(5)));
+        assert_eq!(parse_command("c"), Ok(Command::Continue));
+        assert_eq!(parse_command("n"), Ok(Command::StepOver));
+        assert_eq!(parse_command("b"), Ok(Command::Breakpoint(None)));
+        assert_eq!(
+            parse_command("b $8004"),
+            Ok(Command::Breakpoint(Some(0x8004)))
+        );
+        assert_eq!(
+            parse_command("w fa"),
+            Ok(Command::Watchpoint(Some((
+                0xfa..=0xfa,
+                WatchKind::ReadWrite
+            ))))
+        );
+        assert_eq!(
+            parse_command("w $0200-$05ff w"),
+            Ok(Command::Watchpoint(Some((
+                0x0200..=0x05ff,
+                WatchKind::Write
+            ))))
+        );
+        assert_eq!(parse_command("x 0x0200"), Ok(Command::Hexdump(0x0200, 64)));
+        assert_eq!(parse_command("x 200 16"), Ok(Command::Hexdump(0x0200, 16)));
+        assert_eq!(parse_command("d"), Ok(Command::Disassemble(None, 10)));
+        assert_eq!(
+            parse_command("d c000 3"),
+            Ok(Command::Disassemble(Some(0xc000), 3))
+        );
+        assert_eq!(parse_command("r"), Ok(Command::Registers));
+        assert_eq!(parse_command("bt"), Ok(Command::Backtrace));
+        assert_eq!(parse_command("q"), Ok(Command::Quit));
+    }
+
+    #[test]
+    fn test_bad_commands() {
+        assert!(parse_command("").is_err());
+        assert!(parse_command("z").is_err());
+        assert!(parse_command("x").is_err());
+        assert!(parse_command("c 1").is_err());
+        assert!(parse_command("s 1 2").is_err());
+        assert!(parse_command("w 10 x").is_err());
+        assert!(parse_command("w 20-10").is_err());
+    }
+
+    #[test]
+    fn test_format_registers() {
+        let mut cpu = cpu("BRK");
+        cpu.a = 0x01;
+        cpu.y = 0xff;
+        cpu.status = StatusFlags::NEGATIVE | StatusFlags::UNUSED | StatusFlags::CARRY;
+        assert_eq!(
+            format_registers(&cpu),
+            "PC:8000 A:01 X:00 Y:FF SP:FD P:A1 Nv-bdizC CYC:7"
+        );
+    }
+
+    #[test]
+    fn test_format_stop() {
+        assert_eq!(
+            format_stop(&StopReason::Breakpoint(0x8004)),
+            "breakpoint at $8004"
+        );
+        assert_eq!(
+            format_stop(&StopReason::Watchpoint {
+                addr: 0x00fa,
+                kind: WatchKind::Write,
+                value: 0x05,
+                pc: 0x8004,
+            }),
+            "watchpoint: $8004 wrote $05 at $00FA"
+        );
+    }
+
+    #[test]
+    fn test_scripted_session() {
+        let mut cpu = cpu("
+                LDA #$05
+                LDX #$01
+                STA $fa
+                INX
+                BRK
+            ");
+        let out = run_script(&mut cpu, "s 2\nw fa w\nc\nr\nx fa 1\nq\ns\n");
+
+        assert_eq!(
+            out,
+            "> 8000  A9 05     LDA #$05                        A:00 X:00 Y:00 P:04 SP:FD\n\
+             8002  A2 01     LDX #$01                        A:05 X:00 Y:00 P:04 SP:FD\n\
+             > watchpoint $00FA-$00FA w\n\
+             > watchpoint: $8004 wrote $05 at $00FA\n\
+             8006  E8        INX                             A:05 X:01 Y:00 P:04 SP:FD\n\
+             > PC:8006 A:05 X:01 Y:00 SP:FD P:04 nv-bdIzc CYC:14\n\
+             > 00FA  05                                                |.|\n\
+             > "
+        );
+        // q stopped it before the last step
+        assert_eq!(cpu.x, 1);
+    }
+
+    #[test]
+    fn test_empty_line_repeats() {
+        let mut cpu = cpu("INX\nINX\nINX\nBRK");
+        let out = run_script(&mut cpu, "\ns\n\n\nb 8003\nbogus\n");
+
+        assert_eq!(cpu.x, 3);
+        assert!(out.contains("breakpoint at $8003"));
+        assert!(out.contains("unknown command bogus"));
+    }
+}
@@ -0,0 +1,199 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nes_emulator::emulator::Emulator;
+use nes_emulator::joypad::JoypadButton;
+use nes_emulator::render::frame::Frame;
+use nes_emulator::rom::Rom;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+
+const SCALE: u32 = 3;
+const SAMPLE_RATE: i32 = 44_100;
+// how far ahead of the speakers the emulator runs, in samples. any less and a slow frame
+// empties the queue and it crackles, any more and the sound lags behind the picture
+const AUDIO_LATENCY: u32 = SAMPLE_RATE as u32 / 20;
+
+fn button(key: Keycode) -> Option<JoypadButton> {
+    match key {
+        Keycode::Up => Some(JoypadButton::UP),
+        Keycode::Down => Some(JoypadButton::DOWN),
+        Keycode::Left => Some(JoypadButton::LEFT),
+        Keycode::Right => Some(JoypadButton::RIGHT),
+        Keycode::X => Some(JoypadButton::A),
+        Keycode::Z => Some(JoypadButton::B),
+        Keycode::Return => Some(JoypadButton::START),
+        Keycode::RShift => Some(JoypadButton::SELECT),
+        _ => None,
+    }
+}
+
+// F5 saves to <rom>.state and F7 loads it back
+#[cfg(feature = "savestate")]
+fn save_state_key(emulator: &mut Emulator, key: Keycode, rom_path: &Path) {
+    let path = rom_path.with_extension("state");
+    match key {
+        Keycode::F5 => match fs::write(&path, emulator.save_state()) {
+            Ok(()) => println!("saved {}", path.display()),
+            Err(e) => eprintln!("{}: {}", path.display(), e),
+        },
+        Keycode::F7 => match fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|state| emulator.load_state(&state).map_err(|e| e.to_string()))
+        {
+            Ok(()) => println!("loaded {}", path.display()),
+            Err(e) => eprintln!("{}: {}", path.display(), e),
+        },
+        _ => {}
+    }
+}
+
+fn run(rom_path: &Path) -> Result<(), String> {
+    let raw = fs::read(rom_path).map_err(|e| e.to_string())?;
+    let rom = Rom::new(&raw)?;
+    let filename = rom_path.file_name().unwrap_or_default().to_string_lossy();
+    let mut emulator = Emulator::with_filename(rom, &filename)?;
+    // for when there's no audio device to keep time with
+    let frame_time = Duration::from_secs_f64(1.0 / emulator.frame_rate());
+    let bus = emulator.cpu_mut().bus_mut();
+    if bus.save_ram().is_some() {
+        bus.attach_sav_file(rom_path.with_extension("sav"))
+            .map_err(|e| e.to_string())?;
+    }
+
+    let sdl = sdl2::init()?;
+    let window = sdl
+        .video()?
+        .window(
+            "NES",
+            Frame::WIDTH as u32 * SCALE,
+            Frame::HEIGHT as u32 * SCALE,
+        )
+        .position_centered()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(
+            PixelFormatEnum::RGB24,
+            Frame::WIDTH as u32,
+            Frame::HEIGHT as u32,
+        )
+        .map_err(|e| e.to_string())?;
+
+    // no sound isn't a reason not to play, it just means the clock keeps time instead
+    let audio: Option<AudioQueue<f32>> = sdl.audio().ok().and_then(|audio| {
+        let spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: Some(1024),
+        };
+        match audio.open_queue(None, &spec) {
+            Ok(queue) => Some(queue),
+            Err(e) => {
+                eprintln!("no audio: {}", e);
+                None
+            }
+        }
+    });
+    if let Some(queue) = &audio {
+        emulator
+            .cpu_mut()
+            .bus_mut()
+            .apu
+            .set_sample_rate(queue.spec().freq as u32);
+        queue.resume();
+    }
+    let mut samples = vec![];
+
+    let mut events = sdl.event_pump()?;
+    let mut next_frame = Instant::now();
+    loop {
+        for event in events.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => {
+                    let bus = emulator.cpu().bus();
+                    return bus.flush().map_err(|e| e.to_string());
+                }
+                Event::KeyDown {
+                    keycode: Some(key),
+                    repeat: false,
+                    ..
+                } => {
+                    if let Some(button) = button(key) {
+                        let joypad = emulator.cpu_mut().bus_mut().joypad1_mut();
+                        joypad.set_button_pressed_status(button, true);
+                    }
+                    #[cfg(feature = "savestate")]
+                    save_state_key(&mut emulator, key, rom_path);
+                }
+                Event::KeyUp {
+                    keycode: Some(key), ..
+                } => {
+                    if let Some(button) = button(key) {
+                        let joypad = emulator.cpu_mut().bus_mut().joypad1_mut();
+                        joypad.set_button_pressed_status(button, false);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let frame = emulator.run_until_frame().map_err(|e| e.to_string())?;
+        texture
+            .update(None, &frame.data, Frame::WIDTH * 3)
+            .map_err(|e| e.to_string())?;
+        canvas.copy(&texture, None, None)?;
+        canvas.present();
+
+        let apu = &mut emulator.cpu_mut().bus_mut().apu;
+        samples.resize(apu.samples_available(), 0.0);
+        let drained = apu.drain_samples(&mut samples);
+        match &audio {
+            // the apu makes samples at exactly the rate they're played, so waiting for the
+            // queue to drain keeps the emulator at the right speed without drifting
+            Some(queue) => {
+                queue.queue_audio(&samples[..drained])?;
+                let latency = AUDIO_LATENCY * std::mem::size_of::<f32>() as u32;
+                while queue.size() > latency {
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+            None => {
+                next_frame += frame_time;
+                let now = Instant::now();
+                if next_frame > now {
+                    thread::sleep(next_frame - now);
+                } else {
+                    // too far behind to catch up, don't try to
+                    next_frame = now;
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: nes <rom.nes>");
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(Path::new(&path)) {
+        eprintln!("{}: {}", path, e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::watchpoints::WatchKind;
+
+// why run_with_callback came back, or step didn't execute anything
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    // hit BRK, or `halted` was set
+    Halted,
+    // about to fetch from a breakpoint, nothing there has been executed yet
+    Breakpoint(u16),
+    // got to where run_to or step_over was going
+    Reached(u16),
+    // the instruction at `pc` made a `kind` access (Read or Write) of `value` at `addr`. it
+    // has finished, so a write is already in memory
+    Watchpoint {
+        addr: u16,
+        kind: WatchKind,
+        value: u8,
+        pc: u16,
+    },
+}
+
+// the cpu checks these before every fetch, so the cost matters more than anything else here.
+// with nothing set it's two compares an instruction, see the throughput test. once there are
+// some it's a binary search of a sorted Vec, which for the handful anyone sets by hand is a
+// few compares on one cache line and beats hashing the pc every instruction
+#[derive(Debug, Default)]
+pub struct Breakpoints {
+    addrs: Vec<u16>,
+    // the run_to target, gone once it's reached
+    one_shot: Option<u16>,
+    // where the last stop was. the next check there lets the instruction run, so carrying on
+    // from a breakpoint doesn't stop straight away on the same one
+    stopped_at: Option<u16>,
+}
+
+impl Breakpoints {
+    pub fn add(&mut self, addr: u16) {
+        if let Err(i) = self.addrs.binary_search(&addr) {
+            self.addrs.insert(i, addr);
+        }
+    }
+
+    // false when there wasn't one there
+    pub fn remove(&mut self, addr: u16) -> bool {
+        match self.addrs.binary_search(&addr) {
+            Ok(i) => {
+                self.addrs.remove(i);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.addrs.clear();
+        self.stopped_at = None;
+    }
+
+    // in address order
+    pub fn addrs(&self) -> &[u16] {
+        &self.addrs
+    }
+
+    pub fn set_one_shot(&mut self, addr: Option<u16>) {
+        self.one_shot = addr;
+    }
+
+    // whether to stop before fetching from `pc`
+    #[inline]
+    pub fn check(&mut self, pc: u16) -> Option<StopReason> {
+        if self.addrs.is_empty() && self.one_shot.is_none() {
+            return None;
+        }
+        self.check_set(pc)
+    }
+
+    fn check_set(&mut self, pc: u16) -> Option<StopReason> {
+        if self.stopped_at.take() == Some(pc) {
+            return None;
+        }
+
+        let reason = if self.addrs.binary_search(&pc).is_ok() {
+            StopReason::Breakpoint(pc)
+        } else if self.one_shot == Some(pc) {
+            StopReason::Reached(pc)
+        } else {
+            return None;
+        };
+        if self.one_shot == Some(pc) {
+            self.one_shot = None;
+        }
+        self.stopped_at = Some(pc);
+        Some(reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stops_once_per_arrival() {
+        let mut breakpoints = Breakpoints::default();
+        breakpoints.add(0x8003);
+        breakpoints.add(0x8001);
+        breakpoints.add(0x8003);
+        assert_eq!(breakpoints.addrs(), [0x8001, 0x8003]);
+
+        assert_eq!(breakpoints.check(0x8000), None);
+        assert_eq!(
+            breakpoints.check(0x8001),
+            Some(StopReason::Breakpoint(0x8001))
+        );
+        // carrying on from there
+        assert_eq!(breakpoints.check(0x8001), None);
+        // and coming round to it again
+        assert_eq!(
+            breakpoints.check(0x8001),
+            Some(StopReason::Breakpoint(0x8001))
+        );
+
+        assert!(breakpoints.remove(0x8001));
+        assert!(!breakpoints.remove(0x8001));
+        assert_eq!(breakpoints.check(0x8001), None);
+    }
+
+    #[test]
+    fn test_one_shot_goes_once_reached() {
+        let mut breakpoints = Breakpoints::default();
+        breakpoints.set_one_shot(Some(0x9000));
+
+        assert_eq!(breakpoints.check(0x9000), Some(StopReason::Reached(0x9000)));
+        assert_eq!(breakpoints.check(0x9000), None);
+        assert_eq!(breakpoints.check(0x9000), None);
+    }
+}
@@ -0,0 +1,1199 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::apu::Apu;
+use crate::joypad::Joypad;
+use crate::mappers::{self, flat::Flat, LoadWarning, Mapper};
+use crate::ppu::NesPPU;
+use crate::render::{self, frame::Frame};
+use crate::rom::{Mirroring, Rom, TvSystem};
+#[cfg(feature = "savestate")]
+use crate::savestate::StateError;
+use crate::zapper::Zapper;
+
+pub trait Mem {
+    // reads take &mut self because some registers change state when they're read
+    fn mem_read(&mut self, addr: u16) -> u8;
+
+    fn mem_write(&mut self, addr: u16, value: u8);
+
+    // following two functions implement little endianness
+    // the high byte of a word at 0xffff wraps around to 0x0000
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
+        let lo = self.mem_read(pos) as u16;
+        let hi = self.mem_read(pos.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    // a pointer in the zero page. the high byte of one at 0xff comes from 0x00, the cpu only
+    // ever adds 1 to the low byte of the address
+    fn mem_read_zero_page_u16(&mut self, ptr: u8) -> u16 {
+        let lo = self.mem_read(ptr as u16) as u16;
+        let hi = self.mem_read(ptr.wrapping_add(1) as u16) as u16;
+        (hi << 8) | lo
+    }
+
+    fn mem_write_u16(&mut self, pos: u16, data: u16) {
+        let hi = (data >> 8) as u8;
+        let lo = (data & 0xff) as u8;
+        self.mem_write(pos, lo);
+        self.mem_write(pos.wrapping_add(1), hi);
+    }
+}
+
+// 0x0000 - 0x1fff  2KB ram, mirrored four times
+// 0x2000 - 0x3fff  8 ppu registers, mirrored every 8 bytes
+// 0x4000 - 0x401f  apu and io
+// 0x4020 - 0xffff  cartridge, through its mapper
+const RAM: u16 = 0x0000;
+const RAM_MIRRORS_END: u16 = 0x1fff;
+const PPU_REGISTERS: u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3fff;
+const APU_CHANNELS: u16 = 0x4000;
+const APU_CHANNELS_END: u16 = 0x4013;
+const OAM_DMA: u16 = 0x4014;
+const APU_STATUS: u16 = 0x4015;
+const JOYPAD_1: u16 = 0x4016;
+// reads are controller 2, writes the apu frame counter
+const JOYPAD_2: u16 = 0x4017;
+const APU_FRAME_COUNTER: u16 = 0x4017;
+const CARTRIDGE: u16 = 0x4020;
+
+// where load puts a program when it isn't told
+pub const DEFAULT_ORIGIN: u16 = 0x8000;
+// nmi, reset and irq, two bytes each
+const VECTORS: u16 = 0xfffa;
+const RESET_VECTOR: u16 = 0xfffc;
+
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bus {
+    // the 2KB of internal ram the console actually has
+    #[cfg_attr(feature = "savestate", serde(with = "crate::savestate::big_array"))]
+    cpu_vram: [u8; 0x800],
+    pub ppu: NesPPU,
+    pub apu: Apu,
+    #[cfg_attr(feature = "savestate", serde(skip, default = "Bus::no_cartridge"))]
+    mapper: Box<dyn Mapper>,
+    joypad1: Joypad,
+    port2: PortDevice,
+    battery: bool,
+    // where battery backed ram gets written back to, see attach_sav_file
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    sav_file: Option<PathBuf>,
+    // cpu cycles since power on
+    cycles: usize,
+    // cycles oam dma and dmc fetches have halted the cpu for that tick hasn't handed back
+    // yet. always 0 between instructions, so save states leave it out
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    stalled: u16,
+    // an irq source that isn't emulated here, see set_irq_line
+    irq_line: bool,
+    // the last value on the cpu's data bus. nothing drives it when the cpu reads an address
+    // nothing's wired to, so that's what it reads, and registers fill the bits they don't drive
+    // from it too
+    open_bus: u8,
+    tv_system: TvSystem,
+    // pal's 3.2 ppu dots a cpu cycle don't divide evenly, what's left over from the last tick
+    // in fifths of a dot
+    ppu_remainder: u32,
+}
+
+// what's plugged into controller port 2
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub enum PortDevice {
+    Joypad(Joypad),
+    Zapper(Zapper),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LoadError {
+    // the program would run `overflow` bytes past 0xffff
+    TooLarge { size: usize, overflow: usize },
+    // the program runs into the vectors at 0xfffa but stops short of 0xffff, so some of
+    // them would be left half written
+    OverlapsVectors { end: u16 },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::TooLarge { size, overflow } => write!(
+                f,
+                "Program is {} bytes, {} too many to fit below $10000",
+                size, overflow
+            ),
+            LoadError::OverlapsVectors { end } => write!(
+                f,
+                "Program ends at ${:04x}, part way through the vectors at $fffa",
+                end
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+#[derive(Debug)]
+pub enum SaveRamError {
+    // the cartridge has no battery backed ram to load into
+    NoBattery,
+    WrongSize { expected: usize, actual: usize },
+    Io(io::Error),
+}
+
+impl fmt::Display for SaveRamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveRamError::NoBattery => write!(f, "Cartridge has no battery backed RAM"),
+            SaveRamError::WrongSize { expected, actual } => write!(
+                f,
+                "Save RAM is {} bytes but the cartridge has {}",
+                actual, expected
+            ),
+            SaveRamError::Io(e) => write!(f, "Save file error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SaveRamError {}
+
+impl From<io::Error> for SaveRamError {
+    fn from(e: io::Error) -> Self {
+        SaveRamError::Io(e)
+    }
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Bus {
+            cpu_vram: [0; 0x800],
+            ppu: NesPPU::new(),
+            apu: Apu::new(),
+            // without a cartridge, 0x8000 - 0xffff is plain memory for raw programs
+            mapper: Box::new(Flat::new()),
+            joypad1: Joypad::new(),
+            port2: PortDevice::Joypad(Joypad::new()),
+            battery: false,
+            sav_file: None,
+            cycles: 0,
+            stalled: 0,
+            irq_line: false,
+            open_bus: 0,
+            tv_system: TvSystem::Ntsc,
+            ppu_remainder: 0,
+        }
+    }
+
+    // the cartridge's tv system is only what its header says, see set_tv_system
+    pub fn with_rom(rom: Rom) -> Result<Self, String> {
+        let mut bus = Bus::without_mapper(&rom);
+        bus.mapper = mappers::from_rom(rom)?;
+        Ok(bus)
+    }
+
+    // with_rom for a cartridge whose board might not be emulated, see mappers::from_rom_or_nrom
+    pub fn with_rom_or_nrom(rom: Rom) -> Result<(Self, Vec<LoadWarning>), String> {
+        let mut bus = Bus::without_mapper(&rom);
+        let (mapper, warnings) = mappers::from_rom_or_nrom(rom)?;
+        bus.mapper = mapper;
+        Ok((bus, warnings))
+    }
+
+    fn without_mapper(rom: &Rom) -> Self {
+        let mut bus = Bus::new();
+        bus.battery = rom.battery;
+        bus.set_tv_system(rom.tv_system);
+        bus
+    }
+
+    // what a deserialized bus has until it's given the real cartridge, see take_cartridge
+    #[cfg(feature = "savestate")]
+    fn no_cartridge() -> Box<dyn Mapper> {
+        Box::new(Flat::new())
+    }
+
+    // everything but the cartridge is serialized along with the bus, the cartridge saves
+    // itself through its mapper
+    #[cfg(feature = "savestate")]
+    pub(crate) fn mapper_state(&self) -> Vec<u8> {
+        self.mapper.save_state()
+    }
+
+    // for a bus that's just been deserialized: moves `from`'s cartridge and .sav file over,
+    // with the cartridge put back to `mapper_state`
+    #[cfg(feature = "savestate")]
+    pub(crate) fn take_cartridge(
+        &mut self,
+        from: &mut Bus,
+        mapper_state: &[u8],
+    ) -> Result<(), StateError> {
+        from.mapper.load_state(mapper_state)?;
+        std::mem::swap(&mut self.mapper, &mut from.mapper);
+        self.sav_file = from.sav_file.take();
+        Ok(())
+    }
+
+    // the battery backed prg ram, for a frontend to write out as a .sav file
+    pub fn save_ram(&self) -> Option<&[u8]> {
+        if !self.battery {
+            return None;
+        }
+        self.mapper.prg_ram()
+    }
+
+    pub fn load_ram(&mut self, data: &[u8]) -> Result<(), SaveRamError> {
+        if !self.battery {
+            return Err(SaveRamError::NoBattery);
+        }
+        let ram = self.mapper.prg_ram_mut().ok_or(SaveRamError::NoBattery)?;
+        if ram.len() != data.len() {
+            return Err(SaveRamError::WrongSize {
+                expected: ram.len(),
+                actual: data.len(),
+            });
+        }
+        ram.copy_from_slice(data);
+        Ok(())
+    }
+
+    // loads save ram from `path` if it exists, and writes it back there on flush or drop
+    pub fn attach_sav_file(&mut self, path: impl AsRef<Path>) -> Result<(), SaveRamError> {
+        let path = path.as_ref();
+        match fs::read(path) {
+            Ok(data) => self.load_ram(&data)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.sav_file = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<(), SaveRamError> {
+        if let (Some(path), Some(ram)) = (&self.sav_file, self.save_ram()) {
+            fs::write(path, ram)?;
+        }
+        Ok(())
+    }
+
+    // the ppu's view of the cartridge, pattern tables at 0x0000 - 0x1fff
+    pub fn ppu_read(&self, addr: u16) -> u8 {
+        self.mapper.ppu_read(addr)
+    }
+
+    pub fn ppu_write(&mut self, addr: u16, value: u8) {
+        self.mapper.ppu_write(addr, value)
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.mirroring()
+    }
+
+    // level triggered, the cpu keeps taking it while interrupts are enabled until whatever
+    // raised it is acknowledged
+    pub fn irq_pending(&self) -> bool {
+        self.irq_line || self.mapper.irq_pending() || self.apu.irq_pending()
+    }
+
+    // holds the irq line up, or lets it go, on behalf of something the bus doesn't have, like
+    // expansion port hardware or a test. the mapper and apu raise their own
+    pub fn set_irq_line(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
+
+    // the console's side of the reset button. the cartridge isn't wired to it, so the mapper
+    // keeps its banks and any irq it's holding
+    pub fn reset(&mut self) {
+        self.ppu.reset();
+        self.apu.reset();
+        self.irq_line = false;
+    }
+
+    // the power switch, with `rom` being the cartridge that's plugged in so the mapper can
+    // start over from it. ram, the ppu, the apu and the mapper all come back up as they did
+    // the first time, battery backed ram keeps what it had and the controllers stay plugged in
+    pub fn power_cycle(&mut self, rom: Rom) -> Result<(), String> {
+        // the cartridge was let in once already, so whatever board it got then it gets again
+        let (mut mapper, _) = mappers::from_rom_or_nrom(rom)?;
+        if let (Some(saved), Some(ram)) = (self.save_ram(), mapper.prg_ram_mut()) {
+            let len = saved.len().min(ram.len());
+            ram[..len].copy_from_slice(&saved[..len]);
+        }
+        self.mapper = mapper;
+        self.cpu_vram = [0; 0x800];
+        self.ppu.power_cycle();
+        self.apu.power_cycle(self.tv_system);
+        self.cycles = 0;
+        self.irq_line = false;
+        self.ppu_remainder = 0;
+        Ok(())
+    }
+
+    pub fn tv_system(&self) -> TvSystem {
+        self.tv_system
+    }
+
+    // for a console that's about to be switched on
+    pub fn set_tv_system(&mut self, tv_system: TvSystem) {
+        self.tv_system = tv_system;
+        self.ppu_remainder = 0;
+        self.ppu.set_tv_system(tv_system);
+        self.apu.set_tv_system(tv_system);
+    }
+
+    // runs everything else for `cycles` cpu cycles, and returns how many more cycles the cpu
+    // was halted for on top of them, which it has to count as its own
+    pub fn tick(&mut self, cycles: u16) -> u16 {
+        self.run(cycles);
+        std::mem::take(&mut self.stalled)
+    }
+
+    // the ppu runs 3 cycles for every cpu cycle, or 3.2 on pal
+    fn run(&mut self, cycles: u16) {
+        let (dots, per_cycles) = self.tv_system.ppu_ratio();
+        let mut cycles = cycles;
+        // the dmc's sample fetches halt the cpu for another 4 cycles each
+        while cycles > 0 {
+            self.cycles += cycles as usize;
+            let owed = cycles as u32 * dots + self.ppu_remainder;
+            self.ppu_remainder = owed % per_cycles;
+            self.ppu
+                .tick(self.mapper.as_mut(), (owed / per_cycles) as u16);
+            self.apu.tick(cycles);
+
+            cycles = 0;
+            if let Some(addr) = self.apu.dmc.take_dma_request() {
+                let sample = self.mem_read(addr);
+                self.apu.dmc.fill(sample);
+                cycles = 4;
+                self.stalled += cycles;
+            }
+        }
+    }
+
+    // for the frontend to press buttons on between frames
+    pub fn joypad1_mut(&mut self) -> &mut Joypad {
+        &mut self.joypad1
+    }
+
+    pub fn plug_port2(&mut self, device: PortDevice) {
+        self.port2 = device;
+    }
+
+    // None when something else is plugged in
+    pub fn joypad2_mut(&mut self) -> Option<&mut Joypad> {
+        match &mut self.port2 {
+            PortDevice::Joypad(joypad) => Some(joypad),
+            _ => None,
+        }
+    }
+
+    pub fn zapper_mut(&mut self) -> Option<&mut Zapper> {
+        match &mut self.port2 {
+            PortDevice::Zapper(zapper) => Some(zapper),
+            _ => None,
+        }
+    }
+
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    // copies page `page` into OAM starting at OAMADDR. the cpu is halted for the copy, a
+    // read and a write per byte plus one cycle to line up, and one more when it starts on
+    // an odd cycle
+    fn oam_dma(&mut self, page: u8) {
+        let base = (page as u16) << 8;
+        for i in 0..=0xff {
+            let value = self.mem_read(base + i);
+            self.ppu.write_to_oam_data(value);
+        }
+
+        let stall = 513 + (self.cycles % 2) as u16;
+        self.stalled += stall;
+        self.run(stall);
+    }
+
+    pub fn poll_nmi_status(&mut self) -> bool {
+        self.ppu.poll_nmi()
+    }
+
+    pub fn render(&mut self, frame: &mut Frame) {
+        render::render(&mut self.ppu, self.mapper.as_ref(), frame)
+    }
+
+    // what mem_read would return, without the side effects reading some registers has. for
+    // debuggers and traces, which mustn't clear vblank or shift a joypad along
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM..=RAM_MIRRORS_END => self.cpu_vram[(addr & 0x07ff) as usize],
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => match addr & 0x2007 {
+                0x2002 => self.ppu.peek_status(),
+                0x2004 => self.ppu.read_oam_data(),
+                0x2007 => self.ppu.peek_data(),
+                _ => self.ppu.io_latch,
+            },
+            APU_STATUS => self.apu.peek_status() | (self.open_bus & 0x20),
+            JOYPAD_1 => self.joypad1.peek() | (self.open_bus & 0xe0),
+            JOYPAD_2 => {
+                let port2 = match &self.port2 {
+                    PortDevice::Joypad(joypad) => joypad.peek(),
+                    PortDevice::Zapper(zapper) => zapper.read(&self.ppu.frame),
+                };
+                port2 | (self.open_bus & 0xe0)
+            }
+            CARTRIDGE..=0xffff => self.mapper.cpu_read(addr),
+            _ => self.open_bus,
+        }
+    }
+
+    pub fn peek_u16(&self, addr: u16) -> u16 {
+        let lo = self.peek(addr) as u16;
+        let hi = self.peek(addr.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    // see Mem::mem_read_zero_page_u16
+    pub fn peek_zero_page_u16(&self, ptr: u8) -> u16 {
+        let lo = self.peek(ptr as u16) as u16;
+        let hi = self.peek(ptr.wrapping_add(1) as u16) as u16;
+        (hi << 8) | lo
+    }
+
+    pub fn load(&mut self, program: &[u8]) -> Result<(), LoadError> {
+        self.load_at(program, DEFAULT_ORIGIN)
+    }
+
+    // copies a raw program to `origin` and points the reset vector at it. a program has to
+    // stop before the vectors, or go all the way to 0xffff and bring its own
+    pub fn load_at(&mut self, program: &[u8], origin: u16) -> Result<(), LoadError> {
+        let end = origin as usize + program.len();
+        if end > 0x10000 {
+            return Err(LoadError::TooLarge {
+                size: program.len(),
+                overflow: end - 0x10000,
+            });
+        }
+        let has_vectors = end == 0x10000 && origin <= VECTORS;
+        if end > VECTORS as usize && !has_vectors {
+            return Err(LoadError::OverlapsVectors {
+                end: (end - 1) as u16,
+            });
+        }
+
+        for (i, byte) in program.iter().enumerate() {
+            self.mem_write(origin + i as u16, *byte);
+        }
+        if !has_vectors {
+            self.mem_write_u16(RESET_VECTOR, origin);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        // nowhere to report a failure from here, call flush() to find out
+        let _ = self.flush();
+    }
+}
+
+impl Mem for Bus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let value = match addr {
+            // only 11 address lines are wired to ram, so the top bits are ignored
+            RAM..=RAM_MIRRORS_END => self.cpu_vram[(addr & 0x07ff) as usize],
+            // and only 3 to the ppu
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => match addr & 0x2007 {
+                0x2002 => self.ppu.read_status(),
+                0x2004 => self.ppu.read_oam_data(),
+                0x2007 => self.ppu.read_data(self.mapper.as_ref()),
+                // write only, nothing drives the bus so it still has the last value on it
+                _ => self.ppu.io_latch,
+            },
+            // bit 5 isn't driven
+            APU_STATUS => self.apu.read_status() | (self.open_bus & 0x20),
+            // the controllers only drive the low bits
+            JOYPAD_1 => self.joypad1.read() | (self.open_bus & 0xe0),
+            JOYPAD_2 => {
+                let port2 = match &mut self.port2 {
+                    PortDevice::Joypad(joypad) => joypad.read(),
+                    PortDevice::Zapper(zapper) => zapper.read(&self.ppu.frame),
+                };
+                port2 | (self.open_bus & 0xe0)
+            }
+            CARTRIDGE..=0xffff => self.mapper.cpu_read(addr),
+            // the apu's write only registers, and 0x4018 - 0x401f which are only there in the
+            // cpu's test mode
+            _ => self.open_bus,
+        };
+        self.open_bus = value;
+        value
+    }
+
+    fn mem_write(&mut self, addr: u16, value: u8) {
+        self.open_bus = value;
+        match addr {
+            RAM..=RAM_MIRRORS_END => self.cpu_vram[(addr & 0x07ff) as usize] = value,
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                self.ppu.io_latch = value;
+                match addr & 0x2007 {
+                    0x2000 => self.ppu.write_to_ctrl(value),
+                    0x2001 => self.ppu.write_to_mask(value),
+                    0x2003 => self.ppu.write_to_oam_addr(value),
+                    0x2004 => self.ppu.write_to_oam_data(value),
+                    0x2005 => self.ppu.write_to_scroll(value),
+                    0x2006 => self.ppu.write_to_ppu_addr(value),
+                    0x2007 => self.ppu.write_to_data(self.mapper.as_mut(), value),
+                    // PPUSTATUS is read only, though the write still lands in the latch
+                    _ => {}
+                }
+            }
+            APU_CHANNELS..=APU_CHANNELS_END | APU_STATUS | APU_FRAME_COUNTER => {
+                self.apu.write_register(addr, value)
+            }
+            OAM_DMA => self.oam_dma(value),
+            // one strobe line goes to both ports
+            JOYPAD_1 => {
+                self.joypad1.write(value);
+                if let PortDevice::Joypad(joypad) = &mut self.port2 {
+                    joypad.write(value);
+                }
+            }
+            CARTRIDGE..=0xffff => self.mapper.cpu_write(addr, value),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::joypad::JoypadButton;
+    use crate::ppu::registers::StatusRegister;
+    use crate::rom::tests::{bare_rom, create_rom, header, test_rom, TestRom};
+
+    #[test]
+    fn test_read_back_what_was_written() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x0010, 0x42);
+        bus.mem_write(0x07ff, 0x43);
+        bus.mem_write(0x8000, 0x44);
+
+        assert_eq!(bus.mem_read(0x0010), 0x42);
+        assert_eq!(bus.mem_read(0x07ff), 0x43);
+        assert_eq!(bus.mem_read(0x8000), 0x44);
+    }
+
+    #[test]
+    fn test_words_wrap() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x00ff, 0x34);
+        bus.mem_write(0x0000, 0x12);
+        bus.mem_write(0x0100, 0x56);
+        bus.mem_write(0xffff, 0x78);
+
+        assert_eq!(bus.mem_read_zero_page_u16(0xff), 0x1234);
+        assert_eq!(bus.peek_zero_page_u16(0xff), 0x1234);
+        assert_eq!(bus.mem_read_u16(0x00ff), 0x5634);
+        assert_eq!(bus.mem_read_u16(0xffff), 0x1278);
+        assert_eq!(bus.peek_u16(0xffff), 0x1278);
+    }
+
+    #[test]
+    fn test_load_sets_reset_vector() {
+        let mut bus = Bus::new();
+        bus.load(&[0xa9, 0x05, 0x00]).unwrap();
+
+        assert_eq!(bus.mem_read(0x8000), 0xa9);
+        assert_eq!(bus.mem_read(0x8002), 0x00);
+        assert_eq!(bus.mem_read_u16(0xfffc), 0x8000);
+    }
+
+    #[test]
+    fn test_load_at_an_origin() {
+        let mut bus = Bus::new();
+        bus.load_at(&[0xa9, 0x05, 0x00], 0x0600).unwrap();
+
+        assert_eq!(bus.mem_read(0x0600), 0xa9);
+        assert_eq!(bus.mem_read(0x0602), 0x00);
+        assert_eq!(bus.mem_read_u16(0xfffc), 0x0600);
+    }
+
+    #[test]
+    fn test_load_has_to_fit() {
+        let mut bus = Bus::new();
+
+        assert_eq!(
+            bus.load_at(&[0xea; 0x20], 0xfff0),
+            Err(LoadError::TooLarge {
+                size: 0x20,
+                overflow: 0x10,
+            })
+        );
+        assert_eq!(
+            bus.load(&[0xea; 0x8000 - 5]),
+            Err(LoadError::OverlapsVectors { end: 0xfffa })
+        );
+        assert_eq!(bus.load(&[0xea; 0x8000 - 6]), Ok(()));
+        assert_eq!(bus.mem_read_u16(0xfffc), 0x8000);
+    }
+
+    #[test]
+    fn test_program_with_its_own_vectors() {
+        let mut bus = Bus::new();
+        let mut program = vec![0xea; 0x8000];
+        program[0x7ffc] = 0x34;
+        program[0x7ffd] = 0x92;
+
+        assert_eq!(bus.load(&program), Ok(()));
+        assert_eq!(bus.mem_read_u16(0xfffc), 0x9234);
+        assert_eq!(bus.load_at(&[1, 2, 3, 4, 5, 6], 0xfffa), Ok(()));
+        assert_eq!(bus.mem_read_u16(0xfffc), 0x0403);
+    }
+
+    #[test]
+    fn test_ram_is_mirrored_up_to_0x1fff() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x0000, 0x11);
+        bus.mem_write(0x0fff, 0x22);
+        bus.mem_write(0x1234, 0x33);
+
+        assert_eq!(bus.mem_read(0x0800), 0x11);
+        assert_eq!(bus.mem_read(0x1000), 0x11);
+        assert_eq!(bus.mem_read(0x1800), 0x11);
+        assert_eq!(bus.mem_read(0x07ff), 0x22);
+        assert_eq!(bus.mem_read(0x1fff), 0x22);
+        assert_eq!(bus.mem_read(0x0234), 0x33);
+    }
+
+    #[test]
+    fn test_ppu_registers_are_mirrored_every_8_bytes() {
+        let mut bus = Bus::new();
+        // OAMADDR through 0x3ffb, OAMDATA through 0x200c
+        bus.mem_write(0x3ffb, 0x10);
+        bus.mem_write(0x200c, 0x44);
+        bus.mem_write(0x2003, 0x10);
+
+        assert_eq!(bus.mem_read(0x3ffc), 0x44);
+        assert_eq!(bus.mem_read(0x2004), 0x44);
+    }
+
+    #[test]
+    fn test_ppu_data_through_the_bus() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x2006, 0x23);
+        bus.mem_write(0x2006, 0x05);
+        bus.mem_write(0x2007, 0x66);
+
+        assert_eq!(bus.ppu.vram[0x0305], 0x66);
+    }
+
+    #[test]
+    fn test_write_only_ppu_registers_read_back_the_latch() {
+        let mut bus = Bus::new();
+        assert_eq!(bus.mem_read(0x2000), 0);
+
+        bus.mem_write(0x2003, 0x5a);
+        assert_eq!(bus.mem_read(0x2000), 0x5a);
+        assert_eq!(bus.mem_read(0x2006), 0x5a);
+        assert_eq!(bus.peek(0x2005), 0x5a);
+    }
+
+    #[test]
+    fn test_palette_reads_are_6_bits_with_the_latch_on_top() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x2006, 0x3f);
+        bus.mem_write(0x2006, 0x01);
+        bus.mem_write(0x2007, 0xff);
+        bus.mem_write(0x2006, 0x3f);
+        bus.mem_write(0x2006, 0x01);
+        // the last write left 0x01 on the bus
+        assert_eq!(bus.mem_read(0x2007), 0x3f);
+
+        // a write to PPUSTATUS goes nowhere but the latch
+        bus.mem_write(0x2006, 0x3f);
+        bus.mem_write(0x2006, 0x01);
+        bus.mem_write(0x2002, 0xc0);
+        assert_eq!(bus.mem_read(0x2007), 0xff);
+
+        bus.mem_write(0x2001, 0x01);
+        bus.mem_write(0x2006, 0x3f);
+        bus.mem_write(0x2006, 0x01);
+        assert_eq!(bus.mem_read(0x2007), 0x30);
+        bus.mem_write(0x2006, 0x3f);
+        bus.mem_write(0x2006, 0x01);
+        bus.mem_write(0x2002, 0x80);
+        assert_eq!(bus.peek(0x2007), 0xb0);
+        assert_eq!(bus.mem_read(0x2007), 0xb0);
+    }
+
+    #[test]
+    fn test_oam_dma_copies_a_page() {
+        let mut cpu = CPU::new(Bus::new());
+        for i in 0..=0xff {
+            cpu.poke(0x0200 + i, i as u8 ^ 0x5a);
+        }
+
+        // LDA #$02; STA $4014
+        cpu.load_and_run(vec![0xa9, 0x02, 0x8d, 0x14, 0x40, 0x00])
+            .unwrap();
+
+        for i in 0..=0xff {
+            assert_eq!(cpu.bus().ppu.oam_data[i], i as u8 ^ 0x5a);
+        }
+        // the reset and LDA leave it on an odd cycle, so the copy takes the extra one
+        assert_eq!(cpu.cycles, 7 + 2 + 4 + 514);
+        assert_eq!(cpu.bus().cycles() as u64, cpu.cycles);
+    }
+
+    #[test]
+    fn test_oam_dma_starts_at_oam_addr_and_wraps() {
+        let mut bus = Bus::new();
+        // through the mirror at 0x0a00, so it comes out of ram at 0x0200
+        for i in 0..=0xff {
+            bus.mem_write(0x0200 + i, i as u8);
+        }
+        bus.mem_write(0x2003, 0x10);
+        // odd cycle, one more to line up
+        bus.tick(1);
+
+        bus.mem_write(0x4014, 0x0a);
+
+        assert_eq!(bus.ppu.oam_data[0x10], 0x00);
+        assert_eq!(bus.ppu.oam_data[0xff], 0xef);
+        assert_eq!(bus.ppu.oam_data[0x00], 0xf0);
+        assert_eq!(bus.ppu.oam_data[0x0f], 0xff);
+        assert_eq!(bus.cycles(), 1 + 514);
+    }
+
+    #[test]
+    fn test_joypad_through_the_bus() {
+        let mut bus = Bus::new();
+        bus.joypad1_mut()
+            .set_button_pressed_status(JoypadButton::B, true);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+
+        assert_eq!(bus.mem_read(0x4016), 0);
+        assert_eq!(bus.mem_read(0x4016), 1);
+        assert_eq!(bus.mem_read(0x4016), 0);
+    }
+
+    #[test]
+    fn test_two_joypads_read_independently() {
+        let mut bus = Bus::new();
+        bus.joypad1_mut()
+            .set_button_pressed_status(JoypadButton::A, true);
+        bus.joypad2_mut()
+            .unwrap()
+            .set_button_pressed_status(JoypadButton::B, true);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+
+        assert_eq!(bus.mem_read(0x4016), 1);
+        assert_eq!(bus.mem_read(0x4017), 0);
+        assert_eq!(bus.mem_read(0x4017), 1);
+        assert_eq!(bus.mem_read(0x4016), 0);
+        assert_eq!(bus.mem_read(0x4017), 0);
+
+        // writing 0x4017 is the apu's, it doesn't strobe anything
+        bus.mem_write(0x4017, 1);
+        assert_eq!(bus.mem_read(0x4017), 0);
+
+        // the strobe starts both over
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        assert_eq!(bus.mem_read(0x4016), 1);
+        assert_eq!(bus.mem_read(0x4017), 0);
+        assert_eq!(bus.mem_read(0x4017), 1);
+    }
+
+    #[test]
+    fn test_zapper_on_port_2() {
+        let mut bus = Bus::new();
+        bus.plug_port2(PortDevice::Zapper(Zapper::new()));
+        assert!(bus.joypad2_mut().is_none());
+        for y in 0..8 {
+            for x in 0..8 {
+                bus.ppu.frame.set_pixel(x, y, (0xff, 0xff, 0xff));
+            }
+        }
+
+        let zapper = bus.zapper_mut().unwrap();
+        zapper.set_aim(4, 4);
+        zapper.set_trigger(true);
+        assert_eq!(bus.mem_read(0x4017), 0b1_0000);
+
+        bus.zapper_mut().unwrap().set_aim(200, 200);
+        assert_eq!(bus.mem_read(0x4017), 0b1_1000);
+    }
+
+    // a dmc sample at 0xc000 played at the fastest rate from level 64. `length` is in 16
+    // byte units, plus one
+    fn dmc_bus(samples: &[u8], flags: u8, length: u8) -> Bus {
+        let mut bus = Bus::new();
+        for (i, sample) in samples.iter().enumerate() {
+            bus.mem_write(0xc000 + i as u16, *sample);
+        }
+        bus.mem_write(0x4010, flags | 0x0f);
+        bus.mem_write(0x4011, 64);
+        bus.mem_write(0x4012, 0);
+        bus.mem_write(0x4013, length);
+        bus.mem_write(0x4015, 0b1_0000);
+        bus
+    }
+
+    // the cpu cycle each of the first `frames` frames finished on, ticking a cycle at a time
+    fn frame_ends(tv_system: TvSystem, frames: usize) -> Vec<usize> {
+        let mut bus = Bus::new();
+        bus.set_tv_system(tv_system);
+        let mut ends = vec![];
+        while ends.len() < frames {
+            let frame = bus.ppu.frame_count;
+            bus.tick(1);
+            if bus.ppu.frame_count != frame {
+                ends.push(bus.cycles());
+            }
+        }
+        ends
+    }
+
+    #[test]
+    fn test_cycles_per_frame() {
+        // 341 * 262 / 3 = 29780.67 cpu cycles a frame
+        let ntsc = frame_ends(TvSystem::Ntsc, 7);
+        assert_eq!(ntsc[6] - ntsc[0], 178_684);
+        assert!(ntsc
+            .windows(2)
+            .all(|w| [29780, 29781].contains(&(w[1] - w[0]))));
+
+        // 341 * 312 / 3.2 = 33247.5, exactly, with nothing lost to rounding
+        let pal = frame_ends(TvSystem::Pal, 7);
+        assert_eq!(pal[6] - pal[0], 199_485);
+        assert!(pal
+            .windows(2)
+            .all(|w| [33247, 33248].contains(&(w[1] - w[0]))));
+    }
+
+    #[test]
+    fn test_dmc_plays_a_sample_from_prg() {
+        let mut bus = dmc_bus(&[0b0000_1111], 0, 0);
+        let mut levels = vec![bus.apu.dmc.output()];
+        for _ in 0..54 * 24 {
+            bus.tick(1);
+            if levels.last() != Some(&bus.apu.dmc.output()) {
+                levels.push(bus.apu.dmc.output());
+            }
+        }
+
+        assert_eq!(levels, [64, 66, 68, 70, 72, 70, 68, 66, 64]);
+    }
+
+    #[test]
+    fn test_dmc_fetch_stalls_the_cpu() {
+        let mut cpu = CPU::new(dmc_bus(&[0], 0, 0));
+        // NOP, without a reset that would stop the dmc
+        cpu.load(vec![0xea]).unwrap();
+        cpu.program_counter = 0x8000;
+
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.cycles, 2 + 4);
+        assert_eq!(cpu.bus().cycles() as u64, cpu.cycles);
+    }
+
+    #[test]
+    fn test_dmc_irq_at_sample_end() {
+        let mut bus = dmc_bus(&[0; 17], 0b1000_0000, 1);
+        let mut fetches = 0;
+        while bus.apu.dmc.active() {
+            assert!(!bus.irq_pending());
+            let before = bus.cycles();
+            bus.tick(1);
+            fetches += (bus.cycles() - before > 1) as usize;
+        }
+
+        // the irq goes up with the last byte's fetch
+        assert_eq!(fetches, 17);
+        assert!(bus.irq_pending());
+        assert_eq!(bus.mem_read(0x4015) & 0b1001_0000, 0b1000_0000);
+        assert!(!bus.irq_pending());
+    }
+
+    #[test]
+    fn test_dmc_loop_never_raises_irq() {
+        let mut bus = dmc_bus(&[0; 17], 0b1100_0000, 1);
+        for _ in 0..54 * 8 * 40 {
+            bus.tick(1);
+        }
+
+        assert!(bus.apu.dmc.active());
+        assert!(!bus.irq_pending());
+    }
+
+    // 32KB of prg and chr ram on an mmc3
+    fn mmc3_bus() -> Bus {
+        let raw = create_rom(TestRom {
+            header: header(2, 0, 0x40, 0x00),
+            trainer: None,
+            prg_rom: vec![0; 0x8000],
+            chr_rom: vec![],
+        });
+        Bus::with_rom(Rom::new(&raw).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_mmc3_counts_rendered_scanlines() {
+        let mut bus = mmc3_bus();
+        // no frame counter irqs to get mixed up with
+        bus.mem_write(0x4017, 0b0100_0000);
+        bus.mem_write(0xc000, 10);
+        bus.mem_write(0xc001, 0);
+        bus.mem_write(0xe001, 0);
+        bus.mem_write(0x2001, 0b0001_1000);
+
+        // line 0 loads the latch, then lines 1 - 10 count it down
+        while !bus.irq_pending() {
+            assert!(bus.ppu.scanline <= 10);
+            bus.tick(1);
+        }
+        assert_eq!(bus.ppu.scanline, 10);
+        assert!(bus.ppu.cycle > 260);
+
+        // acknowledged, and with rendering off nothing counts
+        bus.mem_write(0xe000, 0);
+        bus.mem_write(0xe001, 0);
+        assert!(!bus.irq_pending());
+        bus.mem_write(0x2001, 0);
+        for _ in 0..2 * 29781 {
+            bus.tick(1);
+        }
+        assert!(!bus.irq_pending());
+    }
+
+    #[test]
+    fn test_peek_has_no_side_effects() {
+        let mut bus = Bus::new();
+        bus.ppu.status.insert(StatusRegister::VBLANK_STARTED);
+        bus.joypad1_mut()
+            .set_button_pressed_status(JoypadButton::B, true);
+        bus.mem_write(JOYPAD_1, 1);
+        bus.mem_write(JOYPAD_1, 0);
+
+        assert_eq!(bus.peek(0x2002), 0x80);
+        assert_eq!(bus.peek(0x2002), 0x80);
+        assert_eq!(bus.peek(JOYPAD_1), 0);
+        assert_eq!(bus.peek(JOYPAD_1), 0);
+
+        assert_eq!(bus.mem_read(0x2002), 0x80);
+        assert_eq!(bus.mem_read(JOYPAD_1) & 1, 0);
+        assert_eq!(bus.peek(0x2002), 0);
+        assert_eq!(bus.peek(JOYPAD_1) & 1, 1);
+    }
+
+    #[test]
+    fn test_cartridge_space_without_a_cartridge_reads_zero() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x4020, 0x77);
+        bus.mem_write(0x6000, 0x77);
+
+        assert_eq!(bus.mem_read(0x4020), 0);
+        assert_eq!(bus.mem_read(0x6000), 0);
+    }
+
+    #[test]
+    fn test_io_registers_read_open_bus() {
+        let mut bus = Bus::new();
+        for addr in 0x4000..=0x401f {
+            bus.mem_write(addr, 0);
+        }
+
+        for addr in 0x4000..=0x401f {
+            bus.mem_write(0x0000, 0xaa);
+            bus.mem_read(0x0000);
+            let value = bus.mem_read(addr);
+            match addr {
+                APU_STATUS => assert_eq!(value, 0x20, "{:04X}", addr),
+                JOYPAD_1 | JOYPAD_2 => assert_eq!(value & 0xe0, 0xa0, "{:04X}", addr),
+                _ => assert_eq!(value, 0xaa, "{:04X}", addr),
+            }
+            assert_eq!(bus.peek(addr), value, "{:04X}", addr);
+        }
+
+        // the writes still land in the apu
+        bus.mem_write(0x4015, 0b0000_0001);
+        bus.mem_write(0x4003, 0b0000_1000);
+        bus.mem_write(0x0000, 0x00);
+        bus.mem_read(0x0000);
+        assert_eq!(bus.mem_read(APU_STATUS), 0b0000_0001);
+        assert_eq!(bus.mem_read(0x4000), 0b0000_0001);
+    }
+
+    #[test]
+    fn test_single_prg_bank_is_mirrored() {
+        let mut prg_rom = vec![0; 0x4000];
+        prg_rom[0x0000] = 0x11;
+        prg_rom[0x3ffc] = 0x22;
+        let mut bus = Bus::with_rom(test_rom(prg_rom)).unwrap();
+
+        assert_eq!(bus.mem_read(0x8000), 0x11);
+        assert_eq!(bus.mem_read(0xc000), 0x11);
+        assert_eq!(bus.mem_read(0xbffc), 0x22);
+        assert_eq!(bus.mem_read(0xfffc), 0x22);
+    }
+
+    #[test]
+    fn test_two_prg_banks_are_not_mirrored() {
+        let mut prg_rom = vec![0; 0x8000];
+        prg_rom[0x0000] = 0x11;
+        prg_rom[0x4000] = 0x22;
+        let mut bus = Bus::with_rom(test_rom(prg_rom)).unwrap();
+
+        assert_eq!(bus.mem_read(0x8000), 0x11);
+        assert_eq!(bus.mem_read(0xc000), 0x22);
+    }
+
+    #[test]
+    fn test_prg_rom_ignores_writes() {
+        let mut bus = Bus::with_rom(test_rom(vec![0x11; 0x4000])).unwrap();
+        bus.mem_write(0x8000, 0x22);
+
+        assert_eq!(bus.mem_read(0x8000), 0x11);
+    }
+
+    #[test]
+    fn test_unknown_mapper_is_an_error() {
+        let raw = create_rom(TestRom {
+            header: header(1, 1, 0xf0, 0xf0),
+            trainer: None,
+            prg_rom: vec![0; 0x4000],
+            chr_rom: vec![0; 0x2000],
+        });
+        let rom = Rom::new(&raw).unwrap();
+
+        match Bus::with_rom(rom) {
+            Ok(_) => panic!("mapper 255 should not load"),
+            Err(str) => assert_eq!(str, "Mapper 255 is not supported"),
+        }
+    }
+
+    // a 16KB battery backed nrom with `program` at 0x8000
+    fn battery_rom(program: &[u8]) -> Rom {
+        let mut prg_rom = vec![0; 0x4000];
+        prg_rom[..program.len()].copy_from_slice(program);
+        // reset vector, 0xfffc is mirrored down to 0xbffc
+        prg_rom[0x3ffc] = 0x00;
+        prg_rom[0x3ffd] = 0x80;
+        let raw = create_rom(TestRom {
+            header: header(1, 1, 0b10, 0x00),
+            trainer: None,
+            prg_rom,
+            chr_rom: vec![0; 0x2000],
+        });
+
+        Rom::new(&raw).unwrap()
+    }
+
+    // LDA $6000; STA $10; LDA $6001; STA $11; LDA #$42; STA $6000; LDA #$43; STA $6001; BRK
+    const SAVE_PROGRAM: [u8; 21] = [
+        0xad, 0x00, 0x60, 0x85, 0x10, 0xad, 0x01, 0x60, 0x85, 0x11, 0xa9, 0x42, 0x8d, 0x00, 0x60,
+        0xa9, 0x43, 0x8d, 0x01, 0x60, 0x00,
+    ];
+
+    #[test]
+    fn test_save_ram_survives_a_fresh_bus() {
+        let mut cpu = CPU::new(Bus::with_rom(battery_rom(&SAVE_PROGRAM)).unwrap());
+        cpu.reset();
+        cpu.run().unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x00);
+        let saved = cpu.bus().save_ram().unwrap().to_vec();
+        assert_eq!(saved.len(), 0x2000);
+
+        let mut bus = Bus::with_rom(battery_rom(&SAVE_PROGRAM)).unwrap();
+        bus.load_ram(&saved).unwrap();
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0x42);
+        assert_eq!(cpu.mem_read(0x11), 0x43);
+    }
+
+    #[test]
+    fn test_load_ram_checks_length() {
+        let mut bus = Bus::with_rom(battery_rom(&[])).unwrap();
+
+        match bus.load_ram(&[0; 0x1000]) {
+            Err(SaveRamError::WrongSize { expected, actual }) => {
+                assert_eq!(expected, 0x2000);
+                assert_eq!(actual, 0x1000);
+            }
+            other => panic!("expected WrongSize, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_no_battery_no_save_ram() {
+        let mut bus = Bus::with_rom(test_rom(vec![0; 0x4000])).unwrap();
+
+        assert!(bus.save_ram().is_none());
+        assert!(matches!(
+            bus.load_ram(&[0; 0x2000]),
+            Err(SaveRamError::NoBattery)
+        ));
+    }
+
+    #[test]
+    fn test_power_cycle_keeps_only_battery_ram() {
+        let mut bus = Bus::with_rom(battery_rom(&[])).unwrap();
+        bus.mem_write(0x6000, 0x42);
+        bus.mem_write(0x0010, 0x43);
+        bus.mem_write(0x2001, 0x18);
+        bus.tick(100);
+
+        bus.power_cycle(battery_rom(&[])).unwrap();
+        assert_eq!(bus.mem_read(0x6000), 0x42);
+        assert_eq!(bus.mem_read(0x0010), 0);
+        assert!(bus.ppu.mask.is_empty());
+        assert_eq!(bus.ppu.cycle, 0);
+        assert_eq!(bus.cycles(), 0);
+
+        // a cartridge that won't start leaves the console as it was
+        bus.mem_write(0x0010, 0x43);
+        assert!(bus.power_cycle(bare_rom(0, 0, 0)).is_err());
+        assert_eq!(bus.mem_read(0x0010), 0x43);
+        assert_eq!(bus.mem_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_sav_file_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("nes_emulator_test_{}.sav", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        {
+            let mut bus = Bus::with_rom(battery_rom(&[])).unwrap();
+            bus.attach_sav_file(&path).unwrap();
+            bus.mem_write(0x6000, 0x42);
+        }
+
+        let mut bus = Bus::with_rom(battery_rom(&[])).unwrap();
+        bus.attach_sav_file(&path).unwrap();
+        assert_eq!(bus.mem_read(0x6000), 0x42);
+
+        bus.mem_write(0x6001, 0x43);
+        bus.flush().unwrap();
+        assert_eq!(fs::read(&path).unwrap()[1], 0x43);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,8 +1,17 @@
-use std::ops::Add;
+use std::fmt;
+use std::ops::RangeInclusive;
 
-use crate::opcode::OpCode;
+use bitflags::bitflags;
 
-#[derive(Debug, PartialEq)]
+use crate::breakpoints::Breakpoints;
+pub use crate::breakpoints::StopReason;
+use crate::bus::{Bus, LoadError, Mem};
+use crate::disasm::{self, DisassembledInstruction};
+use crate::hexdump::hexdump;
+use crate::opcode::{Instruction, OpCode};
+use crate::watchpoints::{WatchKind, Watchpoints};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AddressingMode {
     Immediate,
     ZeroPage,
@@ -13,353 +22,3197 @@ pub enum AddressingMode {
     AbsoluteY,
     IndirectX,
     IndirectY,
+    Indirect,
+    Relative,
+    Accumulator,
     NoneAddressing,
 }
 
-const ZERO_FLAG: u8 = 0b0000_0010;
-const NEGATIVE_FLAG: u8 = 0b1000_0000;
-const CARRY_FLAG: u8 = 0b0000_0001;
-const OVERFLOW_FLAG: u8 = 0b0100_0000;
+bitflags! {
+    // 7 6 5 4 3 2 1 0
+    // N V _ B D I Z C
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+    pub struct StatusFlags: u8 {
+        const CARRY = 0b0000_0001;
+        const ZERO = 0b0000_0010;
+        const INTERRUPT_DISABLE = 0b0000_0100;
+        // the NES ALU has no decimal mode, but the flag itself still round-trips
+        const DECIMAL = 0b0000_1000;
+        // bits 4 and 5 only exist on the copy of the status pushed to the stack
+        const BREAK = 0b0001_0000;
+        const UNUSED = 0b0010_0000;
+        const OVERFLOW = 0b0100_0000;
+        const NEGATIVE = 0b1000_0000;
+    }
+}
+
+// the stack lives in page 1, sp is an offset into it
+const STACK: u16 = 0x0100;
+// where power on leaves sp once the reset sequence has taken its 3 off
+const STACK_RESET: u8 = 0xfd;
+// the reset sequence is an interrupt with the writes turned into reads, so it costs the same
+const RESET_CYCLES: u8 = 7;
+
+const NMI_VECTOR: u16 = 0xfffa;
+const RESET_VECTOR: u16 = 0xfffc;
+// BRK shares it with irq
+const IRQ_VECTOR: u16 = 0xfffe;
+
+const JSR: u8 = 0x20;
+
+// what one call to step did
+#[derive(Debug, PartialEq)]
+pub enum StepResult {
+    Executed {
+        opcode: u8,
+        info: &'static OpCode,
+        // where the instruction was fetched from
+        program_counter: u16,
+        // None for implied and accumulator instructions
+        operand_address: Option<u16>,
+        cycles: u8,
+    },
+    // nothing was executed
+    Stopped(StopReason),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CpuError {
+    // `pc` is where the byte was fetched from
+    UnknownOpcode { opcode: u8, pc: u16 },
+    // an operand lookup for a mode that has no operand, which means the opcode table is wrong
+    InvalidAddressingMode(AddressingMode),
+    // load_and_run couldn't load the program
+    Load(LoadError),
+    // the cartridge couldn't be started back up after a power cycle
+    Cartridge(String),
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode { opcode, pc } => {
+                write!(f, "Unknown opcode ${:02x} at ${:04x}", opcode, pc)
+            }
+            CpuError::InvalidAddressingMode(mode) => {
+                write!(f, "{:?} addressing has no operand address", mode)
+            }
+            CpuError::Load(e) => e.fmt(f),
+            CpuError::Cartridge(e) => write!(f, "Cartridge failed to power on: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+impl From<LoadError> for CpuError {
+    fn from(e: LoadError) -> Self {
+        CpuError::Load(e)
+    }
+}
+
+fn page_crossed(from: u16, to: u16) -> bool {
+    from & 0xff00 != to & 0xff00
+}
 
+#[allow(clippy::upper_case_acronyms)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
 pub struct CPU {
     pub a: u8,
     pub x: u8,
     pub y: u8,
-    pub status: u8,
+    pub sp: u8,
+    pub status: StatusFlags,
     pub program_counter: u16,
-    memory: [u8; 0xffff],
+    // a run_with_callback callback sets this to stop the cpu before the next instruction
+    pub halted: bool,
+    // BRK stops the cpu instead of interrupting, for programs that use it to say they're done.
+    // on for a bare cpu since that's what test programs do, Emulator turns it off
+    pub halt_on_brk: bool,
+    // since power on
+    pub cycles: u64,
+    bus: Bus,
+    // debugger state rather than the machine's, so not in save states
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    breakpoints: Breakpoints,
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    watchpoints: Watchpoints,
+    // set by instructions that put their own address in the pc, so step doesn't move it on
+    // past the operand. always false between instructions, so save states leave it out
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    pc_written: bool,
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new(Bus::new())
+    }
+}
+
+impl Mem for CPU {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let value = self.bus.mem_read(addr);
+        self.watchpoints.check(addr, WatchKind::Read, value);
+        value
+    }
+
+    fn mem_write(&mut self, addr: u16, value: u8) {
+        self.bus.mem_write(addr, value);
+        self.watchpoints.check(addr, WatchKind::Write, value);
+    }
 }
 
 impl CPU {
-    pub fn new() -> Self {
+    pub fn new(bus: Bus) -> Self {
         Self {
             a: 0,
             x: 0,
             y: 0,
-            status: 0,
+            sp: STACK_RESET,
+            status: StatusFlags::empty(),
             program_counter: 0,
-            memory: [0; 0xffff],
+            halted: false,
+            halt_on_brk: true,
+            cycles: 0,
+            bus,
+            breakpoints: Breakpoints::default(),
+            watchpoints: Watchpoints::default(),
+            pc_written: false,
         }
     }
 
-    fn mem_read(&self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+    fn stack_push(&mut self, value: u8) {
+        self.mem_write(STACK + self.sp as u16, value);
+        self.sp = self.sp.wrapping_sub(1);
     }
 
-    fn mem_write(&mut self, addr: u16, value: u8) {
-        self.memory[addr as usize] = value;
+    fn stack_pop(&mut self) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        self.mem_read(STACK + self.sp as u16)
+    }
+
+    // high byte first, so the value sits little endian in memory
+    fn stack_push_u16(&mut self, value: u16) {
+        self.stack_push((value >> 8) as u8);
+        self.stack_push((value & 0xff) as u8);
+    }
+
+    fn stack_pop_u16(&mut self) -> u16 {
+        let lo = self.stack_pop() as u16;
+        let hi = self.stack_pop() as u16;
+        (hi << 8) | lo
+    }
+
+    pub fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.bus
+    }
+
+    // what reading `addr` would give, without reading it. see Bus::peek
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
+    }
+
+    // `len` bytes peeked from `start` on, wrapping round past 0xffff
+    pub fn peek_range(&self, start: u16, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| self.bus.peek(start.wrapping_add(i as u16)))
+            .collect()
+    }
+
+    // what's been pushed, from the last byte pushed down to 0x01ff
+    pub fn stack_slice(&self) -> Vec<u8> {
+        let top = self.sp as u16 + 1;
+        (top..=0xff)
+            .map(|offset| self.bus.peek(STACK | offset))
+            .collect()
+    }
+
+    // a guess at the JSRs that led here, innermost first. JSR pushes the address of its own
+    // last byte, so anything on the stack that points 2 bytes past a JSR opcode is taken for
+    // one. a PHA'd pair of bytes can look like one too
+    pub fn call_stack(&self) -> Vec<u16> {
+        let stack = self.stack_slice();
+        let mut calls = vec![];
+        let mut i = 0;
+        while i + 1 < stack.len() {
+            let call = u16::from_le_bytes([stack[i], stack[i + 1]]).wrapping_sub(2);
+            if self.bus.peek(call) == JSR {
+                calls.push(call);
+                i += 2;
+            } else {
+                i += 1;
+            }
+        }
+        calls
+    }
+
+    // a write for setting things up, which watchpoints don't count
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.bus.mem_write(addr, value);
+    }
+
+    // `addrs` peeked and laid out by hexdump::hexdump
+    pub fn hexdump(&self, addrs: RangeInclusive<u16>) -> String {
+        let (start, end) = (*addrs.start(), *addrs.end());
+        if start > end {
+            return String::new();
+        }
+        hexdump(&self.peek_range(start, (end - start) as usize + 1), start)
     }
 
-    // following two functions implement little endianness
-    fn mem_read_u16(&self, pos: u16) -> u16 {
-        let lo = self.mem_read(pos) as u16;
-        let hi = self.mem_read(pos + 1) as u16;
-        (hi << 8) | (lo as u16)
+    // the `count` instructions from `addr` on, peeked so nothing notices
+    pub fn disassemble_at(&self, addr: u16, count: usize) -> Vec<DisassembledInstruction> {
+        // instructions are 3 bytes at most
+        let bytes = self.peek_range(addr, count * 3);
+        let mut result = disasm::disassemble(&bytes, addr);
+        result.truncate(count);
+        result
     }
 
-    fn mem_write_u16(&mut self, pos: u16, data: u16) {
-        let hi = (data >> 8) as u8;
-        let lo = (data & 0xff) as u8;
-        self.mem_write(pos, lo);
-        self.mem_write(pos + 1, hi);
+    pub fn load_and_run(&mut self, program: Vec<u8>) -> Result<StopReason, CpuError> {
+        self.load(program)?;
+        self.reset();
+        self.run()
     }
 
-    pub fn load_and_run(&mut self, program: Vec<u8>) {
-        self.load(program);
+    pub fn load_and_run_at(&mut self, program: &[u8], origin: u16) -> Result<StopReason, CpuError> {
+        self.load_at(program, origin)?;
         self.reset();
-        self.run();
+        self.run()
+    }
+
+    // at 0x8000, see Bus::load_at
+    pub fn load(&mut self, program: Vec<u8>) -> Result<(), LoadError> {
+        self.bus.load(&program)
     }
 
-    pub fn load(&mut self, program: Vec<u8>) {
-        self.memory[0x8000..(0x8000 + program.len())].copy_from_slice(&program[..]);
-        // 0xfffc is where the program counter start address is read from
-        self.mem_write_u16(0xfffc, 0x8000);
+    pub fn load_at(&mut self, program: &[u8], origin: u16) -> Result<(), LoadError> {
+        self.bus.load_at(program, origin)
     }
 
+    // power on. the registers are cleared, the hardware leaves them undefined, and then it's
+    // the same as soft_reset. memory is the bus's and isn't touched, so a program loaded
+    // before this is still there
     pub fn reset(&mut self) {
         self.a = 0;
         self.x = 0;
-        self.status = 0;
+        self.y = 0;
+        self.sp = 0;
+        self.status = StatusFlags::empty();
+        self.cycles = 0;
+        self.soft_reset();
+    }
 
-        self.program_counter = self.mem_read_u16(0xfffc);
+    // the reset button. the registers and ram keep what they had, the reset sequence pretends
+    // to push the pc and status without writing anything, so sp just goes down by 3, then the
+    // pc comes from the reset vector with interrupts off
+    pub fn soft_reset(&mut self) {
+        self.sp = self.sp.wrapping_sub(3);
+        self.status.insert(StatusFlags::INTERRUPT_DISABLE);
+        self.halted = false;
+        self.bus.reset();
+
+        self.tick(RESET_CYCLES);
+        // through the mapper, which decides what's at the top of memory
+        self.program_counter = self.bus.mem_read_u16(RESET_VECTOR);
     }
 
-    fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
-        match mode {
+    // the address an instruction's operand is at, and whether indexing it crossed into the
+    // next page, which costs the reads an extra cycle
+    fn get_operand_address(&mut self, mode: &AddressingMode) -> Result<(u16, bool), CpuError> {
+        let result = match mode {
             // immediate: current PC value
-            AddressingMode::Immediate => self.program_counter,
+            AddressingMode::Immediate => (self.program_counter, false),
             // zeropage: can only access first byte of addresses
-            AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
+            AddressingMode::ZeroPage => (self.mem_read(self.program_counter) as u16, false),
             // absolute: full memory location
-            AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
+            AddressingMode::Absolute => (self.mem_read_u16(self.program_counter), false),
             // zeropagex: first byte of addresses, but adds the value of X to the address first
             AddressingMode::ZeroPageX => {
                 let pos = self.mem_read(self.program_counter);
-                pos.wrapping_add(self.x) as u16
+                (pos.wrapping_add(self.x) as u16, false)
             }
             // same as above but Y register
             AddressingMode::ZeroPageY => {
                 let pos = self.mem_read(self.program_counter);
-                pos.wrapping_add(self.y) as u16
+                (pos.wrapping_add(self.y) as u16, false)
             }
             // absolute addressing but adding X and Y registers as above
             AddressingMode::AbsoluteX => {
                 let base = self.mem_read_u16(self.program_counter);
-                base.wrapping_add(self.x as u16)
+                let addr = base.wrapping_add(self.x as u16);
+                (addr, page_crossed(base, addr))
             }
             AddressingMode::AbsoluteY => {
                 let base = self.mem_read_u16(self.program_counter);
-                base.wrapping_add(self.y as u16)
+                let addr = base.wrapping_add(self.y as u16);
+                (addr, page_crossed(base, addr))
             }
             // indirectx: take a zeropage address, add the value of X, look up the 2 byte address
-            // ??? why are you like this
+            // ??? why are you like this. the pointer never leaves the zero page, so one at 0xff
+            // has its high byte at 0x00
             AddressingMode::IndirectX => {
                 let base = self.mem_read(self.program_counter);
-                let ptr = (base as u8).wrapping_add(self.x);
-                self.mem_read_u16(ptr as u16)
+                let ptr = base.wrapping_add(self.x);
+                (self.mem_read_zero_page_u16(ptr), false)
             }
-            // indirecty: zeropage address is dereferenced, then Y is added to the address
+            // indirecty: zeropage address is dereferenced, then Y is added to the address. the
+            // pointer wraps the same way
             AddressingMode::IndirectY => {
                 let base = self.mem_read(self.program_counter);
-                let deref_base = self.mem_read_u16(base as u16);
-                deref_base.wrapping_add(self.y as u16)
+                let deref_base = self.mem_read_zero_page_u16(base);
+                let addr = deref_base.wrapping_add(self.y as u16);
+                (addr, page_crossed(deref_base, addr))
             }
-            AddressingMode::NoneAddressing => {
-                panic!("Invalid addressing mode {:?}", mode);
+            // indirect (JMP only): the pointer's high byte never crosses a page, so a pointer at
+            // 0xXXff reads its high byte from 0xXX00 like the real 6502 does
+            AddressingMode::Indirect => {
+                let ptr = self.mem_read_u16(self.program_counter);
+                let lo = self.mem_read(ptr) as u16;
+                let hi = self.mem_read((ptr & 0xff00) | (ptr.wrapping_add(1) & 0x00ff)) as u16;
+                ((hi << 8) | lo, false)
             }
-        }
+            // relative: signed offset from the address of the next instruction, the branch
+            // works out its own penalties
+            AddressingMode::Relative => {
+                let offset = self.mem_read(self.program_counter) as i8;
+                let addr = self
+                    .program_counter
+                    .wrapping_add(1)
+                    .wrapping_add(offset as u16);
+                (addr, false)
+            }
+            AddressingMode::Accumulator | AddressingMode::NoneAddressing => {
+                return Err(CpuError::InvalidAddressingMode(*mode));
+            }
+        };
+        Ok(result)
+    }
+
+    fn adc(&mut self, addr: u16) {
+        let value = self.mem_read(addr);
+
+        self.add_to_register_a(value);
     }
 
-    fn adc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    // A - M - (1 - C) is the same as A + !M + C, so the carry flag ends up meaning "no borrow"
+    fn sbc(&mut self, addr: u16) {
         let value = self.mem_read(addr);
 
-        let previous_carry = self.status & CARRY_FLAG;
+        self.add_to_register_a(!value);
+    }
+
+    fn add_to_register_a(&mut self, value: u8) {
+        let previous_carry = self.status.contains(StatusFlags::CARRY);
         let res = self.a as u16 + value as u16 + previous_carry as u16;
 
         let carry = res > 0xff;
-        self.update_carry_flag(carry);
+        self.status.set(StatusFlags::CARRY, carry);
 
+        // signed overflow: both inputs have the same sign and the result's sign differs
         let overflow = (self.a ^ res as u8) & (value ^ res as u8) & 0x80 != 0;
-        self.update_overflow_flag(overflow);
+        self.status.set(StatusFlags::OVERFLOW, overflow);
 
         self.a = res as u8;
-        self.update_zero_and_negative_flags(res as u8);
+        self.update_zero_and_negative_flags(self.a);
     }
 
-    fn and(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    fn and(&mut self, addr: u16) {
         let value = self.mem_read(addr);
 
         self.a &= value;
         self.update_zero_and_negative_flags(self.a);
     }
 
-    fn lda(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    fn ora(&mut self, addr: u16) {
+        let value = self.mem_read(addr);
+
+        self.a |= value;
+        self.update_zero_and_negative_flags(self.a);
+    }
+
+    fn eor(&mut self, addr: u16) {
+        let value = self.mem_read(addr);
+
+        self.a ^= value;
+        self.update_zero_and_negative_flags(self.a);
+    }
+
+    // bit: zero flag comes from A & M, but N and V are copied straight from bits 7 and 6 of M
+    fn bit(&mut self, addr: u16) {
+        let value = self.mem_read(addr);
+
+        let operand = StatusFlags::from_bits_truncate(value);
+        self.status.set(StatusFlags::ZERO, self.a & value == 0);
+        self.status.set(
+            StatusFlags::OVERFLOW,
+            operand.contains(StatusFlags::OVERFLOW),
+        );
+        self.status.set(
+            StatusFlags::NEGATIVE,
+            operand.contains(StatusFlags::NEGATIVE),
+        );
+    }
+
+    fn asl(&mut self, addr: Option<u16>) -> u8 {
+        self.shift(addr, |value, _| (value << 1, value & 0x80 != 0))
+    }
+
+    fn lsr(&mut self, addr: Option<u16>) -> u8 {
+        self.shift(addr, |value, _| (value >> 1, value & 0x01 != 0))
+    }
+
+    fn rol(&mut self, addr: Option<u16>) -> u8 {
+        self.shift(addr, |value, carry| {
+            ((value << 1) | carry as u8, value & 0x80 != 0)
+        })
+    }
+
+    fn ror(&mut self, addr: Option<u16>) -> u8 {
+        self.shift(addr, |value, carry| {
+            ((value >> 1) | ((carry as u8) << 7), value & 0x01 != 0)
+        })
+    }
+
+    // shared read-modify-write for the shift family: `op` gets the operand and the old carry
+    // and returns the result plus the bit that was shifted out. no address means A
+    fn shift<F>(&mut self, addr: Option<u16>, op: F) -> u8
+    where
+        F: FnOnce(u8, bool) -> (u8, bool),
+    {
+        let carry = self.status.contains(StatusFlags::CARRY);
+
+        let (result, carry) = match addr {
+            None => {
+                let (result, carry) = op(self.a, carry);
+                self.a = result;
+                (result, carry)
+            }
+            Some(addr) => {
+                let (result, carry) = op(self.mem_read(addr), carry);
+                self.mem_write(addr, result);
+                (result, carry)
+            }
+        };
+
+        self.status.set(StatusFlags::CARRY, carry);
+        self.update_zero_and_negative_flags(result);
+        result
+    }
+
+    // inc/dec are read-modify-write: the flags follow the value written back, not A
+    fn inc(&mut self, addr: u16) -> u8 {
+        let value = self.mem_read(addr).wrapping_add(1);
+
+        self.mem_write(addr, value);
+        self.update_zero_and_negative_flags(value);
+        value
+    }
+
+    fn dec(&mut self, addr: u16) -> u8 {
+        let value = self.mem_read(addr).wrapping_sub(1);
+
+        self.mem_write(addr, value);
+        self.update_zero_and_negative_flags(value);
+        value
+    }
+
+    // compare: register - M without storing, carry means register >= M
+    fn compare(&mut self, addr: u16, register: u8) {
+        let value = self.mem_read(addr);
+
+        self.compare_value(register, value);
+    }
+
+    fn compare_value(&mut self, register: u8, value: u8) {
+        self.status.set(StatusFlags::CARRY, register >= value);
+        self.update_zero_and_negative_flags(register.wrapping_sub(value));
+    }
+
+    // taken when `flag` is `set`. returns the extra cycles spent: +1 when the branch is taken,
+    // +1 more if it lands on a different page than the next instruction
+    fn branch(&mut self, flag: StatusFlags, set: bool, target: u16) -> u8 {
+        if self.status.contains(flag) != set {
+            return 0;
+        }
+
+        let next = self.program_counter.wrapping_add(1);
+        self.jump(target);
+
+        if next & 0xff00 != target & 0xff00 {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn lda(&mut self, addr: u16) {
         let value = self.mem_read(addr);
 
         self.a = value;
         self.update_zero_and_negative_flags(value);
     }
 
-    fn sta(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    fn ldx(&mut self, addr: u16) {
+        let value = self.mem_read(addr);
+
+        self.x = value;
+        self.update_zero_and_negative_flags(value);
+    }
+
+    fn ldy(&mut self, addr: u16) {
+        let value = self.mem_read(addr);
+
+        self.y = value;
+        self.update_zero_and_negative_flags(value);
+    }
+
+    // stores never touch the flags
+    fn sta(&mut self, addr: u16) {
         self.mem_write(addr, self.a);
     }
 
+    fn stx(&mut self, addr: u16) {
+        self.mem_write(addr, self.x);
+    }
+
+    fn sty(&mut self, addr: u16) {
+        self.mem_write(addr, self.y);
+    }
+
     fn tax(&mut self) {
         self.x = self.a;
         self.update_zero_and_negative_flags(self.x);
     }
 
+    fn tay(&mut self) {
+        self.y = self.a;
+        self.update_zero_and_negative_flags(self.y);
+    }
+
+    fn txa(&mut self) {
+        self.a = self.x;
+        self.update_zero_and_negative_flags(self.a);
+    }
+
+    fn tya(&mut self) {
+        self.a = self.y;
+        self.update_zero_and_negative_flags(self.a);
+    }
+
+    fn tsx(&mut self) {
+        self.x = self.sp;
+        self.update_zero_and_negative_flags(self.x);
+    }
+
+    // the only transfer that leaves the flags alone
+    fn txs(&mut self) {
+        self.sp = self.x;
+    }
+
+    // for anything that moves the pc somewhere other than the next instruction, even when
+    // that's where it lands
+    fn jump(&mut self, addr: u16) {
+        self.program_counter = addr;
+        self.pc_written = true;
+    }
+
+    // jsr pushes the address of its own last byte, rts adds the missing one back
+    fn jsr(&mut self, target: u16) {
+        self.stack_push_u16(self.program_counter.wrapping_add(2 - 1));
+        self.jump(target);
+    }
+
+    fn rts(&mut self) {
+        let addr = self.stack_pop_u16().wrapping_add(1);
+        self.jump(addr);
+    }
+
+    fn rti(&mut self) {
+        self.plp();
+        let addr = self.stack_pop_u16();
+        self.jump(addr);
+    }
+
+    fn pha(&mut self) {
+        self.stack_push(self.a);
+    }
+
+    fn pla(&mut self) {
+        self.a = self.stack_pop();
+        self.update_zero_and_negative_flags(self.a);
+    }
+
+    // php always pushes with the break and unused bits set
+    fn php(&mut self) {
+        let pushed = self.status | StatusFlags::BREAK | StatusFlags::UNUSED;
+        self.stack_push(pushed.bits());
+    }
+
+    // plp ignores bits 4 and 5 of the pulled value and keeps whatever the live status had
+    fn plp(&mut self) {
+        let mut pulled = StatusFlags::from_bits_truncate(self.stack_pop());
+        pulled.set(StatusFlags::BREAK, self.status.contains(StatusFlags::BREAK));
+        pulled.set(
+            StatusFlags::UNUSED,
+            self.status.contains(StatusFlags::UNUSED),
+        );
+        self.status = pulled;
+    }
+
     fn inx(&mut self) {
         self.x = self.x.wrapping_add(1);
         self.update_zero_and_negative_flags(self.x);
     }
 
-    fn update_zero_and_negative_flags(&mut self, result: u8) {
-        // second LSB is the zero flag
-        if result == 0 {
-            self.status = self.status | ZERO_FLAG;
-        } else {
-            self.status = self.status & !ZERO_FLAG;
-        }
+    fn iny(&mut self) {
+        self.y = self.y.wrapping_add(1);
+        self.update_zero_and_negative_flags(self.y);
+    }
 
-        // MSB is negative flag
-        // if the new value of a is negative, ensure that the negative flag is set
-        if result & 0b1000_0000 != 0 {
-            self.status = self.status | NEGATIVE_FLAG;
-        } else {
-            self.status = self.status & !NEGATIVE_FLAG;
-        }
+    fn dex(&mut self) {
+        self.x = self.x.wrapping_sub(1);
+        self.update_zero_and_negative_flags(self.x);
     }
 
-    fn update_overflow_flag(&mut self, overflow: bool) {
-        if overflow {
-            self.status = self.status | OVERFLOW_FLAG;
-        } else {
-            self.status = self.status & !OVERFLOW_FLAG;
-        }
+    fn dey(&mut self) {
+        self.y = self.y.wrapping_sub(1);
+        self.update_zero_and_negative_flags(self.y);
     }
 
-    fn update_carry_flag(&mut self, carry: bool) {
-        if carry {
-            self.status = self.status | CARRY_FLAG;
-        } else {
-            self.status = self.status & !CARRY_FLAG;
-        }
+    // the unofficial opcodes, mostly two official instructions glued together
+
+    fn lax(&mut self, addr: u16) {
+        self.lda(addr);
+        self.x = self.a;
     }
 
-    pub fn run(&mut self) {
-        loop {
-            let opcode = OpCode::from_u8(self.mem_read(self.program_counter));
-            self.program_counter += 1;
-
-            match opcode.mnemonic {
-                "ADC" => self.adc(&opcode.mode),
-                "AND" => self.and(&opcode.mode),
-                "LDA" => self.lda(&opcode.mode),
-                "STA" => self.sta(&opcode.mode),
-                "TAX" => self.tax(),
-                "INX" => self.inx(),
-                "BRK" => return,
-                _ => unreachable!(),
-            }
+    fn sax(&mut self, addr: u16) {
+        self.mem_write(addr, self.a & self.x);
+    }
 
-            self.program_counter += opcode.bytes as u16 - 1;
-        }
+    fn dcp(&mut self, addr: u16) {
+        let value = self.dec(addr);
+        self.compare_value(self.a, value);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    fn isb(&mut self, addr: u16) {
+        let value = self.inc(addr);
+        self.add_to_register_a(!value);
+    }
 
-    #[test]
-    fn test_adc_immediate() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0x69, 0x05, 0x00]); // ADC #$05
-        cpu.run();
-        assert_eq!(cpu.a, 0x05);
+    fn slo(&mut self, addr: u16) {
+        self.a |= self.asl(Some(addr));
+        self.update_zero_and_negative_flags(self.a);
     }
 
-    #[test]
-    fn test_adc_memory() {
-        let mut cpu = CPU::new();
-        cpu.mem_write(0x10, 0x05);
-        cpu.load_and_run(vec![0x6d, 0x10, 0x00, 0x00]); // ADC $0010
-        cpu.run();
-        assert_eq!(cpu.a, 0x05);
+    fn rla(&mut self, addr: u16) {
+        self.a &= self.rol(Some(addr));
+        self.update_zero_and_negative_flags(self.a);
     }
 
-    #[test]
-    fn test_and_immediate() {
-        let mut cpu = CPU::new();
-        cpu.load(vec![0x29, 0xaa, 0x00]);
-        cpu.a = 0b1010_1010;
-        cpu.run();
-        assert_eq!(cpu.a, 0b1010_1010);
+    fn sre(&mut self, addr: u16) {
+        self.a ^= self.lsr(Some(addr));
+        self.update_zero_and_negative_flags(self.a);
     }
 
-    #[test]
-    fn test_and_memory() {
-        let mut cpu = CPU::new();
-        cpu.mem_write(0x10, 0b1010_1010);
-        cpu.load(vec![0x2d, 0x10, 0x00, 0x00]);
-        cpu.a = 0b1010_1010;
-        cpu.run();
-        assert_eq!(cpu.a, 0b1010_1010);
+    // the carry ROR shifts out is the one ADC adds in
+    fn rra(&mut self, addr: u16) {
+        let value = self.ror(Some(addr));
+        self.add_to_register_a(value);
     }
 
-    #[test]
-    fn test_lda_works_immediate() {
-        let mut cpu = CPU::new();
-        let program = vec![0xa9, 0x05, 0x00];
-        cpu.load_and_run(program);
+    // AND, then the carry is a copy of the sign
+    fn anc(&mut self, addr: u16) {
+        self.and(addr);
+        self.status.set(
+            StatusFlags::CARRY,
+            self.status.contains(StatusFlags::NEGATIVE),
+        );
+    }
 
-        assert_eq!(cpu.a, 0x05);
-        // check zero and negative flags aren't set
-        assert_eq!(cpu.status & 0b0000_0010, 0b0000_0000);
-        assert_eq!(cpu.status & 0b1000_0000, 0b0000_0000);
+    fn alr(&mut self, addr: u16) {
+        self.and(addr);
+        self.lsr(None);
     }
 
-    #[test]
-    fn test_lda_works_zero() {
-        let mut cpu = CPU::new();
-        let program = vec![0xa9, 0x00, 0x00];
-        cpu.load_and_run(program);
+    // AND then ROR A, except carry and overflow come from bits 6 and 5 of the result
+    fn arr(&mut self, addr: u16) {
+        self.and(addr);
+        self.ror(None);
+        let bit_6 = self.a & 0x40 != 0;
+        let bit_5 = self.a & 0x20 != 0;
+        self.status.set(StatusFlags::CARRY, bit_6);
+        self.status.set(StatusFlags::OVERFLOW, bit_6 ^ bit_5);
+    }
 
-        assert_eq!(cpu.a, 0);
-        assert_eq!(cpu.status & 0b0000_0010, 0b0000_0010);
+    // X = (A & X) - M, with the flags of a compare
+    fn axs(&mut self, addr: u16) {
+        let value = self.mem_read(addr);
+
+        let register = self.a & self.x;
+        self.compare_value(register, value);
+        self.x = register.wrapping_sub(value);
     }
 
-    #[test]
-    fn test_lda_works_from_memory() {
-        // zero page
-        let mut cpu = CPU::new();
-        cpu.mem_write(0x69, 0x42);
-        let program = vec![0xa5, 0x69];
-        cpu.load_and_run(program);
+    // what XAA and LXA OR into A first depends on the chip, 0xee is the usual measurement
+    fn xaa(&mut self, addr: u16) {
+        let value = self.mem_read(addr);
 
-        assert_eq!(cpu.a, 0x42);
+        self.a = (self.a | 0xee) & self.x & value;
+        self.update_zero_and_negative_flags(self.a);
+    }
 
-        // absolute
-        let mut cpu = CPU::new();
-        cpu.mem_write(0x69, 0x42);
-        let program = vec![0xad, 0x69, 0x00];
-        cpu.load_and_run(program);
+    fn lxa(&mut self, addr: u16) {
+        let value = self.mem_read(addr);
 
-        assert_eq!(cpu.a, 0x42);
+        self.a = (self.a | 0xee) & value;
+        self.x = self.a;
+        self.update_zero_and_negative_flags(self.a);
     }
 
-    #[test]
-    fn test_sta_works() {
-        let mut cpu = CPU::new();
-        cpu.mem_write(0x00, 42);
-        let program = vec![0xa5, 0x00, 0x85, 0x69, 0x00];
-        cpu.load_and_run(program);
+    // AHX, SHX, SHY and TAS store `value` ANDed with the high byte of the unindexed address
+    // plus one. when indexing crosses a page the real chip also mangles the address, which
+    // isn't done here
+    fn store_and_high(&mut self, addr: u16, index: u8, value: u8) {
+        let high = (addr.wrapping_sub(index as u16) >> 8) as u8;
+        self.mem_write(addr, value & high.wrapping_add(1));
+    }
 
-        assert_eq!(cpu.mem_read(0x69), 42);
+    fn tas(&mut self, addr: u16) {
+        self.sp = self.a & self.x;
+        self.store_and_high(addr, self.y, self.sp);
     }
 
-    #[test]
-    fn test_tax_works() {
-        let mut cpu = CPU::new();
-        let program = vec![0xa9, 0x69, 0xaa, 0x00];
-        cpu.load_and_run(program);
+    fn las(&mut self, addr: u16) {
+        let value = self.mem_read(addr) & self.sp;
 
-        assert_eq!(cpu.x, 0x69);
-        assert_eq!(cpu.status & 0b0000_0010, 0b0000_0000);
-        assert_eq!(cpu.status & 0b1000_0000, 0b0000_0000);
+        self.a = value;
+        self.x = value;
+        self.sp = value;
+        self.update_zero_and_negative_flags(value);
     }
 
-    #[test]
-    fn test_inx_works() {
-        let mut cpu = CPU::new();
-        let program = vec![0xe8, 0x00];
-        cpu.load_and_run(program);
+    // pushes the return address and the status, then jumps through `vector` with interrupts
+    // disabled. the break bit is only set on the pushed status when it's BRK doing this, which
+    // is how a handler shared with irq tells the two apart
+    fn interrupt(&mut self, vector: u16, brk: bool) {
+        self.stack_push_u16(self.program_counter);
+        let mut pushed = self.status | StatusFlags::UNUSED;
+        pushed.set(StatusFlags::BREAK, brk);
+        self.stack_push(pushed.bits());
+        self.status.insert(StatusFlags::INTERRUPT_DISABLE);
 
-        assert_eq!(cpu.x, 0x01);
-        assert_eq!(cpu.status & 0b0000_0010, 0b0000_0000);
-        assert_eq!(cpu.status & 0b1000_0000, 0b0000_0000);
+        let addr = self.mem_read_u16(vector);
+        self.jump(addr);
     }
 
-    #[test]
-    fn test_5_ops_working_together() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]);
+    // BRK has a padding byte after it, so the handler's RTI comes back 2 bytes on
+    fn brk(&mut self) {
+        self.program_counter = self.program_counter.wrapping_add(1);
+        self.interrupt(IRQ_VECTOR, true);
+    }
 
-        assert_eq!(cpu.x, 0xc1)
+    // counts `cycles` and runs the rest of the console for them, along with any the bus
+    // held the cpu up for, so this and Bus::cycles never drift apart
+    fn tick(&mut self, cycles: u8) {
+        let stalled = self.bus.tick(cycles as u16);
+        self.cycles += cycles as u64 + stalled as u64;
+    }
+
+    // nmi and irq take as long as BRK does, but they aren't instructions so step doesn't
+    // count them
+    fn hardware_interrupt(&mut self, vector: u16) {
+        self.tick(7);
+        self.interrupt(vector, false);
+    }
+
+    // like BRK from the outside, except the pushed status has the break bit clear
+    pub fn interrupt_nmi(&mut self) {
+        self.hardware_interrupt(NMI_VECTOR);
+    }
+
+    // ignores the interrupt disable flag, step checks that before taking an irq
+    pub fn interrupt_irq(&mut self) {
+        self.hardware_interrupt(IRQ_VECTOR);
+    }
+
+    fn update_zero_and_negative_flags(&mut self, result: u8) {
+        self.status.set(StatusFlags::ZERO, result == 0);
+        // MSB is the sign bit
+        self.status
+            .set(StatusFlags::NEGATIVE, result & 0b1000_0000 != 0);
+    }
+
+    pub fn run(&mut self) -> Result<StopReason, CpuError> {
+        self.run_with_callback(|_| {})
+    }
+
+    // `callback` gets the cpu before every instruction is fetched, to poke memory, look at
+    // registers, or set `halted` to return once it's done. runs until BRK, that, a
+    // breakpoint, a watchpoint, or an instruction the cpu can't execute
+    pub fn run_with_callback<F>(&mut self, mut callback: F) -> Result<StopReason, CpuError>
+    where
+        F: FnMut(&mut CPU),
+    {
+        self.halted = false;
+        loop {
+            callback(self);
+            if let StepResult::Stopped(reason) = self.step()? {
+                return Ok(reason);
+            }
+            if let Some(reason) = self.watchpoints.take_pending() {
+                return Ok(reason);
+            }
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.add(addr);
+    }
+
+    // false when there wasn't one at `addr`
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(addr)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> &[u16] {
+        self.breakpoints.addrs()
+    }
+
+    // runs until the cpu is about to fetch from `addr`, or stops for some other reason first
+    pub fn run_to(&mut self, addr: u16) -> Result<StopReason, CpuError> {
+        self.breakpoints.set_one_shot(Some(addr));
+        let result = self.run();
+        self.breakpoints.set_one_shot(None);
+        result
+    }
+
+    // one instruction, except a JSR runs until its subroutine has returned
+    pub fn step_over(&mut self) -> Result<StopReason, CpuError> {
+        let program_counter = self.program_counter;
+        if self.bus.peek(program_counter) == JSR {
+            return self.run_to(program_counter.wrapping_add(3));
+        }
+        match self.step()? {
+            StepResult::Executed { .. } => Ok(self
+                .watchpoints
+                .take_pending()
+                .unwrap_or(StopReason::Reached(self.program_counter))),
+            StepResult::Stopped(reason) => Ok(reason),
+        }
+    }
+
+    // stops run_with_callback once an instruction that touched `addrs` has finished
+    pub fn add_watchpoint(&mut self, addrs: RangeInclusive<u16>, kind: WatchKind) {
+        self.watchpoints.add(addrs, kind);
+    }
+
+    // false when there wasn't one with that range and kind
+    pub fn remove_watchpoint(&mut self, addrs: &RangeInclusive<u16>, kind: WatchKind) -> bool {
+        self.watchpoints.remove(addrs, kind)
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    pub fn watchpoints(&self) -> &[(RangeInclusive<u16>, WatchKind)] {
+        self.watchpoints.ranges()
+    }
+
+    // how many accesses the watchpoints have caught, not just the ones that stopped the cpu
+    pub fn watchpoint_hits(&self) -> u64 {
+        self.watchpoints.hits()
+    }
+
+    // whether fetching an opcode counts as a read. operand fetches always do
+    pub fn set_watch_opcode_fetches(&mut self, watch: bool) {
+        self.watchpoints.set_watch_opcode_fetches(watch);
+    }
+
+    // the watchpoint the last instruction step ran hit, run_with_callback takes these itself
+    pub fn take_watchpoint_hit(&mut self) -> Option<StopReason> {
+        self.watchpoints.take_pending()
+    }
+
+    // for a cpu that's just been deserialized, break and watchpoints stay with the debugger
+    // rather than coming and going with save states
+    #[cfg(feature = "savestate")]
+    pub(crate) fn take_debug_state(&mut self, from: &mut CPU) {
+        std::mem::swap(&mut self.breakpoints, &mut from.breakpoints);
+        std::mem::swap(&mut self.watchpoints, &mut from.watchpoints);
+    }
+
+    // takes a pending nmi, or an irq if they're enabled, then executes exactly one
+    // instruction unless there's a breakpoint in the way. interrupts are only ever taken
+    // between instructions, so one raised while I is set goes as soon as CLI or an RTI clears it
+    pub fn step(&mut self) -> Result<StepResult, CpuError> {
+        if self.halted {
+            return Ok(StepResult::Stopped(StopReason::Halted));
+        }
+        // the nmi's pushes count against the instruction it interrupted
+        self.watchpoints.start_instruction(self.program_counter);
+        if self.bus.poll_nmi_status() {
+            self.interrupt_nmi();
+            self.watchpoints.set_instruction_pc(self.program_counter);
+        } else if self.bus.irq_pending() && !self.status.contains(StatusFlags::INTERRUPT_DISABLE) {
+            self.interrupt_irq();
+            self.watchpoints.set_instruction_pc(self.program_counter);
+        }
+        if let Some(reason) = self.breakpoints.check(self.program_counter) {
+            return Ok(StepResult::Stopped(reason));
+        }
+
+        let program_counter = self.program_counter;
+        let code = self.bus.mem_read(program_counter);
+        if self.watchpoints.watch_opcode_fetches() {
+            self.watchpoints
+                .check(program_counter, WatchKind::Read, code);
+        }
+        let unknown = || CpuError::UnknownOpcode {
+            opcode: code,
+            pc: program_counter,
+        };
+        let opcode = OpCode::try_from_u8(code).ok_or_else(unknown)?;
+        self.program_counter = self.program_counter.wrapping_add(1);
+        let mut extra_cycles = 0;
+
+        let (operand_address, page_crossed) = match opcode.mode {
+            AddressingMode::NoneAddressing | AddressingMode::Accumulator => (None, false),
+            ref mode => {
+                let (addr, page_crossed) = self.get_operand_address(mode)?;
+                (Some(addr), page_crossed)
+            }
+        };
+        if page_crossed && opcode.page_cross_penalty {
+            extra_cycles += 1;
+        }
+
+        // the operand is only ever read once, it's resolved above and handed to the
+        // instruction. an instruction that wants one and has none means the table is wrong
+        let mode = opcode.mode;
+        let addr = move || operand_address.ok_or(CpuError::InvalidAddressingMode(mode));
+        self.pc_written = false;
+        match opcode.instruction {
+            Instruction::Adc => self.adc(addr()?),
+            Instruction::Sbc => self.sbc(addr()?),
+            Instruction::And => self.and(addr()?),
+            Instruction::Ora => self.ora(addr()?),
+            Instruction::Eor => self.eor(addr()?),
+            Instruction::Bit => self.bit(addr()?),
+            Instruction::Asl => {
+                self.asl(operand_address);
+            }
+            Instruction::Lsr => {
+                self.lsr(operand_address);
+            }
+            Instruction::Rol => {
+                self.rol(operand_address);
+            }
+            Instruction::Ror => {
+                self.ror(operand_address);
+            }
+            Instruction::Inc => {
+                self.inc(addr()?);
+            }
+            Instruction::Dec => {
+                self.dec(addr()?);
+            }
+            Instruction::Cmp => self.compare(addr()?, self.a),
+            Instruction::Cpx => self.compare(addr()?, self.x),
+            Instruction::Cpy => self.compare(addr()?, self.y),
+            Instruction::Bcc => extra_cycles += self.branch(StatusFlags::CARRY, false, addr()?),
+            Instruction::Bcs => extra_cycles += self.branch(StatusFlags::CARRY, true, addr()?),
+            Instruction::Bne => extra_cycles += self.branch(StatusFlags::ZERO, false, addr()?),
+            Instruction::Beq => extra_cycles += self.branch(StatusFlags::ZERO, true, addr()?),
+            Instruction::Bpl => extra_cycles += self.branch(StatusFlags::NEGATIVE, false, addr()?),
+            Instruction::Bmi => extra_cycles += self.branch(StatusFlags::NEGATIVE, true, addr()?),
+            Instruction::Bvc => extra_cycles += self.branch(StatusFlags::OVERFLOW, false, addr()?),
+            Instruction::Bvs => extra_cycles += self.branch(StatusFlags::OVERFLOW, true, addr()?),
+            Instruction::Lda => self.lda(addr()?),
+            Instruction::Ldx => self.ldx(addr()?),
+            Instruction::Ldy => self.ldy(addr()?),
+            Instruction::Sta => self.sta(addr()?),
+            Instruction::Stx => self.stx(addr()?),
+            Instruction::Sty => self.sty(addr()?),
+            Instruction::Clc => self.status.set(StatusFlags::CARRY, false),
+            Instruction::Sec => self.status.set(StatusFlags::CARRY, true),
+            Instruction::Cli => self.status.set(StatusFlags::INTERRUPT_DISABLE, false),
+            Instruction::Sei => self.status.set(StatusFlags::INTERRUPT_DISABLE, true),
+            Instruction::Clv => self.status.set(StatusFlags::OVERFLOW, false),
+            Instruction::Cld => self.status.set(StatusFlags::DECIMAL, false),
+            Instruction::Sed => self.status.set(StatusFlags::DECIMAL, true),
+            Instruction::Nop => {}
+            Instruction::Tax => self.tax(),
+            Instruction::Tay => self.tay(),
+            Instruction::Txa => self.txa(),
+            Instruction::Tya => self.tya(),
+            Instruction::Tsx => self.tsx(),
+            Instruction::Txs => self.txs(),
+            Instruction::Jmp => self.jump(addr()?),
+            Instruction::Jsr => self.jsr(addr()?),
+            Instruction::Rts => self.rts(),
+            Instruction::Rti => self.rti(),
+            Instruction::Pha => self.pha(),
+            Instruction::Pla => self.pla(),
+            Instruction::Php => self.php(),
+            Instruction::Plp => self.plp(),
+            Instruction::Inx => self.inx(),
+            Instruction::Iny => self.iny(),
+            Instruction::Dex => self.dex(),
+            Instruction::Dey => self.dey(),
+            Instruction::Brk if self.halt_on_brk => {
+                self.halted = true;
+                return Ok(StepResult::Stopped(StopReason::Halted));
+            }
+            Instruction::Brk => self.brk(),
+            Instruction::Lax => self.lax(addr()?),
+            Instruction::Sax => self.sax(addr()?),
+            Instruction::Dcp => self.dcp(addr()?),
+            Instruction::Isb => self.isb(addr()?),
+            Instruction::Slo => self.slo(addr()?),
+            Instruction::Rla => self.rla(addr()?),
+            Instruction::Sre => self.sre(addr()?),
+            Instruction::Rra => self.rra(addr()?),
+            Instruction::Anc => self.anc(addr()?),
+            Instruction::Alr => self.alr(addr()?),
+            Instruction::Arr => self.arr(addr()?),
+            Instruction::Axs => self.axs(addr()?),
+            Instruction::Xaa => self.xaa(addr()?),
+            Instruction::Lxa => self.lxa(addr()?),
+            Instruction::Ahx => self.store_and_high(addr()?, self.y, self.a & self.x),
+            Instruction::Shx => self.store_and_high(addr()?, self.y, self.x),
+            Instruction::Shy => self.store_and_high(addr()?, self.x, self.y),
+            Instruction::Tas => self.tas(addr()?),
+            Instruction::Las => self.las(addr()?),
+        }
+
+        let cycles = opcode.cycles + extra_cycles;
+        self.tick(cycles);
+
+        // instructions that jump set the PC themselves
+        if !std::mem::take(&mut self.pc_written) {
+            self.program_counter = self.program_counter.wrapping_add(opcode.bytes as u16 - 1);
+        }
+
+        Ok(StepResult::Executed {
+            opcode: code,
+            info: opcode,
+            program_counter,
+            operand_address,
+            cycles,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{assemble, assemble_at};
+    use crate::ppu::registers::StatusRegister;
+
+    #[test]
+    fn test_adc_immediate() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0x69, 0x05, 0x00]).unwrap(); // ADC #$05
+        cpu.run().unwrap();
+        assert_eq!(cpu.a, 0x05);
+    }
+
+    #[test]
+    fn test_adc_memory() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x10, 0x05);
+        cpu.load_and_run(vec![0x6d, 0x10, 0x00, 0x00]).unwrap(); // ADC $0010
+        cpu.run().unwrap();
+        assert_eq!(cpu.a, 0x05);
+    }
+
+    #[test]
+    fn test_adc_signed_overflow() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa9, 0x7f, 0x69, 0x01, 0x00])
+            .unwrap(); // LDA #$7f; ADC #$01
+
+        assert_eq!(cpu.a, 0x80);
+        assert!(cpu.status.contains(StatusFlags::OVERFLOW));
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+        assert!(!cpu.status.contains(StatusFlags::CARRY));
+        assert!(!cpu.status.contains(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_adc_unsigned_carry() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa9, 0xff, 0x69, 0x01, 0x00])
+            .unwrap(); // LDA #$ff; ADC #$01
+
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+        assert!(!cpu.status.contains(StatusFlags::OVERFLOW));
+        assert!(!cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_adc_carry_propagates_across_bytes() {
+        // 0x01ff + 0x0001, low byte then high byte
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![
+            0xa9, 0xff, // LDA #$ff
+            0x69, 0x01, // ADC #$01
+            0x85, 0x10, // STA $10
+            0xa9, 0x01, // LDA #$01
+            0x69, 0x00, // ADC #$00
+            0x85, 0x11, // STA $11
+            0x00,
+        ])
+        .unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0x00);
+        assert_eq!(cpu.mem_read(0x11), 0x02);
+        assert!(!cpu.status.contains(StatusFlags::CARRY));
+    }
+
+    #[test]
+    fn test_sbc_no_borrow() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0xe9, 0x03, 0x00]).unwrap(); // SBC #$03
+        cpu.reset();
+        cpu.a = 0x05;
+        cpu.status = StatusFlags::CARRY;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.a, 0x02);
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+        assert!(!cpu.status.contains(StatusFlags::ZERO));
+        assert!(!cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_sbc_uses_carry_as_borrow() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0xe9, 0x03, 0x00]).unwrap(); // SBC #$03
+        cpu.reset();
+        cpu.a = 0x05;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.a, 0x01);
+    }
+
+    #[test]
+    fn test_sbc_borrow_clears_carry() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0xe9, 0x01, 0x00]).unwrap(); // SBC #$01
+        cpu.reset();
+        cpu.a = 0x00;
+        cpu.status = StatusFlags::CARRY;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.a, 0xff);
+        assert!(!cpu.status.contains(StatusFlags::CARRY));
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_sbc_signed_overflow() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x10, 0x01);
+        cpu.load(vec![0xe5, 0x10, 0x00]).unwrap(); // SBC $10
+        cpu.reset();
+        cpu.a = 0x80;
+        cpu.status = StatusFlags::CARRY;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.a, 0x7f);
+        assert!(cpu.status.contains(StatusFlags::OVERFLOW));
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+        assert!(!cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_and_immediate() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0x29, 0xaa, 0x00]).unwrap();
+        cpu.reset();
+        cpu.a = 0b1010_1010;
+        cpu.run().unwrap();
+        assert_eq!(cpu.a, 0b1010_1010);
+    }
+
+    #[test]
+    fn test_and_memory() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x10, 0b1010_1010);
+        cpu.load(vec![0x2d, 0x10, 0x00, 0x00]).unwrap();
+        cpu.reset();
+        cpu.a = 0b1010_1010;
+        cpu.run().unwrap();
+        assert_eq!(cpu.a, 0b1010_1010);
+    }
+
+    #[test]
+    fn test_and_sets_zero_flag() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa9, 0xf0, 0x29, 0x0f, 0x00])
+            .unwrap(); // LDA #$f0; AND #$0f
+
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_ora_immediate() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa9, 0x81, 0x09, 0x18, 0x00])
+            .unwrap(); // LDA #$81; ORA #$18
+
+        assert_eq!(cpu.a, 0x99);
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+        assert!(!cpu.status.contains(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_ora_indirect_y() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write_u16(0x20, 0x0400);
+        cpu.mem_write(0x0403, 0b0000_0110);
+        cpu.load(vec![0x11, 0x20, 0x00]).unwrap(); // ORA ($20),Y
+        cpu.reset();
+        cpu.a = 0b0000_0001;
+        cpu.y = 0x03;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.a, 0b0000_0111);
+    }
+
+    #[test]
+    fn test_eor_immediate() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa9, 0xff, 0x49, 0x0f, 0x00])
+            .unwrap(); // LDA #$ff; EOR #$0f
+
+        assert_eq!(cpu.a, 0xf0);
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_eor_with_itself_is_zero() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x10, 0x5a);
+        cpu.load_and_run(vec![0xa5, 0x10, 0x45, 0x10, 0x00])
+            .unwrap(); // LDA $10; EOR $10
+
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_bit_copies_operand_bits_into_flags() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x10, 0b1100_0000);
+        cpu.load_and_run(vec![0xa9, 0x01, 0x24, 0x10, 0x00])
+            .unwrap(); // LDA #$01; BIT $10
+
+        // a is untouched, A & M == 0 sets zero, N and V come from the operand
+        assert_eq!(cpu.a, 0x01);
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+        assert!(cpu.status.contains(StatusFlags::OVERFLOW));
+    }
+
+    #[test]
+    fn test_bit_clears_flags_from_operand() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x0210, 0b0000_0011);
+        cpu.load(vec![0x2c, 0x10, 0x02, 0x00]).unwrap(); // BIT $0210
+        cpu.reset();
+        cpu.a = 0x02;
+        cpu.status = StatusFlags::NEGATIVE | StatusFlags::OVERFLOW | StatusFlags::ZERO;
+        cpu.run().unwrap();
+
+        assert!(!cpu.status.contains(StatusFlags::ZERO));
+        assert!(!cpu.status.contains(StatusFlags::NEGATIVE));
+        assert!(!cpu.status.contains(StatusFlags::OVERFLOW));
+    }
+
+    #[test]
+    fn test_asl_accumulator_sets_carry_and_zero() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa9, 0x80, 0x0a, 0x00]).unwrap(); // LDA #$80; ASL A
+
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+        assert!(!cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_lsr_accumulator() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa9, 0x03, 0x4a, 0x00]).unwrap(); // LDA #$03; LSR A
+
+        assert_eq!(cpu.a, 0x01);
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+        assert!(!cpu.status.contains(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_rol_accumulator_shifts_carry_in() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0x2a, 0x00]).unwrap(); // ROL A
+        cpu.reset();
+        cpu.a = 0b0100_0000;
+        cpu.status = StatusFlags::CARRY;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.a, 0b1000_0001);
+        assert!(!cpu.status.contains(StatusFlags::CARRY));
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_ror_accumulator_with_carry_is_negative() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0x6a, 0x00]).unwrap(); // ROR A
+        cpu.reset();
+        cpu.a = 0x02;
+        cpu.status = StatusFlags::CARRY;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.a, 0x81);
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+        assert!(!cpu.status.contains(StatusFlags::CARRY));
+    }
+
+    #[test]
+    fn test_asl_memory_modifies_target_not_accumulator() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x10, 0b0101_0101);
+        cpu.load(vec![0x06, 0x10, 0x00]).unwrap(); // ASL $10
+        cpu.reset();
+        cpu.a = 0x42;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0b1010_1010);
+        assert_eq!(cpu.a, 0x42);
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+        assert!(!cpu.status.contains(StatusFlags::CARRY));
+    }
+
+    #[test]
+    fn test_ror_absolute_x_memory() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x0305, 0x01);
+        cpu.load(vec![0x7e, 0x00, 0x03, 0x00]).unwrap(); // ROR $0300,X
+        cpu.reset();
+        cpu.x = 0x05;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x0305), 0x00);
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_inc_zero_page_wraps() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x10, 0xff);
+        cpu.load_and_run(vec![0xa9, 0x42, 0xe6, 0x10, 0x00])
+            .unwrap(); // LDA #$42; INC $10
+
+        assert_eq!(cpu.mem_read(0x10), 0x00);
+        assert_eq!(cpu.a, 0x42);
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+        assert!(!cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_dec_absolute_x_goes_negative() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0xde, 0x00, 0x03, 0x00]).unwrap(); // DEC $0300,X
+        cpu.reset();
+        cpu.x = 0x01;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x0301), 0xff);
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_dec_countdown_loop() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x20, 0x05);
+        cpu.load_and_run(vec![
+            0xe8, // loop: INX
+            0xc6, 0x20, // DEC $20
+            0xd0, 0xfb, // BNE loop
+            0x00,
+        ])
+        .unwrap();
+
+        assert_eq!(cpu.mem_read(0x20), 0x00);
+        assert_eq!(cpu.x, 0x05);
+    }
+
+    #[test]
+    fn test_cmp_less_than_clears_carry_sets_negative() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa9, 0x10, 0xc9, 0x20, 0x00])
+            .unwrap(); // LDA #$10; CMP #$20
+
+        assert_eq!(cpu.a, 0x10);
+        assert!(!cpu.status.contains(StatusFlags::CARRY));
+        assert!(!cpu.status.contains(StatusFlags::ZERO));
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_cmp_equal_sets_carry_and_zero() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x10, 0x42);
+        cpu.load_and_run(vec![0xa9, 0x42, 0xc5, 0x10, 0x00])
+            .unwrap(); // LDA #$42; CMP $10
+
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+        assert!(!cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_cmp_greater_sets_carry() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa9, 0x30, 0xc9, 0x20, 0x00])
+            .unwrap(); // LDA #$30; CMP #$20
+
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+        assert!(!cpu.status.contains(StatusFlags::ZERO));
+        assert!(!cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_cpx_loop() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![
+            0xe8, // loop: INX
+            0xe0, 0x05, // CPX #$05
+            0xd0, 0xfb, // BNE loop
+            0x00,
+        ])
+        .unwrap();
+
+        assert_eq!(cpu.x, 0x05);
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+    }
+
+    #[test]
+    fn test_cpy_absolute() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x0200, 0x80);
+        cpu.load(vec![0xcc, 0x00, 0x02, 0x00]).unwrap(); // CPY $0200
+        cpu.reset();
+        cpu.y = 0x7f;
+        cpu.run().unwrap();
+
+        assert!(!cpu.status.contains(StatusFlags::CARRY));
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_bne_backwards_loop_terminates() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(
+            assemble(
+                "
+                        LDA #$01
+                  loop: INX
+                        ASL A
+                        BNE loop
+                        BRK
+                ",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        // A is shifted out to zero after 8 passes through the loop
+        assert_eq!(cpu.x, 0x08);
+        assert_eq!(cpu.a, 0x00);
+        assert_eq!(cpu.program_counter, 0x8007);
+    }
+
+    #[test]
+    fn test_branch_taken_skips_forward() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![
+            0xa9, 0x00, // LDA #$00
+            0xf0, 0x01, // BEQ +1
+            0xe8, // INX (skipped)
+            0xe8, // INX
+            0x00,
+        ])
+        .unwrap();
+
+        assert_eq!(cpu.x, 0x01);
+    }
+
+    #[test]
+    fn test_branch_not_taken_falls_through() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![
+            0xa9, 0x01, // LDA #$01
+            0xf0, 0x01, // BEQ +1 (not taken)
+            0xe8, // INX
+            0xe8, // INX
+            0x00,
+        ])
+        .unwrap();
+
+        assert_eq!(cpu.x, 0x02);
+    }
+
+    #[test]
+    fn test_branch_on_each_flag() {
+        // each branch is taken over an INX, so X stays 0 if all conditions hold
+        let cases: [(u8, StatusFlags); 8] = [
+            (0x90, StatusFlags::empty()),  // BCC
+            (0xb0, StatusFlags::CARRY),    // BCS
+            (0xd0, StatusFlags::empty()),  // BNE
+            (0xf0, StatusFlags::ZERO),     // BEQ
+            (0x10, StatusFlags::empty()),  // BPL
+            (0x30, StatusFlags::NEGATIVE), // BMI
+            (0x50, StatusFlags::empty()),  // BVC
+            (0x70, StatusFlags::OVERFLOW), // BVS
+        ];
+
+        for (opcode, status) in cases {
+            let mut cpu = CPU::new(Bus::new());
+            cpu.load(vec![opcode, 0x01, 0xe8, 0x00]).unwrap();
+            cpu.reset();
+            cpu.status = status;
+            cpu.run().unwrap();
+
+            assert_eq!(cpu.x, 0, "branch {:#04x} not taken", opcode);
+        }
+    }
+
+    #[test]
+    fn test_branch_cycle_penalties() {
+        let mut cpu = CPU::new(Bus::new());
+        // offset byte at 0x8010, next instruction at 0x8011
+        cpu.mem_write(0x8010, 0x05);
+        cpu.program_counter = 0x8010;
+        let (target, _) = cpu.get_operand_address(&AddressingMode::Relative).unwrap();
+        assert_eq!(cpu.branch(StatusFlags::CARRY, true, target), 0);
+        assert_eq!(cpu.program_counter, 0x8010);
+        assert_eq!(cpu.branch(StatusFlags::CARRY, false, target), 1);
+        assert_eq!(cpu.program_counter, 0x8016);
+
+        // backwards across a page boundary
+        cpu.mem_write(0x8100, 0xf0);
+        cpu.program_counter = 0x8100;
+        let (target, _) = cpu.get_operand_address(&AddressingMode::Relative).unwrap();
+        assert_eq!(cpu.branch(StatusFlags::CARRY, false, target), 2);
+        assert_eq!(cpu.program_counter, 0x80f1);
+    }
+
+    #[test]
+    fn test_lda_works_immediate() {
+        let mut cpu = CPU::new(Bus::new());
+        let program = vec![0xa9, 0x05, 0x00];
+        cpu.load_and_run(program).unwrap();
+
+        assert_eq!(cpu.a, 0x05);
+        // check zero and negative flags aren't set
+        assert!(!cpu.status.contains(StatusFlags::ZERO));
+        assert!(!cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_lda_works_zero() {
+        let mut cpu = CPU::new(Bus::new());
+        let program = vec![0xa9, 0x00, 0x00];
+        cpu.load_and_run(program).unwrap();
+
+        assert_eq!(cpu.a, 0);
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_lda_works_from_memory() {
+        // zero page
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x69, 0x42);
+        let program = vec![0xa5, 0x69];
+        cpu.load_and_run(program).unwrap();
+
+        assert_eq!(cpu.a, 0x42);
+
+        // absolute
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x69, 0x42);
+        let program = vec![0xad, 0x69, 0x00];
+        cpu.load_and_run(program).unwrap();
+
+        assert_eq!(cpu.a, 0x42);
+    }
+
+    #[test]
+    fn test_lda_zero_page_x() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x15, 0x42);
+        cpu.load(vec![0xb5, 0x10, 0x00]).unwrap();
+        cpu.reset();
+        cpu.x = 0x05;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.a, 0x42);
+    }
+
+    #[test]
+    fn test_lda_zero_page_x_wraps() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x10, 0x42);
+        cpu.load(vec![0xb5, 0xff, 0x00]).unwrap();
+        cpu.reset();
+        cpu.x = 0x11;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.a, 0x42);
+    }
+
+    #[test]
+    fn test_lda_absolute_x() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x0302, 0x42);
+        cpu.load(vec![0xbd, 0x00, 0x03, 0x00]).unwrap();
+        cpu.reset();
+        cpu.x = 0x02;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.a, 0x42);
+    }
+
+    #[test]
+    fn test_lda_absolute_y() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x0304, 0x42);
+        cpu.load(vec![0xb9, 0x00, 0x03, 0x00]).unwrap();
+        cpu.reset();
+        cpu.y = 0x04;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.a, 0x42);
+    }
+
+    #[test]
+    fn test_lda_indirect_x() {
+        let mut cpu = CPU::new(Bus::new());
+        // pointer at 0x24 -> 0x0400
+        cpu.mem_write_u16(0x24, 0x0400);
+        cpu.mem_write(0x0400, 0x42);
+        cpu.load(vec![0xa1, 0x20, 0x00]).unwrap();
+        cpu.reset();
+        cpu.x = 0x04;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.a, 0x42);
+    }
+
+    #[test]
+    fn test_lda_indirect_y() {
+        let mut cpu = CPU::new(Bus::new());
+        // pointer at 0x20 -> 0x0400, then + Y
+        cpu.mem_write_u16(0x20, 0x0400);
+        cpu.mem_write(0x0405, 0x42);
+        cpu.load(vec![0xb1, 0x20, 0x00]).unwrap();
+        cpu.reset();
+        cpu.y = 0x05;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.a, 0x42);
+    }
+
+    #[test]
+    fn test_program_counter_advances_by_table_bytes() {
+        let mut cpu = CPU::new(Bus::new());
+        // LDA $0010 (3 bytes), LDA #$01 (2 bytes), TAX (1 byte), BRK
+        cpu.load_and_run(vec![0xad, 0x10, 0x00, 0xa9, 0x01, 0xaa, 0x00])
+            .unwrap();
+
+        // BRK sits at 0x8006 and PC points past its opcode byte
+        assert_eq!(cpu.program_counter, 0x8007);
+        assert_eq!(cpu.x, 0x01);
+    }
+
+    #[test]
+    fn test_sta_works() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x00, 42);
+        let program = vec![0xa5, 0x00, 0x85, 0x69, 0x00];
+        cpu.load_and_run(program).unwrap();
+
+        assert_eq!(cpu.mem_read(0x69), 42);
+    }
+
+    #[test]
+    fn test_store_and_load_round_trip() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![
+            0xa9, 0x5a, // LDA #$5a
+            0x8d, 0x00, 0x03, // STA $0300
+            0xae, 0x00, 0x03, // LDX $0300
+            0xe0, 0x5a, // CPX #$5a
+            0x00,
+        ])
+        .unwrap();
+
+        assert_eq!(cpu.mem_read(0x0300), 0x5a);
+        assert_eq!(cpu.x, 0x5a);
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_ldx_immediate_sets_flags() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa2, 0x80, 0x00]).unwrap(); // LDX #$80
+
+        assert_eq!(cpu.x, 0x80);
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_ldx_zero_page_y() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x05, 0x42);
+        cpu.load(vec![0xb6, 0xf0, 0x00]).unwrap(); // LDX $f0,Y
+        cpu.reset();
+        cpu.y = 0x15; // wraps within the zero page
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.x, 0x42);
+    }
+
+    #[test]
+    fn test_ldy_absolute_x() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x0310, 0x00);
+        cpu.load(vec![0xbc, 0x00, 0x03, 0x00]).unwrap(); // LDY $0300,X
+        cpu.reset();
+        cpu.x = 0x10;
+        cpu.y = 0x01;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.y, 0x00);
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_stx_zero_page_y() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0x96, 0x10, 0x00]).unwrap(); // STX $10,Y
+        cpu.reset();
+        cpu.x = 0x42;
+        cpu.y = 0x02;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x12), 0x42);
+    }
+
+    #[test]
+    fn test_stores_do_not_touch_flags() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![
+            0x85, 0x10, // STA $10
+            0x8e, 0x11, 0x00, // STX $0011
+            0x94, 0x10, // STY $10,X
+            0x00,
+        ])
+        .unwrap();
+        cpu.reset();
+        cpu.a = 0x00;
+        cpu.x = 0x02;
+        cpu.y = 0x80;
+        cpu.status = StatusFlags::CARRY;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0x00);
+        assert_eq!(cpu.mem_read(0x11), 0x02);
+        assert_eq!(cpu.mem_read(0x12), 0x80);
+        assert_eq!(cpu.status, StatusFlags::CARRY);
+    }
+
+    #[test]
+    fn test_tax_works() {
+        let mut cpu = CPU::new(Bus::new());
+        let program = vec![0xa9, 0x69, 0xaa, 0x00];
+        cpu.load_and_run(program).unwrap();
+
+        assert_eq!(cpu.x, 0x69);
+        assert!(!cpu.status.contains(StatusFlags::ZERO));
+        assert!(!cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_tay_works() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa9, 0x80, 0xa8, 0x00]).unwrap(); // LDA #$80; TAY
+
+        assert_eq!(cpu.y, 0x80);
+        assert!(!cpu.status.contains(StatusFlags::ZERO));
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_txa_works() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa9, 0x42, 0xa2, 0x00, 0x8a, 0x00])
+            .unwrap(); // LDA #$42; LDX #$00; TXA
+
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_tya_works() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa0, 0x69, 0x98, 0x00]).unwrap(); // LDY #$69; TYA
+
+        assert_eq!(cpu.a, 0x69);
+        assert!(!cpu.status.contains(StatusFlags::ZERO));
+        assert!(!cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_tsx_updates_flags() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xba, 0x00]).unwrap(); // TSX
+
+        assert_eq!(cpu.x, 0xfd);
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_txs_does_not_update_flags() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa2, 0x00, 0xa9, 0x01, 0x9a, 0x00])
+            .unwrap(); // LDX #$00; LDA #$01; TXS
+
+        assert_eq!(cpu.sp, 0x00);
+        // zero flag still reflects the LDA, not the transferred value
+        assert!(!cpu.status.contains(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_iny_overflow() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa0, 0xff, 0xc8, 0x00]).unwrap(); // LDY #$ff; INY
+
+        assert_eq!(cpu.y, 0x00);
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+        assert!(!cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_dex_wraps_to_negative() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xca, 0x00]).unwrap(); // DEX
+
+        assert_eq!(cpu.x, 0xff);
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+        assert!(!cpu.status.contains(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_dex_countdown_loop() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![
+            0xa2, 0x08, // LDX #$08
+            0xc8, // loop: INY
+            0xca, // DEX
+            0xd0, 0xfc, // BNE loop
+            0x00,
+        ])
+        .unwrap();
+
+        assert_eq!(cpu.x, 0x00);
+        assert_eq!(cpu.y, 0x08);
+    }
+
+    #[test]
+    fn test_dey_works() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa0, 0x01, 0x88, 0x00]).unwrap(); // LDY #$01; DEY
+
+        assert_eq!(cpu.y, 0x00);
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_sec_clc_leave_other_flags_untouched() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0x38, 0x00]).unwrap(); // SEC
+        cpu.reset();
+        cpu.status = StatusFlags::NEGATIVE | StatusFlags::ZERO;
+        cpu.run().unwrap();
+        assert_eq!(
+            cpu.status,
+            StatusFlags::NEGATIVE | StatusFlags::ZERO | StatusFlags::CARRY
+        );
+
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0x18, 0x00]).unwrap(); // CLC
+        cpu.reset();
+        cpu.status = StatusFlags::OVERFLOW | StatusFlags::CARRY;
+        cpu.run().unwrap();
+        assert_eq!(cpu.status, StatusFlags::OVERFLOW);
+    }
+
+    #[test]
+    fn test_sei_cli() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0x78, 0x00]).unwrap(); // SEI
+        assert!(cpu.status.contains(StatusFlags::INTERRUPT_DISABLE));
+
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0x78, 0x58, 0x00]).unwrap(); // SEI; CLI
+        assert!(!cpu.status.contains(StatusFlags::INTERRUPT_DISABLE));
+    }
+
+    #[test]
+    fn test_clv_clears_overflow() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa9, 0x7f, 0x69, 0x01, 0xb8, 0x00])
+            .unwrap(); // LDA #$7f; ADC #$01; CLV
+
+        assert!(!cpu.status.contains(StatusFlags::OVERFLOW));
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_decimal_flag_round_trips() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xf8, 0x08, 0xd8, 0x00]).unwrap(); // SED; PHP; CLD
+
+        assert!(!cpu.status.contains(StatusFlags::DECIMAL));
+        let pushed = StatusFlags::from_bits_truncate(cpu.mem_read(0x01fd));
+        assert!(pushed.contains(StatusFlags::DECIMAL));
+    }
+
+    #[test]
+    fn test_clc_before_adc() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0x38, 0x18, 0xa9, 0x01, 0x69, 0x01, 0x00])
+            .unwrap(); // SEC; CLC; LDA #1; ADC #1
+
+        assert_eq!(cpu.a, 0x02);
+    }
+
+    #[test]
+    fn test_nop_does_nothing() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xea, 0xea, 0x00]).unwrap(); // NOP; NOP
+
+        assert_eq!(cpu.a, 0);
+        // just what reset left
+        assert_eq!(cpu.status, StatusFlags::INTERRUPT_DISABLE);
+        assert_eq!(cpu.program_counter, 0x8003);
+    }
+
+    #[test]
+    fn test_jmp_absolute() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![
+            0x4c, 0x04, 0x80, // JMP $8004
+            0xe8, // INX (skipped)
+            0xc8, // INY
+            0x00,
+        ])
+        .unwrap();
+
+        assert_eq!(cpu.x, 0x00);
+        assert_eq!(cpu.y, 0x01);
+    }
+
+    #[test]
+    fn test_jmp_indirect() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write_u16(0x0120, 0x8004);
+        cpu.load_and_run(vec![
+            0x6c, 0x20, 0x01, // JMP ($0120)
+            0xe8, // INX (skipped)
+            0xc8, // INY
+            0x00,
+        ])
+        .unwrap();
+
+        assert_eq!(cpu.x, 0x00);
+        assert_eq!(cpu.y, 0x01);
+    }
+
+    #[test]
+    fn test_jmp_indirect_page_boundary_bug() {
+        let mut cpu = CPU::new(Bus::new());
+        // low byte at 0x02ff, high byte fetched from 0x0200 rather than 0x0300
+        cpu.mem_write(0x02ff, 0x05);
+        cpu.mem_write(0x0200, 0x80);
+        cpu.mem_write(0x0300, 0x90);
+        cpu.load_and_run(vec![
+            0x6c, 0xff, 0x02, // JMP ($02ff)
+            0x00, 0x00, // padding
+            0xe8, // INX at 0x8005
+            0x00,
+        ])
+        .unwrap();
+
+        assert_eq!(cpu.x, 0x01);
+        assert_eq!(cpu.program_counter, 0x8007);
+    }
+
+    #[test]
+    fn test_jsr_rts() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(
+            assemble(
+                "
+                        JSR sub
+                        INY
+                        BRK
+                        .byte 0, 0
+                  sub:  INX
+                        RTS
+                ",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(cpu.x, 0x01);
+        assert_eq!(cpu.y, 0x01);
+        assert_eq!(cpu.sp, STACK_RESET);
+        assert_eq!(cpu.program_counter, 0x8005);
+    }
+
+    #[test]
+    fn test_jsr_pushes_address_of_last_byte() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![
+            0x20, 0x04, 0x80, // JSR $8004
+            0x00, // BRK
+            0x00, // $8004: BRK
+        ])
+        .unwrap();
+
+        assert_eq!(cpu.sp, STACK_RESET - 2);
+        assert_eq!(cpu.mem_read(0x01fd), 0x80);
+        assert_eq!(cpu.mem_read(0x01fc), 0x02);
+    }
+
+    #[test]
+    fn test_rti_restores_status_then_pc() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![
+            0x40, // RTI
+            0x00, 0x00, // padding
+            0xe8, // $8003: INX
+            0x00,
+        ])
+        .unwrap();
+        cpu.reset();
+        cpu.stack_push_u16(0x8003);
+        cpu.stack_push((StatusFlags::CARRY | StatusFlags::BREAK | StatusFlags::UNUSED).bits());
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.x, 0x01);
+        assert_eq!(cpu.status, StatusFlags::CARRY);
+        assert_eq!(cpu.sp, STACK_RESET);
+    }
+
+    #[test]
+    fn test_push_pull_reverse_order() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![
+            0xa9, 0x01, 0x48, // LDA #$01; PHA
+            0xa9, 0x02, 0x48, // LDA #$02; PHA
+            0xa9, 0x03, 0x48, // LDA #$03; PHA
+            0x68, 0xaa, // PLA; TAX
+            0x68, 0xa8, // PLA; TAY
+            0x68, // PLA
+            0x00,
+        ])
+        .unwrap();
+
+        assert_eq!(cpu.x, 0x03);
+        assert_eq!(cpu.y, 0x02);
+        assert_eq!(cpu.a, 0x01);
+        assert_eq!(cpu.sp, STACK_RESET);
+    }
+
+    #[test]
+    fn test_pha_writes_page_one_and_decrements_sp() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa9, 0x42, 0x48, 0x48, 0x00])
+            .unwrap(); // LDA #$42; PHA; PHA
+
+        assert_eq!(cpu.sp, 0xfb);
+        assert_eq!(cpu.mem_read(0x01fd), 0x42);
+        assert_eq!(cpu.mem_read(0x01fc), 0x42);
+    }
+
+    #[test]
+    fn test_pla_updates_flags() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(vec![0xa9, 0x00, 0x48, 0xa9, 0x01, 0x68, 0x00])
+            .unwrap(); // LDA #0; PHA; LDA #1; PLA
+
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_php_pushes_break_and_unused_bits() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0x08, 0x00]).unwrap(); // PHP
+        cpu.reset();
+        cpu.status = StatusFlags::CARRY;
+        cpu.run().unwrap();
+
+        let pushed = StatusFlags::CARRY | StatusFlags::BREAK | StatusFlags::UNUSED;
+        assert_eq!(cpu.mem_read(0x01fd), pushed.bits());
+        assert_eq!(cpu.status, StatusFlags::CARRY);
+    }
+
+    #[test]
+    fn test_php_plp_round_trip_does_not_leak_break() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0x08, 0x28, 0x00]).unwrap(); // PHP; PLP
+        cpu.reset();
+        cpu.status = StatusFlags::NEGATIVE | StatusFlags::CARRY;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.status, StatusFlags::NEGATIVE | StatusFlags::CARRY);
+        assert_eq!(cpu.sp, STACK_RESET);
+    }
+
+    #[test]
+    fn test_stack_wraps_within_page_one() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.sp = 0x00;
+        cpu.stack_push(0x11);
+        assert_eq!(cpu.sp, 0xff);
+        assert_eq!(cpu.mem_read(0x0100), 0x11);
+
+        cpu.stack_push_u16(0xbeef);
+        assert_eq!(cpu.mem_read(0x01ff), 0xbe);
+        assert_eq!(cpu.mem_read(0x01fe), 0xef);
+        assert_eq!(cpu.stack_pop_u16(), 0xbeef);
+        assert_eq!(cpu.stack_pop(), 0x11);
+        assert_eq!(cpu.sp, 0x00);
+    }
+
+    #[test]
+    fn test_inx_works() {
+        let mut cpu = CPU::new(Bus::new());
+        let program = vec![0xe8, 0x00];
+        cpu.load_and_run(program).unwrap();
+
+        assert_eq!(cpu.x, 0x01);
+        assert!(!cpu.status.contains(StatusFlags::ZERO));
+        assert!(!cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_5_ops_working_together() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(assemble("LDA #$c0\nTAX\nINX\nBRK").unwrap())
+            .unwrap();
+
+        assert_eq!(cpu.x, 0xc1)
     }
 
     #[test]
     fn test_inx_overflow() {
-        let mut cpu = CPU::new();
+        let mut cpu = CPU::new(Bus::new());
         let mut program = vec![0xe8; 256];
         program.push(0x00);
-        cpu.load_and_run(program);
+        cpu.load_and_run(program).unwrap();
 
         assert_eq!(cpu.x, 0)
     }
+
+    #[test]
+    fn test_top_of_memory_is_addressable() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write_u16(0xfffe, 0x1234);
+
+        assert_eq!(cpu.mem_read(0xffff), 0x12);
+        assert_eq!(cpu.mem_read_u16(0xfffe), 0x1234);
+    }
+
+    #[test]
+    fn test_mem_u16_wraps_at_top_of_memory() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write_u16(0xffff, 0xbeef);
+
+        assert_eq!(cpu.mem_read(0xffff), 0xef);
+        assert_eq!(cpu.mem_read(0x0000), 0xbe);
+        assert_eq!(cpu.mem_read_u16(0xffff), 0xbeef);
+    }
+
+    #[test]
+    fn test_runs_against_a_prepopulated_bus() {
+        let mut bus = Bus::new();
+        bus.load(&[0xa5, 0x10, 0x85, 0x11, 0x00]).unwrap(); // LDA $10; STA $11; BRK
+        bus.mem_write(0x10, 0x42);
+
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x11), 0x42);
+    }
+
+    #[test]
+    fn test_nmi_pushes_state_and_jumps_through_vector() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write_u16(0xfffa, 0x9000);
+        cpu.program_counter = 0x8123;
+        cpu.status = StatusFlags::CARRY | StatusFlags::BREAK;
+
+        cpu.interrupt_nmi();
+
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert!(cpu.status.contains(StatusFlags::INTERRUPT_DISABLE));
+        assert_eq!(cpu.sp, STACK_RESET - 3);
+        let pushed = StatusFlags::from_bits_truncate(cpu.mem_read(STACK + STACK_RESET as u16 - 2));
+        assert_eq!(pushed, StatusFlags::CARRY | StatusFlags::UNUSED);
+        assert_eq!(cpu.mem_read_u16(STACK + STACK_RESET as u16 - 1), 0x8123);
+    }
+
+    #[test]
+    fn test_nmi_handler_runs_every_vblank() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![
+            0x78, // SEI, or the apu frame counter's irq gets in first
+            0xa9, 0x80, // LDA #$80
+            0x8d, 0x00, 0x20, // STA $2000, nmi on
+            0x4c, 0x06, 0x80, // JMP $8006
+            // nmi handler: count, stop on the second one
+            0xe6, 0x10, // INC $10
+            0xa5, 0x10, // LDA $10
+            0xc9, 0x02, // CMP #$02
+            0xd0, 0x01, // BNE +1
+            0x00, // BRK
+            0x40, // RTI
+        ])
+        .unwrap();
+        cpu.mem_write_u16(0xfffa, 0x8009);
+        cpu.reset();
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 2);
+        // the second vblank has only just started
+        assert_eq!(cpu.bus().ppu.scanline, 241);
+    }
+
+    #[test]
+    fn test_callback_sees_every_instruction() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0xa9, 0x05, 0xaa, 0xe8, 0x00]).unwrap(); // LDA #$05; TAX; INX; BRK
+        cpu.reset();
+        let mut count = 0;
+
+        cpu.run_with_callback(|_| count += 1).unwrap();
+
+        // BRK is fetched too
+        assert_eq!(count, 4);
+        assert_eq!(cpu.x, 6);
+    }
+
+    #[test]
+    fn test_callback_can_write_memory() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0xa5, 0xff, 0x85, 0x10, 0x00]).unwrap(); // LDA $ff; STA $10; BRK
+        cpu.reset();
+
+        cpu.run_with_callback(|cpu| cpu.mem_write(0xff, 0x77))
+            .unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0x77);
+    }
+
+    #[test]
+    fn test_callback_halts_an_endless_loop() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0xe8, 0x4c, 0x00, 0x80]).unwrap(); // INX; JMP $8000
+        cpu.reset();
+        let mut count = 0;
+
+        cpu.run_with_callback(|cpu| {
+            count += 1;
+            if count > 10 {
+                cpu.halted = true;
+            }
+        })
+        .unwrap();
+
+        // five times round the loop
+        assert_eq!(cpu.x, 5);
+        assert_eq!(cpu.program_counter, 0x8000);
+    }
+
+    #[test]
+    fn test_step_one_instruction_at_a_time() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0xa9, 0x05, 0x8d, 0x00, 0x02, 0xe8, 0x00])
+            .unwrap(); // LDA #$05; STA $0200; INX; BRK
+        cpu.reset();
+
+        let result = cpu.step().unwrap();
+        assert_eq!(
+            result,
+            StepResult::Executed {
+                opcode: 0xa9,
+                info: OpCode::from_u8(0xa9),
+                program_counter: 0x8000,
+                operand_address: Some(0x8001),
+                cycles: 2,
+            }
+        );
+        assert_eq!(cpu.a, 5);
+        assert_eq!(cpu.program_counter, 0x8002);
+
+        match cpu.step().unwrap() {
+            StepResult::Executed {
+                operand_address, ..
+            } => assert_eq!(operand_address, Some(0x0200)),
+            StepResult::Stopped(reason) => panic!("STA stopped for {:?}", reason),
+        }
+        assert_eq!(cpu.mem_read(0x0200), 5);
+        assert_eq!(cpu.x, 0);
+
+        match cpu.step().unwrap() {
+            StepResult::Executed {
+                operand_address,
+                program_counter,
+                ..
+            } => {
+                assert_eq!(operand_address, None);
+                assert_eq!(program_counter, 0x8005);
+            }
+            StepResult::Stopped(reason) => panic!("INX stopped for {:?}", reason),
+        }
+        assert_eq!(cpu.x, 1);
+    }
+
+    #[test]
+    fn test_step_stops_at_brk() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0xe8, 0x00, 0xe8]).unwrap(); // INX; BRK; INX
+        cpu.reset();
+
+        assert_ne!(cpu.step().unwrap(), StepResult::Stopped(StopReason::Halted));
+        assert_eq!(cpu.step().unwrap(), StepResult::Stopped(StopReason::Halted));
+        let program_counter = cpu.program_counter;
+
+        assert_eq!(cpu.step().unwrap(), StepResult::Stopped(StopReason::Halted));
+        assert_eq!(cpu.program_counter, program_counter);
+        assert_eq!(cpu.x, 1);
+    }
+
+    // the cycles `program` takes, BRK and the reset before it not included
+    fn cycles_for(program: Vec<u8>, x: u8, y: u8) -> u64 {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(program).unwrap();
+        cpu.reset();
+        cpu.x = x;
+        cpu.y = y;
+        cpu.run().unwrap();
+        cpu.cycles - RESET_CYCLES as u64
+    }
+
+    #[test]
+    fn test_indexed_read_page_cross_costs_a_cycle() {
+        // LDA $02f0,X
+        assert_eq!(cycles_for(vec![0xbd, 0xf0, 0x02, 0x00], 0x0f, 0), 4);
+        assert_eq!(cycles_for(vec![0xbd, 0xf0, 0x02, 0x00], 0x10, 0), 5);
+        // LDX $02f0,Y
+        assert_eq!(cycles_for(vec![0xbe, 0xf0, 0x02, 0x00], 0, 0x10), 5);
+    }
+
+    #[test]
+    fn test_indirect_y_page_cross() {
+        // LDA ($10),Y with $10 pointing at 0x02f0
+        let program = vec![
+            0xa9, 0xf0, 0x85, 0x10, 0xa9, 0x02, 0x85, 0x11, 0xb1, 0x10, 0x00,
+        ];
+        let setup = 2 * (2 + 3);
+        assert_eq!(cycles_for(program.clone(), 0, 0x0f), setup + 5);
+        assert_eq!(cycles_for(program, 0, 0x10), setup + 6);
+    }
+
+    #[test]
+    fn test_stores_and_read_modify_writes_never_pay_for_the_page() {
+        // STA $02f0,X
+        assert_eq!(cycles_for(vec![0x9d, 0xf0, 0x02, 0x00], 0x0f, 0), 5);
+        assert_eq!(cycles_for(vec![0x9d, 0xf0, 0x02, 0x00], 0x10, 0), 5);
+        // INC $02f0,X
+        assert_eq!(cycles_for(vec![0xfe, 0xf0, 0x02, 0x00], 0x10, 0), 7);
+    }
+
+    #[test]
+    fn test_branch_cycles_add_up() {
+        // LDX #$03; DEX; BNE -3 : 2, then 2 + 3 twice, then 2 + 2
+        assert_eq!(
+            cycles_for(vec![0xa2, 0x03, 0xca, 0xd0, 0xfd, 0x00], 0, 0),
+            2 + (2 + 3) * 2 + 2 + 2
+        );
+    }
+
+    fn run_asm(source: &str) -> CPU {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load_and_run(assemble(source).unwrap()).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn test_lax_loads_a_and_x() {
+        let cpu = run_asm(
+            "
+            LDA #$80
+            STA $10
+            LDA #$00
+            *LAX $10
+            BRK
+            ",
+        );
+
+        assert_eq!(cpu.a, 0x80);
+        assert_eq!(cpu.x, 0x80);
+        assert!(cpu.status.contains(StatusFlags::NEGATIVE));
+        assert!(!cpu.status.contains(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_dcp_decrements_then_compares() {
+        // $10 goes 0x41 -> 0x40, which equals A
+        let cpu = run_asm(
+            "
+            LDA #$41
+            STA $10
+            LDA #$40
+            *DCP $10
+            BRK
+            ",
+        );
+
+        assert_eq!(cpu.bus().peek(0x10), 0x40);
+        assert_eq!(cpu.a, 0x40);
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+        assert!(cpu.status.contains(StatusFlags::ZERO));
+
+        // 0x00 -> 0xff is more than A, so no carry and the difference is 0x41
+        let cpu = run_asm("LDA #$40\n*DCP $10\nBRK");
+        assert_eq!(cpu.bus().peek(0x10), 0xff);
+        assert!(!cpu.status.contains(StatusFlags::CARRY));
+        assert!(!cpu.status.contains(StatusFlags::ZERO));
+        assert!(!cpu.status.contains(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_isb_increments_then_subtracts() {
+        let cpu = run_asm(
+            "
+            LDA #$0f
+            STA $10
+            LDA #$20
+            SEC
+            *ISB $10
+            BRK
+            ",
+        );
+
+        assert_eq!(cpu.bus().peek(0x10), 0x10);
+        assert_eq!(cpu.a, 0x10);
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+
+        // 0x80 - 0x01 overflows the signed range
+        let cpu = run_asm(
+            "
+            LDA #$80
+            SEC
+            *ISB $10
+            BRK
+            ",
+        );
+        assert_eq!(cpu.a, 0x7f);
+        assert!(cpu.status.contains(StatusFlags::OVERFLOW));
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+    }
+
+    #[test]
+    fn test_shift_combinations() {
+        // SLO: $10 = 0x81 << 1 = 0x02 with carry, A = 0x01 | 0x02
+        let cpu = run_asm("LDA #$81\nSTA $10\nLDA #$01\n*SLO $10\nBRK");
+        assert_eq!((cpu.bus().peek(0x10), cpu.a), (0x02, 0x03));
+        assert!(cpu.status.contains(StatusFlags::CARRY));
+
+        // RRA: $10 = 0x03 >> 1 = 0x01 with carry, A = 0x10 + 0x01 + 1
+        let cpu = run_asm("LDA #$03\nSTA $10\nLDA #$10\n*RRA $10\nBRK");
+        assert_eq!((cpu.bus().peek(0x10), cpu.a), (0x01, 0x12));
+    }
+
+    #[test]
+    fn test_sax_stores_a_and_x() {
+        let cpu = run_asm("LDA #$f0\nLDX #$3c\n*SAX $10\nBRK");
+
+        assert_eq!(cpu.bus().peek(0x10), 0x30);
+    }
+
+    #[test]
+    fn test_unofficial_nops_skip_their_operands() {
+        // one of each size, and abs,X pays for the page like a read
+        assert_eq!(cycles_for(vec![0x1a, 0x00], 0, 0), 2);
+        assert_eq!(cycles_for(vec![0x80, 0xff, 0x00], 0, 0), 2);
+        assert_eq!(cycles_for(vec![0x04, 0xff, 0x00], 0, 0), 3);
+        assert_eq!(cycles_for(vec![0x0c, 0xff, 0xff, 0x00], 0, 0), 4);
+        assert_eq!(cycles_for(vec![0x1c, 0xf0, 0x02, 0x00], 0x0f, 0), 4);
+        assert_eq!(cycles_for(vec![0x1c, 0xf0, 0x02, 0x00], 0x10, 0), 5);
+    }
+
+    #[test]
+    fn test_unknown_opcode_is_an_error() {
+        let mut cpu = CPU::new(Bus::new());
+        // INX, then a JAM the cpu doesn't do
+        let result = cpu.load_and_run(vec![0xe8, 0x02]);
+
+        assert_eq!(
+            result,
+            Err(CpuError::UnknownOpcode {
+                opcode: 0x02,
+                pc: 0x8001,
+            })
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Unknown opcode $02 at $8001"
+        );
+        assert_eq!(cpu.x, 1);
+    }
+
+    #[test]
+    fn test_program_too_large() {
+        let mut cpu = CPU::new(Bus::new());
+
+        assert_eq!(
+            cpu.load(vec![0xea; 0x8010]),
+            Err(LoadError::TooLarge {
+                size: 0x8010,
+                overflow: 0x10,
+            })
+        );
+        assert_eq!(
+            cpu.load_and_run_at(&[0xea; 0x20], 0xfff0),
+            Err(CpuError::Load(LoadError::TooLarge {
+                size: 0x20,
+                overflow: 0x10,
+            }))
+        );
+        assert_eq!(cpu.load(vec![0xea; 0x7ffa]), Ok(()));
+    }
+
+    #[test]
+    fn test_runs_from_a_low_origin() {
+        let mut cpu = CPU::new(Bus::new());
+        let program = assemble_at(
+            "
+                  LDX #$00
+            loop: INX
+                  CPX #$03
+                  BNE loop
+                  JSR sub
+                  BRK
+            sub:  STX $10
+                  RTS
+            ",
+            0x0600,
+        )
+        .unwrap();
+
+        assert_eq!(
+            cpu.load_and_run_at(&program, 0x0600),
+            Ok(StopReason::Halted)
+        );
+        assert_eq!(cpu.peek(0x10), 3);
+        assert_eq!(cpu.bus().peek_u16(0xfffc), 0x0600);
+        // one past the BRK
+        assert_eq!(cpu.program_counter, 0x060b);
+    }
+
+    #[test]
+    fn test_operand_for_implied_mode_is_an_error() {
+        let mut cpu = CPU::new(Bus::new());
+
+        assert_eq!(
+            cpu.get_operand_address(&AddressingMode::NoneAddressing),
+            Err(CpuError::InvalidAddressingMode(
+                AddressingMode::NoneAddressing
+            ))
+        );
+    }
+
+    #[test]
+    fn test_program_counter_wraps_at_the_top_of_memory() {
+        // NOP at 0xffff, then BRK at 0x0000
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0xffff, 0xea);
+        cpu.program_counter = 0xffff;
+
+        cpu.run().unwrap();
+        // one past the BRK
+        assert_eq!(cpu.program_counter, 0x0001);
+    }
+
+    fn load_asm(source: &str) -> CPU {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(assemble(source).unwrap()).unwrap();
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn test_breakpoints_stop_before_the_instruction() {
+        let mut cpu = load_asm(
+            "
+                  LDA #$01
+                  LDX #$02
+                  LDY #$03
+                  LDX #$00
+            loop: INX
+                  CPX #$05
+                  BNE loop
+                  BRK
+            ",
+        );
+        cpu.add_breakpoint(0x8004);
+
+        assert_eq!(cpu.run(), Ok(StopReason::Breakpoint(0x8004)));
+        assert_eq!((cpu.a, cpu.x, cpu.y), (1, 2, 0));
+        assert_eq!(cpu.program_counter, 0x8004);
+
+        // INX, every time round the loop
+        cpu.add_breakpoint(0x8008);
+        let mut hits = 0;
+        loop {
+            match cpu.run().unwrap() {
+                StopReason::Breakpoint(0x8008) => {
+                    assert_eq!(cpu.x, hits);
+                    hits += 1;
+                }
+                StopReason::Halted => break,
+                reason => panic!("stopped for {:?}", reason),
+            }
+        }
+        assert_eq!(hits, 5);
+        assert_eq!((cpu.x, cpu.y), (5, 3));
+    }
+
+    #[test]
+    fn test_step_over_and_run_to() {
+        let source = "
+                 JSR sub
+                 INY
+                 BRK
+            sub: INX
+                 INX
+                 RTS
+            ";
+
+        let mut cpu = load_asm(source);
+        assert_eq!(cpu.step_over(), Ok(StopReason::Reached(0x8003)));
+        assert_eq!((cpu.x, cpu.y), (2, 0));
+        assert_eq!(cpu.step_over(), Ok(StopReason::Reached(0x8004)));
+        assert_eq!(cpu.y, 1);
+        assert_eq!(cpu.step_over(), Ok(StopReason::Halted));
+
+        let mut cpu = load_asm(source);
+        assert_eq!(cpu.run_to(0x8006), Ok(StopReason::Reached(0x8006)));
+        assert_eq!(cpu.x, 1);
+
+        // a breakpoint inside the subroutine wins, and the stop after it is forgotten
+        let mut cpu = load_asm(source);
+        cpu.add_breakpoint(0x8006);
+        assert_eq!(cpu.step_over(), Ok(StopReason::Breakpoint(0x8006)));
+        cpu.clear_breakpoints();
+        assert_eq!(cpu.run(), Ok(StopReason::Halted));
+        assert_eq!((cpu.x, cpu.y), (2, 1));
+    }
+
+    #[test]
+    fn test_write_watchpoint_stops_after_the_store() {
+        let mut cpu = load_asm(
+            "
+                LDA #$05
+                LDX #$01
+                STA $fa
+                INX
+                BRK
+            ",
+        );
+        cpu.add_watchpoint(0x00fa..=0x00fa, WatchKind::Write);
+
+        assert_eq!(
+            cpu.run(),
+            Ok(StopReason::Watchpoint {
+                addr: 0x00fa,
+                kind: WatchKind::Write,
+                value: 5,
+                pc: 0x8004,
+            })
+        );
+        assert_eq!(cpu.bus().peek(0x00fa), 5);
+        assert_eq!(cpu.program_counter, 0x8006);
+        assert_eq!(cpu.x, 1);
+
+        assert_eq!(cpu.run(), Ok(StopReason::Halted));
+        assert_eq!(cpu.x, 2);
+    }
+
+    #[test]
+    fn test_read_watchpoint_on_a_range() {
+        let mut cpu = load_asm(
+            "
+                LDX #$03
+                LDA $01fe,X
+                STA $0300
+                BRK
+            ",
+        );
+        cpu.mem_write(0x0201, 0x42);
+        // stack pushes land in page 1, writes don't count for a read watch
+        cpu.add_watchpoint(0x0100..=0x02ff, WatchKind::Read);
+
+        assert_eq!(
+            cpu.run(),
+            Ok(StopReason::Watchpoint {
+                addr: 0x0201,
+                kind: WatchKind::Read,
+                value: 0x42,
+                pc: 0x8002,
+            })
+        );
+        assert_eq!(cpu.a, 0x42);
+    }
+
+    #[test]
+    fn test_indirect_pointer_is_read_once() {
+        let mut cpu = load_asm(
+            "
+                LDY #$01
+                LDA ($10),Y
+                BRK
+            ",
+        );
+        cpu.mem_write_u16(0x10, 0x0300);
+        cpu.mem_write(0x0301, 0x42);
+        cpu.add_watchpoint(0x0010..=0x0010, WatchKind::Read);
+
+        assert_eq!(
+            cpu.run(),
+            Ok(StopReason::Watchpoint {
+                addr: 0x0010,
+                kind: WatchKind::Read,
+                value: 0x00,
+                pc: 0x8002,
+            })
+        );
+        assert_eq!(cpu.watchpoint_hits(), 1);
+        assert_eq!(cpu.a, 0x42);
+    }
+
+    #[test]
+    fn test_300_pushes_stay_in_page_one() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.sp = 0xff;
+        for i in 0..300 {
+            cpu.stack_push(i as u8);
+        }
+        // 300 pushes went round the page once and 44 bytes more
+        assert_eq!(cpu.sp, 0xff - 44);
+        assert_eq!(cpu.peek(0x01ff), 0);
+        assert_eq!(cpu.peek(0x0100), 0xff);
+        assert_eq!(cpu.peek(0x01d4), 43);
+        assert_eq!(cpu.peek(0x00ff), 0);
+        assert_eq!(cpu.peek(0x0200), 0);
+        assert_eq!(cpu.stack_slice()[..2], [43, 42]);
+
+        cpu.sp = 0xff;
+        assert!(cpu.stack_slice().is_empty());
+        // popping an empty stack wraps round to 0x0100
+        assert_eq!(cpu.stack_pop(), 0xff);
+        assert_eq!(cpu.sp, 0x00);
+        assert_eq!(cpu.stack_slice().len(), 255);
+    }
+
+    #[test]
+    fn test_call_stack_of_nested_jsrs() {
+        let mut cpu = load_asm(
+            "
+                JSR first
+                BRK
+            first:
+                LDA #$01
+                JSR second
+                RTS
+            second:
+                PHA
+                JSR third
+                RTS
+            third:
+                BRK
+            ",
+        );
+        cpu.run().unwrap();
+
+        // the JSRs at 0x8000, 0x8006 and 0x800b, skipping the byte PHA pushed in between
+        assert_eq!(cpu.call_stack(), vec![0x800b, 0x8006, 0x8000]);
+    }
+
+    #[test]
+    fn test_jump_to_just_past_the_opcode_lands_there() {
+        // JMP $8001 and BEQ -1 both go to the byte after their own opcode
+        for program in [[0x4c, 0x01, 0x80], [0xf0, 0xff, 0xea]] {
+            let mut cpu = CPU::new(Bus::new());
+            cpu.load(program.to_vec()).unwrap();
+            cpu.reset();
+            cpu.status.insert(StatusFlags::ZERO);
+            cpu.step().unwrap();
+
+            assert_eq!(cpu.program_counter, 0x8001);
+        }
+    }
+
+    #[test]
+    fn test_opcode_fetches_only_count_when_asked() {
+        let mut cpu = load_asm("INX\nINX\nBRK");
+        cpu.add_watchpoint(0x8001..=0x8001, WatchKind::Read);
+        assert_eq!(cpu.run(), Ok(StopReason::Halted));
+
+        let mut cpu = load_asm("INX\nINX\nBRK");
+        cpu.add_watchpoint(0x8001..=0x8001, WatchKind::Read);
+        cpu.set_watch_opcode_fetches(true);
+        assert_eq!(
+            cpu.run(),
+            Ok(StopReason::Watchpoint {
+                addr: 0x8001,
+                kind: WatchKind::Read,
+                value: 0xe8,
+                pc: 0x8001,
+            })
+        );
+        assert_eq!(cpu.x, 2);
+    }
+
+    #[test]
+    fn test_stack_pushes_hit_write_watchpoints() {
+        let mut cpu = load_asm(
+            "
+                 JSR sub
+                 BRK
+            sub: RTS
+            ",
+        );
+        cpu.add_watchpoint(0x0100..=0x01ff, WatchKind::Write);
+
+        // the high byte of the return address goes first
+        assert_eq!(
+            cpu.run(),
+            Ok(StopReason::Watchpoint {
+                addr: 0x01fd,
+                kind: WatchKind::Write,
+                value: 0x80,
+                pc: 0x8000,
+            })
+        );
+        assert_eq!(cpu.program_counter, 0x8004);
+    }
+
+    #[test]
+    fn test_peek_leaves_vblank_alone() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.bus_mut()
+            .ppu
+            .status
+            .insert(StatusRegister::VBLANK_STARTED);
+
+        // 0x3ffa is the last mirror of PPUSTATUS
+        assert_eq!(cpu.peek(0x3ffa), 0x80);
+        assert_eq!(cpu.peek_range(0x2002, 9), [0x80, 0, 0, 0, 0, 0, 0, 0, 0x80]);
+        assert_eq!(cpu.mem_read(0x200a), 0x80);
+        assert_eq!(cpu.peek(0x2002), 0);
+        assert_eq!(cpu.mem_read(0x2002), 0);
+    }
+
+    #[test]
+    fn test_peek_range_wraps_like_reads() {
+        let mut cpu = CPU::new(Bus::new());
+        for (i, byte) in [0x11, 0x22, 0x33, 0x44].iter().enumerate() {
+            cpu.poke(0x07fe + i as u16, *byte);
+        }
+
+        // 0x0800 is the first mirror of 0x0000
+        let peeked = cpu.peek_range(0x07fe, 4);
+        let read: Vec<u8> = (0x07fe..0x0802).map(|addr| cpu.mem_read(addr)).collect();
+        assert_eq!(peeked, [0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(peeked, read);
+        assert_eq!(cpu.peek_range(0x0000, 2), [0x33, 0x44]);
+
+        // and where the mirrors end, PPUCTRL and PPUMASK are write only
+        let peeked = cpu.peek_range(0x1ffe, 4);
+        let read: Vec<u8> = (0x1ffe..0x2002).map(|addr| cpu.mem_read(addr)).collect();
+        assert_eq!(peeked, [0x11, 0x22, 0x00, 0x00]);
+        assert_eq!(peeked, read);
+    }
+
+    #[test]
+    fn test_hexdump_of_memory() {
+        let mut cpu = CPU::new(Bus::new());
+        for (i, byte) in b"NES\x1a snake!".iter().enumerate() {
+            cpu.poke(0x0200 + i as u16, *byte);
+        }
+
+        assert_eq!(
+            cpu.hexdump(0x0200..=0x0212),
+            "0200  4E 45 53 1A 20 73 6E 61  6B 65 21 00 00 00 00 00  |NES. snake!.....|\n\
+             0210  00 00 00                                          |...|\n"
+        );
+        assert_eq!(cpu.hexdump(0x0200..=0x0200).lines().count(), 1);
+    }
+
+    #[test]
+    fn test_irq_handler_runs_while_the_line_is_up() {
+        let mut cpu = load_asm(
+            "
+                  CLI
+            loop: JMP loop
+            irq:  INC $10
+                  RTI
+            ",
+        );
+        cpu.mem_write_u16(IRQ_VECTOR, 0x8004);
+
+        for count in 1..=3 {
+            for _ in 0..5 {
+                cpu.step().unwrap();
+            }
+            assert_eq!(cpu.peek(0x10), count - 1);
+
+            cpu.bus_mut().set_irq_line(true);
+            let cycles = cpu.cycles;
+            cpu.step().unwrap();
+            // the handler would acknowledge whatever raised it
+            cpu.bus_mut().set_irq_line(false);
+            assert_eq!(cpu.peek(0x10), count);
+            assert_eq!(cpu.cycles - cycles, 7 + 5);
+            assert!(cpu.status.contains(StatusFlags::INTERRUPT_DISABLE));
+            let pushed = StatusFlags::from_bits_truncate(cpu.peek(0x01fb));
+            assert!(!pushed.contains(StatusFlags::BREAK));
+
+            cpu.step().unwrap();
+            assert_eq!(cpu.program_counter, 0x8001);
+            assert!(!cpu.status.contains(StatusFlags::INTERRUPT_DISABLE));
+        }
+    }
+
+    #[test]
+    fn test_irq_waits_for_cli() {
+        let mut cpu = load_asm(
+            "
+                 LDX #$00
+                 INX
+                 CLI
+                 INX
+                 BRK
+            irq: LDY #$01
+                 BRK
+            ",
+        );
+        cpu.mem_write_u16(IRQ_VECTOR, 0x8006);
+        cpu.status.insert(StatusFlags::INTERRUPT_DISABLE);
+        cpu.bus_mut().set_irq_line(true);
+
+        assert_eq!(cpu.run(), Ok(StopReason::Halted));
+        assert_eq!((cpu.x, cpu.y), (1, 1));
+        // it comes back to the INX after CLI
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x8004);
+    }
+
+    #[test]
+    fn test_brk_goes_through_the_irq_vector_and_rti_skips_the_padding() {
+        let mut cpu = load_asm(
+            "
+                     CLI
+                     LDX #$01
+                     BRK
+                     .byte $ff
+                     INX
+            done:    JMP done
+            handler: LDY #$42
+                     RTI
+            ",
+        );
+        cpu.halt_on_brk = false;
+        cpu.mem_write_u16(IRQ_VECTOR, 0x8009);
+
+        assert_eq!(cpu.step_over(), Ok(StopReason::Reached(0x8001)));
+        assert_eq!(cpu.step_over(), Ok(StopReason::Reached(0x8003)));
+        assert_eq!(cpu.step_over(), Ok(StopReason::Reached(0x8009)));
+        assert!(cpu.status.contains(StatusFlags::INTERRUPT_DISABLE));
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x8005);
+        let pushed = StatusFlags::from_bits_truncate(cpu.peek(0x01fb));
+        assert!(pushed.contains(StatusFlags::BREAK | StatusFlags::UNUSED));
+
+        assert_eq!(cpu.run_to(0x8006), Ok(StopReason::Reached(0x8006)));
+        assert_eq!((cpu.x, cpu.y), (2, 0x42));
+        assert_eq!(cpu.sp, STACK_RESET);
+        assert!(!cpu.status.contains(StatusFlags::INTERRUPT_DISABLE));
+        assert!(!cpu.status.contains(StatusFlags::BREAK));
+    }
+
+    #[test]
+    fn test_nmi_ignores_interrupt_disable() {
+        let mut cpu = load_asm(
+            "
+                 LDA #$80
+                 STA $2000
+                 INX
+                 BRK
+            nmi: LDY #$01
+                 BRK
+            irq: LDY #$02
+                 BRK
+            ",
+        );
+        cpu.mem_write_u16(NMI_VECTOR, 0x8007);
+        cpu.mem_write_u16(IRQ_VECTOR, 0x800a);
+        cpu.status.insert(StatusFlags::INTERRUPT_DISABLE);
+        cpu.bus_mut().set_irq_line(true);
+        // turning nmi on in vblank fires one straight away
+        cpu.bus_mut()
+            .ppu
+            .status
+            .insert(StatusRegister::VBLANK_STARTED);
+
+        assert_eq!(cpu.run(), Ok(StopReason::Halted));
+        assert_eq!((cpu.x, cpu.y), (0, 1));
+    }
+
+    #[test]
+    fn test_power_on_reset() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0x00]).unwrap();
+        cpu.mem_write_u16(RESET_VECTOR, 0x8123);
+        cpu.y = 0x42;
+
+        cpu.reset();
+
+        assert_eq!((cpu.a, cpu.x, cpu.y), (0, 0, 0));
+        assert_eq!(cpu.sp, 0xfd);
+        assert_eq!(cpu.status, StatusFlags::INTERRUPT_DISABLE);
+        assert_eq!(cpu.program_counter, 0x8123);
+        assert_eq!(cpu.cycles, 7);
+
+        // the 3 come off whatever sp was
+        cpu.sp = 0x00;
+        cpu.soft_reset();
+        assert_eq!(cpu.sp, 0xfd);
+    }
+
+    #[test]
+    fn test_soft_reset_keeps_ram_and_registers() {
+        let mut cpu = load_asm(
+            "
+                  LDA $10
+                  CLC
+                  ADC #$01
+                  STA $10
+                  LDY #$07
+            loop: JMP loop
+            ",
+        );
+        cpu.bus_mut().set_irq_line(true);
+        cpu.bus_mut().ppu.write_to_ctrl(0x80);
+        for _ in 0..10 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.peek(0x10), 1);
+        let cycles = cpu.cycles;
+
+        cpu.soft_reset();
+
+        assert_eq!(cpu.program_counter, 0x8000);
+        assert_eq!(cpu.sp, STACK_RESET - 3);
+        assert!(cpu.status.contains(StatusFlags::INTERRUPT_DISABLE));
+        assert_eq!((cpu.a, cpu.y), (1, 7));
+        assert_eq!(cpu.cycles, cycles + 7);
+        assert!(!cpu.bus().irq_pending());
+        assert!(cpu.bus().ppu.ctrl.is_empty());
+
+        for _ in 0..10 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.peek(0x10), 2);
+    }
+
+    #[test]
+    fn test_reset_vector_comes_from_the_cartridge() {
+        use crate::rom::tests::test_rom;
+
+        let mut prg_rom = vec![0xea; 0x4000];
+        prg_rom[0x3ffc] = 0x34;
+        prg_rom[0x3ffd] = 0xc2;
+        let mut cpu = CPU::new(Bus::with_rom(test_rom(prg_rom)).unwrap());
+        cpu.reset();
+
+        assert_eq!(cpu.program_counter, 0xc234);
+        cpu.soft_reset();
+        assert_eq!(cpu.program_counter, 0xc234);
+    }
+
+    #[test]
+    fn test_indirect_pointers_wrap_in_the_zero_page() {
+        let mut cpu = CPU::new(Bus::new());
+        // the pointer is 0x0400, 0x0300 if the high byte came from 0x0100
+        cpu.poke(0x00ff, 0x00);
+        cpu.poke(0x0000, 0x04);
+        cpu.poke(0x0100, 0x03);
+        cpu.poke(0x0400, 0x11);
+        cpu.poke(0x0402, 0x22);
+        cpu.poke(0x0300, 0xee);
+        cpu.poke(0x0302, 0xee);
+
+        cpu.load(assemble("LDX #$0f\nLDA ($f0,X)\nBRK").unwrap())
+            .unwrap();
+        cpu.reset();
+        cpu.run().unwrap();
+        assert_eq!(cpu.a, 0x11);
+
+        cpu.load(assemble("LDY #$02\nLDA ($ff),Y\nBRK").unwrap())
+            .unwrap();
+        cpu.reset();
+        cpu.run().unwrap();
+        assert_eq!(cpu.a, 0x22);
+    }
 }
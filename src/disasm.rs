@@ -0,0 +1,197 @@
+use std::fmt;
+
+use crate::cpu::AddressingMode;
+use crate::opcode::OpCode;
+
+// one line of a listing, `.byte` for anything that isn't a whole instruction
+#[derive(Debug, PartialEq)]
+pub struct DisassembledInstruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: &'static str,
+    pub operand: String,
+}
+
+// 8000  BD 00 44  LDA $4400,X
+impl fmt::Display for DisassembledInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = self
+            .bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let line = format!(
+            "{:04X}  {:8}  {} {}",
+            self.address, hex, self.mnemonic, self.operand
+        );
+        f.write_str(line.trim_end())
+    }
+}
+
+// `bytes` as if they were loaded at `origin`. bytes that aren't an opcode, and a last
+// instruction that's missing some of its operand, come out as `.byte`
+pub fn disassemble(bytes: &[u8], origin: u16) -> Vec<DisassembledInstruction> {
+    let mut result = vec![];
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let address = origin.wrapping_add(offset as u16);
+        let instruction = OpCode::try_from_u8(bytes[offset]).and_then(|opcode| {
+            bytes
+                .get(offset..offset + opcode.bytes as usize)
+                .map(|raw| (raw, opcode))
+        });
+
+        match instruction {
+            Some((raw, opcode)) => {
+                result.push(DisassembledInstruction {
+                    address,
+                    bytes: raw.to_vec(),
+                    mnemonic: opcode.mnemonic,
+                    operand: format_operand(&opcode.mode, &raw[1..], address),
+                });
+                offset += raw.len();
+            }
+            None => {
+                result.push(DisassembledInstruction {
+                    address,
+                    bytes: vec![bytes[offset]],
+                    mnemonic: ".byte",
+                    operand: format!("${:02X}", bytes[offset]),
+                });
+                offset += 1;
+            }
+        }
+    }
+
+    result
+}
+
+// the operand as it's written in assembly, `args` being the bytes after the opcode of an
+// instruction at `address`. branches show where they go rather than the offset
+pub fn format_operand(mode: &AddressingMode, args: &[u8], address: u16) -> String {
+    let arg = args.first().copied().unwrap_or(0);
+    let arg16 = (args.get(1).copied().unwrap_or(0) as u16) << 8 | arg as u16;
+
+    match mode {
+        AddressingMode::Immediate => format!("#${:02X}", arg),
+        AddressingMode::ZeroPage => format!("${:02X}", arg),
+        AddressingMode::ZeroPageX => format!("${:02X},X", arg),
+        AddressingMode::ZeroPageY => format!("${:02X},Y", arg),
+        AddressingMode::Absolute => format!("${:04X}", arg16),
+        AddressingMode::AbsoluteX => format!("${:04X},X", arg16),
+        AddressingMode::AbsoluteY => format!("${:04X},Y", arg16),
+        AddressingMode::IndirectX => format!("(${:02X},X)", arg),
+        AddressingMode::IndirectY => format!("(${:02X}),Y", arg),
+        AddressingMode::Indirect => format!("(${:04X})", arg16),
+        AddressingMode::Relative => {
+            let target = address.wrapping_add(2).wrapping_add(arg as i8 as u16);
+            format!("${:04X}", target)
+        }
+        AddressingMode::Accumulator => String::from("A"),
+        AddressingMode::NoneAddressing => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, Mem};
+    use crate::cpu::CPU;
+
+    fn listing(bytes: &[u8], origin: u16) -> Vec<String> {
+        disassemble(bytes, origin)
+            .iter()
+            .map(|i| {
+                format!("{} {}", i.mnemonic, i.operand)
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_every_addressing_mode() {
+        let bytes = [
+            0xa9, 0x44, // LDA #$44
+            0xa5, 0x44, // LDA $44
+            0xb5, 0x44, // LDA $44,X
+            0xb6, 0x44, // LDX $44,Y
+            0xad, 0x00, 0x44, // LDA $4400
+            0xbd, 0x00, 0x44, // LDA $4400,X
+            0xb9, 0x00, 0x44, // LDA $4400,Y
+            0xa1, 0x44, // LDA ($44,X)
+            0xb1, 0x44, // LDA ($44),Y
+            0x6c, 0x00, 0x44, // JMP ($4400)
+            0xd0, 0xfe, // BNE to itself
+            0x0a, // ASL A
+            0xea, // NOP
+        ];
+
+        assert_eq!(
+            listing(&bytes, 0x8000),
+            vec![
+                "LDA #$44",
+                "LDA $44",
+                "LDA $44,X",
+                "LDX $44,Y",
+                "LDA $4400",
+                "LDA $4400,X",
+                "LDA $4400,Y",
+                "LDA ($44,X)",
+                "LDA ($44),Y",
+                "JMP ($4400)",
+                "BNE $8018",
+                "ASL A",
+                "NOP",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_branch_targets() {
+        // forwards past the NOP, then backwards to the start
+        assert_eq!(
+            listing(&[0xf0, 0x01, 0xea, 0x90, 0xfb], 0xc000),
+            vec!["BEQ $C003", "NOP", "BCC $C000"]
+        );
+    }
+
+    #[test]
+    fn test_undefined_and_truncated_bytes() {
+        let result = disassemble(&[0xea, 0x02, 0xad, 0x00], 0x8000);
+
+        assert_eq!(
+            result.iter().map(|i| i.to_string()).collect::<Vec<_>>(),
+            vec![
+                "8000  EA        NOP",
+                "8001  02        .byte $02",
+                "8002  AD        .byte $AD",
+                "8003  00        BRK",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disassemble_at_reads_the_bus() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0xa2, 0x01, 0xe8, 0x8e, 0x00, 0x02, 0x00])
+            .unwrap();
+
+        let lines: Vec<String> = cpu
+            .disassemble_at(0x8000, 3)
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "8000  A2 01     LDX #$01",
+                "8002  E8        INX",
+                "8003  8E 00 02  STX $0200",
+            ]
+        );
+        assert_eq!(cpu.mem_read(0x8000), 0xa2);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::cpu::CPU;
+
+// the memory mapped io of the easy6502 simulator, https://skilldrick.github.io/easy6502/, that
+// programs like its snake game are written for. nothing on the nes is at these addresses, the
+// caller keeps them up to date from CPU::run_with_callback
+
+// programs are assembled to start here
+pub const ORIGIN: u16 = 0x0600;
+// a new random byte before every instruction
+pub const RANDOM: u16 = 0x00fe;
+// ascii for the last key pressed
+pub const LAST_KEY: u16 = 0x00ff;
+// a byte a pixel, row by row, 0x0200 - 0x05ff
+pub const SCREEN: u16 = 0x0200;
+pub const WIDTH: usize = 32;
+pub const HEIGHT: usize = 32;
+
+// the simulator's 16 colours, the same as the c64's. only the low nibble counts
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xff, 0xff, 0xff),
+    (0x88, 0x00, 0x00),
+    (0xaa, 0xff, 0xee),
+    (0xcc, 0x44, 0xcc),
+    (0x00, 0xcc, 0x55),
+    (0x00, 0x00, 0xaa),
+    (0xee, 0xee, 0x77),
+    (0xdd, 0x88, 0x55),
+    (0x66, 0x44, 0x00),
+    (0xff, 0x77, 0x77),
+    (0x33, 0x33, 0x33),
+    (0x77, 0x77, 0x77),
+    (0xaa, 0xff, 0x66),
+    (0x00, 0x88, 0xff),
+    (0xbb, 0xbb, 0xbb),
+];
+
+pub fn colour(value: u8) -> (u8, u8, u8) {
+    PALETTE[(value & 0x0f) as usize]
+}
+
+// the screen as rgb, three bytes a pixel row by row, into `frame`. returns whether anything
+// changed, so there's only something to draw when it did
+pub fn read_screen(cpu: &CPU, frame: &mut [u8; WIDTH * HEIGHT * 3]) -> bool {
+    let mut changed = false;
+    for (i, pixel) in frame.chunks_exact_mut(3).enumerate() {
+        let (r, g, b) = colour(cpu.peek(SCREEN + i as u16));
+        if pixel != [r, g, b] {
+            pixel.copy_from_slice(&[r, g, b]);
+            changed = true;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn test_read_screen() {
+        let mut cpu = CPU::new(Bus::new());
+        let mut frame = [0; WIDTH * HEIGHT * 3];
+        assert!(!read_screen(&cpu, &mut frame));
+
+        cpu.poke(SCREEN, 0x01);
+        cpu.poke(SCREEN + 33, 0x12);
+        cpu.poke(0x05ff, 0x0e);
+        assert!(read_screen(&cpu, &mut frame));
+        assert_eq!(frame[..3], [0xff, 0xff, 0xff]);
+        assert_eq!(frame[33 * 3..34 * 3], [0x88, 0x00, 0x00]);
+        assert_eq!(frame[frame.len() - 3..], [0x00, 0x88, 0xff]);
+        assert!(frame[3..33 * 3].iter().all(|&b| b == 0));
+
+        assert!(!read_screen(&cpu, &mut frame));
+    }
+}
@@ -0,0 +1,967 @@
+use crate::bus::Bus;
+use crate::cpu::{CpuError, StepResult, CPU};
+use crate::joypad::JoypadButton;
+use crate::mappers::LoadWarning;
+use crate::movie::{FrameInput, Movie, MovieError, MovieEvent, MoviePlayer, MovieRecorder};
+use crate::render::frame::Frame;
+#[cfg(feature = "savestate")]
+use crate::rewind::RewindBuffer;
+use crate::rom::{Mirroring, Rom, TvSystem};
+#[cfg(feature = "savestate")]
+use crate::savestate::{self, StateError};
+
+// a state every other frame, and 10 seconds of them at 60 frames a second
+#[cfg(feature = "savestate")]
+const DEFAULT_REWIND_INTERVAL: u32 = 2;
+#[cfg(feature = "savestate")]
+const DEFAULT_REWIND_CAPACITY: usize = 300;
+
+// a cartridge plugged into a console that's been switched on. the cpu, and the bus through
+// it, are there for anything this doesn't wrap
+pub struct Emulator {
+    cpu: CPU,
+    // see Rom::crc32, save states are only loaded back into the same cartridge
+    rom_crc: u32,
+    // the cartridge as it was plugged in, for power_cycle to start it over from
+    rom: Rom,
+    // anything it was let in in spite of, see allowing_unsupported_mappers
+    load_warnings: Vec<LoadWarning>,
+    #[cfg(feature = "savestate")]
+    rewind: RewindBuffer,
+    // run_until_frame saves a state for rewind every this many frames, 0 for never
+    #[cfg(feature = "savestate")]
+    rewind_interval: u32,
+    // a reset or power cycle for the start of the next frame
+    pending_event: Option<MovieEvent>,
+    // see attach_recorder and attach_player
+    recorder: Option<MovieRecorder>,
+    player: Option<MoviePlayer>,
+}
+
+impl Emulator {
+    // ntsc unless the header says pal, see with_filename for a better guess
+    pub fn new(rom: Rom) -> Result<Self, String> {
+        let tv_system = rom.tv_system;
+        Self::with_tv_system(rom, tv_system)
+    }
+
+    // the tv system from the header or the region tag in the name of the file the rom came
+    // from, see TvSystem::detect. with_tv_system is for when the player knows better
+    pub fn with_filename(rom: Rom, filename: &str) -> Result<Self, String> {
+        let tv_system = TvSystem::detect(&rom, filename);
+        Self::with_tv_system(rom, tv_system)
+    }
+
+    pub fn with_tv_system(rom: Rom, tv_system: TvSystem) -> Result<Self, String> {
+        let bus = Bus::with_rom(rom.clone())?;
+        Ok(Self::with_bus(bus, rom, tv_system, Vec::new()))
+    }
+
+    // with_tv_system, except a cartridge on a board that isn't emulated runs as nrom rather
+    // than being refused, with a warning in load_warnings saying so
+    pub fn allowing_unsupported_mappers(rom: Rom, tv_system: TvSystem) -> Result<Self, String> {
+        let (bus, load_warnings) = Bus::with_rom_or_nrom(rom.clone())?;
+        Ok(Self::with_bus(bus, rom, tv_system, load_warnings))
+    }
+
+    fn with_bus(
+        mut bus: Bus,
+        rom: Rom,
+        tv_system: TvSystem,
+        load_warnings: Vec<LoadWarning>,
+    ) -> Self {
+        let rom_crc = rom.crc32();
+        bus.set_tv_system(tv_system);
+        let mut cpu = CPU::new(bus);
+        // games use BRK like any other instruction
+        cpu.halt_on_brk = false;
+        cpu.reset();
+        Emulator {
+            cpu,
+            rom_crc,
+            rom,
+            load_warnings,
+            #[cfg(feature = "savestate")]
+            rewind: RewindBuffer::new(DEFAULT_REWIND_CAPACITY),
+            #[cfg(feature = "savestate")]
+            rewind_interval: DEFAULT_REWIND_INTERVAL,
+            pending_event: None,
+            recorder: None,
+            player: None,
+        }
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn rom_crc(&self) -> u32 {
+        self.rom_crc
+    }
+
+    pub fn load_warnings(&self) -> &[LoadWarning] {
+        &self.load_warnings
+    }
+
+    pub fn tv_system(&self) -> TvSystem {
+        self.cpu.bus().tv_system()
+    }
+
+    // how many frames run_until_frame makes in a second of the console's time
+    pub fn frame_rate(&self) -> f64 {
+        self.tv_system().frames_per_second()
+    }
+
+    pub fn mapper_number(&self) -> u8 {
+        self.rom.mapper
+    }
+
+    // as the board has it right now, some change it as they run
+    pub fn mirroring(&self) -> Mirroring {
+        self.cpu.bus().mirroring()
+    }
+
+    // whether there's save data to keep, see Bus::save_ram
+    pub fn uses_battery(&self) -> bool {
+        self.rom.battery
+    }
+
+    pub fn step(&mut self) -> Result<StepResult, CpuError> {
+        self.cpu.step()
+    }
+
+    // the reset button. it's pressed at the start of the next frame, the same place a movie
+    // has it, so a recording always plays back the same
+    pub fn reset(&mut self) {
+        self.pending_event = MovieEvent::merge(self.pending_event, Some(MovieEvent::Reset));
+    }
+
+    // off and on again at the start of the next frame, see Bus::power_cycle for what survives
+    // it
+    pub fn power_cycle(&mut self) {
+        self.pending_event = MovieEvent::merge(self.pending_event, Some(MovieEvent::Power));
+    }
+
+    // what both controllers have held down for the next frame. joypad 2 is left alone when
+    // something else is plugged into port 2
+    pub fn set_frame_input(&mut self, joypad1: JoypadButton, joypad2: JoypadButton) {
+        let bus = self.cpu.bus_mut();
+        bus.joypad1_mut().set_buttons(joypad1);
+        if let Some(joypad) = bus.joypad2_mut() {
+            joypad.set_buttons(joypad2);
+        }
+    }
+
+    // from here on, every frame run_until_frame runs goes into a movie along with the buttons
+    // held at its start and any reset or power cycle before it. a movie plays back into a
+    // console that's in the same state as this one is now, so start from a fresh one
+    pub fn attach_recorder(&mut self) {
+        self.recorder = Some(MovieRecorder::new(self.rom_crc, self.tv_system()));
+    }
+
+    // the movie recorded since attach_recorder
+    pub fn detach_recorder(&mut self) -> Option<Movie> {
+        self.recorder.take().map(MovieRecorder::finish)
+    }
+
+    // run_until_frame takes each frame's input from `movie` rather than the frontend until
+    // it runs out, see is_playing
+    pub fn attach_player(&mut self, movie: Movie) -> Result<(), MovieError> {
+        if movie.rom_crc != self.rom_crc {
+            return Err(MovieError::WrongRom {
+                expected: self.rom_crc,
+                actual: movie.rom_crc,
+            });
+        }
+        if movie.tv_system != self.tv_system() {
+            return Err(MovieError::WrongTvSystem {
+                expected: self.tv_system(),
+                actual: movie.tv_system,
+            });
+        }
+        self.player = Some(MoviePlayer::new(movie));
+        Ok(())
+    }
+
+    pub fn detach_player(&mut self) -> Option<Movie> {
+        self.player.take().map(|player| player.movie().clone())
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.player
+            .as_ref()
+            .is_some_and(|player| !player.finished())
+    }
+
+    // the input for the frame that's about to run, from the movie being played or what the
+    // frontend set, with any reset or power cycle done
+    fn start_frame(&mut self) -> Result<(), CpuError> {
+        let mut event = self.pending_event.take();
+        if let Some(input) = self.player.as_mut().and_then(MoviePlayer::next_frame) {
+            event = MovieEvent::merge(event, input.event);
+            self.set_frame_input(input.joypad1, input.joypad2);
+        }
+        match event {
+            Some(MovieEvent::Reset) => self.cpu.soft_reset(),
+            Some(MovieEvent::Power) => {
+                self.cpu
+                    .bus_mut()
+                    .power_cycle(self.rom.clone())
+                    .map_err(CpuError::Cartridge)?;
+                self.cpu.reset();
+            }
+            None => {}
+        }
+
+        if let Some(recorder) = &mut self.recorder {
+            let bus = self.cpu.bus_mut();
+            let joypad1 = bus.joypad1_mut().buttons();
+            let joypad2 = bus
+                .joypad2_mut()
+                .map_or(JoypadButton::empty(), |joypad| joypad.buttons());
+            recorder.record_frame(FrameInput {
+                joypad1,
+                joypad2,
+                event,
+            });
+        }
+        Ok(())
+    }
+
+    // frames the ppu has finished since power on
+    pub fn frame_count(&self) -> u64 {
+        self.cpu.bus().ppu.frame_count
+    }
+
+    // runs until the ppu finishes the frame it's on, or the cpu stops. nmis, mapper irqs and
+    // dma stalls all happen along the way as the cpu steps
+    pub fn run_until_frame(&mut self) -> Result<&Frame, CpuError> {
+        self.start_frame()?;
+        let frame = self.frame_count();
+        while self.frame_count() == frame {
+            if matches!(self.cpu.step()?, StepResult::Stopped(_)) {
+                break;
+            }
+        }
+
+        #[cfg(feature = "savestate")]
+        {
+            let frame = self.frame_count();
+            if self.rewind_interval != 0
+                && self.rewind.capacity() != 0
+                && frame.is_multiple_of(self.rewind_interval as u64)
+            {
+                self.rewind.push(frame, self.save_state());
+            }
+        }
+
+        Ok(self.frame())
+    }
+
+    // run_until_frame `frames` times, the frames in between are drawn and thrown away
+    pub fn run_frames(&mut self, frames: u32) -> Result<&Frame, CpuError> {
+        for _ in 0..frames {
+            self.run_until_frame()?;
+        }
+        Ok(self.frame())
+    }
+
+    // the last frame the ppu finished, and whatever's been drawn of the next since
+    pub fn frame(&self) -> &Frame {
+        &self.cpu.bus().ppu.frame
+    }
+
+    // the whole machine apart from the roms, see savestate for the header in front
+    #[cfg(feature = "savestate")]
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = savestate::header(self.rom_crc);
+        state.extend(savestate::encode(&(
+            &self.cpu,
+            self.cpu.bus().mapper_state(),
+        )));
+        state
+    }
+
+    // nothing changes unless the whole state loads. what rewind had is thrown away, it's
+    // another timeline now
+    #[cfg(feature = "savestate")]
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        self.restore(state)?;
+        self.rewind.clear();
+        Ok(())
+    }
+
+    // back to the newest rewind state at least `frames` frames ago, and the ones after it are
+    // dropped. false when there isn't one that old
+    #[cfg(feature = "savestate")]
+    pub fn rewind(&mut self, frames: u32) -> bool {
+        let frame = self.frame_count().saturating_sub(frames as u64);
+        match self.rewind.rewind_to(frame) {
+            Some((_, state)) => {
+                self.restore(&state)
+                    .expect("rewind states are all from this cartridge");
+                true
+            }
+            None => false,
+        }
+    }
+
+    // how many states rewind keeps, 0 turns it off
+    #[cfg(feature = "savestate")]
+    pub fn rewind_capacity(&self) -> usize {
+        self.rewind.capacity()
+    }
+
+    #[cfg(feature = "savestate")]
+    pub fn set_rewind_capacity(&mut self, capacity: usize) {
+        self.rewind.set_capacity(capacity);
+    }
+
+    #[cfg(feature = "savestate")]
+    pub fn rewind_interval(&self) -> u32 {
+        self.rewind_interval
+    }
+
+    #[cfg(feature = "savestate")]
+    pub fn set_rewind_interval(&mut self, frames: u32) {
+        self.rewind_interval = frames;
+    }
+
+    // bytes the rewind states take up
+    #[cfg(feature = "savestate")]
+    pub fn rewind_memory_usage(&self) -> usize {
+        self.rewind.memory_usage()
+    }
+
+    #[cfg(feature = "savestate")]
+    fn restore(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let body = savestate::check_header(state, self.rom_crc)?;
+        let (mut cpu, mapper_state): (CPU, Vec<u8>) = savestate::decode(body)?;
+        cpu.bus_mut()
+            .take_cartridge(self.cpu.bus_mut(), &mapper_state)?;
+        cpu.take_debug_state(&mut self.cpu);
+        self.cpu = cpu;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{assemble, assemble_at};
+    use crate::rom::tests::{bare_rom, create_rom, header, TestRom};
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    // counts in ram and draws the count into the nametable and a sprite every vblank, so
+    // ram, oam and the frame all keep changing. it sums up what's read from joypad 1 too
+    const RESET: &str = "
+            SEI
+            LDX #$ff
+            TXS
+            LDA #$3f
+            STA $2006
+            LDA #$00
+            STA $2006
+            LDA #$0f
+            STA $2007
+            LDA #$16
+            STA $2007
+            LDA #$27
+            STA $2007
+            LDA #$30
+            STA $2007
+            LDA #$80
+            STA $2000
+            LDA #$1e
+            STA $2001
+            LDX #$00
+        loop:
+            INC $10
+            LDA $10
+            STA $0300,X
+            INX
+            JMP loop
+    ";
+
+    // at 0x9000
+    const NMI: &str = "
+            PHA
+            TXA
+            PHA
+            LDA #$01
+            STA $4016
+            LDA #$00
+            STA $4016
+            LDX #$08
+        read:
+            LDA $4016
+            LSR A
+            ROL $12
+            DEX
+            BNE read
+            LDA $12
+            CLC
+            ADC $13
+            STA $13
+            INC $11
+            LDA $11
+            STA $0200
+            STA $0203
+            LDA #$02
+            STA $4014
+            LDA #$20
+            STA $2006
+            LDA $11
+            AND #$1f
+            STA $2006
+            LDA $10
+            STA $2007
+            LDA #$00
+            STA $2005
+            STA $2005
+            PLA
+            TAX
+            PLA
+            RTI
+    ";
+
+    fn test_rom(fill: u8) -> Rom {
+        let reset = assemble(RESET).unwrap();
+        let nmi = assemble_at(NMI, 0x9000).unwrap();
+        let mut prg_rom = vec![fill; 0x4000];
+        prg_rom[..reset.len()].copy_from_slice(&reset);
+        prg_rom[0x1000..0x1000 + nmi.len()].copy_from_slice(&nmi);
+        // 0xfffa mirrors down to 0xbffa in a 16KB rom
+        prg_rom[0x3ffa..].copy_from_slice(&[0x00, 0x90, 0x00, 0x80, 0x00, 0x00]);
+        let raw = create_rom(TestRom {
+            header: header(1, 1, 0x00, 0x00),
+            trainer: None,
+            prg_rom,
+            chr_rom: (0..0x2000).map(|i| (i * 7) as u8).collect(),
+        });
+
+        Rom::new(&raw).unwrap()
+    }
+
+    fn run(emulator: &mut Emulator, instructions: usize) {
+        for _ in 0..instructions {
+            emulator.step().unwrap();
+        }
+    }
+
+    type Snapshot = (Vec<u64>, Vec<u8>, Vec<u8>, Vec<u8>);
+
+    fn snapshot(emulator: &Emulator) -> Snapshot {
+        let cpu = emulator.cpu();
+        let registers = vec![
+            cpu.a as u64,
+            cpu.x as u64,
+            cpu.y as u64,
+            cpu.sp as u64,
+            cpu.status.bits() as u64,
+            cpu.program_counter as u64,
+            cpu.cycles,
+        ];
+        let ram = (0..0x800).map(|addr| cpu.bus().peek(addr)).collect();
+        let ppu = &cpu.bus().ppu;
+        (
+            registers,
+            ram,
+            ppu.oam_data.to_vec(),
+            ppu.frame.data.clone(),
+        )
+    }
+
+    #[test]
+    fn test_program_reaches_its_nmi() {
+        let mut emulator = Emulator::new(test_rom(0)).unwrap();
+        run(&mut emulator, 30_000);
+
+        let (_, ram, oam, _) = snapshot(&emulator);
+        assert_ne!(ram[0x11], 0);
+        assert_eq!(oam[0], ram[0x11]);
+    }
+
+    #[cfg(feature = "savestate")]
+    #[test]
+    fn test_restored_state_runs_the_same() {
+        let mut emulator = Emulator::new(test_rom(0)).unwrap();
+        run(&mut emulator, 20_000);
+        let state = emulator.save_state();
+        let saved = snapshot(&emulator);
+
+        run(&mut emulator, 60_000);
+        let first = snapshot(&emulator);
+        assert_ne!(first, saved);
+
+        emulator.load_state(&state).unwrap();
+        assert_eq!(snapshot(&emulator), saved);
+        run(&mut emulator, 60_000);
+        assert_eq!(snapshot(&emulator), first);
+
+        // and into a console that's never run at all
+        let mut fresh = Emulator::new(test_rom(0)).unwrap();
+        fresh.load_state(&state).unwrap();
+        run(&mut fresh, 60_000);
+        assert_eq!(snapshot(&fresh), first);
+    }
+
+    #[cfg(feature = "savestate")]
+    #[test]
+    fn test_state_for_another_rom_is_refused() {
+        let state = Emulator::new(test_rom(0)).unwrap().save_state();
+        let mut other = Emulator::new(test_rom(0xea)).unwrap();
+        run(&mut other, 100);
+        let before = snapshot(&other);
+
+        assert!(matches!(
+            other.load_state(&state),
+            Err(StateError::WrongRom { .. })
+        ));
+        assert_eq!(snapshot(&other), before);
+    }
+
+    #[cfg(feature = "savestate")]
+    #[test]
+    fn test_truncated_state_is_refused() {
+        let mut emulator = Emulator::new(test_rom(0)).unwrap();
+        let state = emulator.save_state();
+
+        assert!(matches!(
+            emulator.load_state(&state[..state.len() / 2]),
+            Err(StateError::Corrupt(_))
+        ));
+        assert!(matches!(
+            emulator.load_state(b"not a state"),
+            Err(StateError::NotAState)
+        ));
+    }
+
+    #[test]
+    fn test_run_until_frame_counts_frames() {
+        let mut emulator = Emulator::new(test_rom(0)).unwrap();
+        for _ in 0..3 {
+            emulator.run_until_frame().unwrap();
+        }
+
+        assert_eq!(emulator.frame_count(), 3);
+        // the nmi ran once a frame, apart from the first which was over before it was on
+        assert_eq!(emulator.cpu().bus().peek(0x11), 2);
+    }
+
+    fn frame_hash(frame: &Frame) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        frame.data.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_run_frames_is_deterministic() {
+        let mut first = Emulator::new(test_rom(0)).unwrap();
+        let mut second = Emulator::new(test_rom(0)).unwrap();
+        let mut hashes = vec![];
+        for _ in 0..6 {
+            hashes.push(frame_hash(first.run_frames(10).unwrap()));
+        }
+
+        assert_eq!(first.frame_count(), 60);
+        // the first frame is over 241 lines after power on, then 59 more of 29780.67 cycles,
+        // give or take the instruction the last one ends in
+        assert!(first.cpu().bus().cycles().abs_diff(27_394 + 1_757_059) < 8);
+        // the nametable and sprite the nmi draws move every frame
+        assert_ne!(hashes[4], hashes[5]);
+
+        for hash in hashes {
+            assert_eq!(frame_hash(second.run_frames(10).unwrap()), hash);
+        }
+        assert_eq!(second.cpu().bus().cycles(), first.cpu().bus().cycles());
+    }
+
+    #[test]
+    fn test_run_until_frame_takes_longer_on_pal() {
+        let cycles = |tv_system| {
+            let mut emulator = Emulator::with_tv_system(test_rom(0), tv_system).unwrap();
+            emulator.run_until_frame().unwrap();
+            // the bus's count has the oam dma stalls in it too
+            let start = emulator.cpu().bus().cycles();
+            for _ in 0..10 {
+                emulator.run_until_frame().unwrap();
+            }
+            emulator.cpu().bus().cycles() - start
+        };
+
+        // a frame is over on the instruction it ends in, so each end can be a few cycles late
+        let ntsc = cycles(TvSystem::Ntsc);
+        let pal = cycles(TvSystem::Pal);
+        assert!(ntsc.abs_diff(297_807) < 8, "{}", ntsc);
+        assert!(pal.abs_diff(332_475) < 8, "{}", pal);
+        assert!((pal - ntsc).abs_diff(10 * 3467) < 16);
+    }
+
+    #[test]
+    fn test_tv_system_detection() {
+        let pal_header = |rom: Rom| Rom {
+            tv_system: TvSystem::Pal,
+            ..rom
+        };
+        let tv_system = |emulator: Result<Emulator, String>| emulator.unwrap().tv_system();
+
+        assert_eq!(tv_system(Emulator::new(test_rom(0))), TvSystem::Ntsc);
+        assert_eq!(
+            tv_system(Emulator::new(pal_header(test_rom(0)))),
+            TvSystem::Pal
+        );
+        // the header beats the file name
+        assert_eq!(
+            tv_system(Emulator::with_filename(
+                pal_header(test_rom(0)),
+                "Game (U).nes"
+            )),
+            TvSystem::Pal
+        );
+        assert_eq!(
+            tv_system(Emulator::with_filename(test_rom(0), "Game (E).nes")),
+            TvSystem::Pal
+        );
+        assert_eq!(
+            tv_system(Emulator::with_filename(test_rom(0), "Game (PAL).nes")),
+            TvSystem::Pal
+        );
+        assert_eq!(
+            tv_system(Emulator::with_filename(test_rom(0), "Game (U).nes")),
+            TvSystem::Ntsc
+        );
+        // and asking for one beats both
+        assert_eq!(
+            tv_system(Emulator::with_tv_system(
+                pal_header(test_rom(0)),
+                TvSystem::Ntsc
+            )),
+            TvSystem::Ntsc
+        );
+    }
+
+    #[test]
+    fn test_frame_rate_is_the_rate_frames_are_run_at() {
+        for tv_system in [TvSystem::Ntsc, TvSystem::Pal] {
+            let mut emulator = Emulator::with_tv_system(test_rom(0), tv_system).unwrap();
+            emulator.run_until_frame().unwrap();
+            let start = emulator.cpu().bus().cycles();
+            emulator.run_frames(10).unwrap();
+            let cycles = emulator.cpu().bus().cycles() - start;
+
+            let expected = 10.0 * tv_system.cpu_frequency() as f64 / emulator.frame_rate();
+            assert!(
+                (cycles as f64 - expected).abs() < 8.0,
+                "{:?} {}",
+                tv_system,
+                cycles
+            );
+        }
+    }
+
+    #[test]
+    fn test_cartridge_accessors() {
+        let emulator = Emulator::new(test_rom(0)).unwrap();
+        assert_eq!(emulator.mapper_number(), 0);
+        assert_eq!(emulator.mirroring(), Mirroring::Horizontal);
+        assert!(!emulator.uses_battery());
+
+        let emulator = Emulator::new(Rom {
+            battery: true,
+            ..bare_rom(1, 0x8000, 0)
+        })
+        .unwrap();
+        assert_eq!(emulator.mapper_number(), 1);
+        assert!(emulator.uses_battery());
+    }
+
+    // an mmc3 cart that asks for an irq 16 lines into the picture, then waits for it
+    const MMC3_RESET: &str = "
+            SEI
+            LDX #$ff
+            TXS
+            LDA #$40
+            STA $4017
+            LDA #$10
+            STA $c000
+            STA $c001
+            STA $e001
+            LDA #$18
+            STA $2001
+            CLI
+        loop:
+            JMP loop
+    ";
+
+    // at 0xe100, acknowledges and turns the irq off so it's taken once
+    const MMC3_IRQ: &str = "
+            STA $e000
+            INC $20
+            RTI
+    ";
+
+    fn mmc3_rom() -> Rom {
+        let reset = assemble_at(MMC3_RESET, 0xe000).unwrap();
+        let irq = assemble_at(MMC3_IRQ, 0xe100).unwrap();
+        // the last 8KB bank is always at 0xe000
+        let mut prg_rom = vec![0; 0x8000];
+        prg_rom[0x6000..0x6000 + reset.len()].copy_from_slice(&reset);
+        prg_rom[0x6100..0x6100 + irq.len()].copy_from_slice(&irq);
+        prg_rom[0x7ffa..].copy_from_slice(&[0x00, 0xe1, 0x00, 0xe0, 0x00, 0xe1]);
+        let raw = create_rom(TestRom {
+            header: header(2, 1, 0x40, 0x00),
+            trainer: None,
+            prg_rom,
+            chr_rom: vec![0; 0x2000],
+        });
+
+        Rom::new(&raw).unwrap()
+    }
+
+    #[test]
+    fn test_mmc3_irq_is_taken_within_a_frame() {
+        let mut emulator = Emulator::new(mmc3_rom()).unwrap();
+        emulator.run_until_frame().unwrap();
+
+        assert_eq!(emulator.frame_count(), 1);
+        assert_eq!(emulator.cpu().bus().peek(0x20), 1);
+        assert!(!emulator.cpu().bus().irq_pending());
+    }
+
+    #[test]
+    fn test_cartridge_without_prg_rom_is_refused() {
+        let raw = create_rom(TestRom {
+            header: header(0, 0, 0x00, 0x00),
+            trainer: None,
+            prg_rom: vec![],
+            chr_rom: vec![],
+        });
+        assert!(Rom::new(&raw).and_then(Emulator::new).is_err());
+        assert!(Emulator::new(bare_rom(0, 0, 0)).is_err());
+    }
+
+    // mapper 200, a multicart board that isn't emulated, with each 16KB bank filled with its
+    // number from 1 and a program in the last one that reads both halves of prg into ram
+    fn mapper_200_rom() -> Rom {
+        let program = assemble_at(
+            "
+                LDA $8100
+                STA $00
+                LDA $c100
+                STA $01
+            loop:
+                JMP loop
+            ",
+            0xc000,
+        )
+        .unwrap();
+        let mut prg_rom: Vec<u8> = (0..0x10000).map(|i| (i / 0x4000 + 1) as u8).collect();
+        prg_rom[0xc000..0xc000 + program.len()].copy_from_slice(&program);
+        prg_rom[0xfffc..0xfffe].copy_from_slice(&[0x00, 0xc0]);
+        let raw = create_rom(TestRom {
+            header: header(4, 2, 0x80, 0xc0),
+            trainer: None,
+            prg_rom,
+            chr_rom: vec![0x42; 0x4000],
+        });
+
+        Rom::new(&raw).unwrap()
+    }
+
+    #[test]
+    fn test_unsupported_mapper_is_refused_by_default() {
+        match Emulator::new(mapper_200_rom()) {
+            Err(e) => assert_eq!(e, "Mapper 200 is not supported"),
+            Ok(_) => panic!("mapper 200 loaded"),
+        }
+    }
+
+    #[test]
+    fn test_unsupported_mapper_runs_as_nrom_when_allowed() {
+        let mut emulator =
+            Emulator::allowing_unsupported_mappers(mapper_200_rom(), TvSystem::Ntsc).unwrap();
+        assert_eq!(
+            emulator.load_warnings(),
+            [LoadWarning::UnsupportedMapper { number: 200 }]
+        );
+
+        run(&mut emulator, 5);
+        let bus = emulator.cpu().bus();
+        assert_eq!(bus.peek(0x0000), 1);
+        assert_eq!(bus.peek(0x0001), 4);
+
+        // and it's still there after a power cycle
+        emulator.power_cycle();
+        emulator.run_until_frame().unwrap();
+        assert_eq!(emulator.cpu().bus().peek(0x0001), 4);
+    }
+
+    #[test]
+    fn test_supported_mapper_has_no_load_warnings() {
+        let emulator = Emulator::allowing_unsupported_mappers(test_rom(0), TvSystem::Ntsc).unwrap();
+        assert!(emulator.load_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_power_cycle_starts_the_console_over() {
+        for rom in [test_rom, |_| mmc3_rom()] {
+            let mut fresh = Emulator::new(rom(0)).unwrap();
+            fresh.run_until_frame().unwrap();
+
+            let mut emulator = Emulator::new(rom(0)).unwrap();
+            emulator.run_frames(5).unwrap();
+            emulator.power_cycle();
+            emulator.run_until_frame().unwrap();
+            assert_eq!(snapshot(&emulator), snapshot(&fresh));
+            assert_eq!(emulator.frame_count(), 6);
+        }
+    }
+
+    #[test]
+    fn test_power_cycle_failure_is_returned() {
+        let mut emulator = Emulator::new(test_rom(0)).unwrap();
+        emulator.rom.prg_rom.clear();
+        emulator.power_cycle();
+
+        assert!(matches!(
+            emulator.run_until_frame(),
+            Err(CpuError::Cartridge(_))
+        ));
+    }
+
+    // a different set of buttons every frame
+    fn buttons(frame: u64) -> JoypadButton {
+        JoypadButton::from_bits_truncate((frame * 37) as u8)
+    }
+
+    // ram and the frame, hashed
+    fn console_hash(emulator: &Emulator) -> u64 {
+        let (registers, ram, oam, frame) = snapshot(emulator);
+        let mut hasher = DefaultHasher::new();
+        (registers, ram, oam, frame).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_movie_replays_the_same() {
+        let mut emulator = Emulator::new(test_rom(0)).unwrap();
+        emulator.attach_recorder();
+        for frame in 0..300 {
+            match frame {
+                150 => emulator.reset(),
+                220 => {
+                    emulator.reset();
+                    emulator.power_cycle();
+                }
+                _ => {}
+            }
+            emulator.set_frame_input(buttons(frame), buttons(frame + 1));
+            emulator.run_until_frame().unwrap();
+        }
+        let movie = emulator.detach_recorder().unwrap();
+        assert_eq!(movie.frames.len(), 300);
+        assert_eq!(movie.frames[150].event, Some(MovieEvent::Reset));
+        assert_eq!(movie.frames[220].event, Some(MovieEvent::Power));
+        assert_ne!(emulator.cpu().bus().peek(0x13), 0);
+        let recorded = console_hash(&emulator);
+
+        let mut replay = Emulator::new(test_rom(0)).unwrap();
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        replay.attach_player(movie).unwrap();
+        while replay.is_playing() {
+            replay.run_until_frame().unwrap();
+        }
+        assert_eq!(console_hash(&replay), recorded);
+    }
+
+    #[test]
+    fn test_movie_for_another_rom_is_refused() {
+        let mut emulator = Emulator::new(test_rom(0)).unwrap();
+        emulator.attach_recorder();
+        emulator.run_frames(5).unwrap();
+        let movie = emulator.detach_recorder().unwrap();
+
+        let mut other = Emulator::new(test_rom(0xea)).unwrap();
+        assert_eq!(
+            other.attach_player(movie.clone()),
+            Err(MovieError::WrongRom {
+                expected: other.rom_crc(),
+                actual: emulator.rom_crc(),
+            })
+        );
+        assert!(!other.is_playing());
+
+        let mut pal = Emulator::with_tv_system(test_rom(0), TvSystem::Pal).unwrap();
+        assert!(matches!(
+            pal.attach_player(movie),
+            Err(MovieError::WrongTvSystem { .. })
+        ));
+    }
+
+    // the frame number and a hash of ram after each frame up to `last`
+    #[cfg(feature = "savestate")]
+    fn run_scripted(emulator: &mut Emulator, last: u64) -> Vec<(u64, u64)> {
+        let mut hashes = vec![];
+        while emulator.frame_count() < last {
+            let next = emulator.frame_count() + 1;
+            let joypad = emulator.cpu_mut().bus_mut().joypad1_mut();
+            joypad.set_button_pressed_status(JoypadButton::all(), false);
+            joypad.set_button_pressed_status(buttons(next), true);
+            emulator.run_until_frame().unwrap();
+
+            let mut hasher = DefaultHasher::new();
+            snapshot(emulator).1.hash(&mut hasher);
+            hashes.push((emulator.frame_count(), hasher.finish()));
+        }
+        hashes
+    }
+
+    #[cfg(feature = "savestate")]
+    #[test]
+    fn test_rewind_replays_the_same_frames() {
+        let mut emulator = Emulator::new(test_rom(0)).unwrap();
+        let first = run_scripted(&mut emulator, 90);
+        assert_ne!(emulator.cpu().bus().peek(0x13), 0);
+
+        assert!(emulator.rewind(30));
+        assert_eq!(emulator.frame_count(), 60);
+        let replay = run_scripted(&mut emulator, 90);
+
+        assert_eq!(replay, first[60..]);
+    }
+
+    #[cfg(feature = "savestate")]
+    #[test]
+    fn test_rewind_goes_to_the_nearest_older_state() {
+        let mut emulator = Emulator::new(test_rom(0)).unwrap();
+        emulator.set_rewind_interval(4);
+        run_scripted(&mut emulator, 21);
+
+        // states at 4, 8, 12, 16 and 20
+        assert!(emulator.rewind(6));
+        assert_eq!(emulator.frame_count(), 12);
+        assert!(!emulator.rewind(13));
+        assert_eq!(emulator.frame_count(), 12);
+    }
+
+    #[cfg(feature = "savestate")]
+    #[test]
+    fn test_rewind_states_are_small() {
+        let mut emulator = Emulator::new(test_rom(0)).unwrap();
+        run_scripted(&mut emulator, 100);
+        let state = emulator.save_state().len();
+
+        // 50 of them in not much more than the space of two
+        assert!(emulator.rewind_memory_usage() < 2 * state);
+
+        emulator.set_rewind_capacity(0);
+        assert_eq!(emulator.rewind_memory_usage(), 0);
+        assert!(!emulator.rewind(1));
+    }
+}
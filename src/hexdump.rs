@@ -0,0 +1,53 @@
+// `bytes` as if they were at `start`, 16 to a line with the printable ones repeated at the end:
+//
+// 0200  48 65 6C 6C 6F 00 00 00  00 00 00 00 00 00 00 00  |Hello...........|
+//
+// lines count up from `start` rather than lining up on multiples of 16, so the first byte asked
+// for is always first
+pub fn hexdump(bytes: &[u8], start: u16) -> String {
+    let mut result = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let address = start.wrapping_add(i as u16 * 16);
+        let mut hex = String::new();
+        for (j, byte) in line.iter().enumerate() {
+            if j == 8 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{:02X} ", byte));
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        result.push_str(&format!("{:04X}  {:49} |{}|\n", address, hex, ascii));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hexdump_lines() {
+        let mut bytes = b"Hello, world!\n".to_vec();
+        bytes.extend([0x00, 0xff, 0x7f, 0x20, 0x41]);
+
+        assert_eq!(
+            hexdump(&bytes, 0x0200),
+            "0200  48 65 6C 6C 6F 2C 20 77  6F 72 6C 64 21 0A 00 FF  |Hello, world!...|\n\
+             0210  7F 20 41                                          |. A|\n"
+        );
+    }
+
+    #[test]
+    fn test_hexdump_nothing() {
+        assert_eq!(hexdump(&[], 0x8000), "");
+    }
+}
@@ -0,0 +1,142 @@
+use bitflags::bitflags;
+
+bitflags! {
+    // the order the buttons are shifted out in, A first
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+    pub struct JoypadButton: u8 {
+        const A = 0b0000_0001;
+        const B = 0b0000_0010;
+        const SELECT = 0b0000_0100;
+        const START = 0b0000_1000;
+        const UP = 0b0001_0000;
+        const DOWN = 0b0010_0000;
+        const LEFT = 0b0100_0000;
+        const RIGHT = 0b1000_0000;
+    }
+}
+
+// a standard controller: the buttons are latched into a shift register while strobe is high
+// and read back one bit at a time, see https://www.nesdev.org/wiki/Standard_controller
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Joypad {
+    strobe: bool,
+    button_status: JoypadButton,
+    latched: JoypadButton,
+    button_index: u8,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            strobe: false,
+            button_status: JoypadButton::empty(),
+            latched: JoypadButton::empty(),
+            button_index: 0,
+        }
+    }
+
+    pub fn set_button_pressed_status(&mut self, button: JoypadButton, pressed: bool) {
+        self.button_status.set(button, pressed);
+    }
+
+    // everything in `buttons` pressed and the rest let go
+    pub fn set_buttons(&mut self, buttons: JoypadButton) {
+        self.button_status = buttons;
+    }
+
+    // what's held down, latched or not
+    pub fn buttons(&self) -> JoypadButton {
+        self.button_status
+    }
+
+    pub fn write(&mut self, data: u8) {
+        let was_strobing = self.strobe;
+        self.strobe = data & 1 == 1;
+        // the register keeps reloading while strobe is high, so it holds whatever was pressed
+        // when strobe went low
+        if self.strobe || was_strobing {
+            self.latched = self.button_status;
+            self.button_index = 0;
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        let bit = self.peek();
+        if !self.strobe && self.button_index <= 7 {
+            self.button_index += 1;
+        }
+        bit
+    }
+
+    // the bit the next read returns, without shifting it out
+    pub fn peek(&self) -> u8 {
+        if self.strobe {
+            return self.button_status.contains(JoypadButton::A) as u8;
+        }
+        // the shift register fills up with 1s behind the last button
+        if self.button_index > 7 {
+            return 1;
+        }
+        (self.latched.bits() >> self.button_index) & 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strobe(joypad: &mut Joypad) {
+        joypad.write(1);
+        joypad.write(0);
+    }
+
+    #[test]
+    fn test_reads_buttons_in_order_then_ones() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JoypadButton::A, true);
+        joypad.set_button_pressed_status(JoypadButton::START, true);
+        joypad.set_button_pressed_status(JoypadButton::LEFT, true);
+        strobe(&mut joypad);
+
+        let reads: Vec<u8> = (0..9).map(|_| joypad.read()).collect();
+
+        assert_eq!(reads, [1, 0, 0, 1, 0, 0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn test_changes_after_latching_wait_for_the_next_strobe() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JoypadButton::B, true);
+        strobe(&mut joypad);
+        assert_eq!(joypad.read(), 0);
+
+        joypad.set_button_pressed_status(JoypadButton::B, false);
+        joypad.set_button_pressed_status(JoypadButton::SELECT, true);
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 0);
+
+        strobe(&mut joypad);
+        let reads: Vec<u8> = (0..3).map(|_| joypad.read()).collect();
+        assert_eq!(reads, [0, 0, 1]);
+    }
+
+    #[test]
+    fn test_strobe_high_keeps_reading_a() {
+        let mut joypad = Joypad::new();
+        joypad.write(1);
+        joypad.set_button_pressed_status(JoypadButton::A, true);
+
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 1);
+
+        joypad.set_button_pressed_status(JoypadButton::A, false);
+        assert_eq!(joypad.read(), 0);
+    }
+}
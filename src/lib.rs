@@ -0,0 +1,25 @@
+pub mod apu;
+pub mod asm;
+pub mod breakpoints;
+pub mod bus;
+pub mod cpu;
+pub mod disasm;
+pub mod easy6502;
+pub mod emulator;
+pub mod hexdump;
+pub mod joypad;
+pub mod mappers;
+pub mod movie;
+pub mod opcode;
+pub mod ppu;
+pub mod render;
+#[cfg(feature = "savestate")]
+pub mod rewind;
+pub mod rom;
+#[cfg(feature = "savestate")]
+pub mod savestate;
+pub mod trace;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod watchpoints;
+pub mod zapper;
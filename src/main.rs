@@ -1,7 +0,0 @@
-mod cpu;
-mod opcode;
-
-fn main() {
-    println!("Hello, world!");
-    println!("Hello, world!");
-}
@@ -0,0 +1,146 @@
+use super::{apply_bus_conflict, check_size, Mapper};
+use crate::rom::{Mirroring, Rom};
+#[cfg(feature = "savestate")]
+use crate::savestate::{self, StateError};
+
+const PRG_ROM: u16 = 0x8000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+// mapper 3: prg is fixed like nrom, writes to 0x8000 - 0xffff pick the 8KB chr bank
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cnrom {
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    prg_rom: Vec<u8>,
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    chr_rom: Vec<u8>,
+    chr_bank: u8,
+    mirroring: Mirroring,
+    // off by default, see apply_bus_conflict
+    pub bus_conflicts: bool,
+}
+
+impl Cnrom {
+    pub fn new(rom: Rom) -> Result<Self, String> {
+        check_size("PRG ROM", &rom.prg_rom, 1)?;
+        check_size("CHR ROM", &rom.chr_rom, CHR_BANK_SIZE)?;
+        Ok(Cnrom {
+            prg_rom: rom.prg_rom,
+            chr_rom: rom.chr_rom,
+            chr_bank: 0,
+            mirroring: rom.screen_mirroring,
+            bus_conflicts: false,
+        })
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            // a single 16KB bank shows up in both halves
+            PRG_ROM..=0xffff => self.prg_rom[(addr - PRG_ROM) as usize % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        if addr >= PRG_ROM {
+            self.chr_bank = apply_bus_conflict(self.bus_conflicts, value, self.cpu_read(addr));
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        // selects past the last bank wrap around instead of reading off the end
+        let bank = self.chr_bank as usize % (self.chr_rom.len() / CHR_BANK_SIZE);
+        self.chr_rom[bank * CHR_BANK_SIZE + (addr & 0x1fff) as usize]
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _value: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    #[cfg(feature = "savestate")]
+    fn save_state(&self) -> Vec<u8> {
+        savestate::encode(self)
+    }
+
+    #[cfg(feature = "savestate")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let restored: Cnrom = savestate::decode(state)?;
+        *self = Cnrom {
+            prg_rom: std::mem::take(&mut self.prg_rom),
+            chr_rom: std::mem::take(&mut self.chr_rom),
+            ..restored
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, Mem};
+    use crate::rom::tests::{bare_rom, create_rom, header, TestRom};
+
+    // four chr banks, each filled with its own number + 0x10
+    fn cnrom_rom(prg_fill: u8) -> Rom {
+        let mut chr_rom = vec![0; 4 * CHR_BANK_SIZE];
+        for (bank, chunk) in chr_rom.chunks_mut(CHR_BANK_SIZE).enumerate() {
+            chunk.fill(bank as u8 + 0x10);
+        }
+        let raw = create_rom(TestRom {
+            header: header(1, 4, 0x30, 0x00),
+            trainer: None,
+            prg_rom: vec![prg_fill; 0x4000],
+            chr_rom,
+        });
+
+        Rom::new(&raw).unwrap()
+    }
+
+    #[test]
+    fn test_bank_select_through_cpu_bus() {
+        let mut bus = Bus::with_rom(cnrom_rom(0xff)).unwrap();
+        assert_eq!(bus.ppu_read(0x0123), 0x10);
+
+        for bank in [2, 1, 3, 0] {
+            bus.mem_write(0x8000, bank);
+            assert_eq!(bus.ppu_read(0x0123), bank + 0x10);
+            assert_eq!(bus.ppu_read(0x1fff), bank + 0x10);
+        }
+    }
+
+    #[test]
+    fn test_out_of_range_bank_wraps() {
+        let mut cnrom = Cnrom::new(cnrom_rom(0xff)).unwrap();
+        cnrom.cpu_write(0xffff, 6);
+
+        assert_eq!(cnrom.ppu_read(0x0000), 0x12);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut cnrom = Cnrom::new(cnrom_rom(0b01)).unwrap();
+        cnrom.bus_conflicts = true;
+        cnrom.cpu_write(0x8000, 0b11);
+
+        assert_eq!(cnrom.ppu_read(0x0000), 0x11);
+    }
+
+    #[test]
+    fn test_chr_rom_ignores_writes() {
+        let mut cnrom = Cnrom::new(cnrom_rom(0xff)).unwrap();
+        cnrom.ppu_write(0x0000, 0x42);
+
+        assert_eq!(cnrom.ppu_read(0x0000), 0x10);
+    }
+
+    #[test]
+    fn test_missing_rom_is_refused() {
+        assert!(Cnrom::new(bare_rom(3, 0, 0x2000)).is_err());
+        assert!(Cnrom::new(bare_rom(3, 0x4000, 0)).is_err());
+        assert!(Cnrom::new(bare_rom(3, 0x4000, 0x1000)).is_err());
+        assert!(Cnrom::new(bare_rom(3, 0x4000, 0x2000)).is_ok());
+    }
+}
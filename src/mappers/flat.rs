@@ -0,0 +1,72 @@
+use super::Mapper;
+use crate::rom::Mirroring;
+#[cfg(feature = "savestate")]
+use crate::savestate::{self, StateError};
+
+const PRG_SPACE: u16 = 0x8000;
+
+// not a real board: what the bus uses when there is no cartridge, so raw programs can be
+// loaded into 0x8000 - 0xffff and poked at like ordinary memory
+pub struct Flat {
+    prg_space: Box<[u8; 0x8000]>,
+}
+
+impl Default for Flat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Flat {
+    pub fn new() -> Self {
+        Flat {
+            // built on the heap, a big array by value is enough to overflow small stacks
+            prg_space: vec![0; 0x8000].into_boxed_slice().try_into().unwrap(),
+        }
+    }
+}
+
+impl Mapper for Flat {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            PRG_SPACE..=0xffff => self.prg_space[(addr - PRG_SPACE) as usize],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        if addr >= PRG_SPACE {
+            self.prg_space[(addr - PRG_SPACE) as usize] = value;
+        }
+    }
+
+    fn ppu_read(&self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _value: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+
+    // there's no rom here, the whole space is the state
+    #[cfg(feature = "savestate")]
+    fn save_state(&self) -> Vec<u8> {
+        savestate::encode(&self.prg_space[..])
+    }
+
+    #[cfg(feature = "savestate")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let prg_space: Vec<u8> = savestate::decode(state)?;
+        if prg_space.len() != self.prg_space.len() {
+            return Err(StateError::Corrupt(format!(
+                "{} bytes of program space instead of {}",
+                prg_space.len(),
+                self.prg_space.len()
+            )));
+        }
+        self.prg_space.copy_from_slice(&prg_space);
+        Ok(())
+    }
+}
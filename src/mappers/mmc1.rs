@@ -0,0 +1,392 @@
+use super::{check_size, Mapper};
+use crate::rom::{Mirroring, Rom};
+#[cfg(feature = "savestate")]
+use crate::savestate::{self, StateError};
+
+const PRG_RAM: u16 = 0x6000;
+const PRG_RAM_END: u16 = 0x7fff;
+const PRG_ROM: u16 = 0x8000;
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+// the shift register starts with a marker bit, once it falls out the bottom five bits are in
+const SHIFT_RESET: u8 = 0b1_0000;
+
+// mapper 1: registers are loaded one bit at a time through writes to 0x8000 - 0xffff,
+// see https://www.nesdev.org/wiki/MMC1
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mmc1 {
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    prg_rom: Vec<u8>,
+    #[cfg_attr(feature = "savestate", serde(with = "crate::savestate::big_array"))]
+    prg_ram: [u8; 0x2000],
+    // saved on its own when it's ram, see save_state
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    shift: u8,
+    // 4bit0
+    // -----
+    // CPPMM
+    // |||||
+    // |||++- mirroring (0: one screen lower, 1: one screen upper, 2: vertical, 3: horizontal)
+    // |++--- prg mode (0, 1: 32KB at 0x8000, 2: first bank fixed at 0x8000, 3: last bank fixed at 0xc000)
+    // +----- chr mode (0: one 8KB bank, 1: two 4KB banks)
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+}
+
+impl Mmc1 {
+    pub fn new(rom: Rom) -> Result<Self, String> {
+        check_size("PRG ROM", &rom.prg_rom, PRG_BANK_SIZE)?;
+        let chr_is_ram = rom.chr_rom.is_empty();
+        if !chr_is_ram {
+            check_size("CHR ROM", &rom.chr_rom, CHR_BANK_SIZE)?;
+        }
+        Ok(Mmc1 {
+            prg_rom: rom.prg_rom,
+            prg_ram: [0; 0x2000],
+            chr: if chr_is_ram {
+                vec![0; 0x2000]
+            } else {
+                rom.chr_rom
+            },
+            chr_is_ram,
+            shift: SHIFT_RESET,
+            // boards power up with the last bank fixed at 0xc000 so the reset vector is there
+            control: 0b0_1100,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+        })
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        // bit 7 set clears the shift register and goes back to fixing the last bank
+        if value & 0b1000_0000 != 0 {
+            self.shift = SHIFT_RESET;
+            self.control |= 0b0_1100;
+            return;
+        }
+
+        let full = self.shift & 1 == 1;
+        self.shift = (self.shift >> 1) | ((value & 1) << 4);
+        if !full {
+            return;
+        }
+
+        // only the address of the fifth write picks the register
+        match addr {
+            0x8000..=0x9fff => self.control = self.shift,
+            0xa000..=0xbfff => self.chr_bank_0 = self.shift,
+            0xc000..=0xdfff => self.chr_bank_1 = self.shift,
+            _ => self.prg_bank = self.shift,
+        }
+        self.shift = SHIFT_RESET;
+    }
+
+    fn prg_bank_at(&self, addr: u16) -> usize {
+        let bank = (self.prg_bank & 0b1111) as usize;
+        let last = self.prg_rom.len() / PRG_BANK_SIZE - 1;
+        let upper = addr >= 0xc000;
+
+        match (self.control >> 2) & 0b11 {
+            // 32KB mode ignores the low bit of the bank number
+            0 | 1 => (bank & !1) + upper as usize,
+            2 => {
+                if upper {
+                    bank
+                } else {
+                    0
+                }
+            }
+            _ => {
+                if upper {
+                    last
+                } else {
+                    bank
+                }
+            }
+        }
+    }
+
+    fn chr_address(&self, addr: u16) -> usize {
+        let addr = (addr & 0x1fff) as usize;
+        let bank = if self.control & 0b1_0000 == 0 {
+            // 8KB mode ignores the low bit of the bank number
+            (self.chr_bank_0 & !1) as usize + addr / CHR_BANK_SIZE
+        } else if addr < CHR_BANK_SIZE {
+            self.chr_bank_0 as usize
+        } else {
+            self.chr_bank_1 as usize
+        };
+        let banks = self.chr.len() / CHR_BANK_SIZE;
+
+        (bank % banks) * CHR_BANK_SIZE + addr % CHR_BANK_SIZE
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            PRG_RAM..=PRG_RAM_END => self.prg_ram[(addr - PRG_RAM) as usize],
+            PRG_ROM..=0xffff => {
+                let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+                let bank = self.prg_bank_at(addr) % banks;
+                self.prg_rom[bank * PRG_BANK_SIZE + (addr as usize % PRG_BANK_SIZE)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            PRG_RAM..=PRG_RAM_END => self.prg_ram[(addr - PRG_RAM) as usize] = value,
+            PRG_ROM..=0xffff => self.write_register(addr, value),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.chr_address(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let addr = self.chr_address(addr);
+            self.chr[addr] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    #[cfg(feature = "savestate")]
+    fn save_state(&self) -> Vec<u8> {
+        let chr_ram = self.chr_is_ram.then_some(&self.chr);
+        savestate::encode(&(self, chr_ram))
+    }
+
+    #[cfg(feature = "savestate")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let (restored, chr_ram): (Mmc1, Option<Vec<u8>>) = savestate::decode(state)?;
+        *self = Mmc1 {
+            prg_rom: std::mem::take(&mut self.prg_rom),
+            chr: chr_ram.unwrap_or_else(|| std::mem::take(&mut self.chr)),
+            ..restored
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{bare_rom, create_rom, header, TestRom};
+
+    // every prg bank is filled with its own number, every 4KB of chr with its number + 0x10
+    fn mmc1(prg_banks: u8, chr_banks: u8) -> Mmc1 {
+        let mut prg_rom = vec![0; prg_banks as usize * PRG_BANK_SIZE];
+        for (bank, chunk) in prg_rom.chunks_mut(PRG_BANK_SIZE).enumerate() {
+            chunk.fill(bank as u8);
+        }
+        let mut chr_rom = vec![0; chr_banks as usize * 0x2000];
+        for (bank, chunk) in chr_rom.chunks_mut(CHR_BANK_SIZE).enumerate() {
+            chunk.fill(bank as u8 + 0x10);
+        }
+        let raw = create_rom(TestRom {
+            header: header(prg_banks, chr_banks, 0x10, 0x00),
+            trainer: None,
+            prg_rom,
+            chr_rom,
+        });
+
+        Mmc1::new(Rom::new(&raw).unwrap()).unwrap()
+    }
+
+    // five writes, low bit first
+    fn load(mmc1: &mut Mmc1, addr: u16, value: u8) {
+        for i in 0..5 {
+            mmc1.cpu_write(addr, (value >> i) & 1);
+        }
+    }
+
+    #[test]
+    fn test_powers_up_with_last_bank_fixed() {
+        let mmc1 = mmc1(8, 1);
+
+        assert_eq!(mmc1.cpu_read(0x8000), 0);
+        assert_eq!(mmc1.cpu_read(0xc000), 7);
+        assert_eq!(mmc1.cpu_read(0xffff), 7);
+    }
+
+    #[test]
+    fn test_register_only_loads_on_fifth_write() {
+        let mut mmc1 = mmc1(8, 1);
+        for _ in 0..4 {
+            mmc1.cpu_write(0xe000, 1);
+            assert_eq!(mmc1.cpu_read(0x8000), 0);
+        }
+        mmc1.cpu_write(0xe000, 0);
+
+        assert_eq!(mmc1.cpu_read(0x8000), 0b1111 % 8);
+    }
+
+    #[test]
+    fn test_reset_bit_mid_sequence() {
+        let mut mmc1 = mmc1(8, 1);
+        mmc1.cpu_write(0xe000, 1);
+        mmc1.cpu_write(0xe000, 1);
+        mmc1.cpu_write(0xe000, 0x80);
+        load(&mut mmc1, 0xe000, 3);
+
+        assert_eq!(mmc1.cpu_read(0x8000), 3);
+        assert_eq!(mmc1.cpu_read(0xc000), 7);
+    }
+
+    #[test]
+    fn test_reset_bit_fixes_last_bank() {
+        let mut mmc1 = mmc1(8, 1);
+        load(&mut mmc1, 0x8000, 0b0_1000);
+        load(&mut mmc1, 0xe000, 3);
+        assert_eq!(mmc1.cpu_read(0xc000), 3);
+
+        mmc1.cpu_write(0x8000, 0x80);
+
+        assert_eq!(mmc1.cpu_read(0x8000), 3);
+        assert_eq!(mmc1.cpu_read(0xc000), 7);
+    }
+
+    #[test]
+    fn test_prg_mode_switch_32kb() {
+        let mut mmc1 = mmc1(8, 1);
+        load(&mut mmc1, 0x8000, 0b0_0000);
+        load(&mut mmc1, 0xe000, 5);
+
+        assert_eq!(mmc1.cpu_read(0x8000), 4);
+        assert_eq!(mmc1.cpu_read(0xc000), 5);
+    }
+
+    #[test]
+    fn test_prg_mode_fix_first_bank() {
+        let mut mmc1 = mmc1(8, 1);
+        load(&mut mmc1, 0x8000, 0b0_1000);
+        load(&mut mmc1, 0xe000, 5);
+
+        assert_eq!(mmc1.cpu_read(0x8000), 0);
+        assert_eq!(mmc1.cpu_read(0xc000), 5);
+    }
+
+    #[test]
+    fn test_prg_mode_fix_last_bank() {
+        let mut mmc1 = mmc1(8, 1);
+        load(&mut mmc1, 0x8000, 0b0_1100);
+        load(&mut mmc1, 0xe000, 5);
+
+        assert_eq!(mmc1.cpu_read(0x8000), 5);
+        assert_eq!(mmc1.cpu_read(0xc000), 7);
+    }
+
+    #[test]
+    fn test_chr_8kb_mode() {
+        let mut mmc1 = mmc1(2, 2);
+        load(&mut mmc1, 0x8000, 0b0_1100);
+        load(&mut mmc1, 0xa000, 3);
+
+        assert_eq!(mmc1.ppu_read(0x0000), 0x12);
+        assert_eq!(mmc1.ppu_read(0x1000), 0x13);
+    }
+
+    #[test]
+    fn test_chr_4kb_mode() {
+        let mut mmc1 = mmc1(2, 2);
+        load(&mut mmc1, 0x8000, 0b1_1100);
+        load(&mut mmc1, 0xa000, 3);
+        load(&mut mmc1, 0xc000, 0);
+
+        assert_eq!(mmc1.ppu_read(0x0000), 0x13);
+        assert_eq!(mmc1.ppu_read(0x1000), 0x10);
+    }
+
+    #[test]
+    fn test_chr_ram_is_writable() {
+        let mut mmc1 = mmc1(2, 0);
+        mmc1.ppu_write(0x1234, 0x42);
+
+        assert_eq!(mmc1.ppu_read(0x1234), 0x42);
+    }
+
+    #[test]
+    fn test_mirroring_changes_at_runtime() {
+        let mut mmc1 = mmc1(2, 1);
+        let modes = [
+            (0, Mirroring::SingleScreenLower),
+            (1, Mirroring::SingleScreenUpper),
+            (2, Mirroring::Vertical),
+            (3, Mirroring::Horizontal),
+        ];
+
+        for (bits, mirroring) in modes {
+            load(&mut mmc1, 0x8000, 0b0_1100 | bits);
+            assert_eq!(mmc1.mirroring(), mirroring);
+        }
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut mmc1 = mmc1(2, 1);
+        mmc1.cpu_write(0x6000, 0x11);
+        mmc1.cpu_write(0x7fff, 0x22);
+
+        assert_eq!(mmc1.cpu_read(0x6000), 0x11);
+        assert_eq!(mmc1.cpu_read(0x7fff), 0x22);
+    }
+
+    #[cfg(feature = "savestate")]
+    #[test]
+    fn test_state_restores_banks_and_half_loaded_shift() {
+        let mut mmc1 = mmc1(8, 2);
+        load(&mut mmc1, 0xe000, 3);
+        mmc1.cpu_write(0x6000, 0x42);
+        // two bits into the next load
+        mmc1.cpu_write(0xe000, 1);
+        mmc1.cpu_write(0xe000, 0);
+        let state = mmc1.save_state();
+
+        load(&mut mmc1, 0xe000, 5);
+        mmc1.cpu_write(0x6000, 0);
+        mmc1.load_state(&state).unwrap();
+
+        assert_eq!(mmc1.cpu_read(0x8000), 3);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x42);
+        for bit in [1, 0, 0] {
+            mmc1.cpu_write(0xe000, bit);
+        }
+        // 0b00101 finished off from where the state left it
+        assert_eq!(mmc1.cpu_read(0x8000), 5);
+    }
+
+    #[test]
+    fn test_less_than_a_bank_is_refused() {
+        assert!(Mmc1::new(bare_rom(1, 0, 0)).is_err());
+        assert!(Mmc1::new(bare_rom(1, 0x2000, 0)).is_err());
+        assert!(Mmc1::new(bare_rom(1, 0x4000, 0x800)).is_err());
+        assert!(Mmc1::new(bare_rom(1, 0x4000, 0)).is_ok());
+    }
+}
@@ -0,0 +1,372 @@
+use super::{check_size, Mapper};
+use crate::rom::{Mirroring, Rom};
+#[cfg(feature = "savestate")]
+use crate::savestate::{self, StateError};
+
+const PRG_RAM: u16 = 0x6000;
+const PRG_RAM_END: u16 = 0x7fff;
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+// mapper 4: 8KB prg banks, 1KB/2KB chr banks and a scanline counter that raises irqs,
+// see https://www.nesdev.org/wiki/MMC3
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mmc3 {
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    prg_rom: Vec<u8>,
+    #[cfg_attr(feature = "savestate", serde(with = "crate::savestate::big_array"))]
+    prg_ram: [u8; 0x2000],
+    // saved on its own when it's ram, see save_state
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    // 76543210
+    // --------
+    // CP---RRR
+    // ||   |||
+    // ||   +++- which of R0 - R7 the next 0x8001 write goes to
+    // |+------- prg mode (0: R6 at 0x8000, 1: R6 at 0xc000)
+    // +-------- chr mode (0: 2KB banks at 0x0000, 1: 2KB banks at 0x1000)
+    bank_select: u8,
+    registers: [u8; 8],
+    mirroring: Mirroring,
+    four_screen: bool,
+    prg_ram_enabled: bool,
+    prg_ram_write_protected: bool,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Mmc3 {
+    pub fn new(rom: Rom) -> Result<Self, String> {
+        // the second last bank is always mapped somewhere
+        check_size("PRG ROM", &rom.prg_rom, 2 * PRG_BANK_SIZE)?;
+        let chr_is_ram = rom.chr_rom.is_empty();
+        if !chr_is_ram {
+            check_size("CHR ROM", &rom.chr_rom, CHR_BANK_SIZE)?;
+        }
+        Ok(Mmc3 {
+            prg_rom: rom.prg_rom,
+            prg_ram: [0; 0x2000],
+            chr: if chr_is_ram {
+                vec![0; 0x2000]
+            } else {
+                rom.chr_rom
+            },
+            chr_is_ram,
+            bank_select: 0,
+            registers: [0; 8],
+            mirroring: rom.screen_mirroring,
+            four_screen: rom.screen_mirroring == Mirroring::FourScreen,
+            prg_ram_enabled: true,
+            prg_ram_write_protected: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        })
+    }
+
+    fn set_mirroring(&mut self, value: u8) {
+        // hardwired four screen boards ignore this
+        if self.four_screen {
+            return;
+        }
+        self.mirroring = if value & 1 == 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+    }
+
+    fn prg_address(&self, addr: u16) -> usize {
+        let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+        let second_last = banks - 2;
+        let r6 = self.registers[6] as usize;
+        let r7 = self.registers[7] as usize;
+        let prg_mode = self.bank_select & 0b0100_0000 != 0;
+
+        let bank = match (addr, prg_mode) {
+            (0x8000..=0x9fff, false) => r6,
+            (0x8000..=0x9fff, true) => second_last,
+            (0xa000..=0xbfff, _) => r7,
+            (0xc000..=0xdfff, false) => second_last,
+            (0xc000..=0xdfff, true) => r6,
+            _ => banks - 1,
+        };
+
+        (bank % banks) * PRG_BANK_SIZE + (addr as usize % PRG_BANK_SIZE)
+    }
+
+    fn chr_address(&self, addr: u16) -> usize {
+        let addr = (addr & 0x1fff) as usize;
+        // chr mode swaps the 2KB half and the 1KB half
+        let addr_in_mode = if self.bank_select & 0b1000_0000 != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+
+        let r = &self.registers;
+        let bank = match addr_in_mode / CHR_BANK_SIZE {
+            // R0 and R1 are 2KB, so they ignore the low bit
+            0 => r[0] & !1,
+            1 => r[0] | 1,
+            2 => r[1] & !1,
+            3 => r[1] | 1,
+            n => r[n - 2],
+        } as usize;
+        let banks = self.chr.len() / CHR_BANK_SIZE;
+
+        (bank % banks) * CHR_BANK_SIZE + addr % CHR_BANK_SIZE
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            PRG_RAM..=PRG_RAM_END if self.prg_ram_enabled => {
+                self.prg_ram[(addr - PRG_RAM) as usize]
+            }
+            0x8000..=0xffff => self.prg_rom[self.prg_address(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        let even = addr & 1 == 0;
+        match addr {
+            PRG_RAM..=PRG_RAM_END if self.prg_ram_enabled && !self.prg_ram_write_protected => {
+                self.prg_ram[(addr - PRG_RAM) as usize] = value;
+            }
+            0x8000..=0x9fff if even => self.bank_select = value,
+            0x8000..=0x9fff => self.registers[(self.bank_select & 0b111) as usize] = value,
+            0xa000..=0xbfff if even => self.set_mirroring(value),
+            0xa000..=0xbfff => {
+                self.prg_ram_enabled = value & 0b1000_0000 != 0;
+                self.prg_ram_write_protected = value & 0b0100_0000 != 0;
+            }
+            0xc000..=0xdfff if even => self.irq_latch = value,
+            // the counter picks the latch up on its next clock
+            0xc000..=0xdfff => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            // disabling also acknowledges an irq that is already pending
+            0xe000..=0xffff if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xe000..=0xffff => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.chr_address(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let addr = self.chr_address(addr);
+            self.chr[addr] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn notify_a12_rise(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    #[cfg(feature = "savestate")]
+    fn save_state(&self) -> Vec<u8> {
+        let chr_ram = self.chr_is_ram.then_some(&self.chr);
+        savestate::encode(&(self, chr_ram))
+    }
+
+    #[cfg(feature = "savestate")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let (restored, chr_ram): (Mmc3, Option<Vec<u8>>) = savestate::decode(state)?;
+        *self = Mmc3 {
+            prg_rom: std::mem::take(&mut self.prg_rom),
+            chr: chr_ram.unwrap_or_else(|| std::mem::take(&mut self.chr)),
+            ..restored
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{bare_rom, create_rom, header, TestRom};
+
+    // 8 prg banks of 8KB and 8 chr banks of 1KB, each filled with its own number
+    fn mmc3() -> Mmc3 {
+        let mut prg_rom = vec![0; 4 * 0x4000];
+        for (bank, chunk) in prg_rom.chunks_mut(PRG_BANK_SIZE).enumerate() {
+            chunk.fill(bank as u8);
+        }
+        let mut chr_rom = vec![0; 0x2000];
+        for (bank, chunk) in chr_rom.chunks_mut(CHR_BANK_SIZE).enumerate() {
+            chunk.fill(bank as u8 + 0x10);
+        }
+        let raw = create_rom(TestRom {
+            header: header(4, 1, 0x40, 0x00),
+            trainer: None,
+            prg_rom,
+            chr_rom,
+        });
+
+        Mmc3::new(Rom::new(&raw).unwrap()).unwrap()
+    }
+
+    fn set_register(mmc3: &mut Mmc3, mode: u8, register: u8, value: u8) {
+        mmc3.cpu_write(0x8000, mode | register);
+        mmc3.cpu_write(0x8001, value);
+    }
+
+    #[test]
+    fn test_prg_mode_0() {
+        let mut mmc3 = mmc3();
+        set_register(&mut mmc3, 0, 6, 2);
+        set_register(&mut mmc3, 0, 7, 3);
+
+        assert_eq!(mmc3.cpu_read(0x8000), 2);
+        assert_eq!(mmc3.cpu_read(0xa000), 3);
+        assert_eq!(mmc3.cpu_read(0xc000), 6);
+        assert_eq!(mmc3.cpu_read(0xe000), 7);
+    }
+
+    #[test]
+    fn test_prg_mode_1() {
+        let mut mmc3 = mmc3();
+        set_register(&mut mmc3, 0b0100_0000, 6, 2);
+        set_register(&mut mmc3, 0b0100_0000, 7, 3);
+
+        assert_eq!(mmc3.cpu_read(0x8000), 6);
+        assert_eq!(mmc3.cpu_read(0xa000), 3);
+        assert_eq!(mmc3.cpu_read(0xc000), 2);
+        assert_eq!(mmc3.cpu_read(0xe000), 7);
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut mmc3 = mmc3();
+        set_register(&mut mmc3, 0, 0, 2);
+        set_register(&mut mmc3, 0, 1, 4);
+        set_register(&mut mmc3, 0, 2, 7);
+        set_register(&mut mmc3, 0, 5, 1);
+
+        assert_eq!(mmc3.ppu_read(0x0000), 0x12);
+        assert_eq!(mmc3.ppu_read(0x0400), 0x13);
+        assert_eq!(mmc3.ppu_read(0x0800), 0x14);
+        assert_eq!(mmc3.ppu_read(0x1000), 0x17);
+        assert_eq!(mmc3.ppu_read(0x1c00), 0x11);
+
+        // chr mode swaps the halves
+        mmc3.cpu_write(0x8000, 0b1000_0000);
+        assert_eq!(mmc3.ppu_read(0x1000), 0x12);
+        assert_eq!(mmc3.ppu_read(0x0000), 0x17);
+    }
+
+    #[test]
+    fn test_mirroring_control() {
+        let mut mmc3 = mmc3();
+        mmc3.cpu_write(0xa000, 1);
+        assert_eq!(mmc3.mirroring(), Mirroring::Horizontal);
+
+        mmc3.cpu_write(0xa000, 0);
+        assert_eq!(mmc3.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_prg_ram_protect() {
+        let mut mmc3 = mmc3();
+        mmc3.cpu_write(0x6000, 0x11);
+        mmc3.cpu_write(0xa001, 0b1100_0000);
+        mmc3.cpu_write(0x6000, 0x22);
+        assert_eq!(mmc3.cpu_read(0x6000), 0x11);
+
+        mmc3.cpu_write(0xa001, 0);
+        assert_eq!(mmc3.cpu_read(0x6000), 0);
+    }
+
+    #[test]
+    fn test_irq_fires_once_then_again_after_reload() {
+        let mut mmc3 = mmc3();
+        mmc3.cpu_write(0xc000, 3);
+        mmc3.cpu_write(0xc001, 0);
+        mmc3.cpu_write(0xe001, 0);
+
+        // the first clock only loads the latch, then it counts 3, 2, 1, 0
+        for _ in 0..3 {
+            mmc3.notify_a12_rise();
+            assert!(!mmc3.irq_pending());
+        }
+        mmc3.notify_a12_rise();
+        assert!(mmc3.irq_pending());
+
+        // acknowledge and re-enable
+        mmc3.cpu_write(0xe000, 0);
+        mmc3.cpu_write(0xe001, 0);
+        assert!(!mmc3.irq_pending());
+
+        mmc3.cpu_write(0xc001, 0);
+        for _ in 0..3 {
+            mmc3.notify_a12_rise();
+            assert!(!mmc3.irq_pending());
+        }
+        mmc3.notify_a12_rise();
+        assert!(mmc3.irq_pending());
+    }
+
+    #[test]
+    fn test_irq_disabled_does_not_fire() {
+        let mut mmc3 = mmc3();
+        mmc3.cpu_write(0xc000, 1);
+        mmc3.cpu_write(0xc001, 0);
+
+        for _ in 0..4 {
+            mmc3.notify_a12_rise();
+        }
+
+        assert!(!mmc3.irq_pending());
+    }
+
+    #[test]
+    fn test_too_few_banks_are_refused() {
+        assert!(Mmc3::new(bare_rom(4, 0, 0)).is_err());
+        assert!(Mmc3::new(bare_rom(4, 0x2000, 0)).is_err());
+        assert!(Mmc3::new(bare_rom(4, 0x4000, 0x200)).is_err());
+        assert!(Mmc3::new(bare_rom(4, 0x4000, 0)).is_ok());
+    }
+}
@@ -0,0 +1,166 @@
+pub mod cnrom;
+pub mod flat;
+pub mod mmc1;
+pub mod mmc3;
+pub mod nrom;
+pub mod uxrom;
+
+use std::fmt;
+
+use crate::rom::{Mirroring, Rom};
+#[cfg(feature = "savestate")]
+use crate::savestate::StateError;
+
+// everything on the cartridge side of the buses goes through one of these, the cpu sees
+// 0x4020 - 0xffff and the ppu sees the pattern tables at 0x0000 - 0x1fff
+pub trait Mapper {
+    fn cpu_read(&self, addr: u16) -> u8;
+
+    fn cpu_write(&mut self, addr: u16, value: u8);
+
+    fn ppu_read(&self, addr: u16) -> u8;
+
+    fn ppu_write(&mut self, addr: u16, value: u8);
+
+    // how the nametables are laid out right now, some boards change this as they run
+    fn mirroring(&self) -> Mirroring;
+
+    // the ppu calls this whenever A12 on its address bus goes from low to high, which
+    // happens about once a scanline while rendering. boards that count scanlines hook it
+    fn notify_a12_rise(&mut self) {}
+
+    // the state of the cartridge's irq line
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    // the 8KB at 0x6000 on boards that have it, so it can be saved when it's battery backed
+    fn prg_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    // the board's registers and ram for a save state. the roms stay out of it, load_state
+    // is only ever given a state saved with the same cartridge
+    #[cfg(feature = "savestate")]
+    fn save_state(&self) -> Vec<u8>;
+
+    #[cfg(feature = "savestate")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError>;
+}
+
+pub fn from_rom(rom: Rom) -> Result<Box<dyn Mapper>, String> {
+    match rom.mapper {
+        0 => Ok(Box::new(nrom::Nrom::new(rom)?)),
+        1 => Ok(Box::new(mmc1::Mmc1::new(rom)?)),
+        2 => Ok(Box::new(uxrom::Uxrom::new(rom)?)),
+        3 => Ok(Box::new(cnrom::Cnrom::new(rom)?)),
+        4 => Ok(Box::new(mmc3::Mmc3::new(rom)?)),
+        n => Err(format!("Mapper {} is not supported", n)),
+    }
+}
+
+// something a cartridge was loaded in spite of, for a frontend to tell the player about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadWarning {
+    // the board isn't emulated, the game's running on nrom instead, see from_rom_or_nrom
+    UnsupportedMapper { number: u8 },
+}
+
+impl fmt::Display for LoadWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadWarning::UnsupportedMapper { number } => write!(
+                f,
+                "Mapper {} is not supported, running it as mapper 0 instead",
+                number
+            ),
+        }
+    }
+}
+
+// from_rom, except a board that isn't emulated gets nrom rather than refusing the game.
+// plenty of games on the exotic boards are mostly nrom with extras, and get somewhere
+// like this
+pub fn from_rom_or_nrom(rom: Rom) -> Result<(Box<dyn Mapper>, Vec<LoadWarning>), String> {
+    match rom.mapper {
+        // the boards from_rom has
+        0..=4 => Ok((from_rom(rom)?, Vec::new())),
+        number => Ok((
+            Box::new(as_nrom(rom)?),
+            vec![LoadWarning::UnsupportedMapper { number }],
+        )),
+    }
+}
+
+// the first and last 16KB of prg fixed where most boards have them at power on, so the
+// reset vector's right, and the first 8KB of chr. writes to the board's registers go nowhere
+fn as_nrom(mut rom: Rom) -> Result<nrom::Nrom, String> {
+    if rom.prg_rom.len() > 0x8000 {
+        let last_bank = rom.prg_rom.len() - 0x4000;
+        rom.prg_rom.copy_within(last_bank.., 0x4000);
+        rom.prg_rom.truncate(0x8000);
+    }
+    rom.chr_rom.truncate(0x2000);
+    nrom::Nrom::new(rom)
+}
+
+// a board reads its roms with bank numbers worked out modulo how many banks there are, so
+// one with less rom than that would divide by zero or read off the end. boards check what
+// they're given with this when they're made
+fn check_size(what: &str, rom: &[u8], min: usize) -> Result<(), String> {
+    if rom.len() < min {
+        return Err(format!(
+            "{} is {} bytes but the board needs at least {}",
+            what,
+            rom.len(),
+            min
+        ));
+    }
+    Ok(())
+}
+
+// boards with bus conflicts let the rom drive the data bus during a write as well, so the
+// value that lands is the written byte ANDed with the rom byte at that address
+fn apply_bus_conflict(bus_conflicts: bool, value: u8, in_rom: u8) -> u8 {
+    if bus_conflicts {
+        value & in_rom
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::bare_rom;
+
+    #[test]
+    fn test_unsupported_board_runs_on_its_first_and_last_prg_banks() {
+        let mut rom = bare_rom(200, 0x10000, 0x4000);
+        for (bank, chunk) in rom.prg_rom.chunks_mut(0x4000).enumerate() {
+            chunk.fill(bank as u8 + 1);
+        }
+        for (bank, chunk) in rom.chr_rom.chunks_mut(0x2000).enumerate() {
+            chunk.fill(bank as u8 + 1);
+        }
+        assert_eq!(
+            from_rom(rom.clone()).err().unwrap(),
+            "Mapper 200 is not supported"
+        );
+
+        let (mut mapper, warnings) = from_rom_or_nrom(rom).unwrap();
+        assert_eq!(warnings, [LoadWarning::UnsupportedMapper { number: 200 }]);
+        mapper.cpu_write(0x8000, 0x03);
+        mapper.cpu_write(0xffff, 0x03);
+        assert_eq!(mapper.cpu_read(0x8000), 1);
+        assert_eq!(mapper.cpu_read(0xbfff), 1);
+        assert_eq!(mapper.cpu_read(0xc000), 4);
+        assert_eq!(mapper.cpu_read(0xffff), 4);
+        assert_eq!(mapper.ppu_read(0x0000), 1);
+        assert_eq!(mapper.ppu_read(0x1fff), 1);
+    }
+}
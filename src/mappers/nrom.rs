@@ -0,0 +1,176 @@
+use super::{check_size, Mapper};
+use crate::rom::{Mirroring, Rom};
+#[cfg(feature = "savestate")]
+use crate::savestate::{self, StateError};
+
+const PRG_RAM: u16 = 0x6000;
+const PRG_RAM_END: u16 = 0x7fff;
+const PRG_ROM: u16 = 0x8000;
+
+// mapper 0: no bank switching at all, 16KB or 32KB of prg rom and 8KB of chr
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Nrom {
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    prg_rom: Vec<u8>,
+    #[cfg_attr(feature = "savestate", serde(with = "crate::savestate::big_array"))]
+    prg_ram: [u8; 0x2000],
+    // saved on its own when it's ram, see save_state
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    chr: Vec<u8>,
+    // boards that declare no chr rom have 8KB of chr ram instead
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(rom: Rom) -> Result<Self, String> {
+        check_size("PRG ROM", &rom.prg_rom, 1)?;
+        let chr_is_ram = rom.chr_rom.is_empty();
+        if !chr_is_ram {
+            check_size("CHR ROM", &rom.chr_rom, 0x2000)?;
+        }
+        Ok(Nrom {
+            prg_rom: rom.prg_rom,
+            prg_ram: [0; 0x2000],
+            chr: if chr_is_ram {
+                vec![0; 0x2000]
+            } else {
+                rom.chr_rom
+            },
+            chr_is_ram,
+            mirroring: rom.screen_mirroring,
+        })
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            PRG_RAM..=PRG_RAM_END => self.prg_ram[(addr - PRG_RAM) as usize],
+            // a single 16KB bank shows up in both halves
+            PRG_ROM..=0xffff => self.prg_rom[(addr - PRG_ROM) as usize % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        // writes to rom go nowhere
+        if let PRG_RAM..=PRG_RAM_END = addr {
+            self.prg_ram[(addr - PRG_RAM) as usize] = value;
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[(addr & 0x1fff) as usize]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            self.chr[(addr & 0x1fff) as usize] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    #[cfg(feature = "savestate")]
+    fn save_state(&self) -> Vec<u8> {
+        let chr_ram = self.chr_is_ram.then_some(&self.chr);
+        savestate::encode(&(self, chr_ram))
+    }
+
+    #[cfg(feature = "savestate")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let (restored, chr_ram): (Nrom, Option<Vec<u8>>) = savestate::decode(state)?;
+        *self = Nrom {
+            prg_rom: std::mem::take(&mut self.prg_rom),
+            chr: chr_ram.unwrap_or_else(|| std::mem::take(&mut self.chr)),
+            ..restored
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::{bare_rom, create_rom, header, TestRom};
+
+    fn nrom(prg_banks: u8, chr_banks: u8) -> Nrom {
+        let mut prg_rom = vec![0; prg_banks as usize * 0x4000];
+        for (bank, chunk) in prg_rom.chunks_mut(0x4000).enumerate() {
+            chunk.fill(bank as u8 + 1);
+        }
+        let raw = create_rom(TestRom {
+            header: header(prg_banks, chr_banks, 0x00, 0x00),
+            trainer: None,
+            prg_rom,
+            chr_rom: vec![0x42; chr_banks as usize * 0x2000],
+        });
+
+        Nrom::new(Rom::new(&raw).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_16kb_prg_is_mirrored() {
+        let nrom = nrom(1, 1);
+
+        assert_eq!(nrom.cpu_read(0x8000), 1);
+        assert_eq!(nrom.cpu_read(0x8000), nrom.cpu_read(0xc000));
+        assert_eq!(nrom.cpu_read(0xbfff), nrom.cpu_read(0xffff));
+    }
+
+    #[test]
+    fn test_32kb_prg_is_not_mirrored() {
+        let nrom = nrom(2, 1);
+
+        assert_eq!(nrom.cpu_read(0x8000), 1);
+        assert_eq!(nrom.cpu_read(0xc000), 2);
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut nrom = nrom(1, 1);
+        nrom.cpu_write(0x6000, 0x11);
+        nrom.cpu_write(0x7fff, 0x22);
+        nrom.cpu_write(0x8000, 0x33);
+
+        assert_eq!(nrom.cpu_read(0x6000), 0x11);
+        assert_eq!(nrom.cpu_read(0x7fff), 0x22);
+        assert_eq!(nrom.cpu_read(0x8000), 1);
+    }
+
+    #[test]
+    fn test_chr_rom_ignores_writes() {
+        let mut nrom = nrom(1, 1);
+        nrom.ppu_write(0x0010, 0x11);
+
+        assert_eq!(nrom.ppu_read(0x0010), 0x42);
+    }
+
+    #[test]
+    fn test_chr_ram_is_writable() {
+        let mut nrom = nrom(1, 0);
+        nrom.ppu_write(0x0010, 0x11);
+        nrom.ppu_write(0x1fff, 0x22);
+
+        assert_eq!(nrom.ppu_read(0x0010), 0x11);
+        assert_eq!(nrom.ppu_read(0x1fff), 0x22);
+    }
+
+    #[test]
+    fn test_too_little_rom_is_refused() {
+        assert!(Nrom::new(bare_rom(0, 0, 0)).is_err());
+        assert!(Nrom::new(bare_rom(0, 0x4000, 0x1000)).is_err());
+        assert!(Nrom::new(bare_rom(0, 0x4000, 0)).is_ok());
+    }
+}
@@ -0,0 +1,152 @@
+use super::{apply_bus_conflict, check_size, Mapper};
+use crate::rom::{Mirroring, Rom};
+#[cfg(feature = "savestate")]
+use crate::savestate::{self, StateError};
+
+const PRG_ROM: u16 = 0x8000;
+const PRG_BANK_SIZE: usize = 0x4000;
+
+// mapper 2: any write to 0x8000 - 0xffff picks the 16KB bank at 0x8000, the last bank is
+// always at 0xc000 and chr is 8KB of ram
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Uxrom {
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    prg_rom: Vec<u8>,
+    #[cfg_attr(feature = "savestate", serde(with = "crate::savestate::big_array"))]
+    chr_ram: [u8; 0x2000],
+    prg_bank: u8,
+    mirroring: Mirroring,
+    // off by default, turn on for boards where the rom fights the cpu over the data bus on
+    // writes and the selected bank is the written value ANDed with the rom byte
+    pub bus_conflicts: bool,
+}
+
+impl Uxrom {
+    pub fn new(rom: Rom) -> Result<Self, String> {
+        check_size("PRG ROM", &rom.prg_rom, PRG_BANK_SIZE)?;
+        Ok(Uxrom {
+            prg_rom: rom.prg_rom,
+            chr_ram: [0; 0x2000],
+            prg_bank: 0,
+            mirroring: rom.screen_mirroring,
+            bus_conflicts: false,
+        })
+    }
+
+    fn banks(&self) -> usize {
+        self.prg_rom.len() / PRG_BANK_SIZE
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x8000..=0xbfff => self.prg_bank as usize % self.banks(),
+            0xc000..=0xffff => self.banks() - 1,
+            _ => return 0,
+        };
+        self.prg_rom[bank * PRG_BANK_SIZE + (addr as usize % PRG_BANK_SIZE)]
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        if addr < PRG_ROM {
+            return;
+        }
+        self.prg_bank = apply_bus_conflict(self.bus_conflicts, value, self.cpu_read(addr));
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr_ram[(addr & 0x1fff) as usize]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        self.chr_ram[(addr & 0x1fff) as usize] = value;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    #[cfg(feature = "savestate")]
+    fn save_state(&self) -> Vec<u8> {
+        savestate::encode(self)
+    }
+
+    #[cfg(feature = "savestate")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let restored: Uxrom = savestate::decode(state)?;
+        *self = Uxrom {
+            prg_rom: std::mem::take(&mut self.prg_rom),
+            ..restored
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::rom::tests::{bare_rom, create_rom, header, TestRom};
+
+    // four prg banks, each filled with its own number
+    fn uxrom_rom() -> Rom {
+        let mut prg_rom = vec![0; 4 * PRG_BANK_SIZE];
+        for (bank, chunk) in prg_rom.chunks_mut(PRG_BANK_SIZE).enumerate() {
+            chunk.fill(bank as u8);
+        }
+        let raw = create_rom(TestRom {
+            header: header(4, 0, 0x20, 0x00),
+            trainer: None,
+            prg_rom,
+            chr_rom: vec![],
+        });
+
+        Rom::new(&raw).unwrap()
+    }
+
+    #[test]
+    fn test_switches_lower_bank_only() {
+        let mut uxrom = Uxrom::new(uxrom_rom()).unwrap();
+        assert_eq!(uxrom.cpu_read(0x8000), 0);
+        assert_eq!(uxrom.cpu_read(0xc000), 3);
+
+        for bank in [2, 1, 3, 0] {
+            uxrom.cpu_write(0x8000, bank);
+            assert_eq!(uxrom.cpu_read(0x8000), bank);
+            assert_eq!(uxrom.cpu_read(0xbfff), bank);
+            assert_eq!(uxrom.cpu_read(0xc000), 3);
+            assert_eq!(uxrom.cpu_read(0xffff), 3);
+        }
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut uxrom = Uxrom::new(uxrom_rom()).unwrap();
+        uxrom.bus_conflicts = true;
+        // bank 0 is at 0x8000, so everything written there ANDs down to 0
+        uxrom.cpu_write(0x8000, 2);
+        assert_eq!(uxrom.cpu_read(0x8000), 0);
+
+        // the fixed bank holds 3, so 2 survives
+        uxrom.cpu_write(0xc000, 2);
+        assert_eq!(uxrom.cpu_read(0x8000), 2);
+    }
+
+    #[test]
+    fn test_chr_ram_through_ppu_path() {
+        let mut bus = Bus::with_rom(uxrom_rom()).unwrap();
+        bus.ppu_write(0x0000, 0x11);
+        bus.ppu_write(0x1fff, 0x22);
+
+        assert_eq!(bus.ppu_read(0x0000), 0x11);
+        assert_eq!(bus.ppu_read(0x1fff), 0x22);
+    }
+
+    #[test]
+    fn test_less_than_a_bank_is_refused() {
+        assert!(Uxrom::new(bare_rom(2, 0, 0)).is_err());
+        assert!(Uxrom::new(bare_rom(2, 0x3fff, 0)).is_err());
+        assert!(Uxrom::new(bare_rom(2, 0x4000, 0)).is_ok());
+    }
+}
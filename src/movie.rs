@@ -0,0 +1,352 @@
+use std::fmt;
+
+use crate::joypad::JoypadButton;
+use crate::rom::TvSystem;
+
+// the input for every frame of a session, to play back into a console started up the same
+// way. a file is:
+//
+//   0   "NESM"
+//   4   MOVIE_VERSION, u16 little endian
+//   6   the rom's crc32, u32 little endian, see Rom::crc32
+//   10  the tv system, 0 ntsc and 1 pal
+//   11  the length of the version of the emulator that recorded it, then that many bytes of it
+//   ..  the number of frames, u32 little endian
+//   ..  3 bytes a frame: joypad 1 and joypad 2 as JoypadButton bits, then 0, or 1 for a reset
+//       and 2 for a power cycle before the frame ran
+pub const MAGIC: [u8; 4] = *b"NESM";
+pub const MOVIE_VERSION: u16 = 1;
+// this build, so a movie that stops replaying properly can be traced back to what made it
+pub const EMULATOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieEvent {
+    Reset,
+    Power,
+}
+
+impl MovieEvent {
+    // a power cycle does everything a reset does and more, so when both are asked for before
+    // the same frame it's the one that happens
+    pub fn merge(a: Option<MovieEvent>, b: Option<MovieEvent>) -> Option<MovieEvent> {
+        if a == Some(MovieEvent::Power) {
+            a
+        } else {
+            b.or(a)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameInput {
+    pub joypad1: JoypadButton,
+    pub joypad2: JoypadButton,
+    // happens before the buttons are set and the frame runs
+    pub event: Option<MovieEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_crc: u32,
+    pub tv_system: TvSystem,
+    pub emulator_version: String,
+    pub frames: Vec<FrameInput>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MovieError {
+    // too short or the wrong magic, this was never a movie
+    NotAMovie,
+    UnsupportedVersion {
+        expected: u16,
+        actual: u16,
+    },
+    // the movie was recorded with a different cartridge in
+    WrongRom {
+        expected: u32,
+        actual: u32,
+    },
+    WrongTvSystem {
+        expected: TvSystem,
+        actual: TvSystem,
+    },
+    Corrupt(String),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::NotAMovie => write!(f, "Data is not a movie"),
+            MovieError::UnsupportedVersion { expected, actual } => write!(
+                f,
+                "Movie is version {} but this build reads version {}",
+                actual, expected
+            ),
+            MovieError::WrongRom { expected, actual } => write!(
+                f,
+                "Movie is for ROM {:08x} but ROM {:08x} is loaded",
+                actual, expected
+            ),
+            MovieError::WrongTvSystem { expected, actual } => write!(
+                f,
+                "Movie was recorded on {:?} but the console is {:?}",
+                actual, expected
+            ),
+            MovieError::Corrupt(e) => write!(f, "Movie is corrupt: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl Movie {
+    pub fn new(rom_crc: u32, tv_system: TvSystem) -> Self {
+        Movie {
+            rom_crc,
+            tv_system,
+            emulator_version: EMULATOR_VERSION.to_string(),
+            frames: vec![],
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let version = &self.emulator_version.as_bytes()[..self.emulator_version.len().min(255)];
+        let mut bytes = Vec::with_capacity(16 + version.len() + self.frames.len() * 3);
+        bytes.extend(MAGIC);
+        bytes.extend(MOVIE_VERSION.to_le_bytes());
+        bytes.extend(self.rom_crc.to_le_bytes());
+        bytes.push(match self.tv_system {
+            TvSystem::Ntsc => 0,
+            TvSystem::Pal => 1,
+        });
+        bytes.push(version.len() as u8);
+        bytes.extend(version);
+        bytes.extend((self.frames.len() as u32).to_le_bytes());
+        for frame in &self.frames {
+            bytes.push(frame.joypad1.bits());
+            bytes.push(frame.joypad2.bits());
+            bytes.push(match frame.event {
+                None => 0,
+                Some(MovieEvent::Reset) => 1,
+                Some(MovieEvent::Power) => 2,
+            });
+        }
+        bytes
+    }
+
+    // doesn't know what's loaded, Emulator::attach_player checks it's the right cartridge
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MovieError> {
+        if bytes.len() < 12 || bytes[0..4] != MAGIC {
+            return Err(MovieError::NotAMovie);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion {
+                expected: MOVIE_VERSION,
+                actual: version,
+            });
+        }
+        let rom_crc = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+        let tv_system = match bytes[10] {
+            0 => TvSystem::Ntsc,
+            1 => TvSystem::Pal,
+            other => return Err(MovieError::Corrupt(format!("no tv system {}", other))),
+        };
+
+        let truncated = || MovieError::Corrupt("file is cut short".to_string());
+        let version_end = 12 + bytes[11] as usize;
+        let emulator_version = bytes.get(12..version_end).ok_or_else(truncated)?;
+        let emulator_version = String::from_utf8_lossy(emulator_version).into_owned();
+        let count = bytes
+            .get(version_end..version_end + 4)
+            .ok_or_else(truncated)?;
+        let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize;
+
+        let body = &bytes[version_end + 4..];
+        let needed = count
+            .checked_mul(3)
+            .ok_or_else(|| MovieError::Corrupt(format!("{} frames is too many", count)))?;
+        if body.len() != needed {
+            return Err(MovieError::Corrupt(format!(
+                "{} frames need {} bytes but there are {}",
+                count,
+                needed,
+                body.len()
+            )));
+        }
+        let frames = body
+            .chunks_exact(3)
+            .map(|frame| {
+                let event = match frame[2] {
+                    0 => None,
+                    1 => Some(MovieEvent::Reset),
+                    2 => Some(MovieEvent::Power),
+                    other => return Err(MovieError::Corrupt(format!("no event {}", other))),
+                };
+                Ok(FrameInput {
+                    joypad1: JoypadButton::from_bits_truncate(frame[0]),
+                    joypad2: JoypadButton::from_bits_truncate(frame[1]),
+                    event,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Movie {
+            rom_crc,
+            tv_system,
+            emulator_version,
+            frames,
+        })
+    }
+}
+
+// builds up a Movie a frame at a time, see Emulator::attach_recorder
+pub struct MovieRecorder {
+    movie: Movie,
+}
+
+impl MovieRecorder {
+    pub fn new(rom_crc: u32, tv_system: TvSystem) -> Self {
+        MovieRecorder {
+            movie: Movie::new(rom_crc, tv_system),
+        }
+    }
+
+    pub fn record_frame(&mut self, input: FrameInput) {
+        self.movie.frames.push(input);
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+// hands a Movie's frames back out in order, see Emulator::attach_player
+pub struct MoviePlayer {
+    movie: Movie,
+    next: usize,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        MoviePlayer { movie, next: 0 }
+    }
+
+    // None once every frame's been played
+    pub fn next_frame(&mut self) -> Option<FrameInput> {
+        let frame = self.movie.frames.get(self.next).copied();
+        if frame.is_some() {
+            self.next += 1;
+        }
+        frame
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next == self.movie.frames.len()
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movie() -> Movie {
+        let mut recorder = MovieRecorder::new(0x1234_5678, TvSystem::Pal);
+        for (joypad1, joypad2, event) in [
+            (JoypadButton::A, JoypadButton::empty(), None),
+            (
+                JoypadButton::START | JoypadButton::UP,
+                JoypadButton::B,
+                Some(MovieEvent::Reset),
+            ),
+            (
+                JoypadButton::empty(),
+                JoypadButton::all(),
+                Some(MovieEvent::Power),
+            ),
+        ] {
+            recorder.record_frame(FrameInput {
+                joypad1,
+                joypad2,
+                event,
+            });
+        }
+        recorder.finish()
+    }
+
+    #[test]
+    fn test_power_wins_over_reset() {
+        let (reset, power) = (Some(MovieEvent::Reset), Some(MovieEvent::Power));
+        assert_eq!(MovieEvent::merge(power, reset), power);
+        assert_eq!(MovieEvent::merge(reset, power), power);
+        assert_eq!(MovieEvent::merge(None, reset), reset);
+        assert_eq!(MovieEvent::merge(reset, None), reset);
+        assert_eq!(MovieEvent::merge(None, None), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let movie = movie();
+        let bytes = movie.to_bytes();
+        assert_eq!(bytes[..4], MAGIC);
+        assert_eq!(bytes[6..10], [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(bytes[10], 1);
+        assert_eq!(Movie::from_bytes(&bytes).unwrap(), movie);
+    }
+
+    #[test]
+    fn test_bad_files_are_refused() {
+        let bytes = movie().to_bytes();
+
+        assert_eq!(Movie::from_bytes(b"NESS"), Err(MovieError::NotAMovie));
+        assert!(matches!(
+            Movie::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(MovieError::Corrupt(_))
+        ));
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert_eq!(
+            Movie::from_bytes(&newer),
+            Err(MovieError::UnsupportedVersion {
+                expected: 1,
+                actual: 2
+            })
+        );
+        let mut bad_event = bytes;
+        *bad_event.last_mut().unwrap() = 3;
+        assert!(matches!(
+            Movie::from_bytes(&bad_event),
+            Err(MovieError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_huge_frame_count_is_corrupt() {
+        let mut bytes = Movie::new(0, TvSystem::Ntsc).to_bytes();
+        let count = bytes.len() - 4;
+        bytes[count..].copy_from_slice(&0xffff_ffffu32.to_le_bytes());
+        assert!(matches!(
+            Movie::from_bytes(&bytes),
+            Err(MovieError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_player_runs_out() {
+        let mut player = MoviePlayer::new(movie());
+        for _ in 0..3 {
+            assert!(!player.finished());
+            assert!(player.next_frame().is_some());
+        }
+        assert!(player.finished());
+        assert_eq!(player.next_frame(), None);
+    }
+}
@@ -2,44 +2,325 @@ use lazy_static::lazy_static;
 
 use crate::cpu::AddressingMode;
 
+// what an opcode does, for the cpu to dispatch on without comparing strings. the unofficial
+// opcodes that do exactly what an official one does, the extra NOPs and 0xeb's SBC, share its
+// variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Adc,
+    Sbc,
+    And,
+    Ora,
+    Eor,
+    Bit,
+    Asl,
+    Lsr,
+    Rol,
+    Ror,
+    Inc,
+    Dec,
+    Cmp,
+    Cpx,
+    Cpy,
+    Bcc,
+    Bcs,
+    Bne,
+    Beq,
+    Bpl,
+    Bmi,
+    Bvc,
+    Bvs,
+    Lda,
+    Ldx,
+    Ldy,
+    Sta,
+    Stx,
+    Sty,
+    Clc,
+    Sec,
+    Cli,
+    Sei,
+    Clv,
+    Cld,
+    Sed,
+    Nop,
+    Tax,
+    Tay,
+    Txa,
+    Tya,
+    Tsx,
+    Txs,
+    Jmp,
+    Jsr,
+    Rts,
+    Rti,
+    Pha,
+    Pla,
+    Php,
+    Plp,
+    Inx,
+    Iny,
+    Dex,
+    Dey,
+    Brk,
+    // unofficial
+    Lax,
+    Sax,
+    Dcp,
+    Isb,
+    Slo,
+    Rla,
+    Sre,
+    Rra,
+    Anc,
+    Alr,
+    Arr,
+    Axs,
+    Xaa,
+    Lxa,
+    Ahx,
+    Shx,
+    Shy,
+    Tas,
+    Las,
+}
+
+impl Instruction {
+    // the official mnemonic, or the unofficial one without its *
+    fn from_mnemonic(mnemonic: &str) -> Instruction {
+        match mnemonic.trim_start_matches('*') {
+            "ADC" => Instruction::Adc,
+            "SBC" => Instruction::Sbc,
+            "AND" => Instruction::And,
+            "ORA" => Instruction::Ora,
+            "EOR" => Instruction::Eor,
+            "BIT" => Instruction::Bit,
+            "ASL" => Instruction::Asl,
+            "LSR" => Instruction::Lsr,
+            "ROL" => Instruction::Rol,
+            "ROR" => Instruction::Ror,
+            "INC" => Instruction::Inc,
+            "DEC" => Instruction::Dec,
+            "CMP" => Instruction::Cmp,
+            "CPX" => Instruction::Cpx,
+            "CPY" => Instruction::Cpy,
+            "BCC" => Instruction::Bcc,
+            "BCS" => Instruction::Bcs,
+            "BNE" => Instruction::Bne,
+            "BEQ" => Instruction::Beq,
+            "BPL" => Instruction::Bpl,
+            "BMI" => Instruction::Bmi,
+            "BVC" => Instruction::Bvc,
+            "BVS" => Instruction::Bvs,
+            "LDA" => Instruction::Lda,
+            "LDX" => Instruction::Ldx,
+            "LDY" => Instruction::Ldy,
+            "STA" => Instruction::Sta,
+            "STX" => Instruction::Stx,
+            "STY" => Instruction::Sty,
+            "CLC" => Instruction::Clc,
+            "SEC" => Instruction::Sec,
+            "CLI" => Instruction::Cli,
+            "SEI" => Instruction::Sei,
+            "CLV" => Instruction::Clv,
+            "CLD" => Instruction::Cld,
+            "SED" => Instruction::Sed,
+            "NOP" => Instruction::Nop,
+            "TAX" => Instruction::Tax,
+            "TAY" => Instruction::Tay,
+            "TXA" => Instruction::Txa,
+            "TYA" => Instruction::Tya,
+            "TSX" => Instruction::Tsx,
+            "TXS" => Instruction::Txs,
+            "JMP" => Instruction::Jmp,
+            "JSR" => Instruction::Jsr,
+            "RTS" => Instruction::Rts,
+            "RTI" => Instruction::Rti,
+            "PHA" => Instruction::Pha,
+            "PLA" => Instruction::Pla,
+            "PHP" => Instruction::Php,
+            "PLP" => Instruction::Plp,
+            "INX" => Instruction::Inx,
+            "INY" => Instruction::Iny,
+            "DEX" => Instruction::Dex,
+            "DEY" => Instruction::Dey,
+            "BRK" => Instruction::Brk,
+            "LAX" => Instruction::Lax,
+            "SAX" => Instruction::Sax,
+            "DCP" => Instruction::Dcp,
+            "ISB" => Instruction::Isb,
+            "SLO" => Instruction::Slo,
+            "RLA" => Instruction::Rla,
+            "SRE" => Instruction::Sre,
+            "RRA" => Instruction::Rra,
+            "ANC" => Instruction::Anc,
+            "ALR" => Instruction::Alr,
+            "ARR" => Instruction::Arr,
+            "AXS" => Instruction::Axs,
+            "XAA" => Instruction::Xaa,
+            "LXA" => Instruction::Lxa,
+            "AHX" => Instruction::Ahx,
+            "SHX" => Instruction::Shx,
+            "SHY" => Instruction::Shy,
+            "TAS" => Instruction::Tas,
+            "LAS" => Instruction::Las,
+            _ => panic!("no instruction {}", mnemonic),
+        }
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub struct OpCode {
     pub hex: u8,
+    // for showing, unofficial ones start with a *
     pub mnemonic: &'static str,
+    pub instruction: Instruction,
     pub bytes: u8,
     pub cycles: u8,
     pub mode: AddressingMode,
+    // reads that index across a page boundary take a cycle more than `cycles`. stores and
+    // read-modify-writes always take the slow path and it's in their base cost
+    pub page_cross_penalty: bool,
 }
 
 lazy_static! {
     pub static ref CPU_OP_CODES: Vec<OpCode> = vec![
         // ADC
-        OpCode::new(0x69, "ADC", 2, 3, AddressingMode::Immediate),
+        OpCode::new(0x69, "ADC", 2, 2, AddressingMode::Immediate),
         OpCode::new(0x65, "ADC", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0x75, "ADC", 2, 4, AddressingMode::ZeroPageX),
         OpCode::new(0x6d, "ADC", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x7d, "ADC", 3, 4 /* +1 if page crossed */, AddressingMode::AbsoluteY),
-        OpCode::new(0x79, "ADC", 3, 4 /* +1 if page crossed */, AddressingMode::AbsoluteY),
+        OpCode::new(0x7d, "ADC", 3, 4, AddressingMode::AbsoluteX).page_cross_costs_one(),
+        OpCode::new(0x79, "ADC", 3, 4, AddressingMode::AbsoluteY).page_cross_costs_one(),
         OpCode::new(0x61, "ADC", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0x71, "ADC", 2, 5 /* +2 if page crossed */, AddressingMode::IndirectY),
+        OpCode::new(0x71, "ADC", 2, 5, AddressingMode::IndirectY).page_cross_costs_one(),
+        // SBC
+        OpCode::new(0xe9, "SBC", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xe5, "SBC", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xf5, "SBC", 2, 4, AddressingMode::ZeroPageX),
+        OpCode::new(0xed, "SBC", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0xfd, "SBC", 3, 4, AddressingMode::AbsoluteX).page_cross_costs_one(),
+        OpCode::new(0xf9, "SBC", 3, 4, AddressingMode::AbsoluteY).page_cross_costs_one(),
+        OpCode::new(0xe1, "SBC", 2, 6, AddressingMode::IndirectX),
+        OpCode::new(0xf1, "SBC", 2, 5, AddressingMode::IndirectY).page_cross_costs_one(),
         // AND
         OpCode::new(0x29, "AND", 2, 2, AddressingMode::Immediate),
         OpCode::new(0x25, "AND", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0x35, "AND", 2, 4, AddressingMode::ZeroPageX),
         OpCode::new(0x2d, "AND", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x3d, "AND", 3, 4 /* +1 if page crossed */, AddressingMode::AbsoluteX),
-        OpCode::new(0x39, "AND", 3, 4 /* +1 if page crossed */, AddressingMode::AbsoluteY),
+        OpCode::new(0x3d, "AND", 3, 4, AddressingMode::AbsoluteX).page_cross_costs_one(),
+        OpCode::new(0x39, "AND", 3, 4, AddressingMode::AbsoluteY).page_cross_costs_one(),
         OpCode::new(0x21, "AND", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0x31, "AND", 2, 5 /* +1 if page crossed */, AddressingMode::IndirectY),
+        OpCode::new(0x31, "AND", 2, 5, AddressingMode::IndirectY).page_cross_costs_one(),
+        // ORA
+        OpCode::new(0x09, "ORA", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x05, "ORA", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x15, "ORA", 2, 4, AddressingMode::ZeroPageX),
+        OpCode::new(0x0d, "ORA", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0x1d, "ORA", 3, 4, AddressingMode::AbsoluteX).page_cross_costs_one(),
+        OpCode::new(0x19, "ORA", 3, 4, AddressingMode::AbsoluteY).page_cross_costs_one(),
+        OpCode::new(0x01, "ORA", 2, 6, AddressingMode::IndirectX),
+        OpCode::new(0x11, "ORA", 2, 5, AddressingMode::IndirectY).page_cross_costs_one(),
+        // EOR
+        OpCode::new(0x49, "EOR", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x45, "EOR", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x55, "EOR", 2, 4, AddressingMode::ZeroPageX),
+        OpCode::new(0x4d, "EOR", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0x5d, "EOR", 3, 4, AddressingMode::AbsoluteX).page_cross_costs_one(),
+        OpCode::new(0x59, "EOR", 3, 4, AddressingMode::AbsoluteY).page_cross_costs_one(),
+        OpCode::new(0x41, "EOR", 2, 6, AddressingMode::IndirectX),
+        OpCode::new(0x51, "EOR", 2, 5, AddressingMode::IndirectY).page_cross_costs_one(),
+        // BIT
+        OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x2c, "BIT", 3, 4, AddressingMode::Absolute),
+        // ASL
+        OpCode::new(0x0a, "ASL", 1, 2, AddressingMode::Accumulator),
+        OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPageX),
+        OpCode::new(0x0e, "ASL", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x1e, "ASL", 3, 7, AddressingMode::AbsoluteX),
+        // LSR
+        OpCode::new(0x4a, "LSR", 1, 2, AddressingMode::Accumulator),
+        OpCode::new(0x46, "LSR", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x56, "LSR", 2, 6, AddressingMode::ZeroPageX),
+        OpCode::new(0x4e, "LSR", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x5e, "LSR", 3, 7, AddressingMode::AbsoluteX),
+        // ROL
+        OpCode::new(0x2a, "ROL", 1, 2, AddressingMode::Accumulator),
+        OpCode::new(0x26, "ROL", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x36, "ROL", 2, 6, AddressingMode::ZeroPageX),
+        OpCode::new(0x2e, "ROL", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x3e, "ROL", 3, 7, AddressingMode::AbsoluteX),
+        // ROR
+        OpCode::new(0x6a, "ROR", 1, 2, AddressingMode::Accumulator),
+        OpCode::new(0x66, "ROR", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x76, "ROR", 2, 6, AddressingMode::ZeroPageX),
+        OpCode::new(0x6e, "ROR", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x7e, "ROR", 3, 7, AddressingMode::AbsoluteX),
+        // INC
+        OpCode::new(0xe6, "INC", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xf6, "INC", 2, 6, AddressingMode::ZeroPageX),
+        OpCode::new(0xee, "INC", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0xfe, "INC", 3, 7, AddressingMode::AbsoluteX),
+        // DEC
+        OpCode::new(0xc6, "DEC", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xd6, "DEC", 2, 6, AddressingMode::ZeroPageX),
+        OpCode::new(0xce, "DEC", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0xde, "DEC", 3, 7, AddressingMode::AbsoluteX),
+        // CMP
+        OpCode::new(0xc9, "CMP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xc5, "CMP", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xd5, "CMP", 2, 4, AddressingMode::ZeroPageX),
+        OpCode::new(0xcd, "CMP", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0xdd, "CMP", 3, 4, AddressingMode::AbsoluteX).page_cross_costs_one(),
+        OpCode::new(0xd9, "CMP", 3, 4, AddressingMode::AbsoluteY).page_cross_costs_one(),
+        OpCode::new(0xc1, "CMP", 2, 6, AddressingMode::IndirectX),
+        OpCode::new(0xd1, "CMP", 2, 5, AddressingMode::IndirectY).page_cross_costs_one(),
+        // CPX
+        OpCode::new(0xe0, "CPX", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xe4, "CPX", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xec, "CPX", 3, 4, AddressingMode::Absolute),
+        // CPY
+        OpCode::new(0xc0, "CPY", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xc4, "CPY", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xcc, "CPY", 3, 4, AddressingMode::Absolute),
+        // BCC
+        OpCode::new(0x90, "BCC", 2, 2 /* +1 if branch succeeds, +2 if to a new page */, AddressingMode::Relative),
+        // BCS
+        OpCode::new(0xb0, "BCS", 2, 2 /* +1 if branch succeeds, +2 if to a new page */, AddressingMode::Relative),
+        // BEQ
+        OpCode::new(0xf0, "BEQ", 2, 2 /* +1 if branch succeeds, +2 if to a new page */, AddressingMode::Relative),
+        // BNE
+        OpCode::new(0xd0, "BNE", 2, 2 /* +1 if branch succeeds, +2 if to a new page */, AddressingMode::Relative),
+        // BMI
+        OpCode::new(0x30, "BMI", 2, 2 /* +1 if branch succeeds, +2 if to a new page */, AddressingMode::Relative),
+        // BPL
+        OpCode::new(0x10, "BPL", 2, 2 /* +1 if branch succeeds, +2 if to a new page */, AddressingMode::Relative),
+        // BVC
+        OpCode::new(0x50, "BVC", 2, 2 /* +1 if branch succeeds, +2 if to a new page */, AddressingMode::Relative),
+        // BVS
+        OpCode::new(0x70, "BVS", 2, 2 /* +1 if branch succeeds, +2 if to a new page */, AddressingMode::Relative),
         // LDA
         OpCode::new(0xa9, "LDA", 2, 2, AddressingMode::Immediate),
         OpCode::new(0xa5, "LDA", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0xb5, "LDA", 2, 4, AddressingMode::ZeroPageX),
         OpCode::new(0xad, "LDA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xbd, "LDA", 3, 4 /* +1 if page crossed */, AddressingMode::AbsoluteX),
-        OpCode::new(0xb9, "LDA", 3, 4 /* +1 if page crossed */, AddressingMode::AbsoluteY),
+        OpCode::new(0xbd, "LDA", 3, 4, AddressingMode::AbsoluteX).page_cross_costs_one(),
+        OpCode::new(0xb9, "LDA", 3, 4, AddressingMode::AbsoluteY).page_cross_costs_one(),
         OpCode::new(0xa1, "LDA", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0xb1, "LDA", 2, 5 /* +1 if page crossed */, AddressingMode::IndirectX),
+        OpCode::new(0xb1, "LDA", 2, 5, AddressingMode::IndirectY).page_cross_costs_one(),
+        // LDX
+        OpCode::new(0xa2, "LDX", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xa6, "LDX", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xb6, "LDX", 2, 4, AddressingMode::ZeroPageY),
+        OpCode::new(0xae, "LDX", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0xbe, "LDX", 3, 4, AddressingMode::AbsoluteY).page_cross_costs_one(),
+        // LDY
+        OpCode::new(0xa0, "LDY", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xa4, "LDY", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xb4, "LDY", 2, 4, AddressingMode::ZeroPageX),
+        OpCode::new(0xac, "LDY", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0xbc, "LDY", 3, 4, AddressingMode::AbsoluteX).page_cross_costs_one(),
         // STA
         OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPageX),
@@ -48,17 +329,196 @@ lazy_static! {
         OpCode::new(0x99, "STA", 3, 5, AddressingMode::AbsoluteY),
         OpCode::new(0x81, "STA", 2, 6, AddressingMode::IndirectX),
         OpCode::new(0x91, "STA", 2, 6, AddressingMode::IndirectY),
+        // STX
+        OpCode::new(0x86, "STX", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x96, "STX", 2, 4, AddressingMode::ZeroPageY),
+        OpCode::new(0x8e, "STX", 3, 4, AddressingMode::Absolute),
+        // STY
+        OpCode::new(0x84, "STY", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPageX),
+        OpCode::new(0x8c, "STY", 3, 4, AddressingMode::Absolute),
         // TAX
         OpCode::new(0xaa, "TAX", 1, 2, AddressingMode::NoneAddressing),
+        // TAY
+        OpCode::new(0xa8, "TAY", 1, 2, AddressingMode::NoneAddressing),
+        // TXA
+        OpCode::new(0x8a, "TXA", 1, 2, AddressingMode::NoneAddressing),
+        // TYA
+        OpCode::new(0x98, "TYA", 1, 2, AddressingMode::NoneAddressing),
+        // TSX
+        OpCode::new(0xba, "TSX", 1, 2, AddressingMode::NoneAddressing),
+        // TXS
+        OpCode::new(0x9a, "TXS", 1, 2, AddressingMode::NoneAddressing),
+        // JMP
+        OpCode::new(0x4c, "JMP", 3, 3, AddressingMode::Absolute),
+        OpCode::new(0x6c, "JMP", 3, 5, AddressingMode::Indirect),
+        // JSR
+        OpCode::new(0x20, "JSR", 3, 6, AddressingMode::Absolute),
+        // RTS
+        OpCode::new(0x60, "RTS", 1, 6, AddressingMode::NoneAddressing),
+        // RTI
+        OpCode::new(0x40, "RTI", 1, 6, AddressingMode::NoneAddressing),
+        // PHA
+        OpCode::new(0x48, "PHA", 1, 3, AddressingMode::NoneAddressing),
+        // PLA
+        OpCode::new(0x68, "PLA", 1, 4, AddressingMode::NoneAddressing),
+        // PHP
+        OpCode::new(0x08, "PHP", 1, 3, AddressingMode::NoneAddressing),
+        // PLP
+        OpCode::new(0x28, "PLP", 1, 4, AddressingMode::NoneAddressing),
         // INX
         OpCode::new(0xe8, "INX", 1, 2, AddressingMode::NoneAddressing),
+        // INY
+        OpCode::new(0xc8, "INY", 1, 2, AddressingMode::NoneAddressing),
+        // DEX
+        OpCode::new(0xca, "DEX", 1, 2, AddressingMode::NoneAddressing),
+        // DEY
+        OpCode::new(0x88, "DEY", 1, 2, AddressingMode::NoneAddressing),
+        // CLC
+        OpCode::new(0x18, "CLC", 1, 2, AddressingMode::NoneAddressing),
+        // SEC
+        OpCode::new(0x38, "SEC", 1, 2, AddressingMode::NoneAddressing),
+        // CLI
+        OpCode::new(0x58, "CLI", 1, 2, AddressingMode::NoneAddressing),
+        // SEI
+        OpCode::new(0x78, "SEI", 1, 2, AddressingMode::NoneAddressing),
+        // CLV
+        OpCode::new(0xb8, "CLV", 1, 2, AddressingMode::NoneAddressing),
+        // CLD
+        OpCode::new(0xd8, "CLD", 1, 2, AddressingMode::NoneAddressing),
+        // SED
+        OpCode::new(0xf8, "SED", 1, 2, AddressingMode::NoneAddressing),
+        // NOP
+        OpCode::new(0xea, "NOP", 1, 2, AddressingMode::NoneAddressing),
         // BRK
         OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
+        // the unofficial opcodes, see https://www.nesdev.org/wiki/CPU_unofficial_opcodes
+        // they're prefixed with * like nestest.log does, which also keeps the assembler from
+        // picking them for the official mnemonics
+        // NOP
+        OpCode::new(0x1a, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x3a, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x5a, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x7a, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xda, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xfa, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x80, "*NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x82, "*NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x89, "*NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xc2, "*NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xe2, "*NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x04, "*NOP", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x44, "*NOP", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x64, "*NOP", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x14, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+        OpCode::new(0x34, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+        OpCode::new(0x54, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+        OpCode::new(0x74, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+        OpCode::new(0xd4, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+        OpCode::new(0xf4, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+        OpCode::new(0x0c, "*NOP", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0x1c, "*NOP", 3, 4, AddressingMode::AbsoluteX).page_cross_costs_one(),
+        OpCode::new(0x3c, "*NOP", 3, 4, AddressingMode::AbsoluteX).page_cross_costs_one(),
+        OpCode::new(0x5c, "*NOP", 3, 4, AddressingMode::AbsoluteX).page_cross_costs_one(),
+        OpCode::new(0x7c, "*NOP", 3, 4, AddressingMode::AbsoluteX).page_cross_costs_one(),
+        OpCode::new(0xdc, "*NOP", 3, 4, AddressingMode::AbsoluteX).page_cross_costs_one(),
+        OpCode::new(0xfc, "*NOP", 3, 4, AddressingMode::AbsoluteX).page_cross_costs_one(),
+        // LAX: LDA and LDX at once
+        OpCode::new(0xa7, "*LAX", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xb7, "*LAX", 2, 4, AddressingMode::ZeroPageY),
+        OpCode::new(0xaf, "*LAX", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0xbf, "*LAX", 3, 4, AddressingMode::AbsoluteY).page_cross_costs_one(),
+        OpCode::new(0xa3, "*LAX", 2, 6, AddressingMode::IndirectX),
+        OpCode::new(0xb3, "*LAX", 2, 5, AddressingMode::IndirectY).page_cross_costs_one(),
+        // SAX: stores A & X
+        OpCode::new(0x87, "*SAX", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x97, "*SAX", 2, 4, AddressingMode::ZeroPageY),
+        OpCode::new(0x8f, "*SAX", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0x83, "*SAX", 2, 6, AddressingMode::IndirectX),
+        // SBC: the same as 0xe9
+        OpCode::new(0xeb, "*SBC", 2, 2, AddressingMode::Immediate),
+        // DCP: DEC then CMP
+        OpCode::new(0xc7, "*DCP", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xd7, "*DCP", 2, 6, AddressingMode::ZeroPageX),
+        OpCode::new(0xcf, "*DCP", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0xdf, "*DCP", 3, 7, AddressingMode::AbsoluteX),
+        OpCode::new(0xdb, "*DCP", 3, 7, AddressingMode::AbsoluteY),
+        OpCode::new(0xc3, "*DCP", 2, 8, AddressingMode::IndirectX),
+        OpCode::new(0xd3, "*DCP", 2, 8, AddressingMode::IndirectY),
+        // ISB: INC then SBC
+        OpCode::new(0xe7, "*ISB", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xf7, "*ISB", 2, 6, AddressingMode::ZeroPageX),
+        OpCode::new(0xef, "*ISB", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0xff, "*ISB", 3, 7, AddressingMode::AbsoluteX),
+        OpCode::new(0xfb, "*ISB", 3, 7, AddressingMode::AbsoluteY),
+        OpCode::new(0xe3, "*ISB", 2, 8, AddressingMode::IndirectX),
+        OpCode::new(0xf3, "*ISB", 2, 8, AddressingMode::IndirectY),
+        // SLO: ASL then ORA
+        OpCode::new(0x07, "*SLO", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x17, "*SLO", 2, 6, AddressingMode::ZeroPageX),
+        OpCode::new(0x0f, "*SLO", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x1f, "*SLO", 3, 7, AddressingMode::AbsoluteX),
+        OpCode::new(0x1b, "*SLO", 3, 7, AddressingMode::AbsoluteY),
+        OpCode::new(0x03, "*SLO", 2, 8, AddressingMode::IndirectX),
+        OpCode::new(0x13, "*SLO", 2, 8, AddressingMode::IndirectY),
+        // RLA: ROL then AND
+        OpCode::new(0x27, "*RLA", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x37, "*RLA", 2, 6, AddressingMode::ZeroPageX),
+        OpCode::new(0x2f, "*RLA", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x3f, "*RLA", 3, 7, AddressingMode::AbsoluteX),
+        OpCode::new(0x3b, "*RLA", 3, 7, AddressingMode::AbsoluteY),
+        OpCode::new(0x23, "*RLA", 2, 8, AddressingMode::IndirectX),
+        OpCode::new(0x33, "*RLA", 2, 8, AddressingMode::IndirectY),
+        // SRE: LSR then EOR
+        OpCode::new(0x47, "*SRE", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x57, "*SRE", 2, 6, AddressingMode::ZeroPageX),
+        OpCode::new(0x4f, "*SRE", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x5f, "*SRE", 3, 7, AddressingMode::AbsoluteX),
+        OpCode::new(0x5b, "*SRE", 3, 7, AddressingMode::AbsoluteY),
+        OpCode::new(0x43, "*SRE", 2, 8, AddressingMode::IndirectX),
+        OpCode::new(0x53, "*SRE", 2, 8, AddressingMode::IndirectY),
+        // RRA: ROR then ADC
+        OpCode::new(0x67, "*RRA", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x77, "*RRA", 2, 6, AddressingMode::ZeroPageX),
+        OpCode::new(0x6f, "*RRA", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x7f, "*RRA", 3, 7, AddressingMode::AbsoluteX),
+        OpCode::new(0x7b, "*RRA", 3, 7, AddressingMode::AbsoluteY),
+        OpCode::new(0x63, "*RRA", 2, 8, AddressingMode::IndirectX),
+        OpCode::new(0x73, "*RRA", 2, 8, AddressingMode::IndirectY),
+        // the immediate combinations
+        OpCode::new(0x0b, "*ANC", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x2b, "*ANC", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x4b, "*ALR", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x6b, "*ARR", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xcb, "*AXS", 2, 2, AddressingMode::Immediate),
+        // unstable on real hardware, these do what most chips are measured doing
+        OpCode::new(0x8b, "*XAA", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xab, "*LXA", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x9f, "*AHX", 3, 5, AddressingMode::AbsoluteY),
+        OpCode::new(0x93, "*AHX", 2, 6, AddressingMode::IndirectY),
+        OpCode::new(0x9c, "*SHY", 3, 5, AddressingMode::AbsoluteX),
+        OpCode::new(0x9e, "*SHX", 3, 5, AddressingMode::AbsoluteY),
+        OpCode::new(0x9b, "*TAS", 3, 5, AddressingMode::AbsoluteY),
+        OpCode::new(0xbb, "*LAS", 3, 4, AddressingMode::AbsoluteY).page_cross_costs_one(),
     ];
+
+    // CPU_OP_CODES indexed by the opcode byte, so decoding doesn't search the table
+    pub static ref OPCODES_MAP: [Option<&'static OpCode>; 256] = {
+        let mut map = [None; 256];
+        for op in CPU_OP_CODES.iter() {
+            assert!(
+                map[op.hex as usize].is_none(),
+                "opcode {:02x} is in the table twice",
+                op.hex
+            );
+            map[op.hex as usize] = Some(op);
+        }
+        map
+    };
 }
 
 impl OpCode {
-    pub const fn new(
+    pub fn new(
         hex: u8,
         mnemonic: &'static str,
         bytes: u8,
@@ -68,20 +528,29 @@ impl OpCode {
         OpCode {
             hex,
             mnemonic,
+            instruction: Instruction::from_mnemonic(mnemonic),
             bytes,
             cycles,
             mode,
+            page_cross_penalty: false,
         }
     }
 
-    pub fn from_u8(val: u8) -> &'static OpCode {
-        for op in CPU_OP_CODES.iter() {
-            if val == op.hex {
-                return op;
-            }
+    // for the table, marks the reads that pay for indexing into the next page
+    fn page_cross_costs_one(self) -> OpCode {
+        OpCode {
+            page_cross_penalty: true,
+            ..self
         }
+    }
+
+    pub fn from_u8(val: u8) -> &'static OpCode {
+        Self::try_from_u8(val).expect("Invalid opcode")
+    }
 
-        panic!("Invalid opcode");
+    // None for bytes that aren't an instruction the cpu knows
+    pub fn try_from_u8(val: u8) -> Option<&'static OpCode> {
+        OPCODES_MAP[val as usize]
     }
 }
 
@@ -97,10 +566,131 @@ mod tests {
             OpCode {
                 hex: 0xa5,
                 mnemonic: "LDA",
+                instruction: Instruction::Lda,
                 bytes: 2,
                 cycles: 3,
                 mode: AddressingMode::ZeroPage,
+                page_cross_penalty: false,
             }
         );
     }
+
+    #[test]
+    fn test_every_opcode_but_the_jams_is_in_the_map() {
+        let jams = [
+            0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2,
+        ];
+
+        for hex in 0..=0xff {
+            let op = OpCode::try_from_u8(hex);
+            assert_eq!(op.is_none(), jams.contains(&hex), "{:02x}", hex);
+            if let Some(op) = op {
+                assert_eq!(op.hex, hex);
+            }
+        }
+        assert_eq!(CPU_OP_CODES.len(), 256 - jams.len());
+    }
+
+    #[test]
+    fn test_bytes_match_the_addressing_mode() {
+        for op in CPU_OP_CODES.iter() {
+            let bytes = match op.mode {
+                AddressingMode::NoneAddressing | AddressingMode::Accumulator => 1,
+                AddressingMode::Absolute
+                | AddressingMode::AbsoluteX
+                | AddressingMode::AbsoluteY
+                | AddressingMode::Indirect => 3,
+                _ => 2,
+            };
+            assert_eq!(op.bytes, bytes, "{:02x} {}", op.hex, op.mnemonic);
+        }
+    }
+
+    // the ALU and load/store group is laid out as aaabbb01, where bbb picks the addressing
+    // mode the same way for every instruction in it
+    #[test]
+    fn test_alu_group_modes() {
+        let modes = [
+            AddressingMode::IndirectX,
+            AddressingMode::ZeroPage,
+            AddressingMode::Immediate,
+            AddressingMode::Absolute,
+            AddressingMode::IndirectY,
+            AddressingMode::ZeroPageX,
+            AddressingMode::AbsoluteY,
+            AddressingMode::AbsoluteX,
+        ];
+        let group = ["ORA", "AND", "EOR", "ADC", "STA", "LDA", "CMP", "SBC"];
+
+        let mut checked = 0;
+        let official = CPU_OP_CODES
+            .iter()
+            .filter(|op| op.hex & 0b11 == 0b01 && !op.mnemonic.starts_with('*'));
+        for op in official {
+            assert_eq!(op.mnemonic, group[(op.hex >> 5) as usize], "{:02x}", op.hex);
+            assert_eq!(
+                op.mode,
+                modes[((op.hex >> 2) & 0b111) as usize],
+                "{:02x}",
+                op.hex
+            );
+            checked += 1;
+        }
+        // everything but STA #imm, which is a NOP
+        assert_eq!(checked, 8 * 8 - 1);
+    }
+
+    #[test]
+    fn test_only_indexed_reads_pay_for_page_crosses() {
+        let paying: Vec<u8> = CPU_OP_CODES
+            .iter()
+            .filter(|op| op.page_cross_penalty)
+            .map(|op| op.hex)
+            .collect();
+
+        for hex in [0xbd, 0xb9, 0xb1, 0x7d, 0xdd, 0xbe, 0xbc, 0xbf, 0xbb, 0x1c] {
+            assert!(paying.contains(&hex), "{:02x}", hex);
+        }
+        // STA and the read-modify-writes never do
+        for hex in [0x9d, 0x99, 0x91, 0x1e, 0xfe, 0xdf] {
+            assert!(!paying.contains(&hex), "{:02x}", hex);
+        }
+        assert!(paying.iter().all(|&hex| matches!(
+            OpCode::from_u8(hex).mode,
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY | AddressingMode::IndirectY
+        )));
+    }
+
+    #[test]
+    fn test_lda_adc_sta_cycles() {
+        let reference = [
+            (0xa9, 2),
+            (0xa5, 3),
+            (0xb5, 4),
+            (0xad, 4),
+            (0xbd, 4),
+            (0xb9, 4),
+            (0xa1, 6),
+            (0xb1, 5),
+            (0x69, 2),
+            (0x65, 3),
+            (0x75, 4),
+            (0x6d, 4),
+            (0x7d, 4),
+            (0x79, 4),
+            (0x61, 6),
+            (0x71, 5),
+            (0x85, 3),
+            (0x95, 4),
+            (0x8d, 4),
+            (0x9d, 5),
+            (0x99, 5),
+            (0x81, 6),
+            (0x91, 6),
+        ];
+
+        for (hex, cycles) in reference {
+            assert_eq!(OpCode::from_u8(hex).cycles, cycles, "{:02x}", hex);
+        }
+    }
 }
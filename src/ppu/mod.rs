@@ -0,0 +1,877 @@
+pub mod registers;
+
+use crate::mappers::Mapper;
+use crate::render::{self, frame::Frame, palette};
+use crate::rom::{Mirroring, TvSystem};
+use registers::{ControlRegister, LoopyRegister, MaskRegister, StatusRegister};
+
+// the cycle of a rendered line the sprite pattern fetches start on
+const A12_RISE_CYCLE: usize = 260;
+
+// chr lives on the cartridge, so anything that touches the pattern tables takes the
+// mapper from the bus
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct NesPPU {
+    // the console has 2KB for two nametables, the second half is the extra ram four screen
+    // cartridges bring along
+    #[cfg_attr(feature = "savestate", serde(with = "crate::savestate::big_array"))]
+    pub vram: [u8; 4096],
+    pub palette_table: [u8; 32],
+    #[cfg_attr(feature = "savestate", serde(with = "crate::savestate::big_array"))]
+    pub oam_data: [u8; 256],
+    pub oam_addr: u8,
+
+    pub ctrl: ControlRegister,
+    pub mask: MaskRegister,
+    pub status: StatusRegister,
+    // current vram address, temporary address (what the next frame or line starts from) and
+    // fine x scroll, see LoopyRegister
+    pub v: LoopyRegister,
+    pub t: LoopyRegister,
+    pub fine_x: u8,
+    // PPUSCROLL and PPUADDR share one latch, false means the next write is the first of a pair
+    write_latch: bool,
+    // PPUDATA reads below the palettes come out of here one read late
+    internal_data_buf: u8,
+    // the last value on the bus between the cpu and the ppu's registers. reads of the write
+    // only registers and the bits a register doesn't drive come from here
+    pub io_latch: u8,
+
+    // 262 scanlines of 341 cycles each, 0 - 239 are visible and 261 is the pre-render line.
+    // pal has 312, vblank still starts on 241 but lasts until the pre-render line at 311
+    pub scanline: u16,
+    tv_system: TvSystem,
+    pub cycle: usize,
+    // frames finished since power on, one goes up as the last visible line is done
+    pub frame_count: u64,
+    // filled in a line at a time while ticking with rendering on
+    pub frame: Frame,
+    // the rgb for each colour index, swappable for a different look
+    #[cfg_attr(feature = "savestate", serde(with = "crate::savestate::big_array"))]
+    palette: [(u8, u8, u8); 64],
+    // raised at the start of vblank when PPUCTRL asks for it, the cpu takes it with poll_nmi
+    nmi_interrupt: bool,
+}
+
+impl Default for NesPPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NesPPU {
+    pub fn new() -> Self {
+        NesPPU {
+            vram: [0; 4096],
+            palette_table: [0; 32],
+            oam_data: [0; 256],
+            oam_addr: 0,
+            ctrl: ControlRegister::empty(),
+            mask: MaskRegister::empty(),
+            status: StatusRegister::empty(),
+            v: LoopyRegister::new(),
+            t: LoopyRegister::new(),
+            fine_x: 0,
+            write_latch: false,
+            internal_data_buf: 0,
+            io_latch: 0,
+            scanline: 0,
+            cycle: 0,
+            frame_count: 0,
+            tv_system: TvSystem::Ntsc,
+            frame: Frame::new(),
+            palette: palette::SYSTEM_PALETTE,
+            nmi_interrupt: false,
+        }
+    }
+
+    pub fn tick(&mut self, mapper: &mut dyn Mapper, cycles: u16) {
+        let from = self.cycle;
+        self.cycle += cycles as usize;
+        self.check_sprite_zero_hit(mapper);
+        self.check_a12_rise(mapper, from);
+
+        while self.cycle >= 341 {
+            // the whole line is drawn with the state it ends with, then v moves down a line and
+            // picks up the horizontal scroll from t
+            if self.rendering_enabled() && self.scanline < 240 {
+                self.render_line(mapper);
+                let t = self.t;
+                self.v.increment_y();
+                self.v.copy_horizontal(&t);
+            }
+
+            self.cycle -= 341;
+            self.scanline += 1;
+
+            if self.scanline == 241 {
+                self.frame_count += 1;
+                self.status.insert(StatusRegister::VBLANK_STARTED);
+                if self.ctrl.contains(ControlRegister::GENERATE_NMI) {
+                    self.nmi_interrupt = true;
+                }
+            }
+            let scanlines = self.tv_system.scanlines();
+            if self.scanline == scanlines - 1 {
+                // pre-render
+                self.status.remove(
+                    StatusRegister::VBLANK_STARTED
+                        | StatusRegister::SPRITE_ZERO_HIT
+                        | StatusRegister::SPRITE_OVERFLOW,
+                );
+            }
+            if self.scanline == scanlines {
+                // the end of pre-render reloads all of v, so the next frame starts at t
+                if self.rendering_enabled() {
+                    self.v = self.t;
+                }
+                self.scanline = 0;
+            }
+            self.check_sprite_zero_hit(mapper);
+            self.check_a12_rise(mapper, 0);
+        }
+    }
+
+    pub fn set_tv_system(&mut self, tv_system: TvSystem) {
+        self.tv_system = tv_system;
+    }
+
+    // true once for every nmi raised
+    pub fn poll_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_interrupt)
+    }
+
+    // what the reset button does: PPUCTRL, PPUMASK, the scroll, the shared latch and the
+    // PPUDATA buffer are cleared, memory and PPUADDR are left as they were
+    pub fn reset(&mut self) {
+        self.ctrl = ControlRegister::empty();
+        self.mask = MaskRegister::empty();
+        self.t = LoopyRegister::new();
+        self.fine_x = 0;
+        self.write_latch = false;
+        self.internal_data_buf = 0;
+        self.nmi_interrupt = false;
+    }
+
+    // off and on again: everything goes back to how new left it apart from the tv system and
+    // palette, which are the frontend's, and the frame count, which keeps counting
+    pub fn power_cycle(&mut self) {
+        *self = NesPPU {
+            tv_system: self.tv_system,
+            palette: self.palette,
+            frame_count: self.frame_count,
+            ..NesPPU::new()
+        };
+    }
+
+    // e.g. the contents of a .pal file
+    pub fn set_palette(&mut self, palette: &[(u8, u8, u8); 64]) {
+        self.palette = *palette;
+    }
+
+    // the rgb a palette ram entry comes out as, with PPUMASK's greyscale and emphasis applied
+    pub fn colour(&self, index: u8) -> (u8, u8, u8) {
+        palette::colour(&self.palette, index, self.mask)
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.mask
+            .intersects(MaskRegister::SHOW_BACKGROUND | MaskRegister::SHOW_SPRITES)
+    }
+
+    fn render_line(&mut self, mapper: &dyn Mapper) {
+        // the frame is lent out while the line reads the rest of the ppu
+        let mut frame = std::mem::replace(&mut self.frame, Frame { data: Vec::new() });
+        if render::render_scanline(self, mapper, &mut frame, self.scanline as usize) {
+            self.status.insert(StatusRegister::SPRITE_OVERFLOW);
+        }
+        self.frame = frame;
+    }
+
+    // pixel x of a line comes out on cycle x + 1, so the flag goes up once that cycle is reached
+    fn check_sprite_zero_hit(&mut self, mapper: &dyn Mapper) {
+        if self.status.contains(StatusRegister::SPRITE_ZERO_HIT) || self.scanline >= 240 {
+            return;
+        }
+        if let Some(x) = render::sprite_zero_hit_x(self, mapper, self.scanline as usize) {
+            if self.cycle > x {
+                self.status.insert(StatusRegister::SPRITE_ZERO_HIT);
+            }
+        }
+    }
+
+    // with the background at 0x0000 and sprites at 0x1000, the layout boards that count
+    // scanlines want, A12 goes high once a line when the sprite fetches start. they're only
+    // done while rendering, on the visible lines and the pre-render line. `from` is the cycle
+    // this line was on before the tick
+    fn check_a12_rise(&mut self, mapper: &mut dyn Mapper, from: usize) {
+        let pre_render = self.tv_system.scanlines() - 1;
+        if from <= A12_RISE_CYCLE
+            && self.cycle > A12_RISE_CYCLE
+            && self.rendering_enabled()
+            && (self.scanline < 240 || self.scanline == pre_render)
+        {
+            mapper.notify_a12_rise();
+        }
+    }
+
+    pub fn write_to_ctrl(&mut self, value: u8) {
+        let nmi_was_enabled = self.ctrl.contains(ControlRegister::GENERATE_NMI);
+        self.ctrl = ControlRegister::from_bits_retain(value);
+        self.t.set_nametable(value & 0b11);
+
+        // turning nmi on in the middle of vblank fires one straight away
+        if !nmi_was_enabled
+            && self.ctrl.contains(ControlRegister::GENERATE_NMI)
+            && self.status.contains(StatusRegister::VBLANK_STARTED)
+        {
+            self.nmi_interrupt = true;
+        }
+    }
+
+    pub fn write_to_mask(&mut self, value: u8) {
+        self.mask = MaskRegister::from_bits_retain(value);
+    }
+
+    // reading the status clears vblank and resets the PPUSCROLL/PPUADDR latch
+    pub fn read_status(&mut self) -> u8 {
+        let data = self.status.bits();
+        // a read right as vblank starts races the flag and the nmi never happens. the cpu only
+        // catches up with the ppu between instructions, so "right as" is the first few cycles
+        if self.scanline == 241 && self.cycle < 3 {
+            self.nmi_interrupt = false;
+        }
+        self.status.remove(StatusRegister::VBLANK_STARTED);
+        self.write_latch = false;
+        data
+    }
+
+    // what read_status would return, without clearing anything
+    pub fn peek_status(&self) -> u8 {
+        self.status.bits()
+    }
+
+    pub fn write_to_oam_addr(&mut self, value: u8) {
+        self.oam_addr = value;
+    }
+
+    pub fn write_to_oam_data(&mut self, value: u8) {
+        self.oam_data[self.oam_addr as usize] = value;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    // reads don't move OAMADDR
+    pub fn read_oam_data(&self) -> u8 {
+        self.oam_data[self.oam_addr as usize]
+    }
+
+    // x first, then y
+    pub fn write_to_scroll(&mut self, value: u8) {
+        if !self.write_latch {
+            self.t.set_coarse_x(value >> 3);
+            self.fine_x = value & 0b111;
+        } else {
+            self.t.set_coarse_y(value >> 3);
+            self.t.set_fine_y(value & 0b111);
+        }
+        self.write_latch = !self.write_latch;
+    }
+
+    // high byte first, v only picks up the new address once the low byte is in
+    pub fn write_to_ppu_addr(&mut self, value: u8) {
+        if !self.write_latch {
+            self.t.set_high_byte(value);
+        } else {
+            self.t.set_low_byte(value);
+            self.v = self.t;
+        }
+        self.write_latch = !self.write_latch;
+    }
+
+    pub fn write_to_data(&mut self, mapper: &mut dyn Mapper, value: u8) {
+        let addr = self.v.get();
+        match addr {
+            0x0000..=0x1fff => mapper.ppu_write(addr, value),
+            0x2000..=0x3eff => {
+                self.vram[Self::mirror_vram_addr(addr, mapper.mirroring()) as usize] = value
+            }
+            _ => self.palette_table[palette_index(addr)] = value,
+        }
+        self.increment_vram_addr();
+    }
+
+    // chr and nametable reads return what the previous read fetched, so the first read after
+    // setting PPUADDR is stale. palettes are answered straight away, but the buffer still gets
+    // refilled from the nametable byte that sits underneath them
+    pub fn read_data(&mut self, mapper: &dyn Mapper) -> u8 {
+        let addr = self.v.get();
+        self.increment_vram_addr();
+
+        let result = match addr {
+            0x0000..=0x1fff => {
+                let result = self.internal_data_buf;
+                self.internal_data_buf = mapper.ppu_read(addr);
+                result
+            }
+            0x2000..=0x3eff => {
+                let result = self.internal_data_buf;
+                self.internal_data_buf =
+                    self.vram[Self::mirror_vram_addr(addr, mapper.mirroring()) as usize];
+                result
+            }
+            _ => {
+                self.internal_data_buf =
+                    self.vram[Self::mirror_vram_addr(addr - 0x1000, mapper.mirroring()) as usize];
+                self.read_palette(addr)
+            }
+        };
+        self.io_latch = result;
+        result
+    }
+
+    // what read_data would return, without moving v or refilling the buffer
+    pub fn peek_data(&self) -> u8 {
+        match self.v.get() {
+            0x0000..=0x3eff => self.internal_data_buf,
+            addr => self.read_palette(addr),
+        }
+    }
+
+    // palette ram is only 6 bits wide, the top 2 of a read are left over on the bus. greyscale
+    // applies to reads as well as to what's drawn
+    fn read_palette(&self, addr: u16) -> u8 {
+        let mut value = self.palette_table[palette_index(addr)] & 0x3f;
+        if self.mask.contains(MaskRegister::GREYSCALE) {
+            value &= 0x30;
+        }
+        value | (self.io_latch & 0xc0)
+    }
+
+    fn increment_vram_addr(&mut self) {
+        self.v.increment(self.ctrl.vram_addr_increment());
+    }
+
+    // turns a nametable address into an index into vram. there are four logical 1KB tables
+    // but only room for two, so the cartridge decides which pairs share memory
+    //   Horizontal:
+    //     [ A ] [ a ]
+    //     [ B ] [ b ]
+    //   Vertical:
+    //     [ A ] [ B ]
+    //     [ a ] [ b ]
+    pub fn mirror_vram_addr(addr: u16, mirroring: Mirroring) -> u16 {
+        // 0x3000 - 0x3eff is a mirror of 0x2000 - 0x2eff
+        let vram_index = (addr & 0x2fff) - 0x2000;
+        let name_table = vram_index / 0x400;
+        let offset = vram_index % 0x400;
+
+        let table = match (mirroring, name_table) {
+            (Mirroring::Horizontal, 0 | 1) => 0,
+            (Mirroring::Horizontal, _) => 1,
+            (Mirroring::Vertical, 0 | 2) => 0,
+            (Mirroring::Vertical, _) => 1,
+            (Mirroring::SingleScreenLower, _) => 0,
+            (Mirroring::SingleScreenUpper, _) => 1,
+            (Mirroring::FourScreen, n) => n,
+        };
+        table * 0x400 + offset
+    }
+}
+
+// 0x3f10, 0x3f14, 0x3f18 and 0x3f1c are mirrors of the background entries below them
+fn palette_index(addr: u16) -> usize {
+    let index = (addr & 0x1f) as usize;
+    match index {
+        0x10 | 0x14 | 0x18 | 0x1c => index - 0x10,
+        _ => index,
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::mappers::flat::Flat;
+    use crate::mappers::nrom::Nrom;
+    use crate::rom::tests::{create_rom, header, TestRom};
+    use crate::rom::Rom;
+
+    fn nrom_with(control_1: u8) -> Nrom {
+        let raw = create_rom(TestRom {
+            header: header(1, 1, control_1, 0x00),
+            trainer: None,
+            prg_rom: vec![0; 0x4000],
+            chr_rom: vec![0; 0x2000],
+        });
+        Nrom::new(Rom::new(&raw).unwrap()).unwrap()
+    }
+
+    fn write_through(ppu: &mut NesPPU, mapper: &mut dyn Mapper, addr: u16, value: u8) {
+        ppu.write_to_ppu_addr((addr >> 8) as u8);
+        ppu.write_to_ppu_addr((addr & 0xff) as u8);
+        ppu.write_to_data(mapper, value);
+    }
+
+    fn read_through(ppu: &mut NesPPU, mapper: &dyn Mapper, addr: u16) -> u8 {
+        ppu.write_to_ppu_addr((addr >> 8) as u8);
+        ppu.write_to_ppu_addr((addr & 0xff) as u8);
+        ppu.read_data(mapper); // load into buffer
+        ppu.read_data(mapper)
+    }
+
+    #[test]
+    fn test_vertical_mirroring() {
+        let mut ppu = NesPPU::new();
+        let mut mapper = nrom_with(0b1);
+        write_through(&mut ppu, &mut mapper, 0x2005, 0x66);
+        write_through(&mut ppu, &mut mapper, 0x2405, 0x77);
+
+        assert_eq!(read_through(&mut ppu, &mapper, 0x2805), 0x66);
+        assert_eq!(read_through(&mut ppu, &mapper, 0x2c05), 0x77);
+        assert_eq!(read_through(&mut ppu, &mapper, 0x3005), 0x66);
+    }
+
+    #[test]
+    fn test_horizontal_mirroring() {
+        let mut ppu = NesPPU::new();
+        let mut mapper = nrom_with(0b0);
+        write_through(&mut ppu, &mut mapper, 0x2005, 0x66);
+        write_through(&mut ppu, &mut mapper, 0x2805, 0x77);
+
+        assert_eq!(read_through(&mut ppu, &mapper, 0x2405), 0x66);
+        assert_eq!(read_through(&mut ppu, &mapper, 0x2c05), 0x77);
+    }
+
+    #[test]
+    fn test_four_screen_keeps_tables_apart() {
+        let mut ppu = NesPPU::new();
+        let mut mapper = nrom_with(0b1000);
+        for (i, table) in [0x2005, 0x2405, 0x2805, 0x2c05].into_iter().enumerate() {
+            write_through(&mut ppu, &mut mapper, table, i as u8 + 1);
+        }
+
+        assert_eq!(read_through(&mut ppu, &mapper, 0x2005), 1);
+        assert_eq!(read_through(&mut ppu, &mapper, 0x2405), 2);
+        assert_eq!(read_through(&mut ppu, &mapper, 0x2805), 3);
+        assert_eq!(read_through(&mut ppu, &mapper, 0x2c05), 4);
+    }
+
+    #[test]
+    fn test_single_screen_mirroring() {
+        assert_eq!(
+            NesPPU::mirror_vram_addr(0x2c05, Mirroring::SingleScreenLower),
+            0x0005
+        );
+        assert_eq!(
+            NesPPU::mirror_vram_addr(0x2005, Mirroring::SingleScreenUpper),
+            0x0405
+        );
+    }
+
+    #[test]
+    fn test_ppu_vram_writes() {
+        let mut ppu = NesPPU::new();
+        let mut mapper = Flat::new();
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_data(&mut mapper, 0x66);
+
+        assert_eq!(ppu.vram[0x0305], 0x66);
+    }
+
+    #[test]
+    fn test_ppu_addr_is_high_byte_first() {
+        let mut ppu = NesPPU::new();
+        ppu.write_to_ppu_addr(0x21);
+        // v only changes once both halves are in
+        assert_eq!(ppu.t.get(), 0x2100);
+        assert_eq!(ppu.v.get(), 0);
+
+        ppu.write_to_ppu_addr(0x34);
+        assert_eq!(ppu.v.get(), 0x2134);
+    }
+
+    #[test]
+    fn test_ppu_addr_mirrors_down_to_14_bits() {
+        let mut ppu = NesPPU::new();
+        ppu.write_to_ppu_addr(0x63);
+        ppu.write_to_ppu_addr(0x05);
+
+        assert_eq!(ppu.v.get(), 0x2305);
+    }
+
+    #[test]
+    fn test_ppu_vram_reads() {
+        let mut ppu = NesPPU::new();
+        let mapper = Flat::new();
+        ppu.vram[0x0305] = 0x66;
+        ppu.vram[0x0306] = 0x77;
+
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(&mapper); // load into buffer
+        assert_eq!(ppu.read_data(&mapper), 0x66);
+        assert_eq!(ppu.read_data(&mapper), 0x77);
+        assert_eq!(ppu.v.get(), 0x2308);
+    }
+
+    #[test]
+    fn test_ppu_vram_reads_step_32() {
+        let mut ppu = NesPPU::new();
+        let mapper = Flat::new();
+        ppu.write_to_ctrl(0b100);
+        ppu.vram[0x01ff] = 0x66;
+        ppu.vram[0x01ff + 32] = 0x77;
+        ppu.vram[0x01ff + 64] = 0x88;
+
+        ppu.write_to_ppu_addr(0x21);
+        ppu.write_to_ppu_addr(0xff);
+
+        ppu.read_data(&mapper); // load into buffer
+        assert_eq!(ppu.read_data(&mapper), 0x66);
+        assert_eq!(ppu.read_data(&mapper), 0x77);
+        assert_eq!(ppu.read_data(&mapper), 0x88);
+    }
+
+    #[test]
+    fn test_read_status_resets_latch() {
+        let mut ppu = NesPPU::new();
+        let mapper = Flat::new();
+        ppu.vram[0x0305] = 0x66;
+
+        // half a pair, then a status read throws it away
+        ppu.write_to_ppu_addr(0x21);
+        ppu.read_status();
+
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+
+        assert_eq!(ppu.v.get(), 0x2305);
+        ppu.read_data(&mapper); // load into buffer
+        assert_eq!(ppu.read_data(&mapper), 0x66);
+    }
+
+    #[test]
+    fn test_read_status_resets_vblank() {
+        let mut ppu = NesPPU::new();
+        ppu.status.insert(StatusRegister::VBLANK_STARTED);
+
+        let status = ppu.read_status();
+
+        assert_eq!(status >> 7, 1);
+        assert!(!ppu.status.contains(StatusRegister::VBLANK_STARTED));
+    }
+
+    #[test]
+    fn test_reset_clears_registers_but_not_memory() {
+        let mut ppu = NesPPU::new();
+        ppu.write_to_ctrl(0x80);
+        ppu.write_to_mask(0x1e);
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_oam_data(0x66);
+        ppu.vram[0x10] = 0x77;
+        ppu.status.insert(StatusRegister::VBLANK_STARTED);
+        ppu.write_to_ctrl(0x80);
+        ppu.write_to_scroll(0x25);
+
+        ppu.reset();
+
+        assert!(ppu.ctrl.is_empty());
+        assert!(ppu.mask.is_empty());
+        assert_eq!(ppu.fine_x, 0);
+        assert!(!ppu.poll_nmi());
+        assert_eq!(ppu.v.get(), 0x2305);
+        assert_eq!(ppu.oam_data[0], 0x66);
+        assert_eq!(ppu.vram[0x10], 0x77);
+        // the latch is back to expecting the first write
+        ppu.write_to_scroll(0x08);
+        assert_eq!(ppu.t.coarse_x(), 1);
+    }
+
+    #[test]
+    fn test_scroll_shares_the_latch() {
+        let mut ppu = NesPPU::new();
+        ppu.write_to_scroll(0x13);
+        ppu.write_to_scroll(0x25);
+        assert_eq!(ppu.t.coarse_x(), 0x13 >> 3);
+        assert_eq!(ppu.fine_x, 0x13 & 0b111);
+        assert_eq!(ppu.t.coarse_y(), 0x25 >> 3);
+        assert_eq!(ppu.t.fine_y(), 0x25 & 0b111);
+
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+        assert_eq!(ppu.v.get(), 0x2305);
+    }
+
+    #[test]
+    fn test_oam_read_write() {
+        let mut ppu = NesPPU::new();
+        ppu.write_to_oam_addr(0x10);
+        ppu.write_to_oam_data(0x66);
+        ppu.write_to_oam_data(0x77);
+
+        ppu.write_to_oam_addr(0x10);
+        assert_eq!(ppu.read_oam_data(), 0x66);
+
+        ppu.write_to_oam_addr(0x11);
+        assert_eq!(ppu.read_oam_data(), 0x77);
+    }
+
+    #[test]
+    fn test_palette_mirrors() {
+        let mut ppu = NesPPU::new();
+        let mut mapper = Flat::new();
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x10);
+        ppu.write_to_data(&mut mapper, 0x2a);
+
+        assert_eq!(ppu.palette_table[0x00], 0x2a);
+    }
+
+    #[test]
+    fn test_read_data_is_buffered() {
+        let mut ppu = NesPPU::new();
+        let mapper = Flat::new();
+        ppu.vram[0x0100] = 0x11;
+        ppu.vram[0x0101] = 0x22;
+
+        ppu.write_to_ppu_addr(0x21);
+        ppu.write_to_ppu_addr(0x00);
+
+        assert_eq!(ppu.read_data(&mapper), 0x00);
+        assert_eq!(ppu.read_data(&mapper), 0x11);
+        assert_eq!(ppu.read_data(&mapper), 0x22);
+    }
+
+    #[test]
+    fn test_palette_reads_bypass_the_buffer() {
+        let mut ppu = NesPPU::new();
+        let mapper = Flat::new();
+        ppu.palette_table[0x00] = 0x2a;
+        ppu.vram[NesPPU::mirror_vram_addr(0x2f10, mapper.mirroring()) as usize] = 0x55;
+
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x10);
+        assert_eq!(ppu.read_data(&mapper), 0x2a);
+
+        // the buffer picked up the nametable byte under the palette
+        ppu.write_to_ppu_addr(0x21);
+        ppu.write_to_ppu_addr(0x00);
+        assert_eq!(ppu.read_data(&mapper), 0x55);
+    }
+
+    // tile 1 is solid colour 1, sprite 0 uses it too and sits over it at (16, 20)
+    fn sprite_zero_setup(mask: u8) -> (NesPPU, Nrom) {
+        let mut ppu = NesPPU::new();
+        let mut mapper = chr_ram_nrom();
+        for row in 0..8 {
+            mapper.ppu_write(16 + row, 0xff);
+        }
+        ppu.vram[2 * 32 + 2] = 1;
+        ppu.oam_data[0..4].copy_from_slice(&[19, 1, 0, 16]);
+        ppu.write_to_mask(mask);
+        (ppu, mapper)
+    }
+
+    fn chr_ram_nrom() -> Nrom {
+        let raw = create_rom(TestRom {
+            header: header(1, 0, 0x00, 0x00),
+            trainer: None,
+            prg_rom: vec![0; 0x4000],
+            chr_rom: vec![],
+        });
+        Nrom::new(Rom::new(&raw).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        let (mut ppu, mut mapper) = sprite_zero_setup(0b0001_1000);
+
+        // up to line 20, the hit is at pixel 16 so cycle 17
+        ppu.tick(&mut mapper, 0);
+        while ppu.scanline < 20 {
+            ppu.tick(&mut mapper, 1);
+        }
+        while ppu.cycle < 17 {
+            assert!(!ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+            ppu.tick(&mut mapper, 1);
+        }
+        assert!(ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+
+        // cleared at pre-render
+        while ppu.scanline != 261 {
+            ppu.tick(&mut mapper, 100);
+        }
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+    }
+
+    #[test]
+    fn test_no_sprite_zero_hit_without_background() {
+        let (mut ppu, mut mapper) = sprite_zero_setup(0b0001_0000);
+
+        for _ in 0..240 {
+            ppu.tick(&mut mapper, 255);
+            ppu.tick(&mut mapper, 86);
+        }
+
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+    }
+
+    #[test]
+    fn test_no_sprite_zero_hit_in_clipped_left_edge() {
+        let (mut ppu, mut mapper) = sprite_zero_setup(0b0001_1000);
+        ppu.vram[2 * 32 + 2] = 0;
+        ppu.vram[2 * 32] = 1;
+        ppu.oam_data[3] = 0;
+
+        for _ in 0..240 {
+            ppu.tick(&mut mapper, 255);
+            ppu.tick(&mut mapper, 86);
+        }
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+
+        // showing both layers on the left edge lets it through
+        ppu.write_to_mask(0b0001_1110);
+        for _ in 0..262 {
+            ppu.tick(&mut mapper, 255);
+            ppu.tick(&mut mapper, 86);
+        }
+        assert!(ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+    }
+
+    #[test]
+    fn test_no_sprite_zero_hit_at_x_255() {
+        let (mut ppu, mut mapper) = sprite_zero_setup(0b0001_1000);
+        ppu.vram[2 * 32 + 2] = 0;
+        ppu.vram[2 * 32 + 31] = 1;
+        ppu.oam_data[3] = 255;
+
+        for _ in 0..240 {
+            ppu.tick(&mut mapper, 255);
+            ppu.tick(&mut mapper, 86);
+        }
+
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+    }
+
+    #[test]
+    fn test_ctrl_sets_nametable_bits_of_t() {
+        let mut ppu = NesPPU::new();
+        ppu.write_to_ctrl(0b10);
+
+        assert_eq!(ppu.t.nametable(), 0b10);
+        assert_eq!(ppu.v.nametable(), 0);
+    }
+
+    #[test]
+    fn test_y_increment_carries_into_nametable() {
+        let mut v = LoopyRegister::new();
+        v.set_coarse_y(29);
+        v.set_fine_y(7);
+        v.increment_y();
+
+        assert_eq!(v.coarse_y(), 0);
+        assert_eq!(v.fine_y(), 0);
+        assert_eq!(v.nametable(), 0b10);
+    }
+
+    #[test]
+    fn test_mid_frame_scroll_splits_the_screen() {
+        let mut ppu = NesPPU::new();
+        let mut mapper = chr_ram_nrom();
+        // tile 1 has an opaque left column, down the left edge of the screen
+        for row in 0..8 {
+            mapper.ppu_write(16 + row, 0b1000_0000);
+        }
+        for row in 0..30 {
+            ppu.vram[row * 32] = 1;
+        }
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[1] = 0x01;
+        ppu.write_to_mask(0b0000_1010);
+
+        while ppu.scanline < 120 {
+            ppu.tick(&mut mapper, 100);
+        }
+        ppu.write_to_scroll(1);
+        ppu.write_to_scroll(0);
+        while ppu.scanline < 240 {
+            ppu.tick(&mut mapper, 100);
+        }
+
+        let colour = render::palette::SYSTEM_PALETTE[0x01];
+        assert_eq!(ppu.frame.get_pixel(0, 100), colour);
+        assert_ne!(ppu.frame.get_pixel(0, 200), colour);
+
+        // the next frame starts from t, so it's shifted all the way down
+        while ppu.scanline != 100 {
+            ppu.tick(&mut mapper, 100);
+        }
+        assert_ne!(ppu.frame.get_pixel(0, 50), colour);
+    }
+
+    fn tick_to_scanline(ppu: &mut NesPPU, mapper: &mut dyn Mapper, scanline: u16) {
+        while ppu.scanline != scanline {
+            ppu.tick(mapper, 1);
+        }
+    }
+
+    #[test]
+    fn test_vblank_raises_nmi() {
+        let mut ppu = NesPPU::new();
+        let mut mapper = Flat::new();
+        ppu.write_to_ctrl(0b1000_0000);
+
+        tick_to_scanline(&mut ppu, &mut mapper, 240);
+        assert!(!ppu.status.contains(StatusRegister::VBLANK_STARTED));
+        assert!(!ppu.poll_nmi());
+
+        tick_to_scanline(&mut ppu, &mut mapper, 241);
+        assert!(ppu.status.contains(StatusRegister::VBLANK_STARTED));
+        assert!(ppu.poll_nmi());
+        // only once
+        assert!(!ppu.poll_nmi());
+
+        tick_to_scanline(&mut ppu, &mut mapper, 261);
+        assert!(!ppu.status.contains(StatusRegister::VBLANK_STARTED));
+    }
+
+    #[test]
+    fn test_no_nmi_when_disabled() {
+        let mut ppu = NesPPU::new();
+        let mut mapper = Flat::new();
+
+        tick_to_scanline(&mut ppu, &mut mapper, 241);
+
+        assert!(ppu.status.contains(StatusRegister::VBLANK_STARTED));
+        assert!(!ppu.poll_nmi());
+    }
+
+    #[test]
+    fn test_enabling_nmi_during_vblank_fires_immediately() {
+        let mut ppu = NesPPU::new();
+        let mut mapper = Flat::new();
+        tick_to_scanline(&mut ppu, &mut mapper, 250);
+
+        ppu.write_to_ctrl(0b1000_0000);
+        assert!(ppu.poll_nmi());
+
+        // already on, so writing it again does nothing
+        ppu.write_to_ctrl(0b1000_0000);
+        assert!(!ppu.poll_nmi());
+    }
+
+    #[test]
+    fn test_status_read_as_vblank_starts_suppresses_nmi() {
+        let mut ppu = NesPPU::new();
+        let mut mapper = Flat::new();
+        ppu.write_to_ctrl(0b1000_0000);
+        tick_to_scanline(&mut ppu, &mut mapper, 241);
+
+        let status = ppu.read_status();
+
+        assert_ne!(status & 0b1000_0000, 0);
+        assert!(!ppu.poll_nmi());
+    }
+}
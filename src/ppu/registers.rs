@@ -0,0 +1,220 @@
+use bitflags::bitflags;
+
+bitflags! {
+    // 7  bit  0
+    // ---- ----
+    // VPHB SINN
+    // |||| ||||
+    // |||| ||++- base nametable address (0 = $2000; 1 = $2400; 2 = $2800; 3 = $2C00)
+    // |||| |+--- vram address increment per PPUDATA access (0: add 1, going across; 1: add 32, going down)
+    // |||| +---- sprite pattern table address for 8x8 sprites (0: $0000; 1: $1000)
+    // |||+------ background pattern table address (0: $0000; 1: $1000)
+    // ||+------- sprite size (0: 8x8 pixels; 1: 8x16 pixels)
+    // |+-------- ppu master/slave select
+    // +--------- generate an nmi at the start of vblank
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+    pub struct ControlRegister: u8 {
+        const NAMETABLE1 = 0b0000_0001;
+        const NAMETABLE2 = 0b0000_0010;
+        const VRAM_ADD_INCREMENT = 0b0000_0100;
+        const SPRITE_PATTERN_ADDR = 0b0000_1000;
+        const BACKGROUND_PATTERN_ADDR = 0b0001_0000;
+        const SPRITE_SIZE = 0b0010_0000;
+        const MASTER_SLAVE_SELECT = 0b0100_0000;
+        const GENERATE_NMI = 0b1000_0000;
+    }
+}
+
+impl ControlRegister {
+    pub fn nametable_addr(&self) -> u16 {
+        0x2000 + (self.bits() & 0b11) as u16 * 0x400
+    }
+
+    pub fn bknd_pattern_addr(&self) -> u16 {
+        if self.contains(ControlRegister::BACKGROUND_PATTERN_ADDR) {
+            0x1000
+        } else {
+            0
+        }
+    }
+
+    pub fn sprt_pattern_addr(&self) -> u16 {
+        if self.contains(ControlRegister::SPRITE_PATTERN_ADDR) {
+            0x1000
+        } else {
+            0
+        }
+    }
+
+    pub fn sprite_size(&self) -> u8 {
+        if self.contains(ControlRegister::SPRITE_SIZE) {
+            16
+        } else {
+            8
+        }
+    }
+
+    pub fn vram_addr_increment(&self) -> u8 {
+        if self.contains(ControlRegister::VRAM_ADD_INCREMENT) {
+            32
+        } else {
+            1
+        }
+    }
+}
+
+bitflags! {
+    // 7  bit  0
+    // ---- ----
+    // BGRs bMmG
+    // |||| ||||
+    // |||| |||+- greyscale
+    // |||| ||+-- show background in the leftmost 8 pixels of the screen
+    // |||| |+--- show sprites in the leftmost 8 pixels of the screen
+    // |||| +---- show background
+    // |||+------ show sprites
+    // ||+------- emphasize red
+    // |+-------- emphasize green
+    // +--------- emphasize blue
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+    pub struct MaskRegister: u8 {
+        const GREYSCALE = 0b0000_0001;
+        const LEFTMOST_8PXL_BACKGROUND = 0b0000_0010;
+        const LEFTMOST_8PXL_SPRITE = 0b0000_0100;
+        const SHOW_BACKGROUND = 0b0000_1000;
+        const SHOW_SPRITES = 0b0001_0000;
+        const EMPHASISE_RED = 0b0010_0000;
+        const EMPHASISE_GREEN = 0b0100_0000;
+        const EMPHASISE_BLUE = 0b1000_0000;
+    }
+}
+
+bitflags! {
+    // 7  bit  0
+    // ---- ----
+    // VSO. ....
+    // |||
+    // ||+------- sprite overflow
+    // |+-------- sprite 0 hit
+    // +--------- vblank has started
+    // the low 5 bits aren't driven and read back as whatever was last on the bus
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+    pub struct StatusRegister: u8 {
+        const SPRITE_OVERFLOW = 0b0010_0000;
+        const SPRITE_ZERO_HIT = 0b0100_0000;
+        const VBLANK_STARTED = 0b1000_0000;
+    }
+}
+
+// the ppu's internal vram address, used both for PPUDATA accesses and, while rendering, as
+// the position of the tile being fetched. see https://www.nesdev.org/wiki/PPU_scrolling
+//   yyy NN YYYYY XXXXX
+//   ||| || ||||| +++++- coarse x scroll
+//   ||| || +++++------- coarse y scroll
+//   ||| ++------------- nametable select
+//   +++---------------- fine y scroll
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoopyRegister {
+    value: u16,
+}
+
+impl LoopyRegister {
+    pub fn new() -> Self {
+        LoopyRegister::default()
+    }
+
+    pub fn get(&self) -> u16 {
+        self.value & 0x3fff
+    }
+
+    pub fn set(&mut self, value: u16) {
+        self.value = value & 0x7fff;
+    }
+
+    pub fn coarse_x(&self) -> u16 {
+        self.value & 0b11111
+    }
+
+    pub fn coarse_y(&self) -> u16 {
+        (self.value >> 5) & 0b11111
+    }
+
+    pub fn nametable(&self) -> u16 {
+        (self.value >> 10) & 0b11
+    }
+
+    pub fn fine_y(&self) -> u16 {
+        (self.value >> 12) & 0b111
+    }
+
+    pub fn set_coarse_x(&mut self, coarse_x: u8) {
+        self.value = (self.value & !0b11111) | (coarse_x as u16 & 0b11111);
+    }
+
+    pub fn set_coarse_y(&mut self, coarse_y: u8) {
+        self.value = (self.value & !(0b11111 << 5)) | ((coarse_y as u16 & 0b11111) << 5);
+    }
+
+    pub fn set_nametable(&mut self, nametable: u8) {
+        self.value = (self.value & !(0b11 << 10)) | ((nametable as u16 & 0b11) << 10);
+    }
+
+    pub fn set_fine_y(&mut self, fine_y: u8) {
+        self.value = (self.value & !(0b111 << 12)) | ((fine_y as u16 & 0b111) << 12);
+    }
+
+    // PPUADDR writes go high byte first, the top bit of the 15 gets cleared on the way
+    pub fn set_high_byte(&mut self, data: u8) {
+        self.value = (self.value & 0x00ff) | ((data as u16 & 0x3f) << 8);
+    }
+
+    pub fn set_low_byte(&mut self, data: u8) {
+        self.value = (self.value & 0x7f00) | data as u16;
+    }
+
+    pub fn increment(&mut self, inc: u8) {
+        self.value = self.value.wrapping_add(inc as u16) & 0x7fff;
+    }
+
+    // one tile to the right, wrapping into the horizontally adjacent nametable
+    pub fn increment_x(&mut self) {
+        if self.coarse_x() == 31 {
+            self.value &= !0b11111;
+            self.value ^= 0x0400;
+        } else {
+            self.value += 1;
+        }
+    }
+
+    // one pixel down, carrying from fine y into coarse y and then the vertical nametable.
+    // rows 30 and 31 are the attribute table, so coarse y wraps at 29
+    pub fn increment_y(&mut self) {
+        if self.fine_y() < 7 {
+            self.value += 0x1000;
+            return;
+        }
+        self.value &= !0x7000;
+        match self.coarse_y() {
+            29 => {
+                self.set_coarse_y(0);
+                self.value ^= 0x0800;
+            }
+            31 => self.set_coarse_y(0),
+            y => self.set_coarse_y(y as u8 + 1),
+        }
+    }
+
+    // coarse x and the horizontal nametable bit
+    pub fn copy_horizontal(&mut self, from: &LoopyRegister) {
+        self.value = (self.value & !0x041f) | (from.value & 0x041f);
+    }
+
+    // fine y, coarse y and the vertical nametable bit
+    pub fn copy_vertical(&mut self, from: &LoopyRegister) {
+        self.value = (self.value & !0x7be0) | (from.value & 0x7be0);
+    }
+}
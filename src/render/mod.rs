@@ -0,0 +1,584 @@
+pub mod frame;
+pub mod palette;
+
+use crate::mappers::Mapper;
+use crate::ppu::registers::{LoopyRegister, MaskRegister, StatusRegister};
+use crate::ppu::NesPPU;
+use frame::Frame;
+
+// picks the palette for the tile `v` points at out of the attribute table. every attribute
+// byte covers a 4x4 tile area, two bits for each 2x2 quadrant:
+//   7654 3210
+//   |||| ||++- top left
+//   |||| ++--- top right
+//   ||++------ bottom left
+//   ++-------- bottom right
+fn bg_palette(ppu: &NesPPU, mapper: &dyn Mapper, v: &LoopyRegister) -> [u8; 4] {
+    let (coarse_x, coarse_y) = (v.coarse_x(), v.coarse_y());
+    let attr_addr = 0x23c0 | (v.get() & 0x0c00) | ((coarse_y >> 2) << 3) | (coarse_x >> 2);
+    let attr_byte = ppu.vram[NesPPU::mirror_vram_addr(attr_addr, mapper.mirroring()) as usize];
+
+    let shift = ((coarse_y & 0b10) << 1) | (coarse_x & 0b10);
+    let palette_idx = (attr_byte >> shift) & 0b11;
+
+    // colour 0 of every background palette is the universal background colour at 0x3f00
+    let start = 1 + palette_idx as usize * 4;
+    [
+        ppu.palette_table[0],
+        ppu.palette_table[start],
+        ppu.palette_table[start + 1],
+        ppu.palette_table[start + 2],
+    ]
+}
+
+// draws a whole frame in one go, not dot accurate. the scroll is whatever t holds, the same
+// as if it was set during vblank, so mid-frame changes only show up when the ppu is ticked
+pub fn render(ppu: &mut NesPPU, mapper: &dyn Mapper, frame: &mut Frame) {
+    ppu.status.remove(StatusRegister::SPRITE_OVERFLOW);
+    let v = ppu.v;
+    ppu.v = ppu.t;
+
+    for y in 0..Frame::HEIGHT {
+        if render_scanline(ppu, mapper, frame, y) {
+            ppu.status.insert(StatusRegister::SPRITE_OVERFLOW);
+        }
+        let t = ppu.t;
+        ppu.v.increment_y();
+        ppu.v.copy_horizontal(&t);
+    }
+
+    // PPUDATA goes on from wherever it was
+    ppu.v = v;
+}
+
+// draws line `y` with the scroll in v and fine x. returns whether more than 8 sprites were on it
+pub fn render_scanline(ppu: &NesPPU, mapper: &dyn Mapper, frame: &mut Frame, y: usize) -> bool {
+    let mut bg_opaque = [false; Frame::WIDTH];
+
+    for (x, opaque) in bg_opaque.iter_mut().enumerate() {
+        // where the background is hidden the backdrop at 0x3f00 shows instead
+        let colour = if bg_shown(ppu.mask, x) {
+            let (value, palette) = bg_pixel(ppu, mapper, x);
+            *opaque = value != 0;
+            palette[value as usize]
+        } else {
+            ppu.palette_table[0]
+        };
+        frame.set_pixel(x, y, ppu.colour(colour));
+    }
+
+    render_sprites(ppu, mapper, frame, y, &bg_opaque)
+}
+
+// PPUMASK turns each layer on and off, and can hide it in the leftmost 8 pixels on its own
+fn bg_shown(mask: MaskRegister, x: usize) -> bool {
+    mask.contains(MaskRegister::SHOW_BACKGROUND)
+        && (x >= 8 || mask.contains(MaskRegister::LEFTMOST_8PXL_BACKGROUND))
+}
+
+fn sprites_shown(mask: MaskRegister, x: usize) -> bool {
+    mask.contains(MaskRegister::SHOW_SPRITES)
+        && (x >= 8 || mask.contains(MaskRegister::LEFTMOST_8PXL_SPRITE))
+}
+
+// the colour index (0 - 3) of the background at pixel `x` of the line v is on, and the palette
+// it uses
+fn bg_pixel(ppu: &NesPPU, mapper: &dyn Mapper, x: usize) -> (u8, [u8; 4]) {
+    // walk right from the tile v points at, coarse x wraps into the next nametable over
+    let mut v = ppu.v;
+    let fine_x = ppu.fine_x as usize + x;
+    let coarse_x = v.coarse_x() as usize + fine_x / 8;
+    if coarse_x >= 32 {
+        v.set_nametable(v.nametable() as u8 ^ 0b01);
+    }
+    v.set_coarse_x((coarse_x % 32) as u8);
+
+    let tile_addr = 0x2000 | (v.get() & 0x0fff);
+    let tile = ppu.vram[NesPPU::mirror_vram_addr(tile_addr, mapper.mirroring()) as usize] as u16;
+
+    // each row of a tile is two bitplanes, eight bytes apart
+    let bank = ppu.ctrl.bknd_pattern_addr();
+    let plane_0 = mapper.ppu_read(bank + tile * 16 + v.fine_y());
+    let plane_1 = mapper.ppu_read(bank + tile * 16 + v.fine_y() + 8);
+    let shift = 7 - fine_x % 8;
+    let value = (((plane_1 >> shift) & 1) << 1) | ((plane_0 >> shift) & 1);
+
+    (value, bg_palette(ppu, mapper, &v))
+}
+
+// the colour index (0 - 3) of a sprite's pixel, `row` and `column` counted from its top left
+// before flipping
+fn sprite_pixel(ppu: &NesPPU, mapper: &dyn Mapper, sprite: &[u8], row: u16, column: u8) -> u8 {
+    let height = ppu.ctrl.sprite_size() as u16;
+    let attributes = sprite[2];
+    let row = if attributes & 0b1000_0000 != 0 {
+        height - 1 - row
+    } else {
+        row
+    };
+    let column = if attributes & 0b0100_0000 != 0 {
+        7 - column
+    } else {
+        column
+    };
+
+    // 8x16 sprites take their bank from bit 0 of the tile and use two tiles in a row
+    let tile_addr = if height == 16 {
+        let bank = (sprite[1] as u16 & 1) * 0x1000;
+        let tile = (sprite[1] & 0xfe) as u16 + row / 8;
+        bank + tile * 16
+    } else {
+        ppu.ctrl.sprt_pattern_addr() + sprite[1] as u16 * 16
+    };
+
+    let plane_0 = mapper.ppu_read(tile_addr + row % 8);
+    let plane_1 = mapper.ppu_read(tile_addr + row % 8 + 8);
+    (((plane_1 >> (7 - column)) & 1) << 1) | ((plane_0 >> (7 - column)) & 1)
+}
+
+// OAM holds 64 sprites of 4 bytes: y, tile, attributes, x
+//   attributes:
+//   76543210
+//   ||||||++- palette (4 to 7) of sprite
+//   ||+------ priority (0: in front of background; 1: behind background)
+//   |+------- flip sprite horizontally
+//   +-------- flip sprite vertically
+//
+// `bg_opaque` says which pixels of the line the background covers, so sprites behind it know
+// where they're hidden. returns whether a ninth sprite was on the line
+fn render_sprites(
+    ppu: &NesPPU,
+    mapper: &dyn Mapper,
+    frame: &mut Frame,
+    y: usize,
+    bg_opaque: &[bool],
+) -> bool {
+    let height = ppu.ctrl.sprite_size() as usize;
+
+    // only the first 8 sprites in OAM order that cover a line get drawn on it
+    let mut on_line = Vec::with_capacity(8);
+    let mut overflow = false;
+    for sprite in ppu.oam_data.chunks(4) {
+        // sprites are drawn one line below their y
+        let top = sprite[0] as usize + 1;
+        if y < top || y >= top + height {
+            continue;
+        }
+        if on_line.len() == 8 {
+            overflow = true;
+            break;
+        }
+        on_line.push(sprite);
+    }
+
+    for (x, &bg_opaque) in bg_opaque.iter().enumerate() {
+        if !sprites_shown(ppu.mask, x) {
+            continue;
+        }
+        // lower OAM indices win, even when they sit behind the background
+        let hit = on_line.iter().find_map(|sprite| {
+            let left = sprite[3] as usize;
+            if x < left || x >= left + 8 {
+                return None;
+            }
+            let top = sprite[0] as u16 + 1;
+            let value = sprite_pixel(ppu, mapper, sprite, y as u16 - top, (x - left) as u8);
+            (value != 0).then_some((sprite, value))
+        });
+
+        if let Some((sprite, value)) = hit {
+            let behind = sprite[2] & 0b0010_0000 != 0;
+            if behind && bg_opaque {
+                continue;
+            }
+            let palette_idx = (sprite[2] & 0b11) as usize;
+            let colour = ppu.palette_table[0x11 + palette_idx * 4 + value as usize - 1];
+            frame.set_pixel(x, y, ppu.colour(colour));
+        }
+    }
+
+    overflow
+}
+
+// the first x on `y` where an opaque pixel of sprite 0 lands on an opaque background pixel,
+// if there is one and PPUMASK lets it count. the background comes from v, so it has to be
+// on line `y` already
+pub fn sprite_zero_hit_x(ppu: &NesPPU, mapper: &dyn Mapper, y: usize) -> Option<usize> {
+    let mask = ppu.mask;
+    let sprite = &ppu.oam_data[0..4];
+    let top = sprite[0] as usize + 1;
+    if y < top || y >= top + ppu.ctrl.sprite_size() as usize || y >= Frame::HEIGHT {
+        return None;
+    }
+
+    let left = sprite[3] as usize;
+    (left..(left + 8).min(Frame::WIDTH))
+        // the hardware never reports a hit on the last column, or where either layer is hidden
+        .filter(|&x| x != 255 && bg_shown(mask, x) && sprites_shown(mask, x))
+        .find(|&x| {
+            sprite_pixel(ppu, mapper, sprite, (y - top) as u16, (x - left) as u8) != 0
+                && bg_pixel(ppu, mapper, x).0 != 0
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mappers::nrom::Nrom;
+    use crate::rom::tests::{create_rom, header, TestRom};
+    use crate::rom::Rom;
+    use palette::SYSTEM_PALETTE;
+
+    // both layers on, all the way to the left edge
+    fn shown_ppu() -> NesPPU {
+        let mut ppu = NesPPU::new();
+        ppu.write_to_mask(0b0001_1110);
+        ppu
+    }
+
+    // nrom with chr ram so the tests can draw their own tiles
+    fn chr_ram_nrom() -> Nrom {
+        let raw = create_rom(TestRom {
+            header: header(1, 0, 0b1, 0x00),
+            trainer: None,
+            prg_rom: vec![0; 0x4000],
+            chr_rom: vec![],
+        });
+        Nrom::new(Rom::new(&raw).unwrap()).unwrap()
+    }
+
+    // tile 1: left column colour 1, right column colour 2, the rest colour 3 on the top row
+    // and colour 0 below
+    fn draw_tile(mapper: &mut Nrom) {
+        mapper.ppu_write(16, 0b1011_1111);
+        mapper.ppu_write(16 + 8, 0b0111_1111);
+    }
+
+    fn palettes(ppu: &mut NesPPU) {
+        ppu.palette_table[0] = 0x0f;
+        for (i, colour) in (0x01..=0x0c).enumerate() {
+            ppu.palette_table[1 + i / 3 * 4 + i % 3] = colour;
+        }
+    }
+
+    #[test]
+    fn test_tile_pixels() {
+        let mut ppu = shown_ppu();
+        let mut mapper = chr_ram_nrom();
+        draw_tile(&mut mapper);
+        palettes(&mut ppu);
+        ppu.vram[0] = 1;
+        let mut frame = Frame::new();
+
+        render(&mut ppu, &mapper, &mut frame);
+
+        assert_eq!(frame.get_pixel(0, 0), SYSTEM_PALETTE[0x01]);
+        assert_eq!(frame.get_pixel(1, 0), SYSTEM_PALETTE[0x02]);
+        assert_eq!(frame.get_pixel(2, 0), SYSTEM_PALETTE[0x03]);
+        assert_eq!(frame.get_pixel(0, 1), SYSTEM_PALETTE[0x0f]);
+        // tile 0 is blank, so universal background
+        assert_eq!(frame.get_pixel(8, 0), SYSTEM_PALETTE[0x0f]);
+    }
+
+    #[test]
+    fn test_attribute_quadrants() {
+        let mut ppu = shown_ppu();
+        let mut mapper = chr_ram_nrom();
+        draw_tile(&mut mapper);
+        palettes(&mut ppu);
+        // tiles at the top left of each quadrant of the first attribute byte
+        for (column, row) in [(0, 0), (2, 0), (0, 2), (2, 2)] {
+            ppu.vram[row * 32 + column] = 1;
+        }
+        ppu.vram[0x3c0] = 0b11_10_01_00;
+        let mut frame = Frame::new();
+
+        render(&mut ppu, &mapper, &mut frame);
+
+        assert_eq!(frame.get_pixel(0, 0), SYSTEM_PALETTE[0x01]);
+        assert_eq!(frame.get_pixel(16, 0), SYSTEM_PALETTE[0x04]);
+        assert_eq!(frame.get_pixel(0, 16), SYSTEM_PALETTE[0x07]);
+        assert_eq!(frame.get_pixel(16, 16), SYSTEM_PALETTE[0x0a]);
+    }
+
+    #[test]
+    fn test_hidden_background_shows_the_backdrop() {
+        let mut ppu = shown_ppu();
+        let mut mapper = chr_ram_nrom();
+        draw_tile(&mut mapper);
+        palettes(&mut ppu);
+        ppu.vram[0] = 1;
+        ppu.vram[1] = 1;
+        ppu.write_to_mask(0b0001_0100);
+        let mut frame = Frame::new();
+
+        render(&mut ppu, &mapper, &mut frame);
+        assert_eq!(frame.get_pixel(0, 0), SYSTEM_PALETTE[0x0f]);
+        assert_eq!(frame.get_pixel(8, 0), SYSTEM_PALETTE[0x0f]);
+
+        // on, but clipped out of the leftmost 8 pixels
+        ppu.write_to_mask(0b0000_1000);
+        render(&mut ppu, &mapper, &mut frame);
+        assert_eq!(frame.get_pixel(0, 0), SYSTEM_PALETTE[0x0f]);
+        assert_eq!(frame.get_pixel(8, 0), SYSTEM_PALETTE[0x01]);
+    }
+
+    #[test]
+    fn test_sprites_clipped_on_the_left_edge() {
+        let mut ppu = shown_ppu();
+        let mut mapper = chr_ram_nrom();
+        draw_corner_tile(&mut mapper);
+        sprite_palettes(&mut ppu);
+        hide_all_sprites(&mut ppu);
+        put_sprite(&mut ppu, 0, 0, 19, 2, 0);
+        put_sprite(&mut ppu, 1, 8, 19, 2, 0);
+        ppu.write_to_mask(0b0001_1010);
+        let mut frame = Frame::new();
+
+        render(&mut ppu, &mapper, &mut frame);
+
+        assert_eq!(frame.get_pixel(0, 20), SYSTEM_PALETTE[0x00]);
+        assert_eq!(frame.get_pixel(8, 20), SYSTEM_PALETTE[0x11]);
+    }
+
+    #[test]
+    fn test_universal_background_colour() {
+        let mut ppu = shown_ppu();
+        let mut mapper = chr_ram_nrom();
+        draw_tile(&mut mapper);
+        palettes(&mut ppu);
+        // colour 0 of palette 3 is set, but must not be used
+        ppu.palette_table[12] = 0x30;
+        ppu.vram[0] = 1;
+        ppu.vram[0x3c0] = 0b11;
+        let mut frame = Frame::new();
+
+        render(&mut ppu, &mapper, &mut frame);
+
+        assert_eq!(frame.get_pixel(0, 1), SYSTEM_PALETTE[0x0f]);
+    }
+
+    fn sprite_palettes(ppu: &mut NesPPU) {
+        for (i, colour) in (0x11..=0x1c).enumerate() {
+            ppu.palette_table[0x11 + i / 3 * 4 + i % 3] = colour;
+        }
+    }
+
+    // tile 2: a single opaque pixel of colour 1 in the top left corner
+    fn draw_corner_tile(mapper: &mut Nrom) {
+        mapper.ppu_write(2 * 16, 0b1000_0000);
+    }
+
+    fn put_sprite(ppu: &mut NesPPU, index: usize, x: u8, y: u8, tile: u8, attributes: u8) {
+        ppu.oam_data[index * 4..index * 4 + 4].copy_from_slice(&[y, tile, attributes, x]);
+    }
+
+    fn hide_all_sprites(ppu: &mut NesPPU) {
+        for sprite in ppu.oam_data.chunks_mut(4) {
+            sprite[0] = 0xff;
+        }
+    }
+
+    #[test]
+    fn test_sprite_is_drawn_a_line_below_its_y() {
+        let mut ppu = shown_ppu();
+        let mut mapper = chr_ram_nrom();
+        draw_corner_tile(&mut mapper);
+        sprite_palettes(&mut ppu);
+        hide_all_sprites(&mut ppu);
+        put_sprite(&mut ppu, 0, 40, 19, 2, 0b01);
+        let mut frame = Frame::new();
+
+        render(&mut ppu, &mapper, &mut frame);
+
+        assert_eq!(frame.get_pixel(40, 20), SYSTEM_PALETTE[0x14]);
+        assert_eq!(frame.get_pixel(41, 20), SYSTEM_PALETTE[0x00]);
+    }
+
+    #[test]
+    fn test_flipped_sprite() {
+        let mut ppu = shown_ppu();
+        let mut mapper = chr_ram_nrom();
+        draw_corner_tile(&mut mapper);
+        sprite_palettes(&mut ppu);
+        hide_all_sprites(&mut ppu);
+        put_sprite(&mut ppu, 0, 40, 19, 2, 0b0100_0000);
+        put_sprite(&mut ppu, 1, 80, 19, 2, 0b1000_0000);
+        put_sprite(&mut ppu, 2, 120, 19, 2, 0b1100_0000);
+        let mut frame = Frame::new();
+
+        render(&mut ppu, &mapper, &mut frame);
+
+        let colour = SYSTEM_PALETTE[0x11];
+        // horizontal: top right
+        assert_eq!(frame.get_pixel(47, 20), colour);
+        assert_ne!(frame.get_pixel(40, 20), colour);
+        // vertical: bottom left
+        assert_eq!(frame.get_pixel(80, 27), colour);
+        assert_ne!(frame.get_pixel(80, 20), colour);
+        // both: bottom right
+        assert_eq!(frame.get_pixel(127, 27), colour);
+    }
+
+    #[test]
+    fn test_8x16_sprite_uses_two_tiles() {
+        let mut ppu = shown_ppu();
+        let mut mapper = chr_ram_nrom();
+        draw_corner_tile(&mut mapper);
+        // tile 3 has its corner pixel in colour 2
+        mapper.ppu_write(3 * 16 + 8, 0b1000_0000);
+        sprite_palettes(&mut ppu);
+        hide_all_sprites(&mut ppu);
+        ppu.write_to_ctrl(0b0010_0000);
+        put_sprite(&mut ppu, 0, 40, 19, 2, 0);
+        let mut frame = Frame::new();
+
+        render(&mut ppu, &mapper, &mut frame);
+
+        assert_eq!(frame.get_pixel(40, 20), SYSTEM_PALETTE[0x11]);
+        assert_eq!(frame.get_pixel(40, 28), SYSTEM_PALETTE[0x12]);
+    }
+
+    #[test]
+    fn test_sprite_behind_opaque_background() {
+        let mut ppu = shown_ppu();
+        let mut mapper = chr_ram_nrom();
+        draw_tile(&mut mapper);
+        mapper.ppu_write(2 * 16, 0b1100_0000);
+        palettes(&mut ppu);
+        sprite_palettes(&mut ppu);
+        hide_all_sprites(&mut ppu);
+        // background tile 1 at the top left, its row 1 is transparent
+        ppu.vram[0] = 1;
+        put_sprite(&mut ppu, 0, 0, 0xff, 2, 0b0010_0000);
+        put_sprite(&mut ppu, 1, 0, 0, 2, 0b0010_0000);
+        let mut frame = Frame::new();
+
+        render(&mut ppu, &mapper, &mut frame);
+
+        // no sprite ends up on line 0, y 0xff wraps below the screen
+        assert_eq!(frame.get_pixel(0, 0), SYSTEM_PALETTE[0x01]);
+        // line 1 of the background is transparent, so the sprite shows
+        assert_eq!(frame.get_pixel(0, 1), SYSTEM_PALETTE[0x11]);
+
+        // move the sprite up under the opaque top row
+        ppu.vram[32] = 1;
+        put_sprite(&mut ppu, 1, 0, 7, 2, 0b0010_0000);
+        render(&mut ppu, &mapper, &mut frame);
+        assert_eq!(frame.get_pixel(0, 8), SYSTEM_PALETTE[0x01]);
+        assert_eq!(frame.get_pixel(1, 8), SYSTEM_PALETTE[0x02]);
+    }
+
+    #[test]
+    fn test_ninth_sprite_on_a_line_is_dropped() {
+        let mut ppu = shown_ppu();
+        let mut mapper = chr_ram_nrom();
+        draw_corner_tile(&mut mapper);
+        sprite_palettes(&mut ppu);
+        hide_all_sprites(&mut ppu);
+        for i in 0..9 {
+            put_sprite(&mut ppu, i, i as u8 * 10, 19, 2, 0);
+        }
+        let mut frame = Frame::new();
+
+        render(&mut ppu, &mapper, &mut frame);
+
+        assert_eq!(frame.get_pixel(70, 20), SYSTEM_PALETTE[0x11]);
+        assert_ne!(frame.get_pixel(80, 20), SYSTEM_PALETTE[0x11]);
+        assert!(ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+    }
+
+    #[test]
+    fn test_lower_oam_index_wins() {
+        let mut ppu = shown_ppu();
+        let mut mapper = chr_ram_nrom();
+        draw_corner_tile(&mut mapper);
+        sprite_palettes(&mut ppu);
+        hide_all_sprites(&mut ppu);
+        put_sprite(&mut ppu, 0, 40, 19, 2, 0b01);
+        put_sprite(&mut ppu, 1, 40, 19, 2, 0b10);
+        let mut frame = Frame::new();
+
+        render(&mut ppu, &mapper, &mut frame);
+
+        assert_eq!(frame.get_pixel(40, 20), SYSTEM_PALETTE[0x14]);
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+    }
+
+    #[test]
+    fn test_fine_x_scroll() {
+        let mut ppu = shown_ppu();
+        let mut mapper = chr_ram_nrom();
+        draw_tile(&mut mapper);
+        palettes(&mut ppu);
+        ppu.vram[0] = 1;
+        ppu.vram[1] = 1;
+        ppu.write_to_scroll(4);
+        ppu.write_to_scroll(0);
+        let mut frame = Frame::new();
+
+        render(&mut ppu, &mapper, &mut frame);
+
+        // the screen starts 4 pixels into the first tile
+        assert_eq!(frame.get_pixel(0, 0), SYSTEM_PALETTE[0x03]);
+        assert_eq!(frame.get_pixel(3, 0), SYSTEM_PALETTE[0x03]);
+        assert_eq!(frame.get_pixel(4, 0), SYSTEM_PALETTE[0x01]);
+        assert_eq!(frame.get_pixel(5, 0), SYSTEM_PALETTE[0x02]);
+    }
+
+    #[test]
+    fn test_coarse_x_wraps_into_next_nametable() {
+        let mut ppu = shown_ppu();
+        // vertical mirroring, so 0x2400 is the second nametable
+        let mut mapper = chr_ram_nrom();
+        draw_tile(&mut mapper);
+        palettes(&mut ppu);
+        ppu.vram[0] = 1;
+        ppu.vram[0x400] = 1;
+        ppu.vram[0x400 + 0x3c0] = 0b01;
+        ppu.write_to_scroll(31 * 8);
+        ppu.write_to_scroll(0);
+        let mut frame = Frame::new();
+
+        render(&mut ppu, &mapper, &mut frame);
+
+        // column 31 of the first nametable, then column 0 of the second with its own attributes
+        assert_eq!(frame.get_pixel(0, 0), SYSTEM_PALETTE[0x0f]);
+        assert_eq!(frame.get_pixel(8, 0), SYSTEM_PALETTE[0x04]);
+        assert_eq!(frame.get_pixel(9, 0), SYSTEM_PALETTE[0x05]);
+    }
+
+    #[test]
+    fn test_red_emphasis_darkens_green_and_blue() {
+        let mut ppu = shown_ppu();
+        let mut mapper = chr_ram_nrom();
+        draw_tile(&mut mapper);
+        ppu.palette_table[0] = 0x30;
+        let mut frame = Frame::new();
+
+        render(&mut ppu, &mapper, &mut frame);
+        let (r, g, b) = frame.get_pixel(0, 0);
+        ppu.write_to_mask(0b0011_1110);
+        render(&mut ppu, &mapper, &mut frame);
+        let (emphasised_r, emphasised_g, emphasised_b) = frame.get_pixel(0, 0);
+
+        assert_eq!(emphasised_r, r);
+        assert!(emphasised_g < g);
+        assert!(emphasised_b < b);
+    }
+
+    #[test]
+    fn test_custom_palette() {
+        let mut ppu = shown_ppu();
+        let mapper = chr_ram_nrom();
+        let mut custom = [(0, 0, 0); 64];
+        custom[0x21] = (1, 2, 3);
+        ppu.set_palette(&custom);
+        ppu.palette_table[0] = 0x21;
+        let mut frame = Frame::new();
+
+        render(&mut ppu, &mapper, &mut frame);
+
+        assert_eq!(frame.get_pixel(0, 0), (1, 2, 3));
+    }
+}
@@ -0,0 +1,93 @@
+use crate::ppu::registers::MaskRegister;
+
+// the 2C02's 64 colours as rgb
+#[rustfmt::skip]
+pub static SYSTEM_PALETTE: [(u8, u8, u8); 64] = [
+   (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96), (0xA1, 0x00, 0x5E),
+   (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00), (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00),
+   (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E), (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05),
+   (0x05, 0x05, 0x05), (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
+   (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00), (0xC4, 0x62, 0x00),
+   (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55), (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21),
+   (0x09, 0x09, 0x09), (0x09, 0x09, 0x09), (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF),
+   (0xD4, 0x80, 0xFF), (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
+   (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4), (0x05, 0xFB, 0xFF),
+   (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D), (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF),
+   (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB), (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0),
+   (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
+   (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
+];
+
+// emphasised channels stay as they are and the other two get darker, by about this much
+const EMPHASIS_NUMERATOR: u16 = 13;
+const EMPHASIS_DENOMINATOR: u16 = 16;
+
+// the rgb for palette ram `index` out of `palette`, after what PPUMASK does to it
+pub fn colour(palette: &[(u8, u8, u8); 64], index: u8, mask: MaskRegister) -> (u8, u8, u8) {
+    // greyscale keeps only the brightness column of the palette
+    let index = if mask.contains(MaskRegister::GREYSCALE) {
+        index & 0x30
+    } else {
+        index & 0x3f
+    };
+    let (r, g, b) = palette[index as usize];
+
+    let emphasis = [
+        mask.contains(MaskRegister::EMPHASISE_RED),
+        mask.contains(MaskRegister::EMPHASISE_GREEN),
+        mask.contains(MaskRegister::EMPHASISE_BLUE),
+    ];
+    if !emphasis.contains(&true) {
+        return (r, g, b);
+    }
+
+    // a channel is darkened once for each emphasis bit that isn't its own
+    let attenuate = |value: u8, own: usize| {
+        let others = (0..3).filter(|&i| i != own && emphasis[i]).count() as u32;
+        let mut value = value as u16;
+        for _ in 0..others {
+            value = value * EMPHASIS_NUMERATOR / EMPHASIS_DENOMINATOR;
+        }
+        value as u8
+    };
+    (attenuate(r, 0), attenuate(g, 1), attenuate(b, 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_colours() {
+        let mask = MaskRegister::empty();
+
+        assert_eq!(colour(&SYSTEM_PALETTE, 0x00, mask), (0x80, 0x80, 0x80));
+        assert_eq!(colour(&SYSTEM_PALETTE, 0x0d, mask), (0x00, 0x00, 0x00));
+        assert_eq!(colour(&SYSTEM_PALETTE, 0x16, mask), (0xff, 0x22, 0x00));
+        assert_eq!(colour(&SYSTEM_PALETTE, 0x30, mask), (0xff, 0xff, 0xff));
+    }
+
+    #[test]
+    fn test_greyscale_keeps_the_brightness_column() {
+        let mask = MaskRegister::GREYSCALE;
+
+        assert_eq!(
+            colour(&SYSTEM_PALETTE, 0x16, mask),
+            colour(&SYSTEM_PALETTE, 0x10, MaskRegister::empty())
+        );
+        assert_eq!(
+            colour(&SYSTEM_PALETTE, 0x36, mask),
+            colour(&SYSTEM_PALETTE, 0x30, MaskRegister::empty())
+        );
+    }
+
+    #[test]
+    fn test_every_emphasis_bit_darkens_everything() {
+        let mask = MaskRegister::EMPHASISE_RED
+            | MaskRegister::EMPHASISE_GREEN
+            | MaskRegister::EMPHASISE_BLUE;
+        let (r, g, b) = colour(&SYSTEM_PALETTE, 0x30, mask);
+
+        assert!(r < 0xff && r == g && g == b);
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::VecDeque;
+
+// the last few seconds of save states, for Emulator::rewind. one frame's state is nearly the
+// same as the one before it, so only the newest is kept whole and every older one is stored
+// as the difference to the state after it. dropping the oldest never touches the others
+pub struct RewindBuffer {
+    // oldest first, with the frame each was taken at
+    deltas: VecDeque<(u64, Vec<u8>)>,
+    newest: Option<(u64, Vec<u8>)>,
+    capacity: usize,
+}
+
+impl RewindBuffer {
+    pub fn new(capacity: usize) -> Self {
+        RewindBuffer {
+            deltas: VecDeque::new(),
+            newest: None,
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // drops the oldest states when there are more than `capacity`
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.trim();
+    }
+
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+        self.newest = None;
+    }
+
+    // bytes held for states, not counting what the allocator rounds up
+    pub fn memory_usage(&self) -> usize {
+        let deltas: usize = self.deltas.iter().map(|(_, delta)| delta.len()).sum();
+        deltas + self.newest.as_ref().map_or(0, |(_, state)| state.len())
+    }
+
+    pub fn push(&mut self, frame: u64, state: Vec<u8>) {
+        if let Some((newest_frame, newest)) = self.newest.take() {
+            self.deltas
+                .push_back((newest_frame, delta::encode(&newest, &state)));
+        }
+        self.newest = Some((frame, state));
+        self.trim();
+    }
+
+    // the newest state taken at or before `frame`, and everything newer is thrown away. None
+    // leaves the buffer as it was
+    pub fn rewind_to(&mut self, frame: u64) -> Option<(u64, Vec<u8>)> {
+        let (newest_frame, newest) = self.newest.as_ref()?;
+        if *newest_frame <= frame {
+            return Some((*newest_frame, newest.clone()));
+        }
+        if self
+            .deltas
+            .front()
+            .is_none_or(|(oldest, _)| *oldest > frame)
+        {
+            return None;
+        }
+
+        let (_, mut state) = self.newest.take().unwrap();
+        while let Some((taken, delta)) = self.deltas.pop_back() {
+            state = delta::decode(&state, &delta);
+            if taken <= frame {
+                self.newest = Some((taken, state.clone()));
+                return Some((taken, state));
+            }
+        }
+        unreachable!("the oldest state is at or before the frame")
+    }
+
+    fn trim(&mut self) {
+        while self.len() > self.capacity {
+            if self.deltas.pop_front().is_none() {
+                self.newest = None;
+            }
+        }
+    }
+}
+
+// a state as the bytes that differ from another state: runs of unchanged bytes are skipped
+// and the changed ones are stored xored with the other state's, which are mostly zero.
+// states can differ in length now and then, e.g. an Option that's become Some
+mod delta {
+    // target length, then (unchanged run, changed run, changed bytes) until the end, every
+    // number a LEB128 varint
+    pub fn encode(target: &[u8], base: &[u8]) -> Vec<u8> {
+        let mut delta = vec![];
+        write_varint(&mut delta, target.len());
+
+        let xor = |i: usize| target[i] ^ base.get(i).copied().unwrap_or(0);
+        let mut i = 0;
+        while i < target.len() {
+            let unchanged_start = i;
+            while i < target.len() && xor(i) == 0 {
+                i += 1;
+            }
+            let changed_start = i;
+            while i < target.len() && xor(i) != 0 {
+                i += 1;
+            }
+            write_varint(&mut delta, changed_start - unchanged_start);
+            write_varint(&mut delta, i - changed_start);
+            delta.extend((changed_start..i).map(xor));
+        }
+        delta
+    }
+
+    pub fn decode(base: &[u8], delta: &[u8]) -> Vec<u8> {
+        let mut pos = 0;
+        let len = read_varint(delta, &mut pos);
+        let mut target: Vec<u8> = (0..len)
+            .map(|i| base.get(i).copied().unwrap_or(0))
+            .collect();
+
+        let mut i = 0;
+        while pos < delta.len() {
+            i += read_varint(delta, &mut pos);
+            let changed = read_varint(delta, &mut pos);
+            for byte in &delta[pos..pos + changed] {
+                target[i] ^= byte;
+                i += 1;
+            }
+            pos += changed;
+        }
+        target
+    }
+
+    fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = data[*pos];
+            *pos += 1;
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_round_trips() {
+        let base: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let mut target = base.clone();
+        target[0] = 0xff;
+        target[500..503].copy_from_slice(&[1, 2, 3]);
+        target.push(0x42);
+
+        let encoded = delta::encode(&target, &base);
+        assert!(encoded.len() < 20);
+        assert_eq!(delta::decode(&base, &encoded), target);
+
+        let shorter = &base[..10];
+        assert_eq!(
+            delta::decode(&base, &delta::encode(shorter, &base)),
+            shorter
+        );
+    }
+
+    #[test]
+    fn test_rewind_to_the_nearest_older_state() {
+        let mut buffer = RewindBuffer::new(10);
+        for frame in [2, 4, 6, 8] {
+            buffer.push(frame, vec![frame as u8; 100]);
+        }
+
+        assert_eq!(buffer.rewind_to(5), Some((4, vec![4; 100])));
+        // 6 and 8 are gone, 4 is the newest and whole again
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.rewind_to(100), Some((4, vec![4; 100])));
+        assert_eq!(buffer.rewind_to(1), None);
+        assert_eq!(buffer.len(), 2);
+    }
+
+    #[test]
+    fn test_capacity_drops_the_oldest() {
+        let mut buffer = RewindBuffer::new(3);
+        for frame in 0..5 {
+            buffer.push(frame, vec![frame as u8; 100]);
+        }
+
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.rewind_to(1), None);
+        assert_eq!(buffer.rewind_to(2), Some((2, vec![2; 100])));
+
+        buffer.set_capacity(0);
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.memory_usage(), 0);
+    }
+}
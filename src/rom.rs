@@ -0,0 +1,438 @@
+use crate::apu::output::{CPU_FREQUENCY, PAL_CPU_FREQUENCY};
+
+const NES_TAG: [u8; 4] = [0x4e, 0x45, 0x53, 0x1a];
+const PRG_ROM_PAGE_SIZE: usize = 0x4000;
+const CHR_ROM_PAGE_SIZE: usize = 0x2000;
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+    // never in a header, only mappers that switch mirroring at runtime use these
+    SingleScreenLower,
+    SingleScreenUpper,
+}
+
+// which console the game was made for. pal consoles run the cpu slower, give the ppu 3.2 dots
+// a cpu cycle rather than 3 and 312 lines a frame rather than 262, and have their own apu
+// timings, see https://www.nesdev.org/wiki/Cycle_reference_chart
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub enum TvSystem {
+    #[default]
+    Ntsc,
+    Pal,
+}
+
+impl TvSystem {
+    pub fn cpu_frequency(self) -> u32 {
+        match self {
+            TvSystem::Ntsc => CPU_FREQUENCY,
+            TvSystem::Pal => PAL_CPU_FREQUENCY,
+        }
+    }
+
+    // ppu dots for every cpu cycle, as a fraction
+    pub fn ppu_ratio(self) -> (u32, u32) {
+        match self {
+            TvSystem::Ntsc => (3, 1),
+            TvSystem::Pal => (16, 5),
+        }
+    }
+
+    // including the pre-render line
+    pub fn scanlines(self) -> u16 {
+        match self {
+            TvSystem::Ntsc => 262,
+            TvSystem::Pal => 312,
+        }
+    }
+
+    // 60.098 and 50.007, a frame is 341 dots a line. ntsc's odd frames are a dot short on
+    // hardware, which isn't emulated
+    pub fn frames_per_second(self) -> f64 {
+        let (dots, cycles) = self.ppu_ratio();
+        let dots_per_second = self.cpu_frequency() as f64 * dots as f64 / cycles as f64;
+        dots_per_second / (341.0 * self.scanlines() as f64)
+    }
+
+    // hardly any dumps set the header bit, so the region tags goodnes and no-intro put in
+    // file names are looked at too. anything that doesn't say otherwise is ntsc
+    pub fn detect(rom: &Rom, filename: &str) -> TvSystem {
+        if rom.tv_system == TvSystem::Pal {
+            return TvSystem::Pal;
+        }
+        const PAL_TAGS: [&str; 5] = ["(e)", "(europe)", "(pal)", "(eu)", "(a)"];
+        let filename = filename.to_ascii_lowercase();
+        if PAL_TAGS.iter().any(|tag| filename.contains(tag)) {
+            TvSystem::Pal
+        } else {
+            TvSystem::Ntsc
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rom {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub mapper: u8,
+    pub screen_mirroring: Mirroring,
+    // the prg ram at 0x6000 is kept alive by a battery, so it's the game's save data
+    pub battery: bool,
+    // bit 0 of byte 9, which is all an iNES 1.0 header has to say about it
+    pub tv_system: TvSystem,
+}
+
+impl Rom {
+    // parses an iNES 1.0 file, see https://www.nesdev.org/wiki/INES
+    pub fn new(raw: &[u8]) -> Result<Rom, String> {
+        if raw.len() < HEADER_SIZE || raw[0..4] != NES_TAG {
+            return Err("File is not in iNES file format".to_string());
+        }
+
+        // bits 2-3 of control byte 2 are 0b10 on NES 2.0 headers, which lay out the rest
+        // differently. anything else but 0b00 is an archaic header, or one a dumping tool wrote
+        // its name over the end of, and only byte 6 can be trusted
+        let (control_2, flags_10) = match (raw[7] >> 2) & 0b11 {
+            0b00 => (raw[7], raw[9]),
+            0b10 => return Err("NES2.0 format is not supported".to_string()),
+            _ => (0, 0),
+        };
+
+        // the mapper number is split across the high nibbles of both control bytes
+        let mapper = (control_2 & 0b1111_0000) | (raw[6] >> 4);
+
+        let four_screen = raw[6] & 0b1000 != 0;
+        let vertical_mirroring = raw[6] & 0b1 != 0;
+        let screen_mirroring = match (four_screen, vertical_mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+
+        let battery = raw[6] & 0b10 != 0;
+
+        let tv_system = if flags_10 & 1 != 0 {
+            TvSystem::Pal
+        } else {
+            TvSystem::Ntsc
+        };
+
+        // every board needs somewhere to find the reset vector
+        if raw[4] == 0 {
+            return Err("ROM has no PRG ROM".to_string());
+        }
+        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+
+        // a 512 byte trainer may sit between the header and prg rom, nothing uses it so skip it
+        let skip_trainer = raw[6] & 0b100 != 0;
+
+        let prg_rom_start = HEADER_SIZE + if skip_trainer { TRAINER_SIZE } else { 0 };
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+
+        if raw.len() < chr_rom_start + chr_rom_size {
+            return Err("File is shorter than its header says".to_string());
+        }
+
+        Ok(Rom {
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
+            mapper,
+            screen_mirroring,
+            battery,
+            tv_system,
+        })
+    }
+
+    // crc32 of the prg and chr rom together, which is what cartridge databases know games by
+    pub fn crc32(&self) -> u32 {
+        let mut crc = !0u32;
+        for &byte in self.prg_rom.iter().chain(&self.chr_rom) {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub struct TestRom {
+        pub header: Vec<u8>,
+        pub trainer: Option<Vec<u8>>,
+        pub prg_rom: Vec<u8>,
+        pub chr_rom: Vec<u8>,
+    }
+
+    pub fn create_rom(rom: TestRom) -> Vec<u8> {
+        let mut result = Vec::with_capacity(
+            rom.header.len()
+                + rom.trainer.as_ref().map_or(0, |t| t.len())
+                + rom.prg_rom.len()
+                + rom.chr_rom.len(),
+        );
+
+        result.extend(&rom.header);
+        if let Some(t) = rom.trainer {
+            result.extend(t);
+        }
+        result.extend(&rom.prg_rom);
+        result.extend(&rom.chr_rom);
+
+        result
+    }
+
+    // a header for `prg_banks` 16KB banks and `chr_banks` 8KB banks with both control bytes given
+    pub fn header(prg_banks: u8, chr_banks: u8, control_1: u8, control_2: u8) -> Vec<u8> {
+        let mut header = NES_TAG.to_vec();
+        header.extend([prg_banks, chr_banks, control_1, control_2]);
+        header.resize(HEADER_SIZE, 0);
+        header
+    }
+
+    pub fn test_rom(prg_rom: Vec<u8>) -> Rom {
+        let prg_banks = (prg_rom.len() / PRG_ROM_PAGE_SIZE) as u8;
+        let test_rom = create_rom(TestRom {
+            header: header(prg_banks, 1, 0x01, 0x00),
+            trainer: None,
+            prg_rom,
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        Rom::new(&test_rom).unwrap()
+    }
+
+    // a cartridge that didn't come from a file, so the roms can be any size
+    pub fn bare_rom(mapper: u8, prg_len: usize, chr_len: usize) -> Rom {
+        Rom {
+            prg_rom: vec![0; prg_len],
+            chr_rom: vec![0; chr_len],
+            mapper,
+            screen_mirroring: Mirroring::Horizontal,
+            battery: false,
+            tv_system: TvSystem::Ntsc,
+        }
+    }
+
+    #[test]
+    fn test_two_prg_banks() {
+        let test_rom = create_rom(TestRom {
+            header: header(2, 1, 0x31, 0x00),
+            trainer: None,
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&test_rom).unwrap();
+
+        assert_eq!(rom.chr_rom, vec!(2; CHR_ROM_PAGE_SIZE));
+        assert_eq!(rom.prg_rom, vec!(1; 2 * PRG_ROM_PAGE_SIZE));
+        assert_eq!(rom.mapper, 3);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_with_trainer() {
+        let test_rom = create_rom(TestRom {
+            header: header(2, 1, 0x31 | 0b100, 0x00),
+            trainer: Some(vec![0; TRAINER_SIZE]),
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&test_rom).unwrap();
+
+        assert_eq!(rom.chr_rom, vec!(2; CHR_ROM_PAGE_SIZE));
+        assert_eq!(rom.prg_rom, vec!(1; 2 * PRG_ROM_PAGE_SIZE));
+        assert_eq!(rom.mapper, 3);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_single_prg_bank() {
+        let test_rom = create_rom(TestRom {
+            header: header(1, 1, 0x00, 0x00),
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&test_rom).unwrap();
+
+        assert_eq!(rom.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_mapper_uses_both_control_bytes() {
+        let test_rom = create_rom(TestRom {
+            header: header(1, 1, 0x10, 0x40),
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&test_rom).unwrap();
+
+        assert_eq!(rom.mapper, 0x41);
+        assert_eq!(rom.screen_mirroring, Mirroring::Horizontal);
+        assert!(!rom.battery);
+    }
+
+    #[test]
+    fn test_battery() {
+        let test_rom = create_rom(TestRom {
+            header: header(1, 1, 0b10, 0x00),
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&test_rom).unwrap();
+
+        assert!(rom.battery);
+    }
+
+    #[test]
+    fn test_four_screen_wins_over_vertical() {
+        let test_rom = create_rom(TestRom {
+            header: header(1, 1, 0b1001, 0x00),
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&test_rom).unwrap();
+
+        assert_eq!(rom.screen_mirroring, Mirroring::FourScreen);
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        let mut test_rom = create_rom(TestRom {
+            header: header(1, 1, 0x00, 0x00),
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        test_rom[3] = 0x00;
+
+        assert!(Rom::new(&test_rom).is_err());
+    }
+
+    #[test]
+    fn test_nes2_is_not_supported() {
+        let test_rom = create_rom(TestRom {
+            header: header(1, 1, 0x31, 0x08),
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        match Rom::new(&test_rom) {
+            Ok(_) => panic!("should not load NES2.0 files"),
+            Err(str) => assert_eq!(str, "NES2.0 format is not supported"),
+        }
+    }
+
+    #[test]
+    fn test_dirty_header_only_trusts_byte_6() {
+        let mut test_rom = create_rom(TestRom {
+            header: header(1, 1, 0x11, 0x44),
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        test_rom[9] = 0x01;
+
+        let rom = Rom::new(&test_rom).unwrap();
+        assert_eq!(rom.mapper, 1);
+        assert_eq!(rom.tv_system, TvSystem::Ntsc);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_no_prg_rom() {
+        let test_rom = create_rom(TestRom {
+            header: header(0, 1, 0x00, 0x00),
+            trainer: None,
+            prg_rom: vec![],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        assert_eq!(Rom::new(&test_rom).unwrap_err(), "ROM has no PRG ROM");
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let test_rom = create_rom(TestRom {
+            header: header(2, 1, 0x00, 0x00),
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+
+        assert!(Rom::new(&test_rom).is_err());
+    }
+
+    #[test]
+    fn test_crc32_covers_prg_and_chr() {
+        let rom = Rom {
+            prg_rom: b"12345".to_vec(),
+            chr_rom: b"6789".to_vec(),
+            mapper: 0,
+            screen_mirroring: Mirroring::Horizontal,
+            battery: false,
+            tv_system: TvSystem::Ntsc,
+        };
+
+        // the standard check value for crc32 of "123456789"
+        assert_eq!(rom.crc32(), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_tv_system() {
+        let mut raw = create_rom(TestRom {
+            header: header(1, 1, 0x00, 0x00),
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        let ntsc = Rom::new(&raw).unwrap();
+        assert_eq!(ntsc.tv_system, TvSystem::Ntsc);
+        raw[9] = 0x01;
+        let pal = Rom::new(&raw).unwrap();
+        assert_eq!(pal.tv_system, TvSystem::Pal);
+
+        assert_eq!(TvSystem::detect(&ntsc, "Elite (E).nes"), TvSystem::Pal);
+        assert_eq!(TvSystem::detect(&ntsc, "Elite (Europe).nes"), TvSystem::Pal);
+        assert_eq!(
+            TvSystem::detect(&ntsc, "Super Mario Bros. (W).nes"),
+            TvSystem::Ntsc
+        );
+        assert_eq!(TvSystem::detect(&pal, "game.nes"), TvSystem::Pal);
+
+        assert_eq!(
+            (TvSystem::Ntsc.frames_per_second() * 1000.0).round(),
+            60_098.0
+        );
+        assert_eq!(
+            (TvSystem::Pal.frames_per_second() * 1000.0).round(),
+            50_007.0
+        );
+    }
+}
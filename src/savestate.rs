@@ -0,0 +1,176 @@
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+// every state starts with this, then STATE_VERSION and the rom's crc32, both little endian
+pub const MAGIC: [u8; 4] = *b"NESS";
+// bump whenever anything that's serialized changes shape, old states are refused rather than
+// read back as garbage
+pub const STATE_VERSION: u16 = 5;
+pub const HEADER_SIZE: usize = 10;
+
+#[derive(Debug)]
+pub enum StateError {
+    // too short or the wrong magic, this was never a save state
+    NotAState,
+    UnsupportedVersion { expected: u16, actual: u16 },
+    // the state was saved with a different cartridge in
+    WrongRom { expected: u32, actual: u32 },
+    Corrupt(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "Data is not a save state"),
+            StateError::UnsupportedVersion { expected, actual } => write!(
+                f,
+                "Save state is version {} but this build reads version {}",
+                actual, expected
+            ),
+            StateError::WrongRom { expected, actual } => write!(
+                f,
+                "Save state is for ROM {:08x} but ROM {:08x} is loaded",
+                actual, expected
+            ),
+            StateError::Corrupt(e) => write!(f, "Save state is corrupt: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<bincode::Error> for StateError {
+    fn from(e: bincode::Error) -> Self {
+        StateError::Corrupt(e.to_string())
+    }
+}
+
+pub fn header(rom_crc: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    header.extend(MAGIC);
+    header.extend(STATE_VERSION.to_le_bytes());
+    header.extend(rom_crc.to_le_bytes());
+    header
+}
+
+// everything after the header, once it's been checked against the rom that's loaded
+pub fn check_header(state: &[u8], rom_crc: u32) -> Result<&[u8], StateError> {
+    if state.len() < HEADER_SIZE || state[0..4] != MAGIC {
+        return Err(StateError::NotAState);
+    }
+    let version = u16::from_le_bytes([state[4], state[5]]);
+    if version != STATE_VERSION {
+        return Err(StateError::UnsupportedVersion {
+            expected: STATE_VERSION,
+            actual: version,
+        });
+    }
+    let crc = u32::from_le_bytes([state[6], state[7], state[8], state[9]]);
+    if crc != rom_crc {
+        return Err(StateError::WrongRom {
+            expected: rom_crc,
+            actual: crc,
+        });
+    }
+    Ok(&state[HEADER_SIZE..])
+}
+
+pub fn encode<T: Serialize + ?Sized>(value: &T) -> Vec<u8> {
+    // only fails for types serde can't describe, and everything in here derives it
+    bincode::serialize(value).expect("save state should serialize")
+}
+
+pub fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, StateError> {
+    Ok(bincode::deserialize(data)?)
+}
+
+// serde stops at 32 element arrays, this does the rest as fixed length tuples so nothing
+// goes in for the length. use with #[serde(with = "crate::savestate::big_array")]
+pub mod big_array {
+    use std::fmt;
+    use std::marker::PhantomData;
+
+    use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+    use serde::ser::{Serialize, SerializeTuple, Serializer};
+
+    pub fn serialize<S, T, const N: usize>(array: &[T; N], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: Serialize,
+    {
+        let mut tuple = serializer.serialize_tuple(N)?;
+        for item in array {
+            tuple.serialize_element(item)?;
+        }
+        tuple.end()
+    }
+
+    pub fn deserialize<'de, D, T, const N: usize>(deserializer: D) -> Result<[T; N], D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de> + Copy + Default,
+    {
+        struct ArrayVisitor<T, const N: usize>(PhantomData<T>);
+
+        impl<'de, T, const N: usize> Visitor<'de> for ArrayVisitor<T, N>
+        where
+            T: Deserialize<'de> + Copy + Default,
+        {
+            type Value = [T; N];
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an array of {} elements", N)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<[T; N], A::Error> {
+                let mut array = [T::default(); N];
+                for (i, slot) in array.iter_mut().enumerate() {
+                    *slot = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+                }
+                Ok(array)
+            }
+        }
+
+        deserializer.deserialize_tuple(N, ArrayVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let mut state = header(0x1234_5678);
+        state.push(0xaa);
+
+        assert_eq!(check_header(&state, 0x1234_5678).unwrap(), &[0xaa]);
+    }
+
+    #[test]
+    fn test_bad_headers() {
+        assert!(matches!(
+            check_header(b"NES\x1a", 0),
+            Err(StateError::NotAState)
+        ));
+
+        let mut state = header(0);
+        state[4] = 0xff;
+        assert!(matches!(
+            check_header(&state, 0),
+            Err(StateError::UnsupportedVersion { .. })
+        ));
+
+        assert!(matches!(
+            check_header(&header(1), 2),
+            Err(StateError::WrongRom {
+                expected: 2,
+                actual: 1
+            })
+        ));
+    }
+}
@@ -0,0 +1,230 @@
+use crate::cpu::{AddressingMode, CPU};
+use crate::disasm;
+use crate::opcode::{Instruction, OpCode};
+
+// the instruction at the program counter and the registers before it runs, laid out like a
+// line of nestest.log so the two can be diffed:
+//
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD
+//
+// only peeks at memory, so tracing never clears vblank or eats a joypad bit
+pub fn trace(cpu: &CPU) -> String {
+    let pc = cpu.program_counter;
+    let code = cpu.bus().peek(pc);
+    // the cpu would stop with an error here, but the trace can still show where
+    let asm = match OpCode::try_from_u8(code) {
+        Some(opcode) => instruction(cpu, opcode),
+        None => format!("{:04x}  {:02x}        .byte ${:02x}", pc, code, code),
+    };
+
+    format!(
+        "{:47} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}",
+        asm.trim_end(),
+        cpu.a,
+        cpu.x,
+        cpu.y,
+        cpu.status.bits(),
+        cpu.sp
+    )
+    .to_ascii_uppercase()
+}
+
+fn instruction(cpu: &CPU, opcode: &OpCode) -> String {
+    let bus = cpu.bus();
+    let pc = cpu.program_counter;
+
+    let bytes: Vec<u8> = (0..opcode.bytes as u16)
+        .map(|i| bus.peek(pc.wrapping_add(i)))
+        .collect();
+    let arg = bytes.get(1).copied().unwrap_or(0);
+    let arg16 = (bytes.get(2).copied().unwrap_or(0) as u16) << 8 | arg as u16;
+
+    // where the operand ends up pointing, on top of how it's written
+    let resolved = match opcode.mode {
+        AddressingMode::ZeroPage => format!(" = {:02x}", bus.peek(arg as u16)),
+        AddressingMode::ZeroPageX => {
+            let addr = arg.wrapping_add(cpu.x);
+            format!(" @ {:02x} = {:02x}", addr, bus.peek(addr as u16))
+        }
+        AddressingMode::ZeroPageY => {
+            let addr = arg.wrapping_add(cpu.y);
+            format!(" @ {:02x} = {:02x}", addr, bus.peek(addr as u16))
+        }
+        // jumps go somewhere rather than read something, so there's no value to show
+        AddressingMode::Absolute
+            if matches!(opcode.instruction, Instruction::Jmp | Instruction::Jsr) =>
+        {
+            String::new()
+        }
+        AddressingMode::Absolute => format!(" = {:02x}", bus.peek(arg16)),
+        AddressingMode::AbsoluteX => {
+            let addr = arg16.wrapping_add(cpu.x as u16);
+            format!(" @ {:04x} = {:02x}", addr, bus.peek(addr))
+        }
+        AddressingMode::AbsoluteY => {
+            let addr = arg16.wrapping_add(cpu.y as u16);
+            format!(" @ {:04x} = {:02x}", addr, bus.peek(addr))
+        }
+        AddressingMode::IndirectX => {
+            let ptr = arg.wrapping_add(cpu.x);
+            let addr = bus.peek_zero_page_u16(ptr);
+            format!(" @ {:02x} = {:04x} = {:02x}", ptr, addr, bus.peek(addr))
+        }
+        AddressingMode::IndirectY => {
+            let base = bus.peek_zero_page_u16(arg);
+            let addr = base.wrapping_add(cpu.y as u16);
+            format!(" = {:04x} @ {:04x} = {:02x}", base, addr, bus.peek(addr))
+        }
+        // same page bug as the cpu
+        AddressingMode::Indirect => {
+            let lo = bus.peek(arg16) as u16;
+            let hi = bus.peek((arg16 & 0xff00) | (arg16.wrapping_add(1) & 0x00ff)) as u16;
+            format!(" = {:04x}", (hi << 8) | lo)
+        }
+        _ => String::new(),
+    };
+    let operand = disasm::format_operand(&opcode.mode, &bytes[1..], pc) + &resolved;
+
+    let hex = bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("{:04x}  {:8} {:>4} {}", pc, hex, opcode.mnemonic, operand)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, Mem};
+    use crate::cpu::StatusFlags;
+    use crate::ppu::registers::StatusRegister;
+
+    // `program` at 0x0064 with the registers nestest starts with
+    fn cpu_with(program: &[u8]) -> CPU {
+        let mut cpu = CPU::new(Bus::new());
+        for (i, byte) in program.iter().enumerate() {
+            cpu.mem_write(0x64 + i as u16, *byte);
+        }
+        cpu.program_counter = 0x64;
+        cpu.status = StatusFlags::from_bits_truncate(0x24);
+        cpu
+    }
+
+    #[test]
+    fn test_immediate_and_implied() {
+        let mut cpu = cpu_with(&[0xa2, 0x01, 0xca, 0x88, 0x00]);
+        cpu.a = 1;
+        cpu.x = 2;
+        cpu.y = 3;
+
+        let mut result = vec![];
+        cpu.run_with_callback(|cpu| result.push(trace(cpu)))
+            .unwrap();
+
+        assert_eq!(
+            result[0],
+            "0064  A2 01     LDX #$01                        A:01 X:02 Y:03 P:24 SP:FD"
+        );
+        assert_eq!(
+            result[1],
+            "0066  CA        DEX                             A:01 X:01 Y:03 P:24 SP:FD"
+        );
+        assert_eq!(
+            result[2],
+            "0067  88        DEY                             A:01 X:00 Y:03 P:26 SP:FD"
+        );
+    }
+
+    #[test]
+    fn test_absolute_indexed() {
+        let mut cpu = cpu_with(&[0xbd, 0xff, 0x03]);
+        cpu.x = 1;
+        cpu.mem_write(0x0400, 0xaa);
+
+        assert_eq!(
+            trace(&cpu),
+            "0064  BD FF 03  LDA $03FF,X @ 0400 = AA         A:00 X:01 Y:00 P:24 SP:FD"
+        );
+    }
+
+    #[test]
+    fn test_indirect_y() {
+        let mut cpu = cpu_with(&[0x11, 0x33]);
+        cpu.mem_write(0x33, 0x00);
+        cpu.mem_write(0x34, 0x04);
+        cpu.mem_write(0x0400, 0xaa);
+
+        assert_eq!(
+            trace(&cpu),
+            "0064  11 33     ORA ($33),Y = 0400 @ 0400 = AA  A:00 X:00 Y:00 P:24 SP:FD"
+        );
+    }
+
+    // nestest has these at 0xff, both pointers wrap round to 0x00 for their high byte
+    #[test]
+    fn test_indirect_pointers_wrap_in_the_zero_page() {
+        let mut cpu = cpu_with(&[0xa1, 0xf0]);
+        cpu.x = 0x0f;
+        cpu.mem_write(0xff, 0x00);
+        cpu.mem_write(0x00, 0x04);
+        cpu.mem_write(0x100, 0x03);
+        cpu.mem_write(0x0400, 0x5d);
+
+        assert_eq!(
+            trace(&cpu),
+            "0064  A1 F0     LDA ($F0,X) @ FF = 0400 = 5D    A:00 X:0F Y:00 P:24 SP:FD"
+        );
+
+        let mut cpu = cpu_with(&[0xb1, 0xff]);
+        cpu.y = 0x01;
+        cpu.mem_write(0xff, 0x00);
+        cpu.mem_write(0x00, 0x04);
+        cpu.mem_write(0x100, 0x03);
+        cpu.mem_write(0x0401, 0x5e);
+
+        assert_eq!(
+            trace(&cpu),
+            "0064  B1 FF     LDA ($FF),Y = 0400 @ 0401 = 5E  A:00 X:00 Y:01 P:24 SP:FD"
+        );
+    }
+
+    #[test]
+    fn test_jumps_show_no_value() {
+        let cpu = cpu_with(&[0x4c, 0xf5, 0xc5]);
+
+        assert_eq!(
+            trace(&cpu),
+            "0064  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD"
+        );
+    }
+
+    #[test]
+    fn test_tracing_ppustatus_keeps_vblank() {
+        let mut cpu = cpu_with(&[0xad, 0x02, 0x20]);
+        cpu.bus_mut()
+            .ppu
+            .status
+            .insert(StatusRegister::VBLANK_STARTED);
+
+        assert_eq!(
+            trace(&cpu),
+            "0064  AD 02 20  LDA $2002 = 80                  A:00 X:00 Y:00 P:24 SP:FD"
+        );
+        assert!(cpu
+            .bus()
+            .ppu
+            .status
+            .contains(StatusRegister::VBLANK_STARTED));
+    }
+
+    #[test]
+    fn test_unknown_opcode() {
+        let cpu = cpu_with(&[0x02]);
+
+        assert_eq!(
+            trace(&cpu),
+            "0064  02        .BYTE $02                       A:00 X:00 Y:00 P:24 SP:FD"
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use wasm_bindgen::prelude::*;
+
+use crate::emulator::Emulator;
+use crate::joypad::JoypadButton;
+use crate::render::frame::Frame;
+use crate::rom::Rom;
+
+// the emulator as javascript sees it. everything that can go wrong comes back as an Err, which
+// wasm-bindgen throws as an exception, rather than a panic that takes the whole module down
+#[wasm_bindgen]
+pub struct WasmEmulator {
+    emulator: Emulator,
+}
+
+#[wasm_bindgen]
+impl WasmEmulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom_bytes: &[u8]) -> Result<WasmEmulator, JsValue> {
+        let emulator = Rom::new(rom_bytes)
+            .and_then(Emulator::new)
+            .map_err(|e| JsValue::from_str(&e))?;
+        Ok(WasmEmulator { emulator })
+    }
+
+    // runs until the ppu finishes the frame it's on
+    pub fn run_frame(&mut self) -> Result<(), JsValue> {
+        self.emulator
+            .run_until_frame()
+            .map(|_| ())
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    // rgba, four bytes a pixel row by row, the layout ImageData wants
+    pub fn frame_buffer(&self) -> Vec<u8> {
+        let frame = &self.emulator.cpu().bus().ppu.frame;
+        let mut rgba = Vec::with_capacity(Frame::WIDTH * Frame::HEIGHT * 4);
+        for rgb in frame.data.chunks_exact(3) {
+            rgba.extend_from_slice(rgb);
+            rgba.push(0xff);
+        }
+        rgba
+    }
+
+    // `button` is a JoypadButton bit, A is 0x01 up to RIGHT at 0x80. more than one bit sets
+    // them all
+    pub fn set_button(&mut self, button: u8, pressed: bool) {
+        let joypad = self.emulator.cpu_mut().bus_mut().joypad1_mut();
+        joypad.set_button_pressed_status(JoypadButton::from_bits_truncate(button), pressed);
+    }
+
+    // the samples made since the last call, mono at 44100Hz unless set_sample_rate says
+    // otherwise
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        let apu = &mut self.emulator.cpu_mut().bus_mut().apu;
+        let mut samples = vec![0.0; apu.samples_available()];
+        let drained = apu.drain_samples(&mut samples);
+        samples.truncate(drained);
+        samples
+    }
+
+    // to match the AudioContext's rate
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.emulator
+            .cpu_mut()
+            .bus_mut()
+            .apu
+            .set_sample_rate(sample_rate);
+    }
+}
@@ -0,0 +1,166 @@
+use std::ops::RangeInclusive;
+
+use crate::breakpoints::StopReason;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    // whether a watchpoint of this kind cares about an access of `access`, which is only ever
+    // Read or Write
+    fn covers(self, access: WatchKind) -> bool {
+        self == WatchKind::ReadWrite || self == access
+    }
+}
+
+// like breakpoints, these are looked at on every access the cpu makes, so with nothing set
+// it's one compare. with some set it's a walk of a short Vec, ranges overlap and nobody sets
+// more than a few by hand so there's nothing to gain from sorting them
+#[derive(Debug, Default)]
+pub struct Watchpoints {
+    ranges: Vec<(RangeInclusive<u16>, WatchKind)>,
+    // off by default, otherwise watching a buffer that code runs out of stops on every fetch
+    watch_opcode_fetches: bool,
+    // where the instruction doing the accesses was fetched from
+    instruction_pc: u16,
+    // the first hit of the instruction that's running, or last ran
+    pending: Option<StopReason>,
+    // every access any of them has caught since they were cleared
+    hits: u64,
+}
+
+impl Watchpoints {
+    pub fn add(&mut self, addrs: RangeInclusive<u16>, kind: WatchKind) {
+        self.ranges.push((addrs, kind));
+    }
+
+    // false when there wasn't one exactly like it
+    pub fn remove(&mut self, addrs: &RangeInclusive<u16>, kind: WatchKind) -> bool {
+        let len = self.ranges.len();
+        self.ranges.retain(|(a, k)| !(a == addrs && *k == kind));
+        self.ranges.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+        self.pending = None;
+        self.hits = 0;
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    // in the order they were added
+    pub fn ranges(&self) -> &[(RangeInclusive<u16>, WatchKind)] {
+        &self.ranges
+    }
+
+    pub fn set_watch_opcode_fetches(&mut self, watch: bool) {
+        self.watch_opcode_fetches = watch;
+    }
+
+    pub fn watch_opcode_fetches(&self) -> bool {
+        self.watch_opcode_fetches
+    }
+
+    // before each instruction. a hit nobody took from the last one is dropped
+    #[inline]
+    pub fn start_instruction(&mut self, pc: u16) {
+        if !self.ranges.is_empty() {
+            self.instruction_pc = pc;
+            self.pending = None;
+        }
+    }
+
+    // moves on to the next instruction without dropping a hit, for after an interrupt
+    #[inline]
+    pub fn set_instruction_pc(&mut self, pc: u16) {
+        self.instruction_pc = pc;
+    }
+
+    // `access` is Read or Write, `value` is what was read or written
+    #[inline]
+    pub fn check(&mut self, addr: u16, access: WatchKind, value: u8) {
+        if !self.ranges.is_empty() {
+            self.check_ranges(addr, access, value);
+        }
+    }
+
+    fn check_ranges(&mut self, addr: u16, access: WatchKind, value: u8) {
+        let hit = self
+            .ranges
+            .iter()
+            .any(|(addrs, kind)| kind.covers(access) && addrs.contains(&addr));
+        if !hit {
+            return;
+        }
+        self.hits += 1;
+        if self.pending.is_none() {
+            self.pending = Some(StopReason::Watchpoint {
+                addr,
+                kind: access,
+                value,
+                pc: self.instruction_pc,
+            });
+        }
+    }
+
+    pub fn take_pending(&mut self) -> Option<StopReason> {
+        self.pending.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_hit_of_an_instruction_is_kept() {
+        let mut watchpoints = Watchpoints::default();
+        watchpoints.add(0x0200..=0x02ff, WatchKind::Write);
+        watchpoints.start_instruction(0x8000);
+
+        watchpoints.check(0x0210, WatchKind::Read, 1);
+        watchpoints.check(0x0300, WatchKind::Write, 2);
+        assert_eq!(watchpoints.take_pending(), None);
+
+        watchpoints.check(0x0210, WatchKind::Write, 3);
+        watchpoints.check(0x0211, WatchKind::Write, 4);
+        assert_eq!(
+            watchpoints.take_pending(),
+            Some(StopReason::Watchpoint {
+                addr: 0x0210,
+                kind: WatchKind::Write,
+                value: 3,
+                pc: 0x8000,
+            })
+        );
+        assert_eq!(watchpoints.take_pending(), None);
+        // the ones after the first still count
+        assert_eq!(watchpoints.hits(), 2);
+    }
+
+    #[test]
+    fn test_read_write_covers_both() {
+        let mut watchpoints = Watchpoints::default();
+        watchpoints.add(0x00fa..=0x00fa, WatchKind::ReadWrite);
+
+        watchpoints.start_instruction(0x8000);
+        watchpoints.check(0x00fa, WatchKind::Read, 0);
+        assert!(watchpoints.take_pending().is_some());
+
+        watchpoints.start_instruction(0x8002);
+        watchpoints.check(0x00fa, WatchKind::Write, 0);
+        assert!(watchpoints.take_pending().is_some());
+
+        assert!(watchpoints.remove(&(0x00fa..=0x00fa), WatchKind::ReadWrite));
+        assert!(!watchpoints.remove(&(0x00fa..=0x00fa), WatchKind::ReadWrite));
+        watchpoints.check(0x00fa, WatchKind::Write, 0);
+        assert_eq!(watchpoints.take_pending(), None);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::render::frame::Frame;
+
+// how far around the aim point the photodiode sees, in pixels
+const SENSE_RADIUS: usize = 2;
+// luminance (0 - 255) a pixel needs to count as light
+const DEFAULT_LIGHT_THRESHOLD: u8 = 0xc0;
+
+// the light gun, plugged into port 2 and read through 0x4017:
+//   76543210
+//   |||+----- trigger (1: pulled)
+//   ||+------ light sense (0: light detected)
+// see https://www.nesdev.org/wiki/Zapper
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Zapper {
+    trigger: bool,
+    // None when pointed away from the screen
+    aim: Option<(u16, u16)>,
+    light_threshold: u8,
+    // some frontends want the light bit the other way round
+    invert_light: bool,
+}
+
+impl Default for Zapper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Zapper {
+            trigger: false,
+            aim: None,
+            light_threshold: DEFAULT_LIGHT_THRESHOLD,
+            invert_light: false,
+        }
+    }
+
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    pub fn set_aim(&mut self, x: u16, y: u16) {
+        self.aim = Some((x, y));
+    }
+
+    pub fn aim_off_screen(&mut self) {
+        self.aim = None;
+    }
+
+    // lower is more sensitive
+    pub fn set_light_threshold(&mut self, threshold: u8) {
+        self.light_threshold = threshold;
+    }
+
+    pub fn set_invert_light(&mut self, invert: bool) {
+        self.invert_light = invert;
+    }
+
+    // the gun looks at whatever the ppu has drawn so far, so games flash the target white
+    // for a frame before reading this
+    pub fn read(&self, frame: &Frame) -> u8 {
+        let light = self.sees_light(frame) != self.invert_light;
+        ((self.trigger as u8) << 4) | ((!light as u8) << 3)
+    }
+
+    fn sees_light(&self, frame: &Frame) -> bool {
+        let Some((x, y)) = self.aim else {
+            return false;
+        };
+        let (x, y) = (x as usize, y as usize);
+        if x >= Frame::WIDTH || y >= Frame::HEIGHT {
+            return false;
+        }
+
+        let columns = x.saturating_sub(SENSE_RADIUS)..=(x + SENSE_RADIUS).min(Frame::WIDTH - 1);
+        let rows = y.saturating_sub(SENSE_RADIUS)..=(y + SENSE_RADIUS).min(Frame::HEIGHT - 1);
+        rows.flat_map(|row| columns.clone().map(move |column| (column, row)))
+            .any(|(column, row)| luminance(frame.get_pixel(column, row)) >= self.light_threshold)
+    }
+}
+
+fn luminance((r, g, b): (u8, u8, u8)) -> u8 {
+    ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // black, except for a white 16x16 square at (100, 100)
+    fn target_frame() -> Frame {
+        let mut frame = Frame::new();
+        for y in 100..116 {
+            for x in 100..116 {
+                frame.set_pixel(x, y, (0xff, 0xff, 0xff));
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn test_light_sense() {
+        let frame = target_frame();
+        let mut zapper = Zapper::new();
+
+        zapper.set_aim(108, 108);
+        assert_eq!(zapper.read(&frame) & 0b1000, 0);
+
+        zapper.set_aim(20, 20);
+        assert_eq!(zapper.read(&frame) & 0b1000, 0b1000);
+
+        zapper.aim_off_screen();
+        assert_eq!(zapper.read(&frame) & 0b1000, 0b1000);
+    }
+
+    #[test]
+    fn test_trigger_is_independent_of_light() {
+        let frame = target_frame();
+        let mut zapper = Zapper::new();
+        zapper.set_trigger(true);
+
+        zapper.set_aim(20, 20);
+        assert_eq!(zapper.read(&frame), 0b1_1000);
+        zapper.set_aim(108, 108);
+        assert_eq!(zapper.read(&frame), 0b1_0000);
+
+        zapper.set_trigger(false);
+        assert_eq!(zapper.read(&frame), 0);
+    }
+
+    #[test]
+    fn test_threshold_and_invert() {
+        let mut frame = Frame::new();
+        frame.set_pixel(50, 50, (0x80, 0x80, 0x80));
+        let mut zapper = Zapper::new();
+        zapper.set_aim(50, 50);
+        assert_eq!(zapper.read(&frame), 0b1000);
+
+        zapper.set_light_threshold(0x40);
+        assert_eq!(zapper.read(&frame), 0);
+
+        zapper.set_invert_light(true);
+        assert_eq!(zapper.read(&frame), 0b1000);
+    }
+}
@@ -0,0 +1,131 @@
+// the test rom harness, against roms built here so it runs without downloading anything.
+// point BLARGG_ROM at a real one to run that too:
+//
+//   BLARGG_ROM=path/to/instr_test-v5/rom_singles/01-basics.nes cargo test --test blargg -- --ignored
+mod test_rom;
+
+use std::env;
+
+use test_rom::{run_test_rom, run_test_rom_bytes, TestRomResult};
+
+const BUDGET: u64 = 2_000_000;
+
+// a 16KB nrom with `code` at 0xc000, which is where the reset vector points
+fn nrom(code: &[u8]) -> Vec<u8> {
+    let mut prg = vec![0; 0x4000];
+    prg[..code.len()].copy_from_slice(code);
+    prg[0x3ffc] = 0x00;
+    prg[0x3ffd] = 0xc0;
+
+    let mut raw = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0, 0];
+    raw.resize(16, 0);
+    raw.extend(prg);
+    raw.extend(vec![0; 0x2000]);
+    raw
+}
+
+// what the real roms start with, otherwise the apu frame counter's irq goes through a vector
+// that points nowhere
+fn sei() -> Vec<u8> {
+    vec![0x78]
+}
+
+// LDA #value; STA addr
+fn store(code: &mut Vec<u8>, addr: u16, value: u8) {
+    code.extend([0xa9, value, 0x8d, addr as u8, (addr >> 8) as u8]);
+}
+
+fn report(code: &mut Vec<u8>, status: u8, message: &str) {
+    for (i, byte) in message.bytes().chain([0]).enumerate() {
+        store(code, 0x6004 + i as u16, byte);
+    }
+    store(code, 0x6000, status);
+}
+
+// running first, like the real roms, so the status is never mistaken for a result
+fn signature(code: &mut Vec<u8>) {
+    store(code, 0x6000, 0x80);
+    store(code, 0x6001, 0xde);
+    store(code, 0x6002, 0xb0);
+    store(code, 0x6003, 0x61);
+}
+
+// JMP to itself
+fn spin(code: &mut Vec<u8>) {
+    let here = 0xc000 + code.len() as u16;
+    code.extend([0x4c, here as u8, (here >> 8) as u8]);
+}
+
+#[test]
+fn test_passing_rom() {
+    let mut code = sei();
+    signature(&mut code);
+    report(&mut code, 0x00, "Passed\n");
+    spin(&mut code);
+
+    assert_eq!(
+        run_test_rom_bytes(&nrom(&code), BUDGET),
+        Ok(TestRomResult {
+            code: 0,
+            message: String::from("Passed\n"),
+        })
+    );
+}
+
+#[test]
+fn test_failure_surfaces_the_message() {
+    let mut code = sei();
+    signature(&mut code);
+    report(&mut code, 0x03, "LDA #imm\nFailed #3\n");
+    spin(&mut code);
+
+    let result = run_test_rom_bytes(&nrom(&code), BUDGET).unwrap();
+    assert!(!result.passed());
+    assert_eq!(result.code, 3);
+    assert_eq!(result.message, "LDA #imm\nFailed #3\n");
+}
+
+#[test]
+fn test_reset_required() {
+    // prg ram outlives the reset, so 0x6010 tells the second run it's the second run
+    let mut code = sei();
+    code.extend([0xad, 0x10, 0x60, 0xd0, 0x00]);
+    store(&mut code, 0x6010, 1);
+    signature(&mut code);
+    store(&mut code, 0x6000, 0x81);
+    spin(&mut code);
+    code[5] = (code.len() - 6) as u8;
+    report(&mut code, 0x00, "after reset");
+    spin(&mut code);
+
+    let result = run_test_rom_bytes(&nrom(&code), BUDGET).unwrap();
+    assert_eq!(result.message, "after reset");
+    assert!(result.passed());
+}
+
+#[test]
+fn test_out_of_cycles() {
+    let mut code = sei();
+    signature(&mut code);
+    report(&mut code, 0x80, "still going");
+    spin(&mut code);
+
+    assert_eq!(
+        run_test_rom_bytes(&nrom(&code), 10_000),
+        Err(String::from("no result after 10002 cycles: still going"))
+    );
+}
+
+#[test]
+#[ignore = "needs BLARGG_ROM pointing at a test rom"]
+fn blargg_rom() {
+    let path = env::var("BLARGG_ROM").expect("BLARGG_ROM isn't set");
+    let result = run_test_rom(&path, 200_000_000).unwrap();
+    assert!(
+        result.passed(),
+        "{} failed with {}:\n{}",
+        path,
+        result.code,
+        result.message
+    );
+}
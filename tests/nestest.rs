@@ -0,0 +1,87 @@
+// runs nestest.nes in automation mode and diffs every instruction against nestest.log, see
+// https://www.nesdev.org/wiki/Emulator_tests
+//
+// the rom isn't redistributable, so this needs pointing at a copy:
+//
+//   NESTEST_ROM=path/to/nestest.nes cargo test --test nestest -- --ignored
+//
+// NESTEST_LOG    the reference log, defaults to nestest.log next to the rom
+// NESTEST_LINES  stop after this many lines, defaults to the whole log. 5003 is the last
+//                official opcode
+// NESTEST_CYCLES set to compare the CYC column too
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use nes_emulator::bus::Bus;
+use nes_emulator::cpu::{StatusFlags, CPU};
+use nes_emulator::rom::Rom;
+use nes_emulator::trace::trace;
+
+// everything up to and including "SP:xx", the PPU and CYC columns come after
+const REGISTERS_END: usize = 73;
+
+fn cycles_column(line: &str) -> Option<u64> {
+    let start = line.find("CYC:")? + 4;
+    line[start..].split_whitespace().next()?.parse().ok()
+}
+
+#[test]
+#[ignore = "needs NESTEST_ROM pointing at nestest.nes"]
+fn nestest() {
+    let rom_path = PathBuf::from(env::var("NESTEST_ROM").expect("NESTEST_ROM isn't set"));
+    let log_path = env::var("NESTEST_LOG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| rom_path.with_file_name("nestest.log"));
+    let lines = env::var("NESTEST_LINES")
+        .map(|n| n.parse().expect("NESTEST_LINES isn't a number"))
+        .unwrap_or(usize::MAX);
+    let check_cycles = env::var_os("NESTEST_CYCLES").is_some();
+
+    let raw = fs::read(&rom_path).expect("couldn't read the rom");
+    let log = fs::read_to_string(&log_path).expect("couldn't read the log");
+    let expected: Vec<&str> = log.lines().take(lines).collect();
+
+    let bus = Bus::with_rom(Rom::new(&raw).unwrap()).unwrap();
+    let mut cpu = CPU::new(bus);
+    cpu.halt_on_brk = false;
+    cpu.reset();
+    // automation mode starts here instead of the reset vector, with the state the log has
+    cpu.program_counter = 0xc000;
+    cpu.status = StatusFlags::from_bits_truncate(0x24);
+    cpu.cycles = 7;
+
+    let mut line = 0;
+    let mut mismatch = None;
+    cpu.run_with_callback(|cpu| {
+        if line == expected.len() {
+            cpu.halted = true;
+            return;
+        }
+
+        let actual = trace(cpu);
+        let reference = expected[line];
+        let registers_match = reference.get(..REGISTERS_END) == Some(actual.as_str());
+        let cycles_match = !check_cycles || cycles_column(reference) == Some(cpu.cycles);
+        if !registers_match || !cycles_match {
+            mismatch = Some((line + 1, actual, cpu.cycles, reference.to_string()));
+            cpu.halted = true;
+            return;
+        }
+        line += 1;
+    })
+    .unwrap();
+
+    if let Some((number, actual, cycles, reference)) = mismatch {
+        panic!(
+            "line {} differs\n  expected: {}\n  actual:   {} CYC:{}",
+            number, reference, actual, cycles
+        );
+    }
+    assert_eq!(line, expected.len(), "stopped before the end of the log");
+
+    // nestest leaves the number of the first failing official/unofficial test here
+    let bus = cpu.bus();
+    assert_eq!(bus.peek(0x0002), 0, "official opcode error code");
+    assert_eq!(bus.peek(0x0003), 0, "unofficial opcode error code");
+}
@@ -0,0 +1,77 @@
+// the easy6502 snake game, played headless with the keys pressed on a schedule
+use nes_emulator::asm::assemble_at;
+use nes_emulator::breakpoints::StopReason;
+use nes_emulator::bus::Bus;
+use nes_emulator::cpu::CPU;
+use nes_emulator::easy6502::{read_screen, HEIGHT, LAST_KEY, ORIGIN, RANDOM, SCREEN, WIDTH};
+
+const SOURCE: &str = include_str!("../examples/snake.asm");
+
+// where the game goes round its loop, once a move, and where it's just drawn the snake
+const LOOP: u16 = 0x0638;
+const SPIN_WHEELS: u16 = 0x072d;
+
+// the key that's down from each move on
+const KEYS: [(usize, u8); 4] = [(0, b'd'), (8, b's'), (12, b'a'), (20, b'w')];
+
+// never 0, so an apple is never drawn invisible
+fn xorshift(state: &mut u8) -> u8 {
+    *state ^= *state << 1;
+    *state ^= *state >> 1;
+    *state ^= *state << 2;
+    *state
+}
+
+#[test]
+fn test_snake_follows_the_keys() {
+    let mut cpu = CPU::new(Bus::new());
+    cpu.load_at(&assemble_at(SOURCE, ORIGIN).unwrap(), ORIGIN)
+        .unwrap();
+    cpu.reset();
+
+    let mut random = 1;
+    let mut moves = 0;
+    let stop = cpu
+        .run_with_callback(|cpu| {
+            cpu.poke(RANDOM, xorshift(&mut random));
+            match cpu.program_counter {
+                LOOP => {
+                    if let Some(&(_, key)) = KEYS.iter().find(|(from, _)| *from == moves) {
+                        cpu.poke(LAST_KEY, key);
+                    }
+                    moves += 1;
+                }
+                SPIN_WHEELS if moves == 24 => cpu.halted = true,
+                _ => {}
+            }
+        })
+        .unwrap();
+    // stopped by the callback, not by running into something and hitting the BRK at gameOver
+    assert_eq!(stop, StopReason::Halted);
+    assert_eq!(cpu.program_counter, SPIN_WHEELS);
+
+    // 8 right from column 17 of row 16, 4 down, 8 left and 4 up
+    let head = cpu.bus().peek_u16(0x10);
+    assert_eq!(head, SCREEN + 16 * 32 + 17);
+
+    // the head and the body in the screen as 1s, the apple as whatever random byte it was
+    // drawn with, and nothing else
+    let length = cpu.peek(0x03) as u16;
+    let snake: Vec<u16> = (0..length / 2)
+        .map(|i| cpu.bus().peek_u16(0x10 + i * 2))
+        .collect();
+    let apple = cpu.bus().peek_u16(0x00);
+    for addr in SCREEN..SCREEN + (WIDTH * HEIGHT) as u16 {
+        let value = cpu.peek(addr);
+        if snake.contains(&addr) {
+            assert_eq!(value, 1, "snake at ${:04x}", addr);
+        } else if addr == apple {
+            assert_ne!(value, 0);
+        } else {
+            assert_eq!(value, 0, "garbage at ${:04x}", addr);
+        }
+    }
+
+    let mut frame = [0; WIDTH * HEIGHT * 3];
+    assert!(read_screen(&cpu, &mut frame));
+}
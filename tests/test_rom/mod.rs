@@ -0,0 +1,102 @@
+// runs the kind of test rom blargg's suites are built from. they report through prg ram:
+//
+// 0x6000       status, 0x80 while running, 0x81 when the rom wants a reset, otherwise the
+//              result code with 0x00 meaning passed
+// 0x6001-6003  0xde 0xb0 0x61 once the above means anything
+// 0x6004-      what went wrong, zero terminated
+//
+// see https://github.com/christopherpow/nes-test-roms/blob/master/instr_test-v5/readme.txt
+use std::fs;
+use std::path::Path;
+
+use nes_emulator::apu::output::CPU_FREQUENCY;
+use nes_emulator::bus::Bus;
+use nes_emulator::cpu::{StepResult, CPU};
+use nes_emulator::rom::Rom;
+
+const STATUS: u16 = 0x6000;
+const SIGNATURE: u16 = 0x6001;
+const MESSAGE: u16 = 0x6004;
+const MESSAGE_END: u16 = 0x7fff;
+
+const RUNNING: u8 = 0x80;
+const RESET_REQUIRED: u8 = 0x81;
+// the roms ask for the reset and then expect it to take a while to come
+const RESET_DELAY: u64 = CPU_FREQUENCY as u64 / 10;
+
+#[derive(Debug, PartialEq)]
+pub struct TestRomResult {
+    pub code: u8,
+    pub message: String,
+}
+
+impl TestRomResult {
+    pub fn passed(&self) -> bool {
+        self.code == 0
+    }
+}
+
+pub fn run_test_rom(path: impl AsRef<Path>, max_cycles: u64) -> Result<TestRomResult, String> {
+    let path = path.as_ref();
+    let raw = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    run_test_rom_bytes(&raw, max_cycles)
+}
+
+// Err when the rom can't be loaded or is still going after max_cycles
+pub fn run_test_rom_bytes(raw: &[u8], max_cycles: u64) -> Result<TestRomResult, String> {
+    let bus = Bus::with_rom(Rom::new(raw)?)?;
+    let mut cpu = CPU::new(bus);
+    cpu.halt_on_brk = false;
+    cpu.reset();
+
+    let mut reset_at = None;
+    while cpu.cycles < max_cycles {
+        if matches!(
+            cpu.step().map_err(|e| e.to_string())?,
+            StepResult::Stopped(_)
+        ) {
+            break;
+        }
+
+        if !has_signature(cpu.bus()) {
+            continue;
+        }
+        match cpu.bus().peek(STATUS) {
+            RUNNING => {}
+            RESET_REQUIRED => {
+                let at = *reset_at.get_or_insert(cpu.cycles + RESET_DELAY);
+                if cpu.cycles >= at {
+                    reset_at = None;
+                    cpu.soft_reset();
+                }
+            }
+            code => {
+                return Ok(TestRomResult {
+                    code,
+                    message: message(cpu.bus()),
+                })
+            }
+        }
+    }
+
+    Err(format!(
+        "no result after {} cycles: {}",
+        cpu.cycles,
+        message(cpu.bus())
+    ))
+}
+
+fn has_signature(bus: &Bus) -> bool {
+    [0xde, 0xb0, 0x61]
+        .iter()
+        .enumerate()
+        .all(|(i, byte)| bus.peek(SIGNATURE + i as u16) == *byte)
+}
+
+fn message(bus: &Bus) -> String {
+    (MESSAGE..=MESSAGE_END)
+        .map(|addr| bus.peek(addr))
+        .take_while(|byte| *byte != 0)
+        .map(|byte| byte as char)
+        .collect()
+}
@@ -0,0 +1,68 @@
+// how fast the cpu gets through a tight loop, with the ppu and apu ticking along:
+//
+//   cargo test --release --test throughput -- --ignored --nocapture
+use std::time::Instant;
+
+use nes_emulator::asm::assemble;
+use nes_emulator::bus::Bus;
+use nes_emulator::cpu::CPU;
+use nes_emulator::watchpoints::WatchKind;
+
+const INSTRUCTIONS: u64 = 5_000_000;
+
+fn inx_loop(what: &str, debug: impl FnOnce(&mut CPU)) {
+    let mut cpu = CPU::new(Bus::new());
+    cpu.load(
+        assemble(
+            "
+            loop: INX
+                  JMP loop
+            ",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    cpu.reset();
+    debug(&mut cpu);
+
+    let start = Instant::now();
+    for _ in 0..INSTRUCTIONS {
+        cpu.step().unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{} instructions in {:?} {}, {:.1}M/s",
+        INSTRUCTIONS,
+        elapsed,
+        what,
+        INSTRUCTIONS as f64 / elapsed.as_secs_f64() / 1_000_000.0
+    );
+}
+
+#[test]
+#[ignore = "a benchmark, run it in release"]
+fn without_breakpoints() {
+    inx_loop("without breakpoints", |_| {});
+}
+
+// none of them are in the loop, so this is the cost of looking
+#[test]
+#[ignore = "a benchmark, run it in release"]
+fn with_breakpoints() {
+    inx_loop("with breakpoints", |cpu| {
+        for addr in [0x1234, 0x9000, 0xc000, 0xc100, 0xd000, 0xfff0] {
+            cpu.add_breakpoint(addr);
+        }
+    });
+}
+
+// JMP reads its operand, so every other instruction looks through these
+#[test]
+#[ignore = "a benchmark, run it in release"]
+fn with_watchpoints() {
+    inx_loop("with watchpoints", |cpu| {
+        cpu.add_watchpoint(0x0000..=0x07ff, WatchKind::ReadWrite);
+        cpu.add_watchpoint(0x6000..=0x7fff, WatchKind::Write);
+    });
+}
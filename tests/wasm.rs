@@ -0,0 +1,66 @@
+// the browser bindings, run natively with --features wasm or in a headless browser with
+//
+//   wasm-pack test --headless --firefox -- --features wasm
+#![cfg(feature = "wasm")]
+
+use nes_emulator::asm::assemble;
+use nes_emulator::render::frame::Frame;
+use nes_emulator::render::palette::SYSTEM_PALETTE;
+use nes_emulator::wasm::WasmEmulator;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen_test::wasm_bindgen_test;
+
+// sets the backdrop to light blue and turns the background on, so every pixel is that
+const RESET: &str = "
+        SEI
+        LDA #$3f
+        STA $2006
+        LDA #$00
+        STA $2006
+        LDA #$21
+        STA $2007
+        LDA #$0a
+        STA $2001
+    spin:
+        JMP spin
+";
+
+// a 16KB nrom with RESET at 0xc000
+fn rom() -> Vec<u8> {
+    let code = assemble(RESET).unwrap();
+    let mut prg = vec![0; 0x4000];
+    prg[..code.len()].copy_from_slice(&code);
+    prg[0x3ffc] = 0x00;
+    prg[0x3ffd] = 0xc0;
+
+    let mut raw = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0, 0];
+    raw.resize(16, 0);
+    raw.extend(prg);
+    raw.extend(vec![0; 0x2000]);
+    raw
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+fn test_frames_come_out_as_rgba() {
+    let mut emulator = WasmEmulator::new(&rom()).ok().unwrap();
+    for _ in 0..10 {
+        emulator.run_frame().ok().unwrap();
+    }
+
+    let frame = emulator.frame_buffer();
+    assert_eq!(frame.len(), Frame::WIDTH * Frame::HEIGHT * 4);
+    let (r, g, b) = SYSTEM_PALETTE[0x21];
+    for pixel in [
+        0,
+        1,
+        Frame::WIDTH * 120 + 128,
+        Frame::WIDTH * Frame::HEIGHT - 1,
+    ] {
+        assert_eq!(frame[pixel * 4..pixel * 4 + 4], [r, g, b, 0xff]);
+    }
+
+    // 10 frames of silence, but samples all the same
+    assert!(!emulator.audio_samples().is_empty());
+    assert!(emulator.audio_samples().is_empty());
+}